@@ -0,0 +1,759 @@
+use crate::oasm::*;
+use crate::opcode::*;
+use crate::preprocessor::*;
+use crate::program::{LineInfo, Program};
+use crate::utils::defines::*;
+use crate::utils::error::*;
+
+use log::*;
+
+// Turns source into a Program, independent of any OSVM
+pub struct Assembler {
+    oasm: OASM,
+    code: Vec<Opcode>,
+    
+    // Source line of every instruction in `code`
+    files: Vec<String>,
+    lines: Vec<LineInfo>,
+}
+
+impl Assembler {
+    pub fn init() -> Assembler {
+        Assembler {
+            oasm: OASM::init(),
+            code: Vec::new(),
+            files: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+    
+    fn get_operands<'a>(self: &Self, args: &'a str, len1: usize, len2: usize, line: &SourceLine) -> Result<Vec<&'a str>, Error> {
+        let operands: Vec<&str> = if args.trim().is_empty() {
+            Vec::new()
+        } else {
+            args.trim().split(", ").collect()
+        };
+        
+        if operands.len() < len1 || operands.len() > len2 {
+            return Err(Error::with_message(
+                ErrorKind::InvalidOperandCount,
+                format!("expected {} operands, got {}", len2, operands.len())
+            ).at_span(line.span(args.trim())));
+        }
+        
+        Ok(operands)
+    }
+    
+    // Addresses, sizes and stack indices are plain unsigned integers
+    fn parse_index(self: &Self, token: &str, prefix: &str, line: &SourceLine) -> Result<u64, Error> {
+        match token.replace(prefix, "").parse::<u64>() {
+            Ok(value) => Ok(value),
+            Err(_) => Err(Error::with_message(ErrorKind::InvalidOperand, format!("invalid operand `{}`", token)).at_span(line.span(token))),
+        }
+    }
+    
+    // Register operands are resolved here so a bad name is an assembly error
+    fn regs(self: &Self, operands: &[&str], line: &SourceLine) -> Result<OpRegs, Error> {
+        let mut regs = OpRegs::init();
+        for operand in operands {
+            let index = register_index(operand.trim()).ok_or(ErrorKind::InvalidRegister);
+            if let Err(kind) = index.and_then(|index| regs.push(index)) {
+                return Err(Error::with_message(kind, format!("invalid register `{}`", operand)).at_span(line.span(operand)));
+            }
+        }
+        
+        Ok(regs)
+    }
+    
+    pub fn assemble(self: &mut Self, input_path: &str, source: &str) -> Result<Program, Vec<Error>> {
+        self.oasm = OASM::init();
+        self.code.clear();
+        self.files.clear();
+        self.lines.clear();
+        
+        let preprocessor = Preprocessor {};
+        let lines = preprocessor.process(input_path, source)?;
+        
+        // Errors are collected so that every bad line gets reported at once
+        let mut errors = Vec::new();
+        for line in &lines {
+            if let Err(err) = self.translate_line(line) {
+                errors.push(err);
+            }
+            
+            self.record_lines(line);
+        }
+        
+        let entry = match self.oasm.labels_contains("_start") {
+            Some(entry) => entry as usize,
+            None => {
+                errors.push(Error::with_message(ErrorKind::MissingEntryPoint, "the program has no `_start` label"));
+                0
+            }
+        };
+        
+        // A line that failed may not have pushed the opcode its label was for
+        for deferred in &self.oasm.deferred_operands {
+            match (self.oasm.labels_contains(&deferred.label), self.code.get_mut(deferred.addr)) {
+                (Some(label_addr), Some(opcode)) => opcode.op_operand = Some(TypedWord::u64(label_addr as u64)),
+                (Some(_), None) => {}
+                (None, _) => {
+                    errors.push(Error::with_message(
+                        ErrorKind::UndefinedLabel,
+                        format!("label `{}` does not exist", deferred.label)
+                    ).at_span(deferred.span.clone()));
+                }
+            }
+        }
+        
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        
+        Ok(Program {
+            code: self.code.clone(),
+            entry,
+            symbols: self.oasm.labels.clone(),
+            files: self.files.clone(),
+            lines: self.lines.clone(),
+        })
+    }
+    
+    // Attributes every instruction `line` generated to it, through includes
+    fn record_lines(self: &mut Self, line: &SourceLine) {
+        if self.lines.len() == self.code.len() {
+            return;
+        }
+        
+        let file = match self.files.iter().position(|file| *file == line.file) {
+            Some(file) => file,
+            None => {
+                self.files.push(line.file.clone());
+                self.files.len() - 1
+            }
+        };
+        
+        self.lines.resize(self.code.len(), LineInfo { file, line: line.line });
+    }
+    
+    fn translate_line(self: &mut Self, line: &SourceLine) -> Result<(), Error> {
+        let mut tokens: Vec<&str> = line.code.splitn(2, char::is_whitespace).collect();
+        if !tokens.is_empty() && !tokens[0].is_empty() {
+            let mut inst_name = tokens[0];
+            tokens.remove(0);
+            
+            if inst_name.ends_with(':') {
+                let label = &inst_name[..inst_name.len() - 1];
+                if self.oasm.labels_contains(label).is_some() {
+                    return Err(Error::with_message(ErrorKind::DuplicateLabel, format!("label `{}` is already defined", label)).at_span(line.span(label)));
+                }
+                
+                self.oasm.labels_push(label, self.code.len());
+                
+                if tokens.len() > 0 {
+                    tokens = tokens[0].trim().splitn(2, char::is_whitespace).collect();
+                    inst_name = tokens[0];
+                    tokens.remove(0);
+                } else {
+                    return Ok(());
+                }
+            }
+            
+            // The unsplit operands, empty for opcodes without any
+            let args = tokens.first().copied().unwrap_or("");
+            match inst_name {
+                // Register opcodes
+                MOV => {
+                    let operands: Vec<&str> = self.get_operands(args, 2, 2, line)?;
+                    
+                    if operands[1].starts_with("r") {
+                        self.code.push(Opcode { op_type: OpcodeType::Mov, op_operand: None, op_regs: self.regs(&[operands[0], operands[1]], line)? });
+                    } else if operands[1].starts_with(CONST) {
+                        match TypedWord::parse_literal(operands[1]) {
+                            Some(value) => self.code.push(Opcode { op_type: OpcodeType::Mov, op_operand: Some(value), op_regs: self.regs(&[operands[0]], line)? }),
+                            None => return Err(Error::with_message(ErrorKind::InvalidOperand, format!("invalid literal `{}`", operands[1])).at_span(line.span(operands[1]))),
+                        }
+                    } else if operands[1].starts_with(GSI) {
+                        self.code.push(Opcode { op_type: OpcodeType::Movfs, op_operand: Some(TypedWord::u64(self.parse_index(operands[1], GSI, line)?)), op_regs: self.regs(&[operands[0]], line)? });
+                    } else {
+                        return Err(Error::with_message(ErrorKind::InvalidOperand, format!("invalid operand `{}`", operands[1])).at_span(line.span(operands[1])));
+                    }
+                }
+                PHSR => {
+                    error!("[Warning]: `phsr` is deprecated use `mov [reg], $[index]` instead.");
+                    self.code.push(Opcode { op_type: OpcodeType::Phsr, op_operand: None, op_regs: self.regs(&[args], line)? });
+                }
+                
+                SRG => {
+                    let operands: Vec<&str> = self.get_operands(args, 2, 2, line)?;
+                    
+                    self.code.push(Opcode { op_type: OpcodeType::Srg, op_operand: None, op_regs: self.regs(&[operands[0], operands[1]], line)? });
+                }
+                
+                CLR => {
+                    let operands: Vec<&str> = self.get_operands(args, 1, 1, line)?;
+                    
+                    self.code.push(Opcode { op_type: OpcodeType::Clr, op_operand: None, op_regs: self.regs(&[operands[0]], line)? });
+                }
+                
+                ADD | SUB | MUL | DIV => {
+                    let operands: Vec<&str> = self.get_operands(args, 3, 3, line)?;
+                    
+                    match inst_name {
+                        ADD => {
+                            self.code.push(Opcode { op_type: OpcodeType::Add, op_operand: None, op_regs: self.regs(&[operands[0], operands[1], operands[2]], line)? });
+                        }
+                        SUB => {
+                            self.code.push(Opcode { op_type: OpcodeType::Sub, op_operand: None, op_regs: self.regs(&[operands[0], operands[1], operands[2]], line)? });
+                        }
+                        MUL => {
+                            self.code.push(Opcode { op_type: OpcodeType::Mul, op_operand: None, op_regs: self.regs(&[operands[0], operands[1], operands[2]], line)? });
+                        }
+                        DIV => {
+                            self.code.push(Opcode { op_type: OpcodeType::Div, op_operand: None, op_regs: self.regs(&[operands[0], operands[1], operands[2]], line)? });
+                        }
+                
+                        _ => {}
+                    }
+                }
+                
+                DEC => {
+                    let operands: Vec<&str> = self.get_operands(args, 1, 1, line)?;
+                    self.code.push(Opcode { op_type: OpcodeType::Dec, op_operand: None, op_regs: self.regs(&[operands[0]], line)? });
+                }
+                INC => {
+                    let operands: Vec<&str> = self.get_operands(args, 1, 1, line)?;
+                    self.code.push(Opcode { op_type: OpcodeType::Inc, op_operand: None, op_regs: self.regs(&[operands[0]], line)? });
+                }
+                
+                EQUAL | NE | LT | LE | GT | GE => {
+                    let operands: Vec<&str> = self.get_operands(args, 3, 3, line)?;
+                    let op_type = match inst_name {
+                        EQUAL => OpcodeType::Equal,
+                        NE => OpcodeType::Ne,
+                        LT => OpcodeType::Lt,
+                        LE => OpcodeType::Le,
+                        GT => OpcodeType::Gt,
+                        _ => OpcodeType::Ge,
+                    };
+                    
+                    self.code.push(Opcode { op_type, op_operand: None, op_regs: self.regs(&[operands[0], operands[1], operands[2]], line)? });
+                }
+                
+                JT | JZ | JNZ => {
+                    let operands: Vec<&str> = self.get_operands(args, 2, 2, line)?;
+                    
+                    if operands[0].starts_with(CONST) {
+                        match inst_name {
+                            JT => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jt, op_operand: Some(TypedWord::u64(self.parse_index(operands[0], CONST, line)?)), op_regs: self.regs(&[operands[1]], line)? });
+                            }
+                            JZ => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jz, op_operand: Some(TypedWord::u64(self.parse_index(operands[0], CONST, line)?)), op_regs: self.regs(&[operands[1]], line)? });
+                            }
+                            JNZ => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jnz, op_operand: Some(TypedWord::u64(self.parse_index(operands[0], CONST, line)?)), op_regs: self.regs(&[operands[1]], line)? });
+                            }
+                            
+                            _ => {}
+                        }
+                    } else {
+                        // The label is only deferred once the rest of the opcode is known to be good
+                        let op_regs = self.regs(&[operands[1]], line)?;
+                        self.oasm.deferred_operands_push(operands[0], self.code.len(), line.span(operands[0]));
+                        match inst_name {
+                            JT => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jt, op_operand: None, op_regs });
+                            }
+                            JZ => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jz, op_operand: None, op_regs });
+                            }
+                            JNZ => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jnz, op_operand: None, op_regs });
+                            }
+                            
+                            _ => {}
+                        }
+                    }
+                }
+                
+                SYSF => {
+                    if args.is_empty() {
+                        self.code.push(Opcode { op_type: OpcodeType::Sysf, op_operand: None, op_regs: OpRegs::init() });
+                    } else {
+                        let operands = self.get_operands(args, 1, 1, line)?;
+                        self.code.push(Opcode { op_type: OpcodeType::Sysf, op_operand: None, op_regs: self.regs(&[operands[0]], line)? });
+                    }
+                }
+                
+                // Stack opcodes
+                PUSH => {
+                    if args.starts_with('r') {
+                        self.code.push(Opcode { op_type: OpcodeType::Push, op_operand: None, op_regs: self.regs(&[args], line)? });
+                    } else if args.starts_with(CONST) {
+                        match TypedWord::parse_literal(args) {
+                            Some(value) => self.code.push(Opcode { op_type: OpcodeType::Push, op_operand: Some(value), op_regs: OpRegs::init() }),
+                            None => return Err(Error::with_message(ErrorKind::InvalidOperand, format!("invalid literal `{}`", args)).at_span(line.span(args))),
+                        }
+                    } else {
+                        return Err(Error::with_message(ErrorKind::InvalidOperand, format!("invalid operand `{}`", args)).at_span(line.span(args)));
+                    }
+                }
+                
+                ADDS | SUBS | MULS | DIVS => {
+                    match inst_name {
+                        ADDS => {
+                            self.code.push(Opcode { op_type: OpcodeType::Adds, op_operand: None, op_regs: OpRegs::init() });
+                        }
+                        SUBS => {
+                            self.code.push(Opcode { op_type: OpcodeType::Subs, op_operand: None, op_regs: OpRegs::init() });
+                        }
+                        MULS => {
+                            self.code.push(Opcode { op_type: OpcodeType::Muls, op_operand: None, op_regs: OpRegs::init() });
+                        }
+                        DIVS => {
+                            self.code.push(Opcode { op_type: OpcodeType::Divs, op_operand: None, op_regs: OpRegs::init() });
+                        }
+                        
+                        _ => {}
+                    }
+                }
+                
+                DUPL => {
+                    let op = self.parse_index(args, CONST, line)?;
+                    self.code.push(Opcode { op_type: OpcodeType::Dupl, op_operand: Some(TypedWord::u64(op)), op_regs: OpRegs::init() });
+                }
+                
+                EQUALS | NES | LTS | LES | GTS | GES => {
+                    let op_type = match inst_name {
+                        EQUALS => OpcodeType::Equals,
+                        NES => OpcodeType::Nes,
+                        LTS => OpcodeType::Lts,
+                        LES => OpcodeType::Les,
+                        GTS => OpcodeType::Gts,
+                        _ => OpcodeType::Ges,
+                    };
+                    
+                    self.code.push(Opcode { op_type, op_operand: None, op_regs: OpRegs::init() });
+                }
+                
+                JTS | JZS | JNZS => {
+                    if args.starts_with(CONST) {
+                        match inst_name {
+                            JTS => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jts, op_operand: Some(TypedWord::u64(self.parse_index(args, CONST, line)?)), op_regs: OpRegs::init() });
+                            }
+                            JZS => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jzs, op_operand: Some(TypedWord::u64(self.parse_index(args, CONST, line)?)), op_regs: OpRegs::init() });
+                            }
+                            JNZS => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jnzs, op_operand: Some(TypedWord::u64(self.parse_index(args, CONST, line)?)), op_regs: OpRegs::init() });
+                            }
+                            
+                            _ => {}
+                        }
+                    } else {
+                        self.oasm.deferred_operands_push(args, self.code.len(), line.span(args));
+                        match inst_name {
+                            JTS => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jts, op_operand: None, op_regs: OpRegs::init() });
+                            }
+                            JZS => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jzs, op_operand: None, op_regs: OpRegs::init() });
+                            }
+                            JNZS => {
+                                self.code.push(Opcode { op_type: OpcodeType::Jnzs, op_operand: None, op_regs: OpRegs::init() });
+                            }
+                            
+                            _ => {}
+                        }
+                    }
+                }
+                
+                SWC => {
+                    let op = self.parse_index(args, CONST, line)?;
+                    self.code.push(Opcode { op_type: OpcodeType::Swc, op_operand: Some(TypedWord::u64(op)), op_regs: OpRegs::init() });
+                }
+                
+                // Universal opcodes
+                JMP => {
+                    if args.starts_with(CONST) {
+                        self.code.push(Opcode { op_type: OpcodeType::Jmp, op_operand: Some(TypedWord::u64(self.parse_index(args, CONST, line)?)), op_regs: OpRegs::init() });
+                    } else {
+                        self.oasm.deferred_operands_push(args, self.code.len(), line.span(args));
+                        self.code.push(Opcode { op_type: OpcodeType::Jmp, op_operand: None, op_regs: OpRegs::init() });
+                    }
+                }
+                
+                CALL => {
+                    if args.starts_with(CONST) {
+                        self.code.push(Opcode { op_type: OpcodeType::Call, op_operand: Some(TypedWord::u64(self.parse_index(args, CONST, line)?)), op_regs: OpRegs::init() });
+                    } else {
+                        self.oasm.deferred_operands_push(args, self.code.len(), line.span(args));
+                        self.code.push(Opcode { op_type: OpcodeType::Call, op_operand: None, op_regs: OpRegs::init() });
+                    }
+                }
+                
+                READ => {
+                    if args.contains('r') {
+                        let operand = self.get_operands(args, 3, 3, line)?;
+                        self.code.push(Opcode { op_type: OpcodeType::Read, op_operand: Some(TypedWord::u64(self.parse_index(operand[0], CONST, line)?)), op_regs: self.regs(&[operand[1], operand[2]], line)? });
+                    } else {
+                        self.code.push(Opcode { op_type: OpcodeType::Read, op_operand: Some(TypedWord::u64(self.parse_index(args, CONST, line)?)), op_regs: OpRegs::init() });
+                    }
+                }
+                WRITE => {
+                    if args.contains('r') {
+                        let operand = self.get_operands(args, 3, 3, line)?;
+                        self.code.push(Opcode { op_type: OpcodeType::Write, op_operand: Some(TypedWord::u64(self.parse_index(operand[0], CONST, line)?)), op_regs: self.regs(&[operand[1], operand[2]], line)? });
+                    } else {
+                        self.code.push(Opcode { op_type: OpcodeType::Write, op_operand: Some(TypedWord::u64(self.parse_index(args, CONST, line)?)), op_regs: OpRegs::init() });
+                    }
+                }
+                
+                AND => {
+                    if args.starts_with('r') {
+                        let operand = self.get_operands(args, 3, 3, line)?;
+                        self.code.push(Opcode { op_type: OpcodeType::And, op_operand: None, op_regs: self.regs(&[operand[0], operand[1], operand[2]], line)? });
+                    } else {
+                        self.code.push(Opcode { op_type: OpcodeType::And, op_operand: None, op_regs: OpRegs::init() });
+                    }
+                }
+                
+                OR => {
+                    if args.starts_with('r') {
+                        let operand = self.get_operands(args, 3, 3, line)?;
+                        self.code.push(Opcode { op_type: OpcodeType::Or, op_operand: None, op_regs: self.regs(&[operand[0], operand[1], operand[2]], line)? });
+                    } else {
+                        self.code.push(Opcode { op_type: OpcodeType::Or, op_operand: None, op_regs: OpRegs::init() });
+                    }
+                }
+                XOR => {
+                    if args.starts_with('r') {
+                        let operand = self.get_operands(args, 3, 3, line)?;
+                        self.code.push(Opcode { op_type: OpcodeType::Xor, op_operand: None, op_regs: self.regs(&[operand[0], operand[1], operand[2]], line)? });
+                    } else {
+                        self.code.push(Opcode { op_type: OpcodeType::Xor, op_operand: None, op_regs: OpRegs::init() });
+                    }
+                }
+                
+                SHL => {
+                    if args.starts_with('r') {
+                        let operand = self.get_operands(args, 3, 3, line)?;
+                        self.code.push(Opcode { op_type: OpcodeType::Shl, op_operand: None, op_regs: self.regs(&[operand[0], operand[1], operand[2]], line)? });
+                    } else {
+                        self.code.push(Opcode { op_type: OpcodeType::Shl, op_operand: None, op_regs: OpRegs::init() });
+                    }
+                }
+                SHR => {
+                    if args.starts_with('r') {
+                        let operand = self.get_operands(args, 3, 3, line)?;
+                        self.code.push(Opcode { op_type: OpcodeType::Shr, op_operand: None, op_regs: self.regs(&[operand[0], operand[1], operand[2]], line)? });
+                    } else {
+                        self.code.push(Opcode { op_type: OpcodeType::Shr, op_operand: None, op_regs: OpRegs::init() });
+                    }
+                }
+                
+                NOT => {
+                    if args.starts_with('r') {
+                        let operand = self.get_operands(args, 2, 2, line)?;
+                        self.code.push(Opcode { op_type: OpcodeType::Not, op_operand: None, op_regs: self.regs(&[operand[0], operand[1]], line)? });
+                    } else {
+                        self.code.push(Opcode { op_type: OpcodeType::Not, op_operand: None, op_regs: OpRegs::init() });
+                    }
+                }
+                
+                POP => {
+                    if args.starts_with('r') {
+                        self.code.push(Opcode { op_type: OpcodeType::Pop, op_operand: None, op_regs: self.regs(&[args], line)? });
+                    } else {
+                        self.code.push(Opcode { op_type: OpcodeType::Pop, op_operand: None, op_regs: OpRegs::init() });
+                    }
+                }
+                
+                RET => {
+                    self.code.push(Opcode { op_type: OpcodeType::Ret, op_operand: None, op_regs: OpRegs::init() });
+                }
+                
+                HLT => {
+                    self.code.push(Opcode { op_type: OpcodeType::Hlt, op_operand: None, op_regs: OpRegs::init() });
+                }
+                
+                // Typed opcodes
+                ADDI | ADDU | ADDF | SUBI | SUBU | SUBF | MULI | MULU | MULF |
+                DIVI | DIVU | DIVF | MODI | MODU | MODF => {
+                    let operands: Vec<&str> = self.get_operands(args, 3, 3, line)?;
+                    let op_type = match inst_name {
+                        ADDI => OpcodeType::Addi,
+                        ADDU => OpcodeType::Addu,
+                        ADDF => OpcodeType::Addf,
+                        SUBI => OpcodeType::Subi,
+                        SUBU => OpcodeType::Subu,
+                        SUBF => OpcodeType::Subf,
+                        MULI => OpcodeType::Muli,
+                        MULU => OpcodeType::Mulu,
+                        MULF => OpcodeType::Mulf,
+                        DIVI => OpcodeType::Divi,
+                        DIVU => OpcodeType::Divu,
+                        DIVF => OpcodeType::Divf,
+                        MODI => OpcodeType::Modi,
+                        MODU => OpcodeType::Modu,
+                        _ => OpcodeType::Modf,
+                    };
+                    
+                    self.code.push(Opcode { op_type, op_operand: None, op_regs: self.regs(&[operands[0], operands[1], operands[2]], line)? });
+                }
+                
+                ADDIS | ADDUS | ADDFS | SUBIS | SUBUS | SUBFS | MULIS | MULUS | MULFS |
+                DIVIS | DIVUS | DIVFS | MODIS | MODUS | MODFS => {
+                    let op_type = match inst_name {
+                        ADDIS => OpcodeType::Addis,
+                        ADDUS => OpcodeType::Addus,
+                        ADDFS => OpcodeType::Addfs,
+                        SUBIS => OpcodeType::Subis,
+                        SUBUS => OpcodeType::Subus,
+                        SUBFS => OpcodeType::Subfs,
+                        MULIS => OpcodeType::Mulis,
+                        MULUS => OpcodeType::Mulus,
+                        MULFS => OpcodeType::Mulfs,
+                        DIVIS => OpcodeType::Divis,
+                        DIVUS => OpcodeType::Divus,
+                        DIVFS => OpcodeType::Divfs,
+                        MODIS => OpcodeType::Modis,
+                        MODUS => OpcodeType::Modus,
+                        _ => OpcodeType::Modfs,
+                    };
+                    
+                    self.code.push(Opcode { op_type, op_operand: None, op_regs: OpRegs::init() });
+                }
+                
+                NEGI | NEGF | ABSI | ABSF => {
+                    let op_type = match inst_name {
+                        NEGI => OpcodeType::Negi,
+                        NEGF => OpcodeType::Negf,
+                        ABSI => OpcodeType::Absi,
+                        _ => OpcodeType::Absf,
+                    };
+                    
+                    if args.starts_with('r') {
+                        let operand = self.get_operands(args, 2, 2, line)?;
+                        self.code.push(Opcode { op_type, op_operand: None, op_regs: self.regs(&[operand[0], operand[1]], line)? });
+                    } else {
+                        self.code.push(Opcode { op_type, op_operand: None, op_regs: OpRegs::init() });
+                    }
+                }
+                
+                // Typed comparisons
+                LTI | LTU | LTF | LEI | LEU | LEF | GTI | GTU | GTF | GEI | GEU | GEF => {
+                    let operands: Vec<&str> = self.get_operands(args, 3, 3, line)?;
+                    let op_type = match inst_name {
+                        LTI => OpcodeType::Lti,
+                        LTU => OpcodeType::Ltu,
+                        LTF => OpcodeType::Ltf,
+                        LEI => OpcodeType::Lei,
+                        LEU => OpcodeType::Leu,
+                        LEF => OpcodeType::Lef,
+                        GTI => OpcodeType::Gti,
+                        GTU => OpcodeType::Gtu,
+                        GTF => OpcodeType::Gtf,
+                        GEI => OpcodeType::Gei,
+                        GEU => OpcodeType::Geu,
+                        _ => OpcodeType::Gef,
+                    };
+                    
+                    self.code.push(Opcode { op_type, op_operand: None, op_regs: self.regs(&[operands[0], operands[1], operands[2]], line)? });
+                }
+                
+                LTIS | LTUS | LTFS | LEIS | LEUS | LEFS | GTIS | GTUS | GTFS | GEIS | GEUS | GEFS => {
+                    let op_type = match inst_name {
+                        LTIS => OpcodeType::Ltis,
+                        LTUS => OpcodeType::Ltus,
+                        LTFS => OpcodeType::Ltfs,
+                        LEIS => OpcodeType::Leis,
+                        LEUS => OpcodeType::Leus,
+                        LEFS => OpcodeType::Lefs,
+                        GTIS => OpcodeType::Gtis,
+                        GTUS => OpcodeType::Gtus,
+                        GTFS => OpcodeType::Gtfs,
+                        GEIS => OpcodeType::Geis,
+                        GEUS => OpcodeType::Geus,
+                        _ => OpcodeType::Gefs,
+                    };
+                    
+                    self.code.push(Opcode { op_type, op_operand: None, op_regs: OpRegs::init() });
+                }
+                
+                // Conversion opcodes
+                ITOF | FTOI | UTOF | FTOU | SEXT8 | SEXT16 | SEXT32 | ZEXT8 | ZEXT16 | ZEXT32 => {
+                    let op_type = match inst_name {
+                        ITOF => OpcodeType::Itof,
+                        FTOI => OpcodeType::Ftoi,
+                        UTOF => OpcodeType::Utof,
+                        FTOU => OpcodeType::Ftou,
+                        SEXT8 => OpcodeType::Sext8,
+                        SEXT16 => OpcodeType::Sext16,
+                        SEXT32 => OpcodeType::Sext32,
+                        ZEXT8 => OpcodeType::Zext8,
+                        ZEXT16 => OpcodeType::Zext16,
+                        _ => OpcodeType::Zext32,
+                    };
+                    
+                    if args.starts_with('r') {
+                        let operand = self.get_operands(args, 2, 2, line)?;
+                        self.code.push(Opcode { op_type, op_operand: None, op_regs: self.regs(&[operand[0], operand[1]], line)? });
+                    } else {
+                        self.code.push(Opcode { op_type, op_operand: None, op_regs: OpRegs::init() });
+                    }
+                }
+                
+                _ => {
+                    return Err(Error::with_message(ErrorKind::InvalidInstruction, format!("invalid instruction `{}`", inst_name)).at_span(line.span(inst_name)));
+                }
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    
+    // Line, column and length of a span
+    type Location = Option<(usize, usize, usize)>;
+    
+    fn errors(source: &str) -> Vec<(ErrorKind, Location)> {
+        match try_assemble(source) {
+            Ok(_) => panic!("program assembled without errors"),
+            Err(errors) => errors.iter()
+                .map(|err| (err.kind, err.span.as_ref().map(|span| (span.line, span.column, span.len))))
+                .collect(),
+        }
+    }
+    
+    #[test]
+    fn every_bad_line_is_reported() {
+        let errors = errors("
+_start:
+    mov r0, #1
+    foo r0
+    push #zz
+    add r1, r2, rx
+    jmp nowhere
+    hlt
+");
+        assert_eq!(errors, [
+            (ErrorKind::InvalidInstruction, Some((4, 5, 3))),
+            (ErrorKind::InvalidOperand, Some((5, 10, 3))),
+            (ErrorKind::InvalidRegister, Some((6, 17, 2))),
+            (ErrorKind::UndefinedLabel, Some((7, 9, 7))),
+        ]);
+    }
+    
+    #[test]
+    fn repeated_operands_point_at_the_bad_one() {
+        assert_eq!(errors("_start:\n    wrt #7, r1, r1\n    add r1, r2, r1, r1"), [
+            (ErrorKind::InvalidOperandCount, Some((3, 9, 14))),
+        ]);
+        assert_eq!(errors("_start:\nloop:\n    jnz loop, loop"), [(ErrorKind::InvalidRegister, Some((3, 15, 4)))]);
+    }
+    
+    #[test]
+    fn duplicate_labels_are_reported() {
+        let errors = errors("
+_start:
+    jmp done
+done:
+    hlt
+done:
+    hlt
+");
+        assert_eq!(errors, [(ErrorKind::DuplicateLabel, Some((6, 1, 4)))]);
+    }
+    
+    #[test]
+    fn missing_start_is_reported() {
+        assert_eq!(errors("main:\n    hlt"), [(ErrorKind::MissingEntryPoint, None)]);
+        assert_eq!(assemble("main:\n    hlt\n_start:\n    jmp main").entry, 1);
+    }
+    
+    #[test]
+    fn missing_includes_point_at_the_path() {
+        assert_eq!(errors("%include \"missing.osv\"\n_start:\n    hlt"), [(ErrorKind::IncludeNotFound, Some((1, 11, 11)))]);
+    }
+    
+    #[test]
+    fn macro_operands_point_at_the_macro_call() {
+        let errors = errors("
+%define value #zz
+_start:
+    push value!
+    hlt
+");
+        assert_eq!(errors, [(ErrorKind::InvalidOperand, Some((4, 10, 6)))]);
+    }
+    
+    #[test]
+    fn macro_registers_point_at_the_macro_call() {
+        let errors = errors("
+%define reg rq
+_start:
+    mov reg!, #1
+    hlt
+");
+        assert_eq!(errors, [(ErrorKind::InvalidRegister, Some((4, 9, 4)))]);
+    }
+    
+    #[test]
+    fn a_failed_jump_does_not_take_the_next_opcode_s_label() {
+        assert_eq!(errors("\n_start:\n    jz _start\n    jmp _start\n"), [(ErrorKind::InvalidOperandCount, Some((3, 8, 6)))]);
+        assert_eq!(errors("_start:\n    jz _start, r99"), [(ErrorKind::InvalidRegister, Some((2, 16, 3)))]);
+    }
+    
+    const PROGRAM: &str = "
+_start:
+    call work
+    hlt
+work:
+    mov r0, #2
+    ret
+";
+    
+    #[test]
+    fn program_has_code_entry_and_symbols() {
+        let program = assemble(PROGRAM);
+        assert_eq!(program.code.len(), 4);
+        assert_eq!(program.entry, 0);
+        assert_eq!(program.symbol("_start"), Some(0));
+        assert_eq!(program.symbol("work"), Some(2));
+        assert_eq!(program.symbol("missing"), None);
+        assert_eq!(program.code[0].op_operand, Some(TypedWord::u64(2)));
+    }
+    
+    #[test]
+    fn an_assembler_can_be_reused() {
+        let mut assembler = Assembler::init();
+        assert!(assembler.assemble("bad.osv", "_start:\n    foo").is_err());
+        
+        let first = assembler.assemble("test.osv", PROGRAM).unwrap();
+        let second = assembler.assemble("test.osv", PROGRAM).unwrap();
+        assert_eq!(format!("{:?}", first), format!("{:?}", second));
+        assert_eq!(second.symbols.len(), 2);
+    }
+    
+    #[test]
+    fn lines_follow_includes() {
+        let program = assemble("%include \"sys_libs.osv\"\n_start:\n    call sprint\n    hlt");
+        let entry = program.entry;
+        assert_eq!(program.lines.len(), program.code.len());
+        assert_eq!(program.source_line(entry), Some(("test.osv", 3)));
+        assert_eq!(program.source_line(entry + 1), Some(("test.osv", 4)));
+        assert!(program.source_line(0).unwrap().0.ends_with("sys_libs.osv"));
+        
+        let file = program.files.iter().position(|file| file == "test.osv").unwrap();
+        assert_eq!(program.line_address(file, 4), Some(entry + 1));
+        assert_eq!(program.line_address(file, 2), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use crate::opcode::{Opcode, OpcodeType, OpRegs};
+use crate::utils::{defines::*, error::{Error, ErrorKind}};
+
+// Instruction word layout:
+//     bits  0..8   op_type
+//     bits  8..10  register count
+//     bits 10..12  operand kind, see OPERAND_*
+//     bits 12..27  three 5 bit register indices, 0 if unused
+//     bits 27..32  reserved, 0
+//     bits 32..64  inline operand or constant pool index, 0 if there is none
+const REG_COUNT_SHIFT: u32 = 8;
+const OPERAND_KIND_SHIFT: u32 = 10;
+const REGS_SHIFT: u32 = 12;
+const REG_BITS: u32 = 5;
+const PAYLOAD_SHIFT: u32 = 32;
+const RESERVED_MASK: u64 = 0xf800_0000;
+
+const OPERAND_NONE: u64 = 0;
+// A u64 below 2^32, which covers addresses, sizes and most literals
+const OPERAND_INLINE: u64 = 1;
+const OPERAND_POOL: u64 = 2;
+
+// One encoded instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Instruction(pub u64);
+
+impl Instruction {
+    pub fn op_type(self: Self) -> Option<OpcodeType> {
+        OpcodeType::from_u8(self.0 as u8)
+    }
+    
+    pub fn reg_count(self: Self) -> usize {
+        (self.0 >> REG_COUNT_SHIFT) as usize & 0b11
+    }
+    
+    fn operand_kind(self: Self) -> u64 {
+        (self.0 >> OPERAND_KIND_SHIFT) & 0b11
+    }
+    
+    fn reg(self: Self, index: usize) -> u8 {
+        ((self.0 >> (REGS_SHIFT + index as u32 * REG_BITS)) & ((1 << REG_BITS) - 1)) as u8
+    }
+    
+    fn payload(self: Self) -> u64 {
+        self.0 >> PAYLOAD_SHIFT
+    }
+}
+
+// A program in the dense form OSVM executes and .vbin files store: one
+// 64 bit word per instruction, with literals that don't fit inline kept
+// once each in a constant pool
+#[derive(Debug, Clone)]
+pub struct Bytecode {
+    pub code: Vec<Instruction>,
+    pub constants: Vec<TypedWord>,
+}
+
+impl Bytecode {
+    pub fn init() -> Bytecode {
+        Bytecode {
+            code: Vec::new(),
+            constants: Vec::new(),
+        }
+    }
+    
+    pub fn encode(opcodes: &[Opcode]) -> Result<Bytecode, Error> {
+        let mut bytecode = Bytecode::init();
+        let mut pool: HashMap<(u8, u64), u64> = HashMap::new();
+        
+        bytecode.code.reserve_exact(opcodes.len());
+        for opcode in opcodes {
+            let mut word = opcode.op_type as u64 | (opcode.op_regs.len() as u64) << REG_COUNT_SHIFT;
+            for (i, reg) in opcode.op_regs.iter().enumerate() {
+                word |= (reg as u64) << (REGS_SHIFT + i as u32 * REG_BITS);
+            }
+            
+            if let Some(value) = opcode.op_operand {
+                let bits = unsafe { value.word.as_u64 };
+                if value.ty == WordType::U64 && bits <= u32::MAX as u64 {
+                    word |= OPERAND_INLINE << OPERAND_KIND_SHIFT | bits << PAYLOAD_SHIFT;
+                } else {
+                    let next = bytecode.constants.len() as u64;
+                    let index = *pool.entry((value.ty as u8, bits)).or_insert(next);
+                    if index == next {
+                        if next > u32::MAX as u64 {
+                            return Err(Error::with_message(ErrorKind::InvalidOperand, "constant pool is full"));
+                        }
+                        
+                        bytecode.constants.push(value);
+                    }
+                    
+                    word |= OPERAND_POOL << OPERAND_KIND_SHIFT | index << PAYLOAD_SHIFT;
+                }
+            }
+            
+            bytecode.code.push(Instruction(word));
+        }
+        
+        Ok(bytecode)
+    }
+    
+    // Checks every instruction so `get` can't fail on a loaded program
+    pub fn from_parts(code: Vec<Instruction>, constants: Vec<TypedWord>) -> Result<Bytecode, Error> {
+        for (addr, instruction) in code.iter().enumerate() {
+            let count = instruction.reg_count();
+            let regs_valid = (0..VBIN_MAX_REGS).all(|i| {
+                let reg = instruction.reg(i) as usize;
+                if i < count { reg < REGISTERS.len() } else { reg == 0 }
+            });
+            
+            let operand_valid = match instruction.operand_kind() {
+                OPERAND_NONE => instruction.payload() == 0,
+                OPERAND_INLINE => true,
+                OPERAND_POOL => (instruction.payload() as usize) < constants.len(),
+                _ => false,
+            };
+            
+            if instruction.op_type().is_none() || count > VBIN_MAX_REGS || !regs_valid || !operand_valid || instruction.0 & RESERVED_MASK != 0 {
+                return Err(Error::with_message(ErrorKind::CorruptedFile, format!("malformed instruction 0x{:016x} at {}", instruction.0, addr)));
+            }
+        }
+        
+        Ok(Bytecode { code, constants })
+    }
+    
+    pub fn len(self: &Self) -> usize {
+        self.code.len()
+    }
+    
+    pub fn is_empty(self: &Self) -> bool {
+        self.code.is_empty()
+    }
+    
+    // Decodes the instruction at `addr`, a few shifts and no allocation
+    #[inline(always)]
+    pub fn get(self: &Self, addr: usize) -> Option<Opcode> {
+        let instruction = *self.code.get(addr)?;
+        let op_type = instruction.op_type()?;
+        let op_regs = OpRegs::from_raw([instruction.reg(0), instruction.reg(1), instruction.reg(2)], instruction.reg_count());
+        let op_operand = match instruction.operand_kind() {
+            OPERAND_INLINE => Some(TypedWord::u64(instruction.payload())),
+            OPERAND_POOL => Some(self.constants[instruction.payload() as usize]),
+            _ => None,
+        };
+        
+        Some(Opcode { op_type, op_operand, op_regs })
+    }
+    
+    pub fn opcodes(self: &Self) -> Vec<Opcode> {
+        (0..self.len()).filter_map(|addr| self.get(addr)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    
+    fn encode(source: &str) -> Bytecode {
+        Bytecode::encode(&assemble(source).code).unwrap()
+    }
+    
+    fn rejected(word: u64, constants: Vec<TypedWord>) -> bool {
+        match Bytecode::from_parts(vec![Instruction(word)], constants) {
+            Ok(_) => false,
+            Err(err) => err.kind == ErrorKind::CorruptedFile,
+        }
+    }
+    
+    #[test]
+    fn examples_round_trip() {
+        for (name, program) in examples() {
+            let bytecode = Bytecode::encode(&program.code).unwrap();
+            assert_eq!(bytecode.len(), program.code.len(), "{}", name);
+            assert_eq!(format!("{:?}", bytecode.opcodes()), format!("{:?}", program.code), "{}", name);
+            
+            let decoded = Bytecode::from_parts(bytecode.code.clone(), bytecode.constants.clone()).unwrap();
+            assert_eq!(decoded.code, bytecode.code, "{}", name);
+        }
+    }
+    
+    #[test]
+    fn packs_an_instruction_into_one_word() {
+        let bytecode = encode("\n_start:\n    add r16, r8, r0\n    jmp _start\n    hlt\n");
+        assert_eq!(bytecode.code[0].0, OpcodeType::Add as u64 | 3 << REG_COUNT_SHIFT | 16 << REGS_SHIFT | 8 << (REGS_SHIFT + REG_BITS));
+        assert_eq!(bytecode.code[1].0, OpcodeType::Jmp as u64 | OPERAND_INLINE << OPERAND_KIND_SHIFT);
+        assert_eq!(bytecode.code[2].0, OpcodeType::Hlt as u64);
+        assert!(bytecode.constants.is_empty());
+    }
+    
+    #[test]
+    fn large_and_typed_literals_go_to_the_pool_once() {
+        let bytecode = encode("
+_start:
+    push #4294967295
+    push #4294967296
+    push #-1
+    push #18446744073709551615
+    push #1.5
+    push #4294967296
+    push #1.5
+    hlt
+");
+        assert_eq!(bytecode.constants, [
+            TypedWord::u64(1 << 32),
+            TypedWord::i64(-1),
+            TypedWord::u64(u64::MAX),
+            TypedWord::f64(1.5),
+        ]);
+        
+        let payloads: Vec<u64> = bytecode.code.iter().map(|instruction| instruction.payload()).collect();
+        assert_eq!(payloads, [u32::MAX as u64, 0, 1, 2, 3, 0, 3, 0]);
+        assert_eq!(bytecode.get(3).unwrap().op_operand, Some(TypedWord::u64(u64::MAX)));
+        assert_eq!(bytecode.get(4).unwrap().op_operand, Some(TypedWord::f64(1.5)));
+        assert!(bytecode.get(8).is_none());
+    }
+    
+    #[test]
+    fn malformed_instructions_are_rejected() {
+        let mov = OpcodeType::Mov as u64 | 1 << REG_COUNT_SHIFT;
+        assert!(!rejected(mov, Vec::new()));
+        
+        assert!(rejected(0xee, Vec::new()));
+        assert!(rejected(mov | 17 << REGS_SHIFT, Vec::new()));
+        assert!(rejected(mov | 1 << (REGS_SHIFT + REG_BITS), Vec::new()));
+        assert!(rejected(mov | 1 << 27, Vec::new()));
+        assert!(rejected(mov | 5 << PAYLOAD_SHIFT, Vec::new()));
+        assert!(rejected(mov | 3 << OPERAND_KIND_SHIFT, Vec::new()));
+        
+        let pooled = mov | OPERAND_POOL << OPERAND_KIND_SHIFT | 1 << PAYLOAD_SHIFT;
+        assert!(rejected(pooled, vec![TypedWord::f64(1.0)]));
+        assert!(!rejected(pooled, vec![TypedWord::f64(1.0), TypedWord::i64(-2)]));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use crate::opcode::OpcodeType;
+use crate::program::Program;
+
+// Execution counts per instruction, plus how often each conditional
+// jump was taken and not taken
+pub struct Coverage {
+    pub hits: Vec<u64>,
+    pub taken: Vec<u64>,
+    pub not_taken: Vec<u64>,
+}
+
+// Totals for one source file or the whole program
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CoverageSummary {
+    pub lines: usize,
+    pub lines_hit: usize,
+    pub branches: usize,
+    pub branches_hit: usize,
+    pub functions: usize,
+    pub functions_hit: usize,
+}
+
+struct FileCoverage {
+    // Line -> highest execution count of its instructions
+    lines: BTreeMap<usize, u64>,
+    
+    // (line, addr, taken, not taken) of every conditional jump
+    branches: Vec<(usize, usize, u64, u64)>,
+    functions: Vec<(usize, String, u64)>,
+}
+
+fn is_branch(op_type: OpcodeType) -> bool {
+    matches!(op_type, OpcodeType::Jt | OpcodeType::Jz | OpcodeType::Jnz | OpcodeType::Jts | OpcodeType::Jzs | OpcodeType::Jnzs)
+}
+
+impl Coverage {
+    pub fn init(program_len: usize) -> Coverage {
+        Coverage {
+            hits: vec![0; program_len],
+            taken: vec![0; program_len],
+            not_taken: vec![0; program_len],
+        }
+    }
+    
+    pub(crate) fn record(self: &mut Self, pc: usize, op_type: OpcodeType, next_pc: usize) {
+        if pc >= self.hits.len() {
+            return;
+        }
+        
+        self.hits[pc] += 1;
+        if is_branch(op_type) {
+            if next_pc == pc + 1 {
+                self.not_taken[pc] += 1;
+            } else {
+                self.taken[pc] += 1;
+            }
+        }
+    }
+    
+    // Functions are the entry point and every call target
+    fn functions(self: &Self, program: &Program) -> BTreeSet<usize> {
+        let mut functions: BTreeSet<usize> = program.code.iter()
+            .filter(|opcode| opcode.op_type == OpcodeType::Call)
+            .filter_map(|opcode| opcode.operand().ok())
+            .map(|target| unsafe { target.word.as_usize })
+            .collect();
+        functions.insert(program.entry);
+        functions
+    }
+    
+    fn by_file(self: &Self, program: &Program) -> Vec<FileCoverage> {
+        let mut files: Vec<FileCoverage> = program.files.iter()
+            .map(|_| FileCoverage { lines: BTreeMap::new(), branches: Vec::new(), functions: Vec::new() })
+            .collect();
+        
+        for (addr, info) in program.lines.iter().enumerate() {
+            let file = &mut files[info.file];
+            let hits = file.lines.entry(info.line).or_insert(0);
+            *hits = (*hits).max(self.hits[addr]);
+            
+            if is_branch(program.code[addr].op_type) {
+                file.branches.push((info.line, addr, self.taken[addr], self.not_taken[addr]));
+            }
+        }
+        
+        for addr in self.functions(program) {
+            if let Some(info) = program.lines.get(addr) {
+                let name = program.symbols.iter()
+                    .find(|label| label.addr == addr)
+                    .map(|label| label.name.clone())
+                    .unwrap_or_else(|| format!("{:04}", addr));
+                files[info.file].functions.push((info.line, name, self.hits[addr]));
+            }
+        }
+        files
+    }
+    
+    fn summarize(file: &FileCoverage) -> CoverageSummary {
+        CoverageSummary {
+            lines: file.lines.len(),
+            lines_hit: file.lines.values().filter(|hits| **hits > 0).count(),
+            branches: file.branches.len() * 2,
+            branches_hit: file.branches.iter().map(|(_, _, taken, not_taken)| (*taken > 0) as usize + (*not_taken > 0) as usize).sum(),
+            functions: file.functions.len(),
+            functions_hit: file.functions.iter().filter(|(_, _, hits)| *hits > 0).count(),
+        }
+    }
+    
+    // lcov tracefile, one record per source file including the %include'd ones
+    pub fn write_lcov(self: &Self, out: &mut dyn Write, program: &Program) -> io::Result<()> {
+        for (path, file) in program.files.iter().zip(self.by_file(program)) {
+            writeln!(out, "TN:")?;
+            writeln!(out, "SF:{}", path)?;
+            for (line, name, _) in &file.functions {
+                writeln!(out, "FN:{},{}", line, name)?;
+            }
+            for (_, name, hits) in &file.functions {
+                writeln!(out, "FNDA:{},{}", hits, name)?;
+            }
+            
+            let summary = Coverage::summarize(&file);
+            writeln!(out, "FNF:{}", summary.functions)?;
+            writeln!(out, "FNH:{}", summary.functions_hit)?;
+            
+            for (block, (line, addr, taken, not_taken)) in file.branches.iter().enumerate() {
+                // `-` marks a branch whose jump never executed at all
+                let count = |count: u64| if self.hits[*addr] == 0 { "-".to_string() } else { count.to_string() };
+                writeln!(out, "BRDA:{},{},0,{}", line, block, count(*taken))?;
+                writeln!(out, "BRDA:{},{},1,{}", line, block, count(*not_taken))?;
+            }
+            writeln!(out, "BRF:{}", summary.branches)?;
+            writeln!(out, "BRH:{}", summary.branches_hit)?;
+            
+            for (line, hits) in &file.lines {
+                writeln!(out, "DA:{},{}", line, hits)?;
+            }
+            writeln!(out, "LF:{}", summary.lines)?;
+            writeln!(out, "LH:{}", summary.lines_hit)?;
+            writeln!(out, "end_of_record")?;
+        }
+        
+        out.flush()
+    }
+    
+    pub fn summary(self: &Self, program: &Program) -> String {
+        let percent = |hit: usize, total: usize| if total == 0 { 100.0 } else { hit as f64 * 100.0 / total as f64 };
+        let row = |name: &str, summary: &CoverageSummary| format!(
+            "    {:>6.2}% {:>5}/{:<5}  {:>6.2}% {:>5}/{:<5}  {:>6.2}% {:>4}/{:<4}  {}\n",
+            percent(summary.lines_hit, summary.lines), summary.lines_hit, summary.lines,
+            percent(summary.branches_hit, summary.branches), summary.branches_hit, summary.branches,
+            percent(summary.functions_hit, summary.functions), summary.functions_hit, summary.functions,
+            name
+        );
+        
+        if program.lines.is_empty() {
+            let hit = self.hits.iter().filter(|hits| **hits > 0).count();
+            return format!("[Coverage]: {}/{} instructions executed, the program has no line info\n", hit, self.hits.len());
+        }
+        
+        let mut report = String::from("[Coverage]:\n       lines               branches             functions       file\n");
+        let mut total = CoverageSummary::default();
+        for (path, file) in program.files.iter().zip(self.by_file(program)) {
+            let summary = Coverage::summarize(&file);
+            total.lines += summary.lines;
+            total.lines_hit += summary.lines_hit;
+            total.branches += summary.branches;
+            total.branches_hit += summary.branches_hit;
+            total.functions += summary.functions;
+            total.functions_hit += summary.functions_hit;
+            report.push_str(&row(path, &summary));
+        }
+        
+        report.push_str(&row("total", &total));
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    
+    const PROGRAM: &str = "
+_start:
+    mov r0, #2
+loop:
+    dec r0
+    jnz loop, r0
+    call used
+    hlt
+used:
+    ret
+unused:
+    jnz unused, r0
+    ret
+";
+    
+    fn cover(program: &Program) -> Coverage {
+        let (mut osvm, _) = machine(program);
+        osvm.start_coverage();
+        osvm.execute_program().unwrap();
+        osvm.stop_coverage().unwrap()
+    }
+    
+    fn lcov(coverage: &Coverage, program: &Program) -> String {
+        let mut out = Vec::new();
+        coverage.write_lcov(&mut out, program).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+    
+    #[test]
+    fn counts_hits_and_branches() {
+        let coverage = cover(&assemble(PROGRAM));
+        assert_eq!(coverage.hits, [1, 2, 2, 1, 1, 1, 0, 0]);
+        assert_eq!(coverage.taken, [0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(coverage.not_taken, [0, 0, 1, 0, 0, 0, 0, 0]);
+    }
+    
+    #[test]
+    fn writes_lcov() {
+        let program = assemble(PROGRAM);
+        assert_eq!(lcov(&cover(&program), &program), "\
+TN:
+SF:test.osv
+FN:3,_start
+FN:10,used
+FNDA:1,_start
+FNDA:1,used
+FNF:2
+FNH:2
+BRDA:6,0,0,1
+BRDA:6,0,1,1
+BRDA:12,1,0,-
+BRDA:12,1,1,-
+BRF:4
+BRH:2
+DA:3,1
+DA:5,2
+DA:6,2
+DA:7,1
+DA:8,1
+DA:10,1
+DA:12,0
+DA:13,0
+LF:8
+LH:6
+end_of_record
+");
+    }
+    
+    #[test]
+    fn included_files_get_their_own_record() {
+        let program = assemble("
+%include \"sys_libs.osv\"
+_start:
+    push #7
+    call sprintu
+    hlt
+");
+        let coverage = cover(&program);
+        let lcov = lcov(&coverage, &program);
+        let records: Vec<&str> = lcov.lines().filter(|line| line.starts_with("SF:")).collect();
+        assert_eq!(records.len(), program.files.len());
+        assert!(records.iter().any(|record| record.ends_with("sys_libs.osv")));
+        assert!(lcov.contains("FNDA:1,sprintu\n"));
+        
+        let summary = coverage.summary(&program);
+        assert!(summary.starts_with("[Coverage]:\n"));
+        assert!(summary.lines().any(|line| line.ends_with("sys_libs.osv")));
+        assert!(summary.lines().last().unwrap().ends_with("total"));
+    }
+    
+    #[test]
+    fn summarizes_programs_without_line_info() {
+        let program = Program { files: Vec::new(), lines: Vec::new(), ..assemble(PROGRAM) };
+        let coverage = cover(&program);
+        assert_eq!(coverage.summary(&program), "[Coverage]: 6/8 instructions executed, the program has no line info\n");
+        assert_eq!(lcov(&coverage, &program), "");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,757 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::assembler::Assembler;
+use crate::debugger::Debugger;
+use crate::log::Log;
+use crate::osvm::OSVM;
+use crate::program::{LineInfo, Program};
+use crate::utils::buffer::SharedBuffer;
+use crate::utils::defines::*;
+use crate::utils::file::OSVMFile;
+
+// variablesReference of each scope, the VM state is shared by every frame
+const REGISTERS_REF: i64 = 1;
+const STACK_REF: i64 = 2;
+const MEMORY_REF: i64 = 3;
+
+// Bytes of OSVM::memory shown in the Memory scope, the rest is reachable
+// through readMemory
+const MEMORY_ROWS: usize = 16;
+const MEMORY_ROW_SIZE: usize = 16;
+
+// How many instructions run between checks for a pause request
+const PAUSE_INTERVAL: usize = 1024;
+
+#[derive(Clone, Copy)]
+enum Step {
+    Continue,
+    Instruction,
+    
+    // Source line stepping, `over` runs over calls
+    Line { start: usize, line: Option<LineInfo>, depth: usize, over: bool },
+    Out { depth: usize },
+}
+
+// Debug Adapter Protocol server, editors launch `osvm dap` and talk to
+// it over stdio. Breakpoints are set on .osv lines through Program::lines.
+pub struct DapServer {
+    program: Program,
+    
+    // Canonical path of every file in Program::files
+    paths: Vec<String>,
+    breakpoints: HashMap<usize, Vec<usize>>,
+    stop_on_entry: bool,
+    launched: bool,
+    
+    seq: i64,
+    // The print sysfs write here so their output becomes `output` events
+    output: SharedBuffer,
+    pending: VecDeque<Value>,
+    debugger: Debugger,
+}
+
+impl DapServer {
+    pub fn init() -> DapServer {
+        DapServer {
+            program: Program::init(),
+            paths: Vec::new(),
+            breakpoints: HashMap::new(),
+            stop_on_entry: false,
+            launched: false,
+            seq: 0,
+            output: SharedBuffer::default(),
+            pending: VecDeque::new(),
+            debugger: Debugger::init(),
+        }
+    }
+    
+    // Serves one debug session, requests are read on their own thread so
+    // a running program can still be paused
+    pub fn run(self: &mut Self, osvm: &mut OSVM, input: Box<dyn BufRead + Send>, out: &mut dyn Write) {
+        osvm.output = Box::new(self.output.clone());
+        
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = input;
+            while let Some(message) = read_message(&mut *input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        
+        loop {
+            let message = match self.pending.pop_front() {
+                Some(message) => message,
+                None => match messages.recv() {
+                    Ok(message) => message,
+                    Err(_) => break,
+                },
+            };
+            
+            if !self.handle(osvm, &message, &messages, out) {
+                break;
+            }
+        }
+    }
+    
+    fn send(self: &mut Self, out: &mut dyn Write, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        
+        let body = message.to_string();
+        let _ = write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = out.flush();
+    }
+    
+    fn respond(self: &mut Self, out: &mut dyn Write, request: &Value, body: Value) {
+        let response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        });
+        self.send(out, response);
+    }
+    
+    fn respond_error(self: &mut Self, out: &mut dyn Write, request: &Value, message: &str) {
+        let response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        });
+        self.send(out, response);
+    }
+    
+    fn event(self: &mut Self, out: &mut dyn Write, event: &str, body: Value) {
+        self.send(out, json!({ "type": "event", "event": event, "body": body }));
+    }
+    
+    fn flush_output(self: &mut Self, out: &mut dyn Write) {
+        let text = String::from_utf8_lossy(&self.output.0.borrow_mut().split_off(0)).into_owned();
+        if !text.is_empty() {
+            self.event(out, "output", json!({ "category": "stdout", "output": text }));
+        }
+    }
+    
+    // Returns false once the session is over
+    fn handle(self: &mut Self, osvm: &mut OSVM, request: &Value, messages: &Receiver<Value>, out: &mut dyn Write) -> bool {
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or("");
+        match command {
+            "initialize" => {
+                let capabilities = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsSetVariable": true,
+                    "supportsSteppingGranularity": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsWriteMemoryRequest": true,
+                    "supportsEvaluateForHovers": true,
+                    "supportsTerminateRequest": true,
+                });
+                self.respond(out, request, capabilities);
+            }
+            "launch" => match self.launch(osvm, args) {
+                Ok(()) => {
+                    self.respond(out, request, json!({}));
+                    self.event(out, "initialized", json!({}));
+                }
+                Err(message) => {
+                    self.event(out, "output", json!({ "category": "stderr", "output": format!("{}\n", message) }));
+                    self.respond_error(out, request, &message);
+                }
+            },
+            "setBreakpoints" => {
+                let breakpoints = self.set_breakpoints(args);
+                self.respond(out, request, json!({ "breakpoints": breakpoints }));
+            }
+            "setExceptionBreakpoints" => self.respond(out, request, json!({})),
+            "configurationDone" => {
+                self.respond(out, request, json!({}));
+                if self.stop_on_entry {
+                    self.event(out, "stopped", json!({ "reason": "entry", "threadId": 1 }));
+                } else {
+                    self.resume(osvm, Step::Continue, messages, out);
+                }
+            }
+            "threads" => self.respond(out, request, json!({ "threads": [{ "id": 1, "name": "main" }] })),
+            "stackTrace" => {
+                let frames = self.stack_trace(osvm);
+                let total = frames.len();
+                self.respond(out, request, json!({ "stackFrames": frames, "totalFrames": total }));
+            }
+            "scopes" => {
+                let scopes = json!([
+                    { "name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false },
+                    { "name": "Stack", "variablesReference": STACK_REF, "expensive": false },
+                    { "name": "Memory", "variablesReference": MEMORY_REF, "expensive": true },
+                ]);
+                self.respond(out, request, json!({ "scopes": scopes }));
+            }
+            "variables" => {
+                let variables = self.variables(osvm, args["variablesReference"].as_i64().unwrap_or(0));
+                self.respond(out, request, json!({ "variables": variables }));
+            }
+            "setVariable" => match self.set_variable(osvm, args) {
+                Ok(body) => self.respond(out, request, body),
+                Err(message) => self.respond_error(out, request, &message),
+            },
+            "readMemory" => match self.read_memory(osvm, args) {
+                Ok(body) => self.respond(out, request, body),
+                Err(message) => self.respond_error(out, request, &message),
+            },
+            "writeMemory" => match self.write_memory(osvm, args) {
+                Ok(body) => self.respond(out, request, body),
+                Err(message) => self.respond_error(out, request, &message),
+            },
+            "evaluate" => {
+                let pc = osvm.get_pc();
+                let expression = args["expression"].as_str().unwrap_or("");
+                let result = if args["context"] == "repl" {
+                    self.debugger.execute_command(osvm, expression)
+                } else {
+                    self.debugger.execute_command(osvm, &format!("print {}", expression))
+                };
+                
+                match result {
+                    Ok(result) => self.respond(out, request, json!({ "result": result, "variablesReference": 0 })),
+                    Err(message) => self.respond_error(out, request, &message),
+                }
+                
+                // Repl commands can run the program
+                self.flush_output(out);
+                if osvm.get_pc() != pc {
+                    self.event(out, "stopped", json!({ "reason": "step", "threadId": 1 }));
+                }
+            }
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                let step = self.step_kind(osvm, command, args);
+                self.respond(out, request, json!({ "allThreadsContinued": true }));
+                self.resume(osvm, step, messages, out);
+            }
+            "pause" => {
+                self.respond(out, request, json!({}));
+                self.event(out, "stopped", json!({ "reason": "pause", "threadId": 1 }));
+            }
+            "disconnect" | "terminate" => {
+                self.respond(out, request, json!({}));
+                if command == "terminate" {
+                    self.event(out, "terminated", json!({}));
+                }
+                return command == "terminate";
+            }
+            _ => self.respond_error(out, request, &format!("unsupported request `{}`", command)),
+        }
+        
+        true
+    }
+    
+    // `program` is either .osv source, which carries line info, or a .vbin
+    fn launch(self: &mut Self, osvm: &mut OSVM, args: &Value) -> Result<(), String> {
+        let path = args["program"].as_str().ok_or("launch needs a `program`")?;
+        let program = if path.ends_with(".vbin") {
+            OSVMFile {}.load_program_from_file(path).map_err(|err| Log::diagnostic(&err))?
+        } else {
+            let source = fs::read_to_string(path).map_err(|err| format!("could not read `{}`: {}", path, err))?;
+            match Assembler::init().assemble(path, &source) {
+                Ok(program) => program,
+                Err(errors) => {
+                    let diagnostics: Vec<String> = errors.iter().map(Log::diagnostic).collect();
+                    return Err(diagnostics.join("\n"));
+                }
+            }
+        };
+        
+        osvm.load_program(&program).map_err(|err| Log::diagnostic(&err))?;
+        self.paths = program.files.iter().map(|file| canonical_path(file)).collect();
+        self.program = program;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.launched = true;
+        Ok(())
+    }
+    
+    // Lines without code move the breakpoint down to the next line that has some
+    fn set_breakpoints(self: &mut Self, args: &Value) -> Vec<Value> {
+        let path = canonical_path(args["source"]["path"].as_str().unwrap_or(""));
+        let file = self.paths.iter().position(|known| *known == path);
+        let requested: Vec<usize> = args["breakpoints"].as_array()
+            .map(|breakpoints| breakpoints.iter().filter_map(|bp| bp["line"].as_u64()).map(|line| line as usize).collect())
+            .unwrap_or_default();
+        
+        let mut addrs = Vec::new();
+        let mut breakpoints = Vec::new();
+        for line in requested {
+            let resolved = file.and_then(|file| {
+                self.program.lines.iter()
+                    .enumerate()
+                    .filter(|(_, info)| info.file == file && info.line >= line)
+                    .min_by_key(|(addr, info)| (info.line, *addr))
+                    .map(|(addr, info)| (addr, info.line))
+            });
+            
+            match resolved {
+                Some((addr, line)) => {
+                    addrs.push(addr);
+                    breakpoints.push(json!({ "verified": true, "line": line, "id": addr }));
+                }
+                None => breakpoints.push(json!({ "verified": false, "line": line, "message": "no code at this line" })),
+            }
+        }
+        
+        if let Some(file) = file {
+            self.breakpoints.insert(file, addrs);
+        }
+        breakpoints
+    }
+    
+    fn line_at(self: &Self, addr: usize) -> Option<LineInfo> {
+        self.program.lines.get(addr).copied()
+    }
+    
+    fn step_kind(self: &Self, osvm: &OSVM, command: &str, args: &Value) -> Step {
+        let pc = osvm.get_pc();
+        let depth = osvm.call_stack.len();
+        let by_instruction = args["granularity"] == "instruction" || self.program.lines.is_empty();
+        match command {
+            "continue" => Step::Continue,
+            "stepOut" => Step::Out { depth },
+            _ if by_instruction && command == "stepIn" => Step::Instruction,
+            _ => Step::Line { start: pc, line: self.line_at(pc), depth, over: command == "next" },
+        }
+    }
+    
+    fn step_done(self: &Self, osvm: &OSVM, step: Step) -> bool {
+        let depth = osvm.call_stack.len();
+        match step {
+            Step::Continue => false,
+            Step::Instruction => true,
+            Step::Out { depth: start } => depth < start,
+            Step::Line { start, line, depth: start_depth, over } => {
+                if over && depth > start_depth {
+                    return false;
+                }
+                
+                let pc = osvm.get_pc();
+                pc == start || self.line_at(pc) != line || depth < start_depth
+            }
+        }
+    }
+    
+    // Runs until the step is done, a breakpoint, a fault, the end of the program or a pause
+    fn resume(self: &mut Self, osvm: &mut OSVM, step: Step, messages: &Receiver<Value>, out: &mut dyn Write) {
+        let mut executed: usize = 0;
+        let reason = loop {
+            if osvm.is_halted() {
+                break None;
+            }
+            
+            if let Err(err) = osvm.execute_opcode() {
+                self.flush_output(out);
+                self.event(out, "output", json!({ "category": "stderr", "output": format!("{}\n", err) }));
+                let body = json!({ "reason": "exception", "description": err.to_string(), "text": err.to_string(), "threadId": 1 });
+                self.event(out, "stopped", body);
+                return;
+            }
+            
+            if osvm.is_halted() {
+                break None;
+            }
+            
+            if self.step_done(osvm, step) {
+                break Some("step");
+            }
+            
+            if self.breakpoints.values().any(|addrs| addrs.contains(&osvm.get_pc())) {
+                break Some("breakpoint");
+            }
+            
+            executed += 1;
+            if executed.is_multiple_of(PAUSE_INTERVAL) {
+                self.flush_output(out);
+                match self.check_pause(messages, out) {
+                    Some(reason) => break Some(reason),
+                    None => continue,
+                }
+            }
+        };
+        
+        self.flush_output(out);
+        match reason {
+            Some(reason) => self.event(out, "stopped", json!({ "reason": reason, "threadId": 1 })),
+            None => {
+                self.event(out, "exited", json!({ "exitCode": 0 }));
+                self.event(out, "terminated", json!({}));
+            }
+        }
+    }
+    
+    // Pause stops right away, everything else waits until the program stops
+    fn check_pause(self: &mut Self, messages: &Receiver<Value>, out: &mut dyn Write) -> Option<&'static str> {
+        loop {
+            match messages.try_recv() {
+                Ok(message) => {
+                    if message["command"] == "pause" {
+                        self.respond(out, &message, json!({}));
+                        return Some("pause");
+                    }
+                    
+                    let stop = message["command"] == "disconnect" || message["command"] == "terminate";
+                    self.pending.push_back(message);
+                    if stop {
+                        return Some("pause");
+                    }
+                }
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+    }
+    
+    fn frame_name(self: &Self, addr: usize) -> String {
+        self.program.symbols.iter()
+            .filter(|label| label.addr == addr)
+            .map(|label| label.name.clone())
+            .next()
+            .unwrap_or_else(|| format!("{:04}", addr))
+    }
+    
+    fn stack_trace(self: &Self, osvm: &OSVM) -> Vec<Value> {
+        // Frame n runs the function the n-th call jumped to, frame 0 the entry
+        let mut locations = Vec::new();
+        let depth = osvm.call_stack.len();
+        for level in (0..=depth).rev() {
+            let addr = if level == depth { osvm.get_pc() } else { osvm.call_stack[level].call_addr };
+            let function = if level == 0 { osvm.get_entry() } else { osvm.call_stack[level - 1].target_addr };
+            locations.push((addr, self.frame_name(function)));
+        }
+        
+        locations.into_iter()
+            .enumerate()
+            .map(|(id, (addr, name))| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": name,
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("{}", addr),
+                });
+                
+                if let Some(info) = self.line_at(addr) {
+                    let path = &self.paths[info.file];
+                    let file_name = Path::new(path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                    frame["source"] = json!({ "name": file_name, "path": path });
+                    frame["line"] = json!(info.line);
+                    frame["column"] = json!(1);
+                }
+                frame
+            })
+            .collect()
+    }
+    
+    fn variables(self: &Self, osvm: &mut OSVM, reference: i64) -> Vec<Value> {
+        match reference {
+            REGISTERS_REF => {
+                let mut variables: Vec<Value> = osvm.registers().iter()
+                    .zip(REGISTERS.iter())
+                    .map(|(reg, name)| word_variable(name, reg))
+                    .collect();
+                variables.push(json!({ "name": "pc", "value": osvm.get_pc().to_string(), "type": "usize", "variablesReference": 0 }));
+                variables.push(json!({ "name": "tsr", "value": osvm.tsr.to_string(), "type": "usize", "variablesReference": 0 }));
+                variables
+            }
+            STACK_REF => osvm.stack.iter()
+                .rev()
+                .enumerate()
+                .map(|(i, value)| word_variable(&format!("{}{}", GSI, i), value))
+                .collect(),
+            MEMORY_REF => (0..MEMORY_ROWS)
+                .map(|row| {
+                    let addr = row * MEMORY_ROW_SIZE;
+                    let bytes: Vec<String> = osvm.memory[addr..addr + MEMORY_ROW_SIZE].iter().map(|byte| format!("{:02x}", byte)).collect();
+                    json!({
+                        "name": format!("0x{:04x}", addr),
+                        "value": bytes.join(" "),
+                        "variablesReference": 0,
+                        "memoryReference": format!("0x{:x}", addr),
+                    })
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+    
+    fn set_variable(self: &Self, osvm: &mut OSVM, args: &Value) -> Result<Value, String> {
+        let name = args["name"].as_str().unwrap_or("");
+        let value = args["value"].as_str().unwrap_or("").trim();
+        if args["variablesReference"] != REGISTERS_REF {
+            return Err("only registers can be set".to_string());
+        }
+        
+        match name {
+            "pc" => {
+                let pc = parse_address(value).filter(|pc| *pc < osvm.program.len()).ok_or(format!("invalid pc `{}`", value))?;
+                osvm.set_pc(pc);
+                Ok(json!({ "value": pc.to_string(), "type": "usize" }))
+            }
+            "tsr" => {
+                osvm.tsr = parse_address(value).ok_or(format!("invalid tsr `{}`", value))?;
+                Ok(json!({ "value": osvm.tsr.to_string(), "type": "usize" }))
+            }
+            _ => {
+                let word = TypedWord::parse_literal(value).ok_or(format!("invalid literal `{}`", value))?;
+                let reg = osvm.register_by_name(name).ok_or(format!("unknown register `{}`", name))?;
+                *reg = word;
+                let variable = word_variable(name, &word);
+                Ok(json!({ "value": variable["value"], "type": variable["type"] }))
+            }
+        }
+    }
+    
+    fn memory_range(self: &Self, osvm: &OSVM, args: &Value, count: usize) -> Result<usize, String> {
+        let reference = args["memoryReference"].as_str().unwrap_or("");
+        let base = parse_address(reference).ok_or(format!("invalid memory reference `{}`", reference))?;
+        let addr = base as i64 + args["offset"].as_i64().unwrap_or(0);
+        if addr < 0 || addr as usize + count > osvm.memory.len() {
+            return Err(format!("0x{:x} is outside of memory", addr));
+        }
+        
+        Ok(addr as usize)
+    }
+    
+    fn read_memory(self: &Self, osvm: &OSVM, args: &Value) -> Result<Value, String> {
+        let count = args["count"].as_u64().unwrap_or(0) as usize;
+        let addr = self.memory_range(osvm, args, 0)?;
+        let end = (addr + count).min(osvm.memory.len());
+        Ok(json!({
+            "address": format!("0x{:x}", addr),
+            "data": encode_base64(&osvm.memory[addr..end]),
+            "unreadableBytes": count - (end - addr),
+        }))
+    }
+    
+    fn write_memory(self: &Self, osvm: &mut OSVM, args: &Value) -> Result<Value, String> {
+        let data = decode_base64(args["data"].as_str().unwrap_or("")).ok_or("invalid base64 data")?;
+        let addr = self.memory_range(osvm, args, data.len())?;
+        osvm.memory[addr..addr + data.len()].copy_from_slice(&data);
+        Ok(json!({ "bytesWritten": data.len() }))
+    }
+}
+
+fn read_message(input: &mut dyn BufRead) -> Option<Value> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+fn canonical_path(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+fn parse_address(token: &str) -> Option<usize> {
+    match token.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => token.parse::<usize>().ok(),
+    }
+}
+
+fn word_variable(name: &str, word: &TypedWord) -> Value {
+    let (value, ty) = unsafe {
+        match word.ty {
+            WordType::U64 => (word.word.as_u64.to_string(), "u64"),
+            WordType::I64 => (word.word.as_i64.to_string(), "i64"),
+            WordType::F64 => (word.word.as_f64.to_string(), "f64"),
+            WordType::Ptr => (format!("{:p}", word.word.as_ptr), "ptr"),
+        }
+    };
+    
+    json!({ "name": name, "value": value, "type": ty, "variablesReference": 0 })
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| bits | (*byte as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[(bits >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    
+    text
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.bytes().filter(|c| *c != b'=' && !c.is_ascii_whitespace()) {
+        let value = BASE64.iter().position(|b| *b == c)? as u32;
+        bits = bits << 6 | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::process;
+    
+    use super::*;
+    use crate::testing::*;
+    
+    const PROGRAM: &str = "
+_start:
+    mov r0, #5
+    call double
+    hlt
+double:
+    push r0
+    add r1, r0, r0
+    ret
+";
+    
+    // Runs the scripted requests through DapServer::run and returns every
+    // message it sent
+    fn session(requests: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            let body = request.to_string();
+            input.extend_from_slice(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes());
+        }
+        
+        let (mut osvm, _) = machine(&Program::init());
+        let mut out = Vec::new();
+        DapServer::init().run(&mut osvm, Box::new(Cursor::new(input)), &mut out);
+        
+        let mut out = Cursor::new(out);
+        std::iter::from_fn(|| read_message(&mut out)).collect()
+    }
+    
+    fn response(messages: &[Value], seq: usize) -> &Value {
+        let response = messages.iter().find(|message| message["type"] == "response" && message["request_seq"] == seq).unwrap();
+        assert_eq!(response["success"], true, "{}", response);
+        response
+    }
+    
+    fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+        messages.iter().filter(|message| message["event"] == event).collect()
+    }
+    
+    #[test]
+    fn scripted_session() {
+        let path = std::env::temp_dir().join(format!("osvm-dap-{}.osv", process::id()));
+        fs::write(&path, PROGRAM).unwrap();
+        let path = path.to_string_lossy().into_owned();
+        
+        let messages = session(&[
+            json!({ "command": "initialize", "arguments": { "adapterID": "osvm" } }),
+            json!({ "command": "launch", "arguments": { "program": path, "stopOnEntry": true } }),
+            json!({ "command": "setBreakpoints", "arguments": { "source": { "path": path }, "breakpoints": [{ "line": 6 }, { "line": 100 }] } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": REGISTERS_REF } }),
+            json!({ "command": "next", "arguments": { "threadId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": STACK_REF } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ]);
+        let _ = fs::remove_file(&path);
+        
+        assert_eq!(response(&messages, 1)["body"]["supportsConfigurationDoneRequest"], true);
+        response(&messages, 2);
+        assert_eq!(events(&messages, "initialized").len(), 1);
+        
+        // The label line has no code, the breakpoint moves down to `push r0`
+        let breakpoints = &response(&messages, 3)["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[0]["line"], 7);
+        assert_eq!(breakpoints[1]["verified"], false);
+        
+        let stops: Vec<&Value> = events(&messages, "stopped").iter().map(|event| &event["body"]["reason"]).collect();
+        assert_eq!(stops, ["entry", "breakpoint", "step"]);
+        
+        let frames = &response(&messages, 6)["body"]["stackFrames"];
+        assert_eq!(frames.as_array().unwrap().len(), 2);
+        assert_eq!(frames[0]["name"], "double");
+        assert_eq!(frames[0]["line"], 7);
+        assert_eq!(frames[0]["instructionPointerReference"], "3");
+        assert_eq!(frames[1]["name"], "_start");
+        assert_eq!(frames[1]["line"], 4);
+        assert_eq!(frames[1]["source"]["path"], canonical_path(&path));
+        
+        let registers = &response(&messages, 7)["body"]["variables"];
+        assert_eq!(registers[0], json!({ "name": "r0", "value": "5", "type": "u64", "variablesReference": 0 }));
+        assert_eq!(registers[REGISTERS.len()]["name"], "pc");
+        assert_eq!(registers[REGISTERS.len()]["value"], "3");
+        
+        let stack = &response(&messages, 9)["body"]["variables"];
+        assert_eq!(stack, &json!([{ "name": "$0", "value": "5", "type": "u64", "variablesReference": 0 }]));
+        
+        response(&messages, 10);
+        assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+        assert_eq!(events(&messages, "terminated").len(), 1);
+        response(&messages, 11);
+    }
+    
+    #[test]
+    fn launch_reports_assembler_errors() {
+        let messages = session(&[json!({ "command": "launch", "arguments": { "program": "missing.osv" } })]);
+        assert_eq!(messages[1]["type"], "response");
+        assert_eq!(messages[1]["success"], false);
+        assert_eq!(events(&messages, "output")[0]["body"]["category"], "stderr");
+    }
+    
+    #[test]
+    fn base64_round_trips() {
+        for len in 0..8 {
+            let bytes: Vec<u8> = (0..len).map(|i| i * 37 + 1).collect();
+            assert_eq!(decode_base64(&encode_base64(&bytes)), Some(bytes));
+        }
+        
+        assert_eq!(encode_base64(b"osvm"), "b3N2bQ==");
+        assert_eq!(decode_base64("!!"), None);
+    }
+ }
\ No newline at end of file
@@ -0,0 +1,876 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::disassembler::Disassembler;
+use crate::dump::*;
+use crate::log::Log;
+use crate::opcode::OpcodeType;
+use crate::osvm::OSVM;
+use crate::recorder::*;
+use crate::utils::defines::*;
+use crate::watchpoint::*;
+
+const HELP: &str = "\
+break <label|addr>     set a breakpoint, without arguments lists them
+delete [label|addr]    remove a breakpoint, without arguments removes all
+continue               run until a breakpoint or the program halts
+step [n]               execute n instructions (default 1)
+next                   like step, but runs over calls
+finish                 run until the current call returns
+print <reg|pc|tsr|$n>  print a register or the n-th stack slot from the top
+x/<n><x|d|u><b|h|w|g> <addr|reg>
+                       examine n units of memory
+stack                  print the stack, top first
+backtrace              print the call stack
+regs                   print all registers
+dump [view] [json] [range...]
+                       dump registers, stack and the non-zero parts of memory,
+                       marking what changed since the last step. Views: hex,
+                       ascii, u8, u16, u32, u64, i64, f64. Ranges: start..end,
+                       start+len or addr
+set <reg|pc> = <value> assign a register or the pc
+disas [label|addr]     disassemble around the pc or from an address
+watch <reg|addr> [len] stop when a register is assigned or memory is written,
+                       without arguments lists watchpoints
+rwatch <addr> [len]    stop when memory is read
+awatch <addr> [len]    stop when memory is read or written
+unwatch [n]            remove watchpoint n, without arguments removes all
+record [n|stop]        record the last n instructions (default 100000) so they
+                       can be stepped back over
+reverse-step [n]       undo n recorded instructions (default 1)
+reverse-continue       run backwards until a breakpoint or a write watchpoint
+quit                   leave the debugger
+An empty line repeats the last command.";
+
+// Command driven debugger on top of OSVM::execute_opcode
+pub struct Debugger {
+    pub breakpoints: Vec<usize>,
+    
+    last_command: String,
+    names: HashMap<usize, String>,
+    
+    // State before the last command that ran the program, for `dump`
+    baseline: Option<DumpBaseline>,
+    disassembler: Disassembler,
+}
+
+impl Debugger {
+    pub fn init() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            last_command: String::new(),
+            names: HashMap::new(),
+            baseline: None,
+            disassembler: Disassembler {},
+        }
+    }
+    
+    pub fn run(self: &mut Self, osvm: &mut OSVM, input: &mut dyn BufRead, out: &mut dyn Write) {
+        self.names = self.disassembler.target_names(&osvm.program.opcodes(), &osvm.symbols);
+        let _ = writeln!(out, "{}", self.location(osvm));
+        
+        loop {
+            let _ = write!(out, "(osvm) ");
+            let _ = out.flush();
+            
+            let mut line = String::new();
+            match input.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            
+            let mut command = line.trim().to_string();
+            if command.is_empty() {
+                command = self.last_command.clone();
+            } else {
+                self.last_command = command.clone();
+            }
+            
+            if command.is_empty() {
+                continue;
+            }
+            
+            if command == "quit" || command == "q" {
+                break;
+            }
+            
+            match self.execute_command(osvm, &command) {
+                Ok(output) => {
+                    if !output.is_empty() {
+                        let _ = writeln!(out, "{}", output);
+                    }
+                }
+                Err(message) => {
+                    let _ = writeln!(out, "[Error]: {}", message);
+                }
+            }
+        }
+    }
+    
+    // Runs a single command and returns what it printed
+    pub fn execute_command(self: &mut Self, osvm: &mut OSVM, command: &str) -> Result<String, String> {
+        if self.names.is_empty() {
+            self.names = self.disassembler.target_names(&osvm.program.opcodes(), &osvm.symbols);
+        }
+        
+        let mut parts = command.trim().splitn(2, char::is_whitespace);
+        let name = parts.next().unwrap_or("");
+        let args = parts.next().unwrap_or("").trim();
+        if matches!(name, "continue" | "c" | "step" | "s" | "next" | "n" | "finish" | "reverse-step" | "rs" | "reverse-continue" | "rc") {
+            self.baseline = Some(DumpBaseline::capture(osvm));
+        }
+        
+        match name {
+            "break" | "b" => self.set_breakpoint(osvm, args),
+            "delete" | "d" => self.delete_breakpoint(osvm, args),
+            
+            "continue" | "c" => self.resume(osvm, |_| false),
+            "step" | "s" => {
+                let count = if args.is_empty() {
+                    1
+                } else {
+                    args.parse::<usize>().map_err(|_| format!("invalid step count `{}`", args))?
+                };
+                
+                self.step(osvm, count)
+            }
+            "next" | "n" => {
+                let is_call = osvm.program.get(osvm.get_pc()).map(|opcode| opcode.op_type == OpcodeType::Call);
+                if is_call == Some(true) {
+                    let depth = osvm.call_stack.len();
+                    self.resume(osvm, |osvm| osvm.call_stack.len() <= depth)
+                } else {
+                    self.step(osvm, 1)
+                }
+            }
+            "finish" => {
+                let depth = osvm.call_stack.len();
+                if depth == 0 {
+                    return Err("not inside a call".to_string());
+                }
+                
+                self.resume(osvm, |osvm| osvm.call_stack.len() < depth)
+            }
+            
+            "print" | "p" => self.print(osvm, args),
+            "stack" => Ok(self.stack(osvm)),
+            "backtrace" | "bt" => Ok(self.backtrace(osvm)),
+            "regs" | "registers" => Ok(self.registers(osvm)),
+            "dump" => self.dump(osvm, args),
+            "set" => self.set(osvm, args),
+            "disas" => self.disas(osvm, args),
+            "watch" => self.watch(osvm, args, WatchAccess::Write),
+            "rwatch" => self.watch(osvm, args, WatchAccess::Read),
+            "awatch" => self.watch(osvm, args, WatchAccess::Access),
+            "unwatch" => self.unwatch(osvm, args),
+            
+            "record" => self.record(osvm, args),
+            "reverse-step" | "rs" => {
+                let count = if args.is_empty() {
+                    1
+                } else {
+                    args.parse::<usize>().map_err(|_| format!("invalid step count `{}`", args))?
+                };
+                
+                self.reverse(osvm, Some(count))
+            }
+            "reverse-continue" | "rc" => self.reverse(osvm, None),
+            "help" | "h" => Ok(HELP.to_string()),
+            
+            _ if name.starts_with("x/") || name == "x" => self.examine(osvm, &name[1..], args),
+            _ => Err(format!("unknown command `{}`, try `help`", name)),
+        }
+    }
+    
+    fn parse_number(self: &Self, token: &str) -> Option<usize> {
+        let token = token.trim().trim_start_matches(CONST);
+        match token.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => token.parse::<usize>().ok(),
+        }
+    }
+    
+    // Breakpoint targets are addresses or labels from the symbol table
+    fn resolve(self: &Self, osvm: &OSVM, target: &str) -> Result<usize, String> {
+        let addr = match self.parse_number(target) {
+            Some(addr) => addr,
+            None => match osvm.symbols.iter().find(|label| label.name == target) {
+                Some(label) => label.addr,
+                None => return Err(format!("no label named `{}`", target)),
+            },
+        };
+        
+        if addr >= osvm.program.len() {
+            return Err(format!("address {} is outside of the program", addr));
+        }
+        
+        Ok(addr)
+    }
+    
+    fn describe(self: &Self, addr: usize) -> String {
+        match self.names.get(&addr) {
+            Some(name) => format!("{:04} <{}>", addr, name),
+            None => format!("{:04}", addr),
+        }
+    }
+    
+    fn location(self: &Self, osvm: &OSVM) -> String {
+        let pc = osvm.get_pc();
+        match osvm.program.get(pc) {
+            Some(opcode) => format!("=> {}  {}", self.describe(pc), self.disassembler.format_opcode(&opcode, &self.names)),
+            None => format!("=> {}  <end of program>", pc),
+        }
+    }
+    
+    fn set_breakpoint(self: &mut Self, osvm: &OSVM, args: &str) -> Result<String, String> {
+        if args.is_empty() {
+            if self.breakpoints.is_empty() {
+                return Ok("no breakpoints".to_string());
+            }
+            
+            let lines: Vec<String> = self.breakpoints.iter()
+                .enumerate()
+                .map(|(i, addr)| format!("#{:<3} {}", i, self.describe(*addr)))
+                .collect();
+            return Ok(lines.join("\n"));
+        }
+        
+        let addr = self.resolve(osvm, args)?;
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+        
+        Ok(format!("breakpoint at {}", self.describe(addr)))
+    }
+    
+    fn delete_breakpoint(self: &mut Self, osvm: &OSVM, args: &str) -> Result<String, String> {
+        if args.is_empty() {
+            self.breakpoints.clear();
+            return Ok("deleted all breakpoints".to_string());
+        }
+        
+        let addr = self.resolve(osvm, args)?;
+        match self.breakpoints.iter().position(|bp| *bp == addr) {
+            Some(index) => {
+                self.breakpoints.remove(index);
+                Ok(format!("deleted breakpoint at {}", self.describe(addr)))
+            }
+            None => Err(format!("no breakpoint at {}", self.describe(addr))),
+        }
+    }
+    
+    fn step(self: &Self, osvm: &mut OSVM, count: usize) -> Result<String, String> {
+        for _ in 0..count {
+            if osvm.is_halted() {
+                return Ok("program halted".to_string());
+            }
+            
+            if let Err(err) = osvm.execute_opcode() {
+                return Ok(format!("{}\n{}", Log::diagnostic(&err), self.location(osvm)));
+            }
+            
+            if !osvm.watch_hits.is_empty() {
+                return Ok(format!("{}\n{}", self.watch_hits(&osvm.watch_hits), self.location(osvm)));
+            }
+        }
+        
+        if osvm.is_halted() {
+            return Ok("program halted".to_string());
+        }
+        
+        Ok(self.location(osvm))
+    }
+    
+    // Runs until `done` holds, a breakpoint is hit, the program halts or faults
+    fn resume(self: &Self, osvm: &mut OSVM, done: impl Fn(&OSVM) -> bool) -> Result<String, String> {
+        if osvm.is_halted() {
+            return Err("the program has halted".to_string());
+        }
+        
+        loop {
+            if let Err(err) = osvm.execute_opcode() {
+                return Ok(format!("{}\n{}", Log::diagnostic(&err), self.location(osvm)));
+            }
+            
+            if !osvm.watch_hits.is_empty() {
+                return Ok(format!("{}\n{}", self.watch_hits(&osvm.watch_hits), self.location(osvm)));
+            }
+            
+            if osvm.is_halted() {
+                return Ok("program halted".to_string());
+            }
+            
+            if done(osvm) {
+                return Ok(self.location(osvm));
+            }
+            
+            if self.breakpoints.contains(&osvm.get_pc()) {
+                return Ok(format!("breakpoint hit\n{}", self.location(osvm)));
+            }
+        }
+    }
+    
+    fn describe_watchpoint(self: &Self, watchpoint: &Watchpoint) -> String {
+        match watchpoint {
+            Watchpoint::Register(reg) => format!("watch {}", REGISTERS[*reg]),
+            Watchpoint::Memory { start, len, access } => {
+                let name = match access {
+                    WatchAccess::Read => "rwatch",
+                    WatchAccess::Write => "watch",
+                    WatchAccess::Access => "awatch",
+                };
+                
+                format!("{} 0x{:04x} ({} bytes)", name, start, len)
+            }
+        }
+    }
+    
+    fn watch_hits(self: &Self, hits: &[WatchHit]) -> String {
+        let lines: Vec<String> = hits.iter()
+            .map(|hit| {
+                let change = match &hit.event {
+                    WatchEvent::Register { reg, old, new } => format!("{} {:?} -> {:?}", REGISTERS[*reg], old, new),
+                    WatchEvent::Memory { addr, len, access: WatchAccess::Read, new, .. } => {
+                        format!("rd 0x{:04x} ({} bytes) = 0x{:x}", addr, len, new)
+                    }
+                    WatchEvent::Memory { addr, len, old, new, .. } => {
+                        format!("wrt 0x{:04x} ({} bytes) 0x{:x} -> 0x{:x}", addr, len, old, new)
+                    }
+                };
+                
+                format!("watchpoint #{}: {} at {}", hit.watchpoint, change, self.describe(hit.pc))
+            })
+            .collect();
+        lines.join("\n")
+    }
+    
+    fn watch(self: &Self, osvm: &mut OSVM, args: &str, access: WatchAccess) -> Result<String, String> {
+        if args.is_empty() {
+            if osvm.watchpoints.is_empty() {
+                return Ok("no watchpoints".to_string());
+            }
+            
+            let lines: Vec<String> = osvm.watchpoints.iter()
+                .enumerate()
+                .map(|(i, watchpoint)| format!("#{:<3} {}", i, self.describe_watchpoint(watchpoint)))
+                .collect();
+            return Ok(lines.join("\n"));
+        }
+        
+        let mut parts = args.split_whitespace();
+        let target = parts.next().unwrap_or("");
+        let watchpoint = match register_index(target) {
+            Some(reg) if access == WatchAccess::Write => Watchpoint::Register(reg),
+            Some(_) => return Err("registers can only be watched for writes".to_string()),
+            None => {
+                let start = self.parse_number(target).ok_or(format!("invalid address `{}`", target))?;
+                let len = match parts.next() {
+                    Some(len) => self.parse_number(len).filter(|len| *len > 0).ok_or(format!("invalid length `{}`", len))?,
+                    None => 8,
+                };
+                
+                if start >= osvm.memory.len() {
+                    return Err(format!("address 0x{:x} is outside of memory", start));
+                }
+                
+                Watchpoint::Memory { start, len, access }
+            }
+        };
+        
+        let message = self.describe_watchpoint(&watchpoint);
+        let index = osvm.add_watchpoint(watchpoint);
+        Ok(format!("watchpoint #{}: {}", index, message))
+    }
+    
+    fn unwatch(self: &Self, osvm: &mut OSVM, args: &str) -> Result<String, String> {
+        if args.is_empty() {
+            osvm.watchpoints.clear();
+            return Ok("deleted all watchpoints".to_string());
+        }
+        
+        let index = self.parse_number(args).ok_or(format!("invalid watchpoint `{}`", args))?;
+        if index >= osvm.watchpoints.len() {
+            return Err(format!("no watchpoint #{}", index));
+        }
+        
+        osvm.watchpoints.remove(index);
+        Ok(format!("deleted watchpoint #{}", index))
+    }
+    
+    fn record(self: &Self, osvm: &mut OSVM, args: &str) -> Result<String, String> {
+        if args == "stop" {
+            osvm.stop_recording();
+            return Ok("stopped recording".to_string());
+        }
+        
+        let capacity = if args.is_empty() {
+            DEFAULT_RECORD_CAPACITY
+        } else {
+            self.parse_number(args).filter(|n| *n > 0).ok_or(format!("invalid instruction count `{}`", args))?
+        };
+        
+        osvm.start_recording(capacity);
+        Ok(format!("recording the last {} instructions", capacity))
+    }
+    
+    // Write watchpoints the recorded instruction would trigger, old
+    // values come from the delta and new values from the current state
+    fn reverse_watch_hits(self: &Self, osvm: &OSVM, delta: &Delta) -> Vec<WatchHit> {
+        let registers = osvm.registers();
+        let mut hits = Vec::new();
+        for (i, watchpoint) in osvm.watchpoints.iter().enumerate() {
+            match watchpoint {
+                Watchpoint::Register(reg) => {
+                    if let Some((_, old)) = delta.registers.iter().find(|(index, _)| index == reg) {
+                        let event = WatchEvent::Register { reg: *reg, old: *old, new: registers[*reg] };
+                        hits.push(WatchHit { pc: delta.pc, watchpoint: i, event });
+                    }
+                }
+                Watchpoint::Memory { start, len, access } => {
+                    if !access.matches(WatchAccess::Write) {
+                        continue;
+                    }
+                    
+                    for (addr, bytes) in &delta.memory {
+                        if *addr < start.saturating_add(*len) && *start < *addr + bytes.len() {
+                            let mut old = [0; 8];
+                            let mut new = [0; 8];
+                            old[..bytes.len()].copy_from_slice(bytes);
+                            new[..bytes.len()].copy_from_slice(&osvm.memory[*addr..*addr + bytes.len()]);
+                            
+                            let event = WatchEvent::Memory {
+                                addr: *addr,
+                                len: bytes.len(),
+                                access: WatchAccess::Write,
+                                old: u64::from_ne_bytes(old),
+                                new: u64::from_ne_bytes(new),
+                            };
+                            hits.push(WatchHit { pc: delta.pc, watchpoint: i, event });
+                        }
+                    }
+                }
+            }
+        }
+        
+        hits
+    }
+    
+    // Steps back `count` instructions, or until a breakpoint or watchpoint without one
+    fn reverse(self: &Self, osvm: &mut OSVM, count: Option<usize>) -> Result<String, String> {
+        if !osvm.is_recording() {
+            return Err("not recording, start with `record`".to_string());
+        }
+        
+        let mut steps = 0;
+        loop {
+            let hits = match osvm.recorder.as_ref().and_then(|recorder| recorder.last()) {
+                Some(delta) => self.reverse_watch_hits(osvm, delta),
+                None => return Ok(format!("reached the start of the recording\n{}", self.location(osvm))),
+            };
+            
+            osvm.step_back();
+            steps += 1;
+            
+            match count {
+                Some(count) => {
+                    if steps >= count {
+                        return Ok(self.location(osvm));
+                    }
+                }
+                None => {
+                    if !hits.is_empty() {
+                        return Ok(format!("{}\n{}", self.watch_hits(&hits), self.location(osvm)));
+                    }
+                    
+                    if self.breakpoints.contains(&osvm.get_pc()) {
+                        return Ok(format!("breakpoint hit\n{}", self.location(osvm)));
+                    }
+                }
+            }
+        }
+    }
+    
+    fn print(self: &Self, osvm: &mut OSVM, args: &str) -> Result<String, String> {
+        match args {
+            "pc" => return Ok(format!("pc = {}", self.describe(osvm.get_pc()))),
+            "tsr" => return Ok(format!("tsr = {}", osvm.tsr)),
+            _ => {}
+        }
+        
+        if let Some(index) = args.strip_prefix(GSI) {
+            let index = self.parse_number(index).ok_or(format!("invalid stack index `{}`", args))?;
+            if index >= osvm.stack.len() {
+                return Err(format!("stack only has {} values", osvm.stack.len()));
+            }
+            
+            return Ok(format!("{} = {:?}", args, osvm.stack[osvm.stack.len() - 1 - index]));
+        }
+        
+        match osvm.register_by_name(args) {
+            Some(reg) => Ok(format!("{} = {:?}", args, reg)),
+            None => Err(format!("unknown register `{}`", args)),
+        }
+    }
+    
+    fn stack(self: &Self, osvm: &OSVM) -> String {
+        if osvm.stack.is_empty() {
+            return "stack is empty".to_string();
+        }
+        
+        let lines: Vec<String> = osvm.stack.iter()
+            .rev()
+            .enumerate()
+            .map(|(i, value)| format!("{}{:<4} {:?}", GSI, i, value))
+            .collect();
+        lines.join("\n")
+    }
+    
+    fn backtrace(self: &Self, osvm: &OSVM) -> String {
+        let mut lines = vec![format!("#0   {}", self.describe(osvm.get_pc()))];
+        for (depth, frame) in osvm.call_stack.iter().rev().enumerate() {
+            lines.push(format!("#{:<3} {} (called from {})", depth + 1, self.describe(frame.return_addr), frame.call_addr));
+        }
+        
+        lines.join("\n")
+    }
+    
+    fn registers(self: &Self, osvm: &mut OSVM) -> String {
+        let mut lines = Vec::new();
+        for name in REGISTERS {
+            if let Some(reg) = osvm.register_by_name(name) {
+                lines.push(format!("{:<4} {:?}", name, reg));
+            }
+        }
+        
+        lines.push(format!("tsr  {}", osvm.tsr));
+        lines.push(format!("pc   {}", self.describe(osvm.get_pc())));
+        lines.join("\n")
+    }
+    
+    fn dump(self: &Self, osvm: &OSVM, args: &str) -> Result<String, String> {
+        let mut options = DumpOptions::init();
+        let mut json = false;
+        for arg in args.split_whitespace() {
+            match DumpView::parse(arg) {
+                Some(view) => options.view = view,
+                None if arg == "json" => json = true,
+                None => options.add_range(arg)?,
+            }
+        }
+        
+        let dump = StateDump::capture(osvm, &options, self.baseline.as_ref());
+        if json {
+            Ok(dump.to_json().to_string())
+        } else {
+            Ok(dump.to_text())
+        }
+    }
+    
+    fn set(self: &Self, osvm: &mut OSVM, args: &str) -> Result<String, String> {
+        let (target, value) = match args.split_once('=') {
+            Some((target, value)) => (target.trim(), value.trim()),
+            None => return Err("usage: set <reg|pc> = <value>".to_string()),
+        };
+        
+        if target == "pc" {
+            let pc = self.resolve(osvm, value)?;
+            osvm.set_pc(pc);
+            return Ok(self.location(osvm));
+        }
+        
+        let value = TypedWord::parse_literal(value).ok_or(format!("invalid literal `{}`", value))?;
+        match osvm.register_by_name(target) {
+            Some(reg) => {
+                *reg = value;
+                Ok(format!("{} = {:?}", target, value))
+            }
+            None => Err(format!("unknown register `{}`", target)),
+        }
+    }
+    
+    fn disas(self: &Self, osvm: &OSVM, args: &str) -> Result<String, String> {
+        let pc = osvm.get_pc();
+        let start = if args.is_empty() {
+            pc.saturating_sub(3)
+        } else {
+            self.resolve(osvm, args)?
+        };
+        
+        let mut lines = Vec::new();
+        for (addr, opcode) in (start..start + 10).filter_map(|addr| Some((addr, osvm.program.get(addr)?))) {
+            if let Some(name) = self.names.get(&addr) {
+                lines.push(format!("{}:", name));
+            }
+            
+            let marker = if addr == pc { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&addr) { "*" } else { " " };
+            lines.push(format!("{}{} {:04}  {}", marker, breakpoint, addr, self.disassembler.format_opcode(&opcode, &self.names)));
+        }
+        
+        Ok(lines.join("\n"))
+    }
+    
+    // x/<count><format><unit> like gdb, e.g. `x/16x 0x40` or `x/2dg r1`
+    fn examine(self: &Self, osvm: &mut OSVM, spec: &str, args: &str) -> Result<String, String> {
+        let spec = spec.trim_start_matches('/');
+        let digits: String = spec.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let count = if digits.is_empty() { 1 } else { digits.parse::<usize>().map_err(|_| format!("invalid count `{}`", digits))? };
+        
+        let mut format = 'x';
+        let mut size = 1;
+        for c in spec[digits.len()..].chars() {
+            match c {
+                'x' | 'd' | 'u' => format = c,
+                'b' => size = 1,
+                'h' => size = 2,
+                'w' => size = 4,
+                'g' => size = 8,
+                _ => return Err(format!("invalid format `{}`", c)),
+            }
+        }
+        
+        let addr = match self.parse_number(args) {
+            Some(addr) => addr,
+            None => match osvm.register_by_name(args) {
+                Some(reg) => unsafe { reg.word.as_usize },
+                None => return Err(format!("invalid address `{}`", args)),
+            },
+        };
+        
+        let end = count.checked_mul(size).and_then(|len| len.checked_add(addr));
+        if end.is_none_or(|end| end > osvm.memory.len()) {
+            return Err(format!("address 0x{:x} is outside of memory", addr));
+        }
+        
+        let per_row = 16 / size;
+        let mut lines = Vec::new();
+        for row in 0..count.div_ceil(per_row) {
+            let row_addr = addr + row * per_row * size;
+            let mut line = format!("0x{:04x}:", row_addr);
+            for i in 0..per_row.min(count - row * per_row) {
+                let offset = row_addr + i * size;
+                let mut bytes = [0; 8];
+                bytes[..size].copy_from_slice(&osvm.memory[offset..offset + size]);
+                let value = u64::from_ne_bytes(bytes);
+                let text = match format {
+                    'x' => format!("0x{:0width$x}", value, width = size * 2),
+                    'u' => value.to_string(),
+                    _ => {
+                        let shift = 64 - size * 8;
+                        (((value << shift) as i64) >> shift).to_string()
+                    }
+                };
+                
+                line.push(' ');
+                line.push_str(&text);
+            }
+            
+            lines.push(line);
+        }
+        
+        Ok(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    
+    const PROGRAM: &str = "
+_start:
+    mov r0, #0
+    call double
+    mov r2, #65
+    mov r3, #16
+    wrt #8, r3, r2
+    hlt
+double:
+    inc r0
+    push r0
+    push r0
+    adds
+    add r1, r0, r0
+    ret
+";
+    
+    fn debugger() -> (Debugger, OSVM) {
+        let (osvm, _) = machine(&assemble(PROGRAM));
+        (Debugger::init(), osvm)
+    }
+    
+    fn command(debugger: &mut Debugger, osvm: &mut OSVM, command: &str) -> String {
+        match debugger.execute_command(osvm, command) {
+            Ok(output) => output,
+            Err(message) => panic!("`{}` failed: {}", command, message),
+        }
+    }
+    
+    #[test]
+    fn breakpoints_resolve_labels_and_addresses() {
+        let (mut debugger, mut osvm) = debugger();
+        assert_eq!(command(&mut debugger, &mut osvm, "break double"), "breakpoint at 0006 <double>");
+        assert_eq!(command(&mut debugger, &mut osvm, "b 3"), "breakpoint at 0003");
+        assert_eq!(debugger.breakpoints, [6, 3]);
+        
+        assert_eq!(command(&mut debugger, &mut osvm, "continue"), "breakpoint hit\n=> 0006 <double>  inc r0");
+        assert_eq!(osvm.call_stack.len(), 1);
+        assert_eq!(command(&mut debugger, &mut osvm, "c"), "breakpoint hit\n=> 0003  mov r3, #16");
+        assert_eq!(command(&mut debugger, &mut osvm, "c"), "program halted");
+        assert!(osvm.is_halted());
+        
+        assert_eq!(command(&mut debugger, &mut osvm, "delete double"), "deleted breakpoint at 0006 <double>");
+        assert_eq!(debugger.breakpoints, [3]);
+        assert!(debugger.execute_command(&mut osvm, "break nowhere").is_err());
+        assert!(debugger.execute_command(&mut osvm, "break 100").is_err());
+        assert!(debugger.execute_command(&mut osvm, "delete 5").is_err());
+    }
+    
+    #[test]
+    fn step_next_and_finish() {
+        let (mut debugger, mut osvm) = debugger();
+        assert_eq!(command(&mut debugger, &mut osvm, "step"), "=> 0001  call double");
+        assert_eq!(command(&mut debugger, &mut osvm, "next"), "=> 0002  mov r2, #65");
+        assert_eq!(command(&mut debugger, &mut osvm, "print r1"), "r1 = u64: 2");
+        
+        let (mut debugger, mut osvm) = self::debugger();
+        assert_eq!(command(&mut debugger, &mut osvm, "step 3"), "=> 0007  push r0");
+        assert_eq!(command(&mut debugger, &mut osvm, "finish"), "=> 0002  mov r2, #65");
+        assert!(osvm.call_stack.is_empty());
+        assert!(debugger.execute_command(&mut osvm, "finish").is_err());
+        assert!(debugger.execute_command(&mut osvm, "step x").is_err());
+        assert_eq!(command(&mut debugger, &mut osvm, "step 100"), "program halted");
+    }
+    
+    #[test]
+    fn inspects_and_sets_state() {
+        let (mut debugger, mut osvm) = debugger();
+        command(&mut debugger, &mut osvm, "step 5");
+        assert_eq!(command(&mut debugger, &mut osvm, "print r0"), "r0 = u64: 1");
+        assert_eq!(command(&mut debugger, &mut osvm, "print pc"), "pc = 0009");
+        assert_eq!(command(&mut debugger, &mut osvm, "stack"), "$0    u64: 1\n$1    u64: 1");
+        assert_eq!(command(&mut debugger, &mut osvm, "print $1"), "$1 = u64: 1");
+        assert!(debugger.execute_command(&mut osvm, "print $2").is_err());
+        assert!(debugger.execute_command(&mut osvm, "print r99").is_err());
+        assert_eq!(command(&mut debugger, &mut osvm, "backtrace"), "#0   0009\n#1   0002 (called from 1)");
+        
+        assert_eq!(command(&mut debugger, &mut osvm, "set r0 = #-5"), "r0 = i64: -5");
+        assert_eq!(command(&mut debugger, &mut osvm, "print r0"), "r0 = i64: -5");
+        assert_eq!(command(&mut debugger, &mut osvm, "set pc = 2"), "=> 0002  mov r2, #65");
+        assert!(debugger.execute_command(&mut osvm, "set r0 #1").is_err());
+        assert!(debugger.execute_command(&mut osvm, "set r0 = nope").is_err());
+    }
+    
+    #[test]
+    fn examines_memory() {
+        let (mut debugger, mut osvm) = debugger();
+        command(&mut debugger, &mut osvm, "continue");
+        assert_eq!(command(&mut debugger, &mut osvm, "x/4x 0x10"), "0x0010: 0x41 0x00 0x00 0x00");
+        assert_eq!(command(&mut debugger, &mut osvm, "x/1dg r3"), "0x0010: 65");
+        assert_eq!(command(&mut debugger, &mut osvm, "x/2uh 16"), "0x0010: 65 0");
+        assert_eq!(command(&mut debugger, &mut osvm, "x/17xb 0"), format!("0x0000:{}\n0x0010: 0x41", " 0x00".repeat(16)));
+        
+        let end = osvm.memory.len();
+        assert!(debugger.execute_command(&mut osvm, &format!("x/2xb {}", end - 1)).is_err());
+        assert!(debugger.execute_command(&mut osvm, "x/4q 0").is_err());
+    }
+    
+    #[test]
+    fn disassembles_around_the_pc() {
+        let (mut debugger, mut osvm) = debugger();
+        command(&mut debugger, &mut osvm, "break double");
+        command(&mut debugger, &mut osvm, "step");
+        assert_eq!(command(&mut debugger, &mut osvm, "disas double"), "\
+double:
+  * 0006  inc r0
+    0007  push r0
+    0008  push r0
+    0009  adds
+    0010  add r1, r0, r0
+    0011  ret");
+        assert!(command(&mut debugger, &mut osvm, "disas").starts_with("_start:\n    0000  mov r0, #0\n=>  0001  call double"));
+    }
+    
+    #[test]
+    fn reads_commands_and_repeats_the_last_one() {
+        let (mut debugger, mut osvm) = debugger();
+        let mut input = "step\n\nbogus\nquit\nstep\n".as_bytes();
+        let mut out = Vec::new();
+        debugger.run(&mut osvm, &mut input, &mut out);
+        
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+=> 0000 <_start>  mov r0, #0
+(osvm) => 0001  call double
+(osvm) => 0006 <double>  inc r0
+(osvm) [Error]: unknown command `bogus`, try `help`
+(osvm) ");
+        assert_eq!(osvm.get_pc(), 6);
+    }
+    
+    #[test]
+    fn watchpoints_stop_the_program() {
+        let (mut debugger, mut osvm) = debugger();
+        assert_eq!(command(&mut debugger, &mut osvm, "watch r1"), "watchpoint #0: watch r1");
+        assert_eq!(command(&mut debugger, &mut osvm, "awatch 0x10 1"), "watchpoint #1: awatch 0x0010 (1 bytes)");
+        assert_eq!(command(&mut debugger, &mut osvm, "watch"), "#0   watch r1\n#1   awatch 0x0010 (1 bytes)");
+        
+        assert_eq!(command(&mut debugger, &mut osvm, "continue"), "watchpoint #0: r1 u64: 0 -> u64: 2 at 0010\n=> 0011  ret");
+        assert_eq!(command(&mut debugger, &mut osvm, "continue"), "watchpoint #1: wrt 0x0010 (1 bytes) 0x0 -> 0x41 at 0004\n=> 0005  hlt");
+        
+        assert!(debugger.execute_command(&mut osvm, "rwatch r1").is_err());
+        assert!(debugger.execute_command(&mut osvm, "watch 0x10 0").is_err());
+        let end = format!("watch {}", osvm.memory.len());
+        assert!(debugger.execute_command(&mut osvm, &end).is_err());
+        assert!(debugger.execute_command(&mut osvm, "unwatch 2").is_err());
+        assert_eq!(command(&mut debugger, &mut osvm, "unwatch 0"), "deleted watchpoint #0");
+        assert_eq!(osvm.watchpoints, [Watchpoint::Memory { start: 16, len: 1, access: WatchAccess::Access }]);
+        assert_eq!(command(&mut debugger, &mut osvm, "unwatch"), "deleted all watchpoints");
+    }
+    
+    #[test]
+    fn steps_and_continues_backwards() {
+        let (mut debugger, mut osvm) = debugger();
+        assert!(debugger.execute_command(&mut osvm, "reverse-step").is_err());
+        assert_eq!(command(&mut debugger, &mut osvm, "record 100"), "recording the last 100 instructions");
+        
+        command(&mut debugger, &mut osvm, "watch r0");
+        command(&mut debugger, &mut osvm, "continue");
+        command(&mut debugger, &mut osvm, "continue");
+        command(&mut debugger, &mut osvm, "unwatch");
+        assert_eq!(command(&mut debugger, &mut osvm, "continue"), "program halted");
+        
+        assert_eq!(command(&mut debugger, &mut osvm, "reverse-step 2"), "=> 0004  wrt #8, r3, r2");
+        assert_eq!(osvm.memory[16], 0);
+        assert!(!osvm.is_halted());
+        
+        command(&mut debugger, &mut osvm, "watch r1");
+        assert_eq!(command(&mut debugger, &mut osvm, "reverse-continue"), "watchpoint #0: r1 u64: 0 -> u64: 2 at 0010\n=> 0010  add r1, r0, r0");
+        assert_eq!(osvm.registers()[1], TypedWord::u64(0));
+        
+        command(&mut debugger, &mut osvm, "unwatch");
+        command(&mut debugger, &mut osvm, "break 1");
+        assert_eq!(command(&mut debugger, &mut osvm, "rc"), "breakpoint hit\n=> 0001  call double");
+        assert_eq!(command(&mut debugger, &mut osvm, "rc"), "reached the start of the recording\n=> 0000 <_start>  mov r0, #0");
+        
+        assert_eq!(command(&mut debugger, &mut osvm, "record stop"), "stopped recording");
+        assert!(!osvm.is_recording());
+    }
+    
+    #[test]
+    fn dumps_changes_since_the_last_step() {
+        let (mut debugger, mut osvm) = debugger();
+        command(&mut debugger, &mut osvm, "break 5");
+        command(&mut debugger, &mut osvm, "continue");
+        
+        let json: serde_json::Value = serde_json::from_str(&command(&mut debugger, &mut osvm, "dump u8 16+8 json")).unwrap();
+        assert_eq!(json["memory"]["view"], "u8");
+        assert_eq!(json["memory"]["regions"][0]["values"], serde_json::json!([65, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(json["registers"][2]["changed"], true);
+        assert_eq!(json["registers"][7]["changed"], false);
+        
+        command(&mut debugger, &mut osvm, "set pc = 3");
+        command(&mut debugger, &mut osvm, "step");
+        assert!(command(&mut debugger, &mut osvm, "dump 16").contains("\n    r2   u64: 65\n"));
+        assert!(debugger.execute_command(&mut osvm, "dump 9..3").is_err());
+    }
+}
\ No newline at end of file
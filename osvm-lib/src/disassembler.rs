@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use crate::oasm::Label;
+use crate::opcode::*;
+use crate::program::Program;
+use crate::utils::defines::*;
+
+// Turns opcodes back into OASM that the Assembler accepts
+pub struct Disassembler {}
+
+impl Disassembler {
+    // Jump targets are named after the symbol table, or get synthesized
+    // `L_0012` style names when the program has no symbols
+    pub fn target_names(self: &Self, code: &[Opcode], symbols: &[Label]) -> HashMap<usize, String> {
+        let mut names = HashMap::new();
+        for symbol in symbols {
+            names.entry(symbol.addr).or_insert(symbol.name.clone());
+        }
+        
+        if symbols.is_empty() {
+            for opcode in code {
+                if let Some(target) = self.jump_target(opcode) {
+                    names.entry(target).or_insert(format!("L_{:04}", target));
+                }
+            }
+        }
+        
+        names
+    }
+    
+    fn jump_target(self: &Self, opcode: &Opcode) -> Option<usize> {
+        match opcode.op_type {
+            OpcodeType::Jt | OpcodeType::Jz | OpcodeType::Jnz |
+            OpcodeType::Jts | OpcodeType::Jzs | OpcodeType::Jnzs |
+            OpcodeType::Jmp | OpcodeType::Call => {
+                opcode.op_operand.map(|operand| unsafe { operand.word.as_usize })
+            }
+            
+            _ => None,
+        }
+    }
+    
+    // Literals are written so they parse back to the same type: i64 always
+    // takes the `i` suffix, since `#6` would come back as u64. The assembler
+    // never makes ptr literals, they are written as u64.
+    fn literal(self: &Self, value: TypedWord) -> String {
+        unsafe {
+            match value.ty {
+                WordType::U64 => format!("{}{}", CONST, value.word.as_u64),
+                WordType::I64 => format!("{}{}i", CONST, value.word.as_i64),
+                WordType::F64 => format!("{}{:?}", CONST, value.word.as_f64),
+                WordType::Ptr => format!("{}{}", CONST, value.word.as_u64),
+            }
+        }
+    }
+    
+    fn target(self: &Self, opcode: &Opcode, names: &HashMap<usize, String>) -> String {
+        match self.jump_target(opcode) {
+            Some(target) => match names.get(&target) {
+                Some(name) => name.clone(),
+                None => format!("{}{}", CONST, target),
+            },
+            None => String::new(),
+        }
+    }
+    
+    fn index(self: &Self, opcode: &Opcode) -> String {
+        match opcode.op_operand {
+            Some(value) => unsafe { value.word.as_u64.to_string() },
+            None => String::new(),
+        }
+    }
+    
+    pub fn format_opcode(self: &Self, opcode: &Opcode, names: &HashMap<usize, String>) -> String {
+        let mnemonic = opcode.op_type.mnemonic();
+        let regs = opcode.op_regs.names().join(", ");
+        match opcode.op_type {
+            OpcodeType::Mov | OpcodeType::Push if opcode.op_operand.is_some() => {
+                let mut operands: Vec<String> = opcode.op_regs.names().iter().map(|reg| reg.to_string()).collect();
+                operands.push(self.literal(opcode.op_operand.unwrap()));
+                format!("{} {}", mnemonic, operands.join(", "))
+            }
+            OpcodeType::Movfs => {
+                format!("{} {}, {}{}", mnemonic, regs, GSI, self.index(opcode))
+            }
+            
+            OpcodeType::Jt | OpcodeType::Jz | OpcodeType::Jnz => {
+                format!("{} {}, {}", mnemonic, self.target(opcode, names), regs)
+            }
+            OpcodeType::Jts | OpcodeType::Jzs | OpcodeType::Jnzs | OpcodeType::Jmp | OpcodeType::Call => {
+                format!("{} {}", mnemonic, self.target(opcode, names))
+            }
+            
+            OpcodeType::Dupl | OpcodeType::Swc => {
+                format!("{} {}", mnemonic, self.index(opcode))
+            }
+            
+            OpcodeType::Read | OpcodeType::Write => {
+                let size = format!("{}{}", CONST, self.index(opcode));
+                if regs.is_empty() {
+                    format!("{} {}", mnemonic, size)
+                } else {
+                    format!("{} {}, {}", mnemonic, size, regs)
+                }
+            }
+            
+            _ => {
+                if regs.is_empty() {
+                    mnemonic.to_string()
+                } else {
+                    format!("{} {}", mnemonic, regs)
+                }
+            }
+        }
+    }
+    
+    // Reassembling the output gives the same .vbin apart from the line table,
+    // which then points at the listing instead of the original source
+    pub fn disassemble(self: &Self, program: &Program) -> String {
+        let names = self.target_names(&program.code, &program.symbols);
+        
+        // Every symbol is written back so that reassembling gives the same symbol table
+        let mut labels: HashMap<usize, Vec<String>> = HashMap::new();
+        if program.symbols.is_empty() {
+            labels.entry(program.entry).or_default().push("_start".to_string());
+            for (addr, name) in &names {
+                labels.entry(*addr).or_default().push(name.clone());
+            }
+            
+            for names in labels.values_mut() {
+                names.sort();
+            }
+        } else {
+            for symbol in &program.symbols {
+                labels.entry(symbol.addr).or_default().push(symbol.name.clone());
+            }
+        }
+        
+        let mut source = String::new();
+        for addr in 0..=program.code.len() {
+            if let Some(names) = labels.get(&addr) {
+                for name in names {
+                    source.push_str(&format!("{}:\n", name));
+                }
+            }
+            
+            if let Some(opcode) = program.code.get(addr) {
+                source.push_str(&format!("    {}\n", self.format_opcode(opcode, &names)));
+            }
+        }
+        
+        source
+    }
+    
+    pub fn disassemble_code(self: &Self, code: &[Opcode]) -> String {
+        self.disassemble(&Program {
+            code: code.to_vec(),
+            entry: 0,
+            ..Program::init()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oasm::Label;
+    use crate::optimizer::Optimizer;
+    use crate::testing::*;
+    use crate::utils::file::OSVMFile;
+    
+    // The .vbin bytes without the line table, which names the source file
+    fn encoded(program: &Program) -> Vec<u8> {
+        let program = Program { files: Vec::new(), lines: Vec::new(), ..program.clone() };
+        OSVMFile {}.encode_program(&program).unwrap()
+    }
+    
+    fn round_trip(name: &str, program: &Program) {
+        let source = Disassembler {}.disassemble(program);
+        let reassembled = match try_assemble(&source) {
+            Ok(program) => program,
+            Err(errors) => panic!("{} does not reassemble: {:?}\n{}", name, errors.iter().map(|err| err.to_string()).collect::<Vec<_>>(), source),
+        };
+        
+        assert!(encoded(program) == encoded(&reassembled), "{} differs after a round trip\n{}", name, source);
+    }
+    
+    #[test]
+    fn examples_round_trip() {
+        for (name, program) in examples() {
+            round_trip(&name, &program);
+            round_trip(&format!("{} (optimized)", name), &Optimizer::init().optimize(&program));
+        }
+    }
+    
+    #[test]
+    fn typed_literals_round_trip() {
+        let program = assemble("
+_start:
+    mov r0, #6i
+    mov r1, #-3
+    mov r2, #18446744073709551615
+    push #0i
+    push #2.0
+    push #-0.5
+    hlt
+");
+        assert_eq!(program.code[0].op_operand, Some(TypedWord::i64(6)));
+        assert_eq!(program.code[3].op_operand, Some(TypedWord::i64(0)));
+        round_trip("typed literals", &program);
+        
+        // Values that only come out of the machine, e.g. folded constants
+        let mut program = Program::init();
+        program.code = [TypedWord::i64(6), TypedWord::i64(i64::MIN), TypedWord::u64(7), TypedWord::f64(3.0), TypedWord::f64(f64::INFINITY)]
+            .map(|value| Opcode { op_type: OpcodeType::Push, op_operand: Some(value), op_regs: OpRegs::init() })
+            .to_vec();
+        program.symbols.push(Label { name: "_start".to_string(), addr: 0 });
+        round_trip("typed pushes", &program);
+    }
+    
+    #[test]
+    fn typed_folds_round_trip() {
+        let program = assemble("
+_start:
+    push #10
+    push #4
+    subis
+    push #4
+    push #10
+    subis
+    negi
+    itof
+    push #7i
+    push #2i
+    divis
+    push #1.5
+    push #2.0
+    mulfs
+    hlt
+");
+        let optimized = Optimizer::init().optimize(&program);
+        assert_eq!(optimized.code[0].op_operand, Some(TypedWord::i64(6)));
+        assert_eq!(optimized.code[1].op_operand, Some(TypedWord::i64(-6)));
+        assert_eq!(optimized.code[4].op_operand, Some(TypedWord::i64(3)));
+        assert_eq!(optimized.code[5].op_operand, Some(TypedWord::f64(3.0)));
+        round_trip("typed folds", &optimized);
+    }
+    
+    #[test]
+    fn code_without_symbols_round_trips() {
+        for (name, program) in examples() {
+            let stripped = Program { symbols: Vec::new(), ..program.clone() };
+            let source = Disassembler {}.disassemble(&stripped);
+            let reassembled = assemble(&source);
+            assert_eq!(format!("{:?}", reassembled.code), format!("{:?}", program.code), "{}", name);
+            assert_eq!(reassembled.entry, program.entry, "{}", name);
+        }
+    }
+    
+    #[test]
+    fn jump_targets_get_synthesized_labels() {
+        let program = assemble("
+_start:
+    mov r0, #3
+loop:
+    dec r0
+    jnz loop, r0
+    push #-2
+    push #0.5
+    jmp end
+end:
+    hlt
+");
+        let source = Disassembler {}.disassemble_code(&program.code);
+        assert_eq!(source, "\
+_start:
+    mov r0, #3
+L_0001:
+    dec r0
+    jnz L_0001, r0
+    push #-2i
+    push #0.5
+    jmp L_0006
+L_0006:
+    hlt
+");
+    }
+}
\ No newline at end of file
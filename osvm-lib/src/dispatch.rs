@@ -0,0 +1,724 @@
+use crate::bytecode::Bytecode;
+use crate::opcode::{Opcode, OpcodeType};
+use crate::osvm::{Frame, OSVM};
+use crate::utils::{arith::*, defines::*, error::ErrorKind};
+
+// Runs one decoded instruction. Faults only come back as their kind, a
+// handler that has more to say leaves the full error in `OSVM::fault`
+pub type Handler = fn(&mut OSVM, &Decoded) -> Result<(), ErrorKind>;
+
+// An instruction resolved once into the handler that runs it and its
+// operands. Register counts and operand kinds are checked while decoding,
+// anything that doesn't have the expected shape falls back to OSVM::step
+// so it faults exactly like the single-step path.
+#[derive(Clone, Copy)]
+pub struct Decoded {
+    pub handler: Handler,
+    regs: [u8; 3],
+    
+    // Jump target, stack index or access size in bytes
+    arg: usize,
+    operand: TypedWord,
+    
+    arith: ArithOp,
+    cmp: CmpOp,
+    ty: WordType,
+}
+
+impl Decoded {
+    pub fn decode(opcode: &Opcode) -> Decoded {
+        let mut decoded = Decoded {
+            handler: fallback,
+            regs: [0; 3],
+            arg: 0,
+            operand: opcode.op_operand.unwrap_or(TypedWord::u64(0)),
+            arith: ArithOp::Add,
+            cmp: CmpOp::Eq,
+            ty: WordType::U64,
+        };
+        
+        for (i, reg) in opcode.op_regs.iter().enumerate() {
+            decoded.regs[i] = reg as u8;
+        }
+        
+        if let Some(operand) = opcode.op_operand {
+            decoded.arg = unsafe { operand.word.as_usize };
+        }
+        
+        if let Some(cmp) = compare_op(opcode.op_type) {
+            decoded.cmp = cmp;
+        }
+        
+        if let Some((op, ty)) = typed_arith(opcode.op_type) {
+            decoded.arith = op;
+            decoded.ty = ty;
+        }
+        
+        if let Some((cmp, ty)) = typed_compare(opcode.op_type) {
+            decoded.cmp = cmp;
+            decoded.ty = ty;
+        }
+        
+        let regs = opcode.op_regs.len();
+        let operand = opcode.op_operand.is_some();
+        decoded.handler = match (opcode.op_type, regs, operand) {
+            (OpcodeType::Mov, 2, false) => mov_reg,
+            (OpcodeType::Mov, 1, true) => mov_const,
+            (OpcodeType::Movfs, 1, true) => movfs,
+            (OpcodeType::Srg, 2, _) => srg,
+            
+            (OpcodeType::Add | OpcodeType::Sub | OpcodeType::Mul | OpcodeType::Div, 3, _) => {
+                decoded.arith = untyped_arith(opcode.op_type).unwrap();
+                arith_regs
+            }
+            (OpcodeType::Adds | OpcodeType::Subs | OpcodeType::Muls | OpcodeType::Divs, _, _) => {
+                decoded.arith = untyped_arith(opcode.op_type).unwrap();
+                arith_stack
+            }
+            (OpcodeType::Inc, 1, _) => inc,
+            (OpcodeType::Dec, 1, _) => dec,
+            
+            (OpcodeType::Equal | OpcodeType::Ne | OpcodeType::Lt | OpcodeType::Le | OpcodeType::Gt | OpcodeType::Ge, 3, _) => compare_regs,
+            (OpcodeType::Equals | OpcodeType::Nes | OpcodeType::Lts | OpcodeType::Les | OpcodeType::Gts | OpcodeType::Ges, _, _) => compare_stack,
+            
+            (OpcodeType::Jt, 1, true) => jt,
+            (OpcodeType::Jz, 1, true) => jz,
+            (OpcodeType::Jnz, 1, true) => jnz,
+            (OpcodeType::Jts, _, true) => jts,
+            (OpcodeType::Jzs, _, true) => jzs,
+            (OpcodeType::Jnzs, _, true) => jnzs,
+            (OpcodeType::Jmp, _, true) => jmp,
+            (OpcodeType::Call, _, true) => call,
+            (OpcodeType::Ret, _, _) => ret,
+            (OpcodeType::Hlt, _, _) => hlt,
+            
+            (OpcodeType::Push, _, true) => push_const,
+            (OpcodeType::Push, 1, false) => push_reg,
+            (OpcodeType::Pop, 0, _) => pop_stack,
+            (OpcodeType::Pop, 1, _) => pop_reg,
+            (OpcodeType::Dupl, _, true) => dupl,
+            (OpcodeType::Swc, _, true) => swc,
+            
+            (OpcodeType::Read | OpcodeType::Write, 0 | 2, true) if matches!(decoded.arg, 8 | 16 | 32 | 64) => {
+                decoded.arg /= 8;
+                match (opcode.op_type, regs) {
+                    (OpcodeType::Read, 0) => read_stack,
+                    (OpcodeType::Read, _) => read_reg,
+                    (_, 0) => write_stack,
+                    _ => write_reg,
+                }
+            }
+            
+            (OpcodeType::Addi | OpcodeType::Addu | OpcodeType::Addf |
+             OpcodeType::Subi | OpcodeType::Subu | OpcodeType::Subf |
+             OpcodeType::Muli | OpcodeType::Mulu | OpcodeType::Mulf |
+             OpcodeType::Divi | OpcodeType::Divu | OpcodeType::Divf |
+             OpcodeType::Modi | OpcodeType::Modu | OpcodeType::Modf, 3, _) => typed_arith_regs,
+            (OpcodeType::Addis | OpcodeType::Addus | OpcodeType::Addfs |
+             OpcodeType::Subis | OpcodeType::Subus | OpcodeType::Subfs |
+             OpcodeType::Mulis | OpcodeType::Mulus | OpcodeType::Mulfs |
+             OpcodeType::Divis | OpcodeType::Divus | OpcodeType::Divfs |
+             OpcodeType::Modis | OpcodeType::Modus | OpcodeType::Modfs, _, _) => typed_arith_stack,
+            (OpcodeType::Lti | OpcodeType::Ltu | OpcodeType::Ltf |
+             OpcodeType::Lei | OpcodeType::Leu | OpcodeType::Lef |
+             OpcodeType::Gti | OpcodeType::Gtu | OpcodeType::Gtf |
+             OpcodeType::Gei | OpcodeType::Geu | OpcodeType::Gef, 3, _) => typed_compare_regs,
+            (OpcodeType::Ltis | OpcodeType::Ltus | OpcodeType::Ltfs |
+             OpcodeType::Leis | OpcodeType::Leus | OpcodeType::Lefs |
+             OpcodeType::Gtis | OpcodeType::Gtus | OpcodeType::Gtfs |
+             OpcodeType::Geis | OpcodeType::Geus | OpcodeType::Gefs, _, _) => typed_compare_stack,
+            
+            _ => fallback,
+        };
+        
+        decoded
+    }
+}
+
+// Decodes the whole program, index i runs the instruction at address i
+pub fn decode_program(program: &Bytecode) -> Vec<Decoded> {
+    (0..program.len())
+        .map(|addr| Decoded::decode(&program.get(addr).unwrap()))
+        .collect()
+}
+
+// Register and stack access with the indices already validated
+#[inline(always)]
+fn reg(osvm: &OSVM, decoded: &Decoded, index: usize) -> TypedWord {
+    osvm.regs[decoded.regs[index] as usize]
+}
+
+#[inline(always)]
+fn set_reg(osvm: &mut OSVM, decoded: &Decoded, index: usize, value: TypedWord) {
+    osvm.regs[decoded.regs[index] as usize] = value;
+}
+
+#[inline(always)]
+fn pop2(osvm: &mut OSVM) -> Result<(TypedWord, TypedWord), ErrorKind> {
+    if osvm.stack.len() < 2 {
+        return Err(ErrorKind::StackUnderflow);
+    }
+    
+    let a = osvm.stack.pop().unwrap();
+    let b = osvm.stack.pop().unwrap();
+    Ok((a, b))
+}
+
+#[inline(always)]
+fn peek2(osvm: &OSVM) -> Result<(TypedWord, TypedWord), ErrorKind> {
+    let len = osvm.stack.len();
+    if len < 2 {
+        return Err(ErrorKind::StackUnderflow);
+    }
+    
+    Ok((osvm.stack[len - 1], osvm.stack[len - 2]))
+}
+
+#[inline(always)]
+fn branch(osvm: &mut OSVM, decoded: &Decoded, taken: bool) {
+    if taken {
+        osvm.pc = decoded.arg;
+    } else {
+        osvm.pc += 1;
+    }
+}
+
+#[inline(always)]
+fn in_bounds(osvm: &OSVM, addr: usize, bytes: usize) -> Result<(), ErrorKind> {
+    if addr >= osvm.memory.len() || osvm.memory.len() - addr < bytes {
+        return Err(ErrorKind::ErrIllegalMemoryAccess);
+    }
+    
+    Ok(())
+}
+
+// Everything without a fast path goes through OSVM::step
+fn fallback(osvm: &mut OSVM, _: &Decoded) -> Result<(), ErrorKind> {
+    let opcode = osvm.program.get(osvm.pc).ok_or(ErrorKind::InvalidOpcodeAccess)?;
+    osvm.step(&opcode).map_err(|err| {
+        let kind = err.kind;
+        osvm.fault = Some(err);
+        kind
+    })
+}
+
+fn mov_reg(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let value = reg(osvm, decoded, 1);
+    osvm.set_tsr(value);
+    set_reg(osvm, decoded, 0, value);
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn mov_const(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    osvm.set_tsr(decoded.operand);
+    set_reg(osvm, decoded, 0, decoded.operand);
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn movfs(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    if osvm.stack.len() <= decoded.arg {
+        return Err(ErrorKind::StackUnderflow);
+    }
+    
+    let value = osvm.stack[osvm.stack.len() - 1 - decoded.arg];
+    osvm.set_tsr(value);
+    set_reg(osvm, decoded, 0, value);
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn srg(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let reg1 = reg(osvm, decoded, 0);
+    let reg2 = reg(osvm, decoded, 1);
+    osvm.set_tsr(reg1);
+    set_reg(osvm, decoded, 0, reg2);
+    set_reg(osvm, decoded, 1, reg1);
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn arith_regs(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let reg1 = reg(osvm, decoded, 1);
+    let reg2 = reg(osvm, decoded, 2);
+    osvm.set_tsr(reg1);
+    set_reg(osvm, decoded, 0, arith_tagged(decoded.arith, reg1, reg2)?);
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn arith_stack(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let (a, b) = pop2(osvm)?;
+    osvm.set_tsr(b);
+    let value = arith_tagged(decoded.arith, b, a)?;
+    osvm.stack.push(value);
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn inc(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let reg1 = reg(osvm, decoded, 0);
+    osvm.set_tsr(reg1);
+    let word = arith(ArithOp::Add, reg1.ty, reg1.word, one(reg1.ty))?;
+    set_reg(osvm, decoded, 0, TypedWord { word, ty: reg1.ty });
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn dec(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let reg1 = reg(osvm, decoded, 0);
+    osvm.set_tsr(reg1);
+    let word = arith(ArithOp::Sub, reg1.ty, reg1.word, one(reg1.ty))?;
+    set_reg(osvm, decoded, 0, TypedWord { word, ty: reg1.ty });
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn compare_regs(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let reg1 = reg(osvm, decoded, 1);
+    let reg2 = reg(osvm, decoded, 2);
+    osvm.set_tsr(reg1);
+    set_reg(osvm, decoded, 0, compare_tagged(decoded.cmp, reg1, reg2));
+    osvm.pc += 1;
+    Ok(())
+}
+
+// Leaves its operands on the stack like OSVM::step does
+fn compare_stack(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let (a, b) = peek2(osvm)?;
+    osvm.set_tsr(b);
+    osvm.stack.push(compare_tagged(decoded.cmp, b, a));
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn typed_arith_regs(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let reg1 = reg(osvm, decoded, 1);
+    let reg2 = reg(osvm, decoded, 2);
+    osvm.tsr = decoded.ty as usize;
+    let word = arith(decoded.arith, decoded.ty, reg1.word, reg2.word)?;
+    set_reg(osvm, decoded, 0, TypedWord { word, ty: decoded.ty });
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn typed_arith_stack(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let (a, b) = pop2(osvm)?;
+    osvm.tsr = decoded.ty as usize;
+    let word = arith(decoded.arith, decoded.ty, b.word, a.word)?;
+    osvm.stack.push(TypedWord { word, ty: decoded.ty });
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn typed_compare_regs(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let reg1 = reg(osvm, decoded, 1);
+    let reg2 = reg(osvm, decoded, 2);
+    osvm.tsr = decoded.ty as usize;
+    set_reg(osvm, decoded, 0, compare(decoded.cmp, decoded.ty, reg1.word, reg2.word));
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn typed_compare_stack(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let (a, b) = peek2(osvm)?;
+    osvm.tsr = decoded.ty as usize;
+    osvm.stack.push(compare(decoded.cmp, decoded.ty, b.word, a.word));
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn jt(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let value = unsafe { reg(osvm, decoded, 0).word.as_u64 };
+    branch(osvm, decoded, value == 1);
+    Ok(())
+}
+
+fn jz(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let value = unsafe { reg(osvm, decoded, 0).word.as_u64 };
+    branch(osvm, decoded, value == 0);
+    Ok(())
+}
+
+fn jnz(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let value = unsafe { reg(osvm, decoded, 0).word.as_u64 };
+    branch(osvm, decoded, value != 0);
+    Ok(())
+}
+
+fn jts(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let value = unsafe { osvm.stack.pop().ok_or(ErrorKind::StackUnderflow)?.word.as_u64 };
+    branch(osvm, decoded, value == 1);
+    Ok(())
+}
+
+fn jzs(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let value = unsafe { osvm.stack.pop().ok_or(ErrorKind::StackUnderflow)?.word.as_u64 };
+    branch(osvm, decoded, value == 0);
+    Ok(())
+}
+
+fn jnzs(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let value = unsafe { osvm.stack.pop().ok_or(ErrorKind::StackUnderflow)?.word.as_u64 };
+    branch(osvm, decoded, value != 0);
+    Ok(())
+}
+
+fn jmp(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    osvm.pc = decoded.arg;
+    Ok(())
+}
+
+fn call(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    if osvm.call_stack.len() >= osvm.call_stack_depth {
+        return Err(ErrorKind::CallStackOverflow);
+    }
+    
+    osvm.call_stack.push(Frame {
+        call_addr: osvm.pc,
+        target_addr: decoded.arg,
+        return_addr: osvm.pc + 1,
+    });
+    osvm.pc = decoded.arg;
+    Ok(())
+}
+
+fn ret(osvm: &mut OSVM, _: &Decoded) -> Result<(), ErrorKind> {
+    let frame = osvm.call_stack.pop().ok_or(ErrorKind::CallStackUnderflow)?;
+    osvm.pc = frame.return_addr;
+    Ok(())
+}
+
+fn hlt(osvm: &mut OSVM, _: &Decoded) -> Result<(), ErrorKind> {
+    osvm.halt = true;
+    Ok(())
+}
+
+fn push_const(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    osvm.stack.push(decoded.operand);
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn push_reg(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let value = reg(osvm, decoded, 0);
+    osvm.stack.push(value);
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn pop_stack(osvm: &mut OSVM, _: &Decoded) -> Result<(), ErrorKind> {
+    osvm.stack.pop().ok_or(ErrorKind::StackUnderflow)?;
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn pop_reg(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    osvm.set_tsr(TypedWord::u64(0));
+    set_reg(osvm, decoded, 0, TypedWord::u64(0));
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn dupl(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    if osvm.stack.len() <= decoded.arg {
+        return Err(ErrorKind::StackUnderflow);
+    }
+    
+    osvm.stack.push(osvm.stack[osvm.stack.len() - 1 - decoded.arg]);
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn swc(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let len = osvm.stack.len();
+    if len < 2 || len <= decoded.arg {
+        return Err(ErrorKind::StackUnderflow);
+    }
+    
+    osvm.stack.swap(len - 1, len - 1 - decoded.arg);
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn read_stack(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let addr = osvm.stack.pop().ok_or(ErrorKind::StackUnderflow)?;
+    osvm.set_tsr(addr);
+    let addr = unsafe { addr.word.as_usize };
+    in_bounds(osvm, addr, decoded.arg)?;
+    
+    let value = osvm.load_memory(addr, decoded.arg);
+    osvm.stack.push(TypedWord::u64(value));
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn read_reg(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let addr = reg(osvm, decoded, 1);
+    osvm.set_tsr(addr);
+    let addr = unsafe { addr.word.as_usize };
+    in_bounds(osvm, addr, decoded.arg)?;
+    
+    let value = osvm.load_memory(addr, decoded.arg);
+    set_reg(osvm, decoded, 0, TypedWord::u64(value));
+    osvm.pc += 1;
+    Ok(())
+}
+
+fn write_stack(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let (addr, value) = pop2(osvm)?;
+    store(osvm, decoded, addr, value)
+}
+
+fn write_reg(osvm: &mut OSVM, decoded: &Decoded) -> Result<(), ErrorKind> {
+    let (addr, value) = (reg(osvm, decoded, 0), reg(osvm, decoded, 1));
+    store(osvm, decoded, addr, value)
+}
+
+#[inline(always)]
+fn store(osvm: &mut OSVM, decoded: &Decoded, addr: TypedWord, value: TypedWord) -> Result<(), ErrorKind> {
+    osvm.set_tsr(value);
+    let addr = unsafe { addr.word.as_usize };
+    in_bounds(osvm, addr, decoded.arg)?;
+    
+    let value = unsafe { value.word.as_u64 };
+    match decoded.arg {
+        1 => osvm.memory[addr] = value as u8,
+        2 => osvm.memory[addr..addr + 2].copy_from_slice(&(value as u16).to_ne_bytes()),
+        4 => osvm.memory[addr..addr + 4].copy_from_slice(&(value as u32).to_ne_bytes()),
+        _ => osvm.memory[addr..addr + 8].copy_from_slice(&value.to_ne_bytes()),
+    }
+    osvm.pc += 1;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Program;
+    use crate::testing::*;
+    use crate::utils::error::Error;
+    
+    // Programs that between them reach every handler and the fallback
+    const PROGRAMS: [&str; 6] = [
+        "
+_start:
+    mov r0, #12
+    mov r1, #5
+    mov r2, r0
+    add r3, r0, r1
+    sub r4, r0, r1
+    mul r5, r0, r1
+    div r6, r0, r1
+    inc r0
+    dec r1
+    srg r0, r1
+    eq r8, r0, r1
+    ne r9, r0, r1
+    lt r10, r0, r1
+    le r11, r0, r1
+    gt r12, r0, r1
+    ge r13, r0, r1
+    and r14, r0, r1
+    shl r15, r0, r1
+    not r16, r0
+    hlt
+",
+        "
+_start:
+    push #7
+    push #3
+    adds
+    push #4
+    subs
+    push #2
+    muls
+    push #3
+    divs
+    push #4
+    eqs
+    nes
+    lts
+    dupl #2
+    swc #3
+    mov r0, $1
+    phsr r1
+    push r0
+    pop
+    pop r2
+    push #0
+    jzs zero
+    hlt
+zero:
+    push #1
+    jnzs one
+    hlt
+one:
+    push #1
+    jts done
+    hlt
+done:
+    hlt
+",
+        "
+_start:
+    mov r0, #-7
+    mov r1, #2
+    addi r2, r0, r1
+    divi r3, r0, r1
+    modi r4, r0, r1
+    mov r5, #2.5
+    mov r6, #0.5
+    mulf r7, r5, r6
+    ltf r8, r5, r6
+    geu r9, r1, r1
+    negi r10, r0
+    absf r11, r5
+    itof r12, r0
+    ftoi r13, r5
+    sext8 r14, r1
+    push #-1
+    push #-2
+    subis
+    push #3
+    gtis
+    push #1.5
+    push #2.0
+    mulfs
+    hlt
+",
+        "
+_start:
+    mov r0, #16
+    mov r1, #4660
+    wrt #8, r0, r1
+    wrt #16, r0, r1
+    wrt #32, r0, r1
+    wrt #64, r0, r1
+    rd #8, r2, r0
+    rd #16, r3, r0
+    rd #32, r4, r0
+    rd #64, r5, r0
+    push #99
+    push #40
+    wrt #64
+    push #40
+    rd #32
+    hlt
+",
+        "
+_start:
+    mov r0, #5
+    mov r1, #0
+loop:
+    call add
+    dec r0
+    jnz loop, r0
+    jz out, r0
+    hlt
+out:
+    eq r2, r0, r0
+    jt end, r2
+    hlt
+end:
+    jmp stop
+stop:
+    hlt
+add:
+    inc r1
+    ret
+",
+        "
+_start:
+    mov r0, #1
+    mov r1, #0
+    mov r7, #3
+    sysf r0
+    push #2
+    mov r7, #4
+    sysf
+    hlt
+",
+    ];
+    
+    const FAULTS: [&str; 11] = [
+        "\n_start:\n    mov r0, #1\n    mov r1, #0\n    div r2, r0, r1\n    hlt\n",
+        "\n_start:\n    push #1\n    push #0\n    divs\n    hlt\n",
+        "\n_start:\n    mov r0, #-1\n    mov r1, #0\n    modi r2, r0, r1\n    hlt\n",
+        "\n_start:\n    mov r0, #640000\n    rd #64, r1, r0\n    hlt\n",
+        "\n_start:\n    push #1\n    push #639999\n    wrt #16\n    hlt\n",
+        "\n_start:\n    push #1\n    adds\n    hlt\n",
+        "\n_start:\n    pop\n    hlt\n",
+        "\n_start:\n    ret\n",
+        "\n_start:\n    call _start\n",
+        "\n_start:\n    push #1\n    swc #4\n    hlt\n",
+        "\n_start:\n    mov r7, #99\n    sysf\n    hlt\n",
+    ];
+    
+    // Steps through execute_opcode, the path the debugger uses
+    fn step(program: &Program) -> (OSVM, String, Result<(), Error>) {
+        let (mut osvm, output) = machine(program);
+        let mut result = Ok(());
+        while !osvm.is_halted() {
+            if let Err(err) = osvm.execute_opcode() {
+                result = Err(err);
+                break;
+            }
+        }
+        
+        let output = String::from_utf8_lossy(&output.0.borrow()).into_owned();
+        (osvm, output, result)
+    }
+    
+    fn assert_parity(name: &str, program: &Program) {
+        let (stepped, stepped_output, stepped_result) = step(program);
+        let (dispatched, dispatched_output, dispatched_result) = run(program);
+        
+        assert_eq!(dispatched.registers(), stepped.registers(), "{}", name);
+        assert_eq!(dispatched.stack, stepped.stack, "{}", name);
+        assert_eq!(dispatched.call_stack, stepped.call_stack, "{}", name);
+        assert_eq!((dispatched.get_pc(), dispatched.tsr), (stepped.get_pc(), stepped.tsr), "{}", name);
+        assert!(dispatched.memory == stepped.memory, "{} memory differs", name);
+        assert_eq!(dispatched_output, stepped_output, "{}", name);
+        assert_eq!(
+            dispatched_result.map_err(|err| err.to_string()),
+            stepped_result.map_err(|err| err.to_string()),
+            "{}", name
+        );
+    }
+    
+    #[test]
+    fn dispatch_matches_execute_opcode() {
+        for (i, source) in PROGRAMS.iter().enumerate() {
+            let program = assemble(source);
+            if let Err(err) = run(&program).2 { panic!("program {} faulted: {}", i, err); }
+            assert_parity(&format!("program {}", i), &program);
+        }
+        
+        for (name, program) in examples() {
+            assert_parity(&name, &program);
+        }
+    }
+    
+    #[test]
+    fn faults_match_execute_opcode() {
+        for (i, source) in FAULTS.iter().enumerate() {
+            let program = assemble(source);
+            assert!(run(&program).2.is_err(), "fault {} ran to the end", i);
+            assert_parity(&format!("fault {}", i), &program);
+        }
+    }
+    
+    #[test]
+    fn unusual_shapes_fall_back_to_step() {
+        let program = assemble(PROGRAMS[0]);
+        let code = decode_program(&Bytecode::encode(&program.code).unwrap());
+        let handlers: Vec<usize> = code.iter().map(|decoded| decoded.handler as usize).collect();
+        
+        // `and`, `shl` and `not` have no handler of their own
+        assert_eq!(handlers[16], fallback as Handler as usize);
+        assert_eq!(handlers[18], fallback as Handler as usize);
+        assert_eq!(handlers[3], arith_regs as Handler as usize);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,440 @@
+use serde_json::{json, Value};
+
+use crate::osvm::{Frame, OSVM};
+use crate::utils::defines::*;
+
+const ROW_SIZE: usize = 16;
+
+// How memory bytes are shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DumpView {
+    Hex,
+    Ascii,
+    U8,
+    U16,
+    U32,
+    U64,
+    I64,
+    F64,
+}
+
+impl DumpView {
+    pub fn parse(name: &str) -> Option<DumpView> {
+        match name {
+            "hex" => Some(DumpView::Hex),
+            "ascii" => Some(DumpView::Ascii),
+            "u8" => Some(DumpView::U8),
+            "u16" => Some(DumpView::U16),
+            "u32" => Some(DumpView::U32),
+            "u64" => Some(DumpView::U64),
+            "i64" => Some(DumpView::I64),
+            "f64" => Some(DumpView::F64),
+            
+            _ => None,
+        }
+    }
+    
+    pub fn name(self: &Self) -> &'static str {
+        match self {
+            DumpView::Hex => "hex",
+            DumpView::Ascii => "ascii",
+            DumpView::U8 => "u8",
+            DumpView::U16 => "u16",
+            DumpView::U32 => "u32",
+            DumpView::U64 => "u64",
+            DumpView::I64 => "i64",
+            DumpView::F64 => "f64",
+        }
+    }
+    
+    // Bytes per value
+    pub fn unit(self: &Self) -> usize {
+        match self {
+            DumpView::Hex | DumpView::Ascii | DumpView::U8 => 1,
+            DumpView::U16 => 2,
+            DumpView::U32 => 4,
+            DumpView::U64 | DumpView::I64 | DumpView::F64 => 8,
+        }
+    }
+    
+    fn read(self: &Self, bytes: &[u8]) -> u64 {
+        let mut buf = [0; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        u64::from_le_bytes(buf)
+    }
+    
+    fn format_row(self: &Self, bytes: &[u8]) -> String {
+        match self {
+            DumpView::Hex => {
+                let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("{:<47}  |{}|", hex.join(" "), ascii(bytes))
+            }
+            DumpView::Ascii => ascii(bytes),
+            _ => {
+                let values: Vec<String> = bytes.chunks(self.unit())
+                    .map(|unit| {
+                        let value = self.read(unit);
+                        match self {
+                            DumpView::I64 => format!("{:>20}", value as i64),
+                            DumpView::F64 => format!("{:>20}", f64::from_bits(value)),
+                            _ => format!("{:>width$}", value, width = self.unit() * 2 + self.unit() / 2 + 1),
+                        }
+                    })
+                    .collect();
+                values.join(" ")
+            }
+        }
+    }
+    
+    fn to_json(self: &Self, bytes: &[u8]) -> Value {
+        match self {
+            DumpView::Hex => Value::from(bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+            DumpView::Ascii => Value::from(ascii(bytes)),
+            DumpView::I64 => bytes.chunks(8).map(|unit| Value::from(self.read(unit) as i64)).collect(),
+            DumpView::F64 => bytes.chunks(8).map(|unit| Value::from(f64::from_bits(self.read(unit)))).collect(),
+            _ => bytes.chunks(self.unit()).map(|unit| Value::from(self.read(unit))).collect(),
+        }
+    }
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' }).collect()
+}
+
+// What to dump, by default all of memory as hex
+#[derive(Debug, Clone)]
+pub struct DumpOptions {
+    pub ranges: Vec<(usize, usize)>,
+    pub view: DumpView,
+}
+
+impl DumpOptions {
+    pub fn init() -> DumpOptions {
+        DumpOptions {
+            ranges: Vec::new(),
+            view: DumpView::Hex,
+        }
+    }
+    
+    // `start..end`, `start+len` or a single address, numbers may be hex
+    pub fn add_range(self: &mut Self, text: &str) -> Result<(), String> {
+        let number = |token: &str| match token.trim().strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => token.trim().parse::<usize>().ok(),
+        };
+        let invalid = || format!("invalid range `{}`, expected start..end or start+len", text);
+        
+        let range = if let Some((start, end)) = text.split_once("..") {
+            (number(start).ok_or_else(invalid)?, number(end).ok_or_else(invalid)?)
+        } else if let Some((start, len)) = text.split_once('+') {
+            let start = number(start).ok_or_else(invalid)?;
+            (start, start.saturating_add(number(len).ok_or_else(invalid)?))
+        } else {
+            let start = number(text).ok_or_else(invalid)?;
+            (start, start.saturating_add(ROW_SIZE))
+        };
+        
+        if range.0 >= range.1 || range.0 >= MEMORY_CAPACITY {
+            return Err(format!("range `{}` is empty or outside of memory", text));
+        }
+        
+        self.ranges.push(range);
+        Ok(())
+    }
+    
+    // Ranges clamped to memory and widened to whole values of the view
+    fn memory_ranges(self: &Self, memory_len: usize) -> Vec<(usize, usize)> {
+        let unit = self.view.unit();
+        if self.ranges.is_empty() {
+            return vec![(0, memory_len - memory_len % unit)];
+        }
+        
+        self.ranges.iter()
+            .map(|(start, end)| (start - start % unit, end.div_ceil(unit).saturating_mul(unit).min(memory_len - memory_len % unit)))
+            .filter(|(start, end)| start < end)
+            .collect()
+    }
+}
+
+// Registers and stack to diff a later dump against
+#[derive(Debug, Clone)]
+pub struct DumpBaseline {
+    pub registers: [TypedWord; 17],
+    pub stack: Vec<TypedWord>,
+}
+
+impl DumpBaseline {
+    pub fn capture(osvm: &OSVM) -> DumpBaseline {
+        DumpBaseline {
+            registers: osvm.registers(),
+            stack: osvm.stack.clone(),
+        }
+    }
+}
+
+// A run of memory with at least one non-zero byte in every row
+#[derive(Debug, Clone)]
+pub struct MemoryRegion {
+    pub start: usize,
+    pub bytes: Vec<u8>,
+}
+
+// The machine state picked out by DumpOptions, rendered as text or JSON
+#[derive(Debug, Clone)]
+pub struct StateDump {
+    pub pc: usize,
+    pub tsr: usize,
+    pub registers: [TypedWord; 17],
+    pub stack: Vec<TypedWord>,
+    pub call_stack: Vec<Frame>,
+    pub call_stack_capacity: usize,
+    pub view: DumpView,
+    pub regions: Vec<MemoryRegion>,
+    
+    // Register indices and stack slots (from the bottom) that differ from the baseline
+    pub changed_registers: Vec<usize>,
+    pub changed_stack: Vec<usize>,
+}
+
+impl StateDump {
+    pub fn capture(osvm: &OSVM, options: &DumpOptions, baseline: Option<&DumpBaseline>) -> StateDump {
+        let registers = osvm.registers();
+        let (changed_registers, changed_stack) = match baseline {
+            Some(baseline) => (
+                (0..registers.len()).filter(|i| registers[*i] != baseline.registers[*i]).collect(),
+                (0..osvm.stack.len()).filter(|i| baseline.stack.get(*i) != Some(&osvm.stack[*i])).collect(),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+        
+        // Rows are aligned to ROW_SIZE and clipped to the range
+        let mut regions: Vec<MemoryRegion> = Vec::new();
+        for (start, end) in options.memory_ranges(osvm.memory.len()) {
+            let mut row = start;
+            while row < end {
+                let row_end = (row - row % ROW_SIZE + ROW_SIZE).min(end);
+                let bytes = &osvm.memory[row..row_end];
+                if bytes.iter().any(|byte| *byte != 0) {
+                    match regions.last_mut() {
+                        Some(region) if region.start + region.bytes.len() == row => region.bytes.extend_from_slice(bytes),
+                        _ => regions.push(MemoryRegion { start: row, bytes: bytes.to_vec() }),
+                    }
+                }
+                
+                row = row_end;
+            }
+        }
+        
+        StateDump {
+            pc: osvm.get_pc(),
+            tsr: osvm.tsr,
+            registers,
+            stack: osvm.stack.clone(),
+            call_stack: osvm.call_stack.clone(),
+            call_stack_capacity: osvm.call_stack_depth,
+            view: options.view,
+            regions,
+            changed_registers,
+            changed_stack,
+        }
+    }
+    
+    // Changed registers and stack slots are marked with a `*`
+    pub fn to_text(self: &Self) -> String {
+        let marker = |changed: bool| if changed { "*" } else { " " };
+        let mut lines = vec!["[Registers]:".to_string()];
+        for (i, value) in self.registers.iter().enumerate() {
+            lines.push(format!("  {} {:<4} {:?}", marker(self.changed_registers.contains(&i)), REGISTERS[i], value));
+        }
+        
+        lines.push(format!("    tsr  {}", self.tsr));
+        lines.push(format!("    pc   {}", self.pc));
+        
+        if !self.stack.is_empty() {
+            lines.push(format!("[Stack]: ({} values, top first)", self.stack.len()));
+            for (i, value) in self.stack.iter().enumerate().rev() {
+                lines.push(format!("  {} {}{:<4} {:?}", marker(self.changed_stack.contains(&i)), GSI, self.stack.len() - 1 - i, value));
+            }
+        }
+        
+        if !self.call_stack.is_empty() {
+            lines.push(format!("[Call Stack]: ({}/{})", self.call_stack.len(), self.call_stack_capacity));
+            for (depth, frame) in self.call_stack.iter().rev().enumerate() {
+                lines.push(format!("    #{:<3} call {} => {} (returns to {})", depth, frame.call_addr, frame.target_addr, frame.return_addr));
+            }
+        }
+        
+        lines.push(format!("[Memory]: ({})", self.view.name()));
+        if self.regions.is_empty() {
+            lines.push("    all zero".to_string());
+        }
+        
+        for (i, region) in self.regions.iter().enumerate() {
+            if i > 0 {
+                lines.push("    ...".to_string());
+            }
+            
+            // The first row may start off ROW_SIZE alignment
+            let mut addr = region.start;
+            while addr < region.start + region.bytes.len() {
+                let row_end = (addr - addr % ROW_SIZE + ROW_SIZE).min(region.start + region.bytes.len());
+                let bytes = &region.bytes[addr - region.start..row_end - region.start];
+                lines.push(format!("    0x{:04x}: {}", addr, self.view.format_row(bytes)));
+                addr = row_end;
+            }
+        }
+        
+        lines.join("\n")
+    }
+    
+    pub fn to_json(self: &Self) -> Value {
+        let word = |value: &TypedWord| unsafe {
+            match value.ty {
+                WordType::U64 => json!({ "type": "u64", "value": value.word.as_u64 }),
+                WordType::I64 => json!({ "type": "i64", "value": value.word.as_i64 }),
+                WordType::F64 => json!({ "type": "f64", "value": value.word.as_f64 }),
+                WordType::Ptr => json!({ "type": "ptr", "value": format!("{:p}", value.word.as_ptr) }),
+            }
+        };
+        
+        let registers: Vec<Value> = self.registers.iter()
+            .enumerate()
+            .map(|(i, value)| {
+                let mut entry = word(value);
+                entry["name"] = Value::from(REGISTERS[i]);
+                entry["changed"] = Value::from(self.changed_registers.contains(&i));
+                entry
+            })
+            .collect();
+        
+        let stack: Vec<Value> = self.stack.iter()
+            .enumerate()
+            .rev()
+            .map(|(i, value)| {
+                let mut entry = word(value);
+                entry["slot"] = Value::from(self.stack.len() - 1 - i);
+                entry["changed"] = Value::from(self.changed_stack.contains(&i));
+                entry
+            })
+            .collect();
+        
+        let call_stack: Vec<Value> = self.call_stack.iter()
+            .rev()
+            .map(|frame| json!({ "call": frame.call_addr, "target": frame.target_addr, "return": frame.return_addr }))
+            .collect();
+        
+        let regions: Vec<Value> = self.regions.iter()
+            .map(|region| json!({ "start": region.start, "len": region.bytes.len(), "values": self.view.to_json(&region.bytes) }))
+            .collect();
+        
+        json!({
+            "pc": self.pc,
+            "tsr": self.tsr,
+            "registers": registers,
+            "stack": stack,
+            "call_stack": call_stack,
+            "memory": { "view": self.view.name(), "regions": regions },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    
+    fn osvm() -> OSVM {
+        let (mut osvm, _) = machine(&assemble("\n_start:\n    mov r0, #7\n    push #-1\n    hlt\n"));
+        osvm.memory[16..21].copy_from_slice(b"osvm!");
+        osvm.memory[40] = 0xff;
+        osvm.memory[1000] = 1;
+        osvm
+    }
+    
+    fn options(view: DumpView, ranges: &[&str]) -> DumpOptions {
+        let mut options = DumpOptions::init();
+        options.view = view;
+        for range in ranges {
+            options.add_range(range).unwrap();
+        }
+        options
+    }
+    
+    #[test]
+    fn parses_ranges() {
+        let options = options(DumpView::Hex, &["16..32", "0x10+8", "32"]);
+        assert_eq!(options.ranges, [(16, 32), (16, 24), (32, 48)]);
+        
+        let mut options = DumpOptions::init();
+        for range in ["5..5", "8..4", "abc", "1+x", &MEMORY_CAPACITY.to_string()] {
+            assert!(options.add_range(range).is_err(), "accepted `{}`", range);
+        }
+        assert!(options.ranges.is_empty());
+    }
+    
+    #[test]
+    fn widens_ranges_to_whole_values() {
+        assert_eq!(options(DumpView::U64, &["3..9"]).memory_ranges(64), [(0, 16)]);
+        assert_eq!(options(DumpView::U16, &["3+2"]).memory_ranges(64), [(2, 6)]);
+        assert_eq!(options(DumpView::U8, &["60..100"]).memory_ranges(64), [(60, 64)]);
+        assert_eq!(DumpOptions::init().memory_ranges(64), [(0, 64)]);
+    }
+    
+    #[test]
+    fn keeps_only_non_zero_rows() {
+        let dump = StateDump::capture(&osvm(), &DumpOptions::init(), None);
+        let regions: Vec<(usize, usize)> = dump.regions.iter().map(|region| (region.start, region.bytes.len())).collect();
+        assert_eq!(regions, [(16, 32), (992, 16)]);
+        
+        let dump = StateDump::capture(&osvm(), &options(DumpView::Hex, &["18..22", "100+16"]), None);
+        assert_eq!(dump.regions.len(), 1);
+        assert_eq!((dump.regions[0].start, &dump.regions[0].bytes[..]), (18, &b"vm!\0"[..]));
+    }
+    
+    #[test]
+    fn renders_views() {
+        let osvm = osvm();
+        let row = |view: DumpView| {
+            let text = StateDump::capture(&osvm, &options(view, &["16+8"]), None).to_text();
+            text.lines().last().unwrap().to_string()
+        };
+        
+        assert_eq!(row(DumpView::Hex), format!("    0x0010: {:<47}  |osvm!...|", "6f 73 76 6d 21 00 00 00"));
+        assert_eq!(row(DumpView::Ascii), "    0x0010: osvm!...");
+        assert_eq!(row(DumpView::U16), format!("    0x0010: {:>6} {:>6} {:>6} {:>6}", 0x736f, 0x6d76, 0x21, 0));
+        assert_eq!(row(DumpView::U64), format!("    0x0010: {:>21}", u64::from_le_bytes(*b"osvm!\0\0\0")));
+        assert!(StateDump::capture(&osvm, &options(DumpView::Hex, &["100+16"]), None).to_text().ends_with("[Memory]: (hex)\n    all zero"));
+    }
+    
+    #[test]
+    fn marks_changes_since_the_baseline() {
+        let mut osvm = osvm();
+        let baseline = DumpBaseline::capture(&osvm);
+        osvm.execute_opcode().unwrap();
+        osvm.execute_opcode().unwrap();
+        
+        let dump = StateDump::capture(&osvm, &DumpOptions::init(), Some(&baseline));
+        assert_eq!(dump.changed_registers, [0]);
+        assert_eq!(dump.changed_stack, [0]);
+        
+        let text = dump.to_text();
+        assert!(text.contains("\n  * r0   u64: 7\n    r1   u64: 0\n"));
+        assert!(text.contains("[Stack]: (1 values, top first)\n  * $0    i64: -1\n"));
+        
+        let json = dump.to_json();
+        assert_eq!(json["registers"][0], json!({ "name": "r0", "type": "u64", "value": 7, "changed": true }));
+        assert_eq!(json["stack"][0], json!({ "slot": 0, "type": "i64", "value": -1, "changed": true }));
+        assert_eq!(json["pc"], 2);
+        assert_eq!(json["memory"]["regions"][1], json!({ "start": 992, "len": 16, "values": "00000000000000000100000000000000" }));
+    }
+    
+    #[test]
+    fn call_stack_uses_the_machines_depth() {
+        let (mut osvm, _) = machine(&assemble("\n_start:\n    call f\nf:\n    hlt\n"));
+        osvm.set_call_stack_depth(64);
+        osvm.execute_opcode().unwrap();
+        
+        let text = StateDump::capture(&osvm, &DumpOptions::init(), None).to_text();
+        assert!(text.contains("[Call Stack]: (1/64)\n    #0   call 0 => 1 (returns to 1)"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,625 @@
+use std::io::{self, ErrorKind as IoErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::log::Log;
+use crate::osvm::OSVM;
+use crate::utils::defines::*;
+use crate::utils::error::*;
+use crate::watchpoint::*;
+
+// Register numbers as gdb sees them: r0-r16, then pc and tsr
+const GDB_PC: usize = 17;
+const GDB_TSR: usize = 18;
+const GDB_REGISTERS: usize = 19;
+
+const PACKET_SIZE: usize = 0x4000;
+
+// How many instructions run between checks for a ^C from gdb
+const INTERRUPT_INTERVAL: usize = 1024;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+// A stream gdb is connected over
+pub trait Connection: Read + Write {
+    fn set_nonblocking(self: &Self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(self: &Self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+impl Connection for UnixStream {
+    fn set_nonblocking(self: &Self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+// Why the target stopped, turned into a stop reply packet
+enum Stop {
+    Step,
+    Breakpoint,
+    Watchpoint(WatchHit),
+    Interrupted,
+    Halted,
+    Fault(ErrorKind),
+}
+
+// GDB remote serial protocol stub, pc values and breakpoints are
+// instruction indices, memory addresses index OSVM::memory
+pub struct GdbStub {
+    pub breakpoints: Vec<usize>,
+    
+    no_ack: bool,
+}
+
+impl GdbStub {
+    pub fn init() -> GdbStub {
+        GdbStub {
+            breakpoints: Vec::new(),
+            no_ack: false,
+        }
+    }
+    
+    // Waits for a single gdb connection on `host:port` or `unix:<path>`
+    pub fn listen(self: &mut Self, osvm: &mut OSVM, address: &str) -> Result<(), Error> {
+        let io_error = |err: io::Error| Error::with_message(ErrorKind::ConnectionIo, format!("`{}`: {}", address, err));
+        match address.strip_prefix("unix:") {
+            Some(path) => {
+                let _ = std::fs::remove_file(path);
+                let listener = UnixListener::bind(path).map_err(io_error)?;
+                println!("[GDB]: listening on {}", address);
+                let (mut stream, _) = listener.accept().map_err(io_error)?;
+                let result = self.serve(osvm, &mut stream);
+                let _ = std::fs::remove_file(path);
+                result.map_err(io_error)
+            }
+            None => {
+                let listener = TcpListener::bind(address).map_err(io_error)?;
+                println!("[GDB]: listening on {}", address);
+                let (mut stream, _) = listener.accept().map_err(io_error)?;
+                let _ = stream.set_nodelay(true);
+                self.serve(osvm, &mut stream).map_err(io_error)
+            }
+        }
+    }
+    
+    // Answers packets until gdb detaches, kills the target or disconnects
+    pub fn serve(self: &mut Self, osvm: &mut OSVM, conn: &mut dyn Connection) -> io::Result<()> {
+        self.no_ack = false;
+        loop {
+            let packet = match self.read_packet(conn)? {
+                Some(packet) => packet,
+                None => return Ok(()),
+            };
+            
+            match packet.as_str() {
+                "D" => {
+                    self.send_packet(conn, "OK")?;
+                    return Ok(());
+                }
+                "k" => return Ok(()),
+                _ => {}
+            }
+            
+            let reply = self.handle(osvm, &packet, conn)?;
+            self.send_packet(conn, &reply)?;
+        }
+    }
+    
+    fn read_byte(self: &Self, conn: &mut dyn Connection) -> io::Result<Option<u8>> {
+        let mut byte = [0; 1];
+        loop {
+            match conn.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(err) if err.kind() == IoErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    
+    // Reads `$<data>#<checksum>`, skipping acks and stray interrupts
+    fn read_packet(self: &Self, conn: &mut dyn Connection) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte(conn)? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+            
+            let mut data = Vec::new();
+            let mut checksum: u8 = 0;
+            let mut escaped = false;
+            loop {
+                let byte = match self.read_byte(conn)? {
+                    Some(byte) => byte,
+                    None => return Ok(None),
+                };
+                
+                if byte == b'#' && !escaped {
+                    break;
+                }
+                
+                checksum = checksum.wrapping_add(byte);
+                if escaped {
+                    data.push(byte ^ 0x20);
+                    escaped = false;
+                } else if byte == b'}' {
+                    escaped = true;
+                } else {
+                    data.push(byte);
+                }
+            }
+            
+            let mut expected = [0; 2];
+            for digit in &mut expected {
+                *digit = match self.read_byte(conn)? {
+                    Some(byte) => byte,
+                    None => return Ok(None),
+                };
+            }
+            
+            let valid = std::str::from_utf8(&expected).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) == Some(checksum);
+            if !self.no_ack {
+                conn.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+    
+    fn send_packet(self: &Self, conn: &mut dyn Connection, data: &str) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for byte in data.bytes() {
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                packet.push(b'}');
+                packet.push(byte ^ 0x20);
+            } else {
+                packet.push(byte);
+            }
+        }
+        
+        let checksum = packet[1..].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+        
+        loop {
+            conn.write_all(&packet)?;
+            conn.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+            
+            match self.read_byte(conn)? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+    
+    // Replies to one packet, an empty reply tells gdb it is not supported
+    fn handle(self: &mut Self, osvm: &mut OSVM, packet: &str, conn: &mut dyn Connection) -> io::Result<String> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match command {
+            "?" => self.stop_reply(osvm, Stop::Step),
+            "g" => {
+                let mut reply = String::new();
+                for reg in 0..GDB_REGISTERS {
+                    reply.push_str(&encode_hex(&self.read_register(osvm, reg).to_le_bytes()));
+                }
+                reply
+            }
+            "G" => {
+                let bytes = match decode_hex(args) {
+                    Some(bytes) if bytes.len() == GDB_REGISTERS * 8 => bytes,
+                    _ => return Ok("E01".to_string()),
+                };
+                
+                for (reg, value) in bytes.chunks(8).enumerate() {
+                    self.write_register(osvm, reg, u64::from_le_bytes(value.try_into().unwrap()));
+                }
+                "OK".to_string()
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(reg) if reg < GDB_REGISTERS => encode_hex(&self.read_register(osvm, reg).to_le_bytes()),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let (reg, value) = args.split_once('=').unwrap_or(("", ""));
+                let reg = usize::from_str_radix(reg, 16).ok().filter(|reg| *reg < GDB_REGISTERS);
+                match (reg, decode_hex(value)) {
+                    (Some(reg), Some(bytes)) if bytes.len() == 8 => {
+                        self.write_register(osvm, reg, u64::from_le_bytes(bytes.try_into().unwrap()));
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => {
+                let range = args.split_once(',').and_then(|(addr, len)| self.memory_range(osvm, addr, len));
+                match range {
+                    Some((addr, len)) => encode_hex(&osvm.memory[addr..addr + len]),
+                    None => "E01".to_string(),
+                }
+            }
+            "M" => {
+                let (range, data) = args.split_once(':').unwrap_or(("", ""));
+                let range = range.split_once(',').and_then(|(addr, len)| self.memory_range(osvm, addr, len));
+                match (range, decode_hex(data)) {
+                    (Some((addr, len)), Some(bytes)) if bytes.len() == len => {
+                        osvm.memory[addr..addr + len].copy_from_slice(&bytes);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "Z" | "z" => self.breakpoint(osvm, args, command == "Z"),
+            "s" | "c" => {
+                if !args.is_empty() {
+                    match usize::from_str_radix(args, 16) {
+                        Ok(addr) => osvm.set_pc(addr),
+                        Err(_) => return Ok("E01".to_string()),
+                    }
+                }
+                
+                let stop = self.resume(osvm, conn, command == "s")?;
+                self.stop_reply(osvm, stop)
+            }
+            "H" => "OK".to_string(),
+            "T" => "OK".to_string(),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        
+        Ok(reply)
+    }
+    
+    fn query(self: &mut Self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:x};qXfer:features:read+;swbreak+;QStartNoAckMode+", PACKET_SIZE);
+        }
+        
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, len) = args.split_once(',').unwrap_or(("", ""));
+            return match (usize::from_str_radix(offset, 16), usize::from_str_radix(len, 16)) {
+                (Ok(offset), Ok(len)) => {
+                    let xml = target_xml();
+                    let start = offset.min(xml.len());
+                    let end = start.saturating_add(len).min(xml.len());
+                    let prefix = if end == xml.len() { "l" } else { "m" };
+                    format!("{}{}", prefix, &xml[start..end])
+                }
+                _ => "E01".to_string(),
+            };
+        }
+        
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qSymbol::" => "OK".to_string(),
+            _ => String::new(),
+        }
+    }
+    
+    fn read_register(self: &Self, osvm: &OSVM, reg: usize) -> u64 {
+        match reg {
+            GDB_PC => osvm.get_pc() as u64,
+            GDB_TSR => osvm.tsr as u64,
+            _ => unsafe { osvm.registers()[reg].word.as_u64 },
+        }
+    }
+    
+    // Registers keep their type, gdb only changes the bits
+    fn write_register(self: &Self, osvm: &mut OSVM, reg: usize, value: u64) {
+        match reg {
+            GDB_PC => osvm.set_pc(value as usize),
+            GDB_TSR => osvm.tsr = value as usize,
+            _ => {
+                if let Some(reg) = osvm.register_by_index(reg) {
+                    reg.word = Word { as_u64: value };
+                }
+            }
+        }
+    }
+    
+    fn memory_range(self: &Self, osvm: &OSVM, addr: &str, len: &str) -> Option<(usize, usize)> {
+        let addr = usize::from_str_radix(addr, 16).ok()?;
+        let len = usize::from_str_radix(len, 16).ok()?;
+        if addr.checked_add(len)? > osvm.memory.len() {
+            return None;
+        }
+        
+        Some((addr, len))
+    }
+    
+    // Z0/z0 are software breakpoints on instruction indices, Z2-Z4 map to
+    // write, read and access watchpoints on OSVM::memory
+    fn breakpoint(self: &mut Self, osvm: &mut OSVM, args: &str, insert: bool) -> String {
+        let mut parts = args.split(',');
+        let kind = parts.next().unwrap_or("");
+        let addr = parts.next().and_then(|addr| usize::from_str_radix(addr, 16).ok());
+        let len = parts.next().and_then(|len| usize::from_str_radix(len, 16).ok()).unwrap_or(1);
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return "E01".to_string(),
+        };
+        
+        let access = match kind {
+            "0" => {
+                if insert {
+                    if !self.breakpoints.contains(&addr) {
+                        self.breakpoints.push(addr);
+                    }
+                } else {
+                    self.breakpoints.retain(|bp| *bp != addr);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchAccess::Write,
+            "3" => WatchAccess::Read,
+            "4" => WatchAccess::Access,
+            _ => return String::new(),
+        };
+        
+        let watchpoint = Watchpoint::Memory { start: addr, len: len.max(1), access };
+        if insert {
+            osvm.add_watchpoint(watchpoint);
+        } else if let Some(index) = osvm.watchpoints.iter().position(|w| *w == watchpoint) {
+            osvm.watchpoints.remove(index);
+        }
+        "OK".to_string()
+    }
+    
+    // Steps once, or runs until a breakpoint, watchpoint, halt, fault or ^C
+    fn resume(self: &Self, osvm: &mut OSVM, conn: &mut dyn Connection, single_step: bool) -> io::Result<Stop> {
+        let mut executed: usize = 0;
+        loop {
+            if osvm.is_halted() {
+                return Ok(Stop::Halted);
+            }
+            
+            if let Err(err) = osvm.execute_opcode() {
+                Log::report(&err);
+                return Ok(Stop::Fault(err.kind));
+            }
+            
+            if let Some(hit) = osvm.watch_hits.first() {
+                return Ok(Stop::Watchpoint(hit.clone()));
+            }
+            
+            if osvm.is_halted() {
+                return Ok(Stop::Halted);
+            }
+            
+            if single_step {
+                return Ok(Stop::Step);
+            }
+            
+            if self.breakpoints.contains(&osvm.get_pc()) {
+                return Ok(Stop::Breakpoint);
+            }
+            
+            executed += 1;
+            if executed.is_multiple_of(INTERRUPT_INTERVAL) && self.interrupted(conn)? {
+                return Ok(Stop::Interrupted);
+            }
+        }
+    }
+    
+    fn interrupted(self: &Self, conn: &mut dyn Connection) -> io::Result<bool> {
+        conn.set_nonblocking(true)?;
+        let mut byte = [0; 1];
+        let result = conn.read(&mut byte);
+        conn.set_nonblocking(false)?;
+        match result {
+            Ok(1) => Ok(byte[0] == 0x03),
+            Ok(_) => Ok(false),
+            Err(err) if err.kind() == IoErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+    
+    fn stop_reply(self: &Self, osvm: &OSVM, stop: Stop) -> String {
+        if osvm.is_halted() {
+            return "W00".to_string();
+        }
+        
+        match stop {
+            Stop::Step => format!("S{:02x}", SIGTRAP),
+            Stop::Breakpoint => format!("T{:02x}swbreak:;", SIGTRAP),
+            Stop::Watchpoint(hit) => {
+                let (kind, addr) = match osvm.watchpoints.get(hit.watchpoint) {
+                    Some(Watchpoint::Memory { start, access: WatchAccess::Read, .. }) => ("rwatch", *start),
+                    Some(Watchpoint::Memory { start, access: WatchAccess::Access, .. }) => ("awatch", *start),
+                    Some(Watchpoint::Memory { start, .. }) => ("watch", *start),
+                    _ => return format!("S{:02x}", SIGTRAP),
+                };
+                format!("T{:02x}{}:{:x};", SIGTRAP, kind, addr)
+            }
+            Stop::Interrupted => format!("S{:02x}", SIGINT),
+            Stop::Halted => "W00".to_string(),
+            Stop::Fault(kind) => {
+                let signal = match kind {
+                    ErrorKind::ErrIllegalMemoryAccess | ErrorKind::StackOverflow | ErrorKind::StackUnderflow => SIGSEGV,
+                    ErrorKind::DivByZero => SIGFPE,
+                    _ => SIGILL,
+                };
+                format!("S{:02x}", signal)
+            }
+        }
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n<feature name=\"org.osvm.core\">\n");
+    for (i, name) in REGISTERS.iter().enumerate() {
+        xml.push_str(&format!("<reg name=\"{}\" bitsize=\"64\" type=\"uint64\" regnum=\"{}\"/>\n", name, i));
+    }
+    
+    xml.push_str(&format!("<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\" regnum=\"{}\"/>\n", GDB_PC));
+    xml.push_str(&format!("<reg name=\"tsr\" bitsize=\"64\" type=\"uint64\" regnum=\"{}\"/>\n", GDB_TSR));
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    
+    use super::*;
+    use crate::testing::*;
+    
+    const PROGRAM: &str = "
+_start:
+    mov r0, #5
+    mov r1, #16
+    wrt #16, r1, r0
+    inc r0
+    hlt
+";
+    
+    // A minimal gdb: sends each packet, acks the reply and collects it
+    fn client(mut conn: UnixStream, packets: &[String]) -> Vec<String> {
+        let read_byte = |conn: &mut UnixStream| {
+            let mut byte = [0; 1];
+            conn.read_exact(&mut byte).unwrap();
+            byte[0]
+        };
+        
+        let mut replies = Vec::new();
+        for packet in packets {
+            let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            conn.write_all(format!("${}#{:02x}", packet, checksum).as_bytes()).unwrap();
+            assert_eq!(read_byte(&mut conn), b'+');
+            
+            assert_eq!(read_byte(&mut conn), b'$');
+            let mut reply = Vec::new();
+            loop {
+                match read_byte(&mut conn) {
+                    b'#' => break,
+                    byte => reply.push(byte),
+                }
+            }
+            
+            let checksum = [read_byte(&mut conn), read_byte(&mut conn)];
+            let expected = reply.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            assert_eq!(std::str::from_utf8(&checksum).unwrap(), format!("{:02x}", expected));
+            conn.write_all(b"+").unwrap();
+            replies.push(String::from_utf8(reply).unwrap());
+        }
+        
+        replies
+    }
+    
+    // Serves PROGRAM to a client sending `packets`, then detaches
+    fn session(source: &str, packets: &[&str]) -> (OSVM, Vec<String>) {
+        let (mut osvm, _) = machine(&assemble(source));
+        let (mut stub_end, client_end) = UnixStream::pair().unwrap();
+        let mut packets: Vec<String> = packets.iter().map(|packet| packet.to_string()).collect();
+        packets.push("D".to_string());
+        let client = thread::spawn(move || client(client_end, &packets));
+        
+        GdbStub::init().serve(&mut osvm, &mut stub_end).unwrap();
+        let mut replies = client.join().unwrap();
+        assert_eq!(replies.pop().as_deref(), Some("OK"));
+        (osvm, replies)
+    }
+    
+    fn register(value: u64) -> String {
+        encode_hex(&value.to_le_bytes())
+    }
+    
+    #[test]
+    fn answers_a_debugging_session() {
+        let (osvm, replies) = session(PROGRAM, &["?", "Z0,3", "c", "g", "m10,4", "s", "p0", "c"]);
+        assert_eq!(replies[0], "S05");
+        assert_eq!(replies[1], "OK");
+        assert_eq!(replies[2], "T05swbreak:;");
+        
+        let registers = &replies[3];
+        assert_eq!(registers.len(), GDB_REGISTERS * 16);
+        assert_eq!(registers[..16], register(5));
+        assert_eq!(registers[16..32], register(16));
+        assert_eq!(registers[GDB_PC * 16..GDB_TSR * 16], register(3));
+        
+        assert_eq!(replies[4], "05000000");
+        assert_eq!(replies[5], "S05");
+        assert_eq!(replies[6], register(6));
+        assert_eq!(replies[7], "W00");
+        assert!(osvm.is_halted());
+    }
+    
+    #[test]
+    fn writes_registers_and_memory() {
+        let (osvm, replies) = session(PROGRAM, &[
+            "Z0,2",
+            "c",
+            &format!("P1={}", register(32)),
+            "M20,2:abcd",
+            "m20,2",
+            "Z2,20,2",
+            "c",
+            "z2,20,2",
+            "m0,fffffffff",
+            "Z0,zz",
+        ]);
+        assert_eq!(replies[1], "T05swbreak:;");
+        assert_eq!(replies[2..6], ["OK", "OK", "abcd", "OK"]);
+        assert_eq!(replies[6], "T05watch:20;");
+        assert_eq!(replies[7], "OK");
+        assert!(osvm.watchpoints.is_empty());
+        assert_eq!(osvm.memory[32..34], [5, 0]);
+        assert_eq!(replies[8], "E01");
+        assert_eq!(replies[9], "E01");
+    }
+    
+    #[test]
+    fn reports_faults_as_signals() {
+        let (osvm, replies) = session("
+_start:
+    mov r0, #1
+    mov r1, #0
+    div r2, r0, r1
+    hlt
+", &["c"]);
+        assert_eq!(replies, ["S08"]);
+        assert_eq!(osvm.get_pc(), 2);
+    }
+    
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(decode_hex("00ff7f"), Some(vec![0, 255, 127]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(encode_hex(&[1, 171]), "01ab");
+    }
+}
\ No newline at end of file
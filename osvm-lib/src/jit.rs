@@ -0,0 +1,198 @@
+use std::mem;
+
+use crate::utils::buffer::SharedBuffer;
+use crate::osvm::OSVM;
+use crate::program::Program;
+use crate::utils::{defines::*, error::*};
+
+// The compiler emits x86-64 and maps its code with mmap, everywhere else
+// there is a stand-in that never compiles anything
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod x86_64;
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub use x86_64::Jit;
+
+// Instructions the interpreter runs before a program counts as long running
+// and gets compiled
+pub const JIT_THRESHOLD: u64 = 10_000;
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+pub struct Jit {
+    pub compiled: usize,
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+impl Jit {
+    pub fn is_supported() -> bool {
+        false
+    }
+    
+    pub fn compile(_program: &crate::bytecode::Bytecode) -> Result<Jit, Error> {
+        Err(Error::with_message(ErrorKind::JitUnavailable, "the JIT needs x86-64 Linux"))
+    }
+    
+    // Nothing is compiled, so the interpreter carries on where it was
+    pub fn run(self: &Self, _osvm: &mut OSVM) {}
+}
+
+// What a run printed and how it ended
+pub type RunOutcome = (Vec<u8>, Result<(), Error>);
+
+// Runs `osvm` on in the interpreter, with whatever tracing, profiling or
+// coverage it has on, and a copy of it compiled from the current pc, and
+// fails on the first difference in output, fault or final machine state.
+// `program` has to be the one `osvm` has loaded. Returns the output and
+// result of the interpreted run.
+pub fn check_jit(osvm: &mut OSVM, program: &Program) -> Result<RunOutcome, Error> {
+    let (mut compiled, jit_output) = copy_machine(osvm, program)?;
+    let jit_result = compiled.execute_program_jit(0);
+    let jit_output = jit_output.0.borrow().clone();
+    
+    let output = SharedBuffer::default();
+    let stdout = mem::replace(&mut osvm.output, Box::new(output.clone()));
+    let result = osvm.execute_program();
+    osvm.output = stdout;
+    let output = output.0.borrow().clone();
+    
+    let interpreted = &*osvm;
+    let mismatch = |what: String| Err(Error::with_message(ErrorKind::JitMismatch, what));
+    
+    if output != jit_output {
+        return mismatch("the output differs".to_string());
+    }
+    
+    let (ended, jit_ended) = (result.as_ref().err().map(|err| err.to_string()), jit_result.as_ref().err().map(|err| err.to_string()));
+    if ended != jit_ended {
+        return mismatch(format!("interpreted run ended with {:?}, compiled run with {:?}", ended, jit_ended));
+    }
+    
+    let registers = interpreted.registers().into_iter().zip(compiled.registers());
+    for (index, (left, right)) in registers.enumerate() {
+        if left != right {
+            return mismatch(format!("`{}` differs: interpreted {:?}, compiled {:?}", REGISTERS[index], left, right));
+        }
+    }
+    
+    if interpreted.get_pc() != compiled.get_pc() || interpreted.tsr != compiled.tsr || interpreted.is_halted() != compiled.is_halted() {
+        return mismatch(format!("pc, tsr or halt differ: interpreted {} {} {}, compiled {} {} {}",
+            interpreted.get_pc(), interpreted.tsr, interpreted.is_halted(), compiled.get_pc(), compiled.tsr, compiled.is_halted()));
+    }
+    
+    if interpreted.stack != compiled.stack {
+        return mismatch(format!("the stack differs: interpreted {:?}, compiled {:?}", interpreted.stack, compiled.stack));
+    }
+    
+    if interpreted.call_stack != compiled.call_stack {
+        return mismatch(format!("the call stack differs: interpreted {:?}, compiled {:?}", interpreted.call_stack, compiled.call_stack));
+    }
+    
+    if let Some(addr) = interpreted.memory.iter().zip(&compiled.memory).position(|(left, right)| left != right) {
+        return mismatch(format!("memory differs at {}: interpreted {}, compiled {}", addr, interpreted.memory[addr], compiled.memory[addr]));
+    }
+    
+    Ok((output, result))
+}
+
+// A machine in the same state as `osvm` that prints into a buffer
+fn copy_machine(osvm: &OSVM, program: &Program) -> Result<(OSVM, SharedBuffer), Error> {
+    let output = SharedBuffer::default();
+    let mut copy = OSVM::init();
+    copy.init_default_sysf();
+    copy.output = Box::new(output.clone());
+    copy.set_call_stack_depth(osvm.call_stack_depth);
+    copy.load_program(program)?;
+    copy.restore(&osvm.snapshot(""))?;
+    Ok((copy, output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    
+    const FAULTS: [&str; 4] = [
+        "\n_start:\n    mov r0, #1\n    mov r1, #0\n    div r2, r0, r1\n    hlt\n",
+        "\n_start:\n    mov r0, #640000\n    rd #64, r1, r0\n    hlt\n",
+        "\n_start:\n    push #1\n    adds\n    hlt\n",
+        "\n_start:\n    ret\n",
+    ];
+    
+    fn check(program: &Program) -> RunOutcome {
+        let (mut osvm, _) = machine(program);
+        match check_jit(&mut osvm, program) {
+            Ok(outcome) => outcome,
+            Err(err) => panic!("{}", err),
+        }
+    }
+    
+    #[test]
+    fn examples_match_compiled() {
+        for (name, program) in examples() {
+            let (output, result) = check(&program);
+            let (_, expected, expected_result) = run(&program);
+            assert_eq!(String::from_utf8_lossy(&output), expected, "{}", name);
+            assert_eq!(result.is_ok(), expected_result.is_ok(), "{}", name);
+        }
+    }
+    
+    #[test]
+    fn faults_match_compiled() {
+        for source in FAULTS {
+            let (_, result) = check(&assemble(source));
+            assert!(result.is_err(), "{}", source);
+        }
+    }
+    
+    #[test]
+    fn checks_from_where_the_machine_is() {
+        let program = assemble("
+_start:
+    mov r0, #100
+    mov r1, #0
+loop:
+    add r1, r1, r0
+    dec r0
+    jnz loop, r0
+    push r1
+    hlt
+");
+        let (mut osvm, _) = machine(&program);
+        for _ in 0..20 {
+            osvm.execute_opcode().unwrap();
+        }
+        
+        assert!(check_jit(&mut osvm, &program).unwrap().1.is_ok());
+        assert_eq!(unsafe { osvm.stack[0].word.as_u64 }, 5050);
+    }
+    
+    #[test]
+    fn interpreted_run_keeps_its_instrumentation() {
+        let program = assemble(FAULTS[0]);
+        let (mut osvm, _) = machine(&program);
+        let trace = SharedBuffer::default();
+        osvm.start_tracing(Box::new(trace.clone()));
+        osvm.start_coverage();
+        
+        assert!(check_jit(&mut osvm, &program).unwrap().1.is_err());
+        osvm.stop_tracing().unwrap();
+        assert!(!trace.0.borrow().is_empty());
+        assert!(osvm.stop_coverage().is_some());
+    }
+    
+    #[test]
+    fn compiles_only_where_supported() {
+        let program = assemble(FAULTS[2]);
+        let code = crate::bytecode::Bytecode::encode(&program.code).unwrap();
+        match Jit::compile(&code) {
+            Ok(jit) => {
+                assert!(Jit::is_supported());
+                assert!(jit.compiled > 0);
+            }
+            Err(err) => {
+                assert!(!Jit::is_supported());
+                assert!(err.to_string().contains("JitUnavailable"));
+            }
+        }
+    }
+}
\ No newline at end of file
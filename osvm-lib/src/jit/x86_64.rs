@@ -0,0 +1,921 @@
+use std::collections::BTreeMap;
+use std::mem::offset_of;
+
+use crate::bytecode::Bytecode;
+use crate::opcode::*;
+use crate::osvm::{Frame, OSVM};
+use crate::utils::{arith::*, defines::*, error::*};
+
+// Free stack and call stack slots made sure of before entering compiled
+// code, running out of them hands back to the interpreter
+const STACK_RESERVE: usize = 1024;
+const CALL_RESERVE: usize = 256;
+
+// Compiled code only keeps values in the register file for as long as an
+// instruction runs, between instructions everything lives in OSVM itself:
+//     rbx  JitContext
+//     r12  OSVM::regs
+//     r13  bottom of OSVM::stack
+//     r14  top of OSVM::stack, one past the last value
+//     r15  end of the stack's capacity
+//     rbp  top of OSVM::call_stack
+// rax, rcx and rdx are scratch.
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+const RBX: u8 = 3;
+const RBP: u8 = 5;
+const R12: u8 = 12;
+const R13: u8 = 13;
+const R14: u8 = 14;
+const R15: u8 = 15;
+
+const CTX: u8 = RBX;
+const REGS: u8 = R12;
+const STACK_BASE: u8 = R13;
+const STACK_TOP: u8 = R14;
+const STACK_END: u8 = R15;
+const CALL_TOP: u8 = RBP;
+
+// Condition codes
+const CC_B: u8 = 0x2;
+const CC_AE: u8 = 0x3;
+const CC_E: u8 = 0x4;
+const CC_NE: u8 = 0x5;
+const CC_BE: u8 = 0x6;
+const CC_A: u8 = 0x7;
+const CC_L: u8 = 0xc;
+const CC_GE: u8 = 0xd;
+const CC_LE: u8 = 0xe;
+const CC_G: u8 = 0xf;
+
+const WORD_SIZE: i32 = size_of::<TypedWord>() as i32;
+const FRAME_SIZE: i32 = size_of::<Frame>() as i32;
+const TY: i32 = offset_of!(TypedWord, ty) as i32;
+
+// What compiled code reads the machine state from and hands it back in
+#[repr(C)]
+struct JitContext {
+    regs: *mut TypedWord,
+    stack_base: *mut TypedWord,
+    stack_top: *mut TypedWord,
+    stack_end: *mut TypedWord,
+    call_base: *mut Frame,
+    call_top: *mut Frame,
+    call_end: *mut Frame,
+    memory: *mut u8,
+    memory_len: usize,
+    table: *const usize,
+    pc: usize,
+    tsr: usize,
+}
+
+const CTX_CALL_BASE: i32 = offset_of!(JitContext, call_base) as i32;
+const CTX_CALL_END: i32 = offset_of!(JitContext, call_end) as i32;
+const CTX_MEMORY: i32 = offset_of!(JitContext, memory) as i32;
+const CTX_MEMORY_LEN: i32 = offset_of!(JitContext, memory_len) as i32;
+const CTX_TABLE: i32 = offset_of!(JitContext, table) as i32;
+const CTX_PC: i32 = offset_of!(JitContext, pc) as i32;
+const CTX_TSR: i32 = offset_of!(JitContext, tsr) as i32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Target {
+    // The compiled instruction at an address
+    Pc(usize),
+    // Back to the interpreter, which carries on at the address
+    Exit(usize),
+    // Back to the interpreter at the address in rax
+    DynamicExit,
+    Epilogue,
+}
+
+// Just enough of an x86-64 assembler for what the compiler emits
+struct Emitter {
+    code: Vec<u8>,
+    // Where a rel32 needs to be patched in and what it jumps to
+    fixups: Vec<(usize, Target)>,
+}
+
+impl Emitter {
+    fn init() -> Emitter {
+        Emitter {
+            code: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+    
+    fn byte(self: &mut Self, byte: u8) {
+        self.code.push(byte);
+    }
+    
+    fn bytes(self: &mut Self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+    
+    fn i32(self: &mut Self, value: i32) {
+        self.bytes(&value.to_le_bytes());
+    }
+    
+    fn rex(self: &mut Self, w: bool, reg: u8, rm: u8) {
+        let rex = 0x40 | (w as u8) << 3 | (reg >> 3 & 1) << 2 | (rm >> 3 & 1);
+        if rex != 0x40 {
+            self.byte(rex);
+        }
+    }
+    
+    // `opcode` with a [base + disp32] operand, `reg` goes into ModRM.reg
+    fn op_mem(self: &mut Self, w: bool, opcode: &[u8], reg: u8, base: u8, disp: i32) {
+        self.rex(w, reg, base);
+        self.bytes(opcode);
+        self.byte(0x80 | (reg & 7) << 3 | (base & 7));
+        if base & 7 == 4 {
+            self.byte(0x24);
+        }
+        self.i32(disp);
+    }
+    
+    // `opcode` with a register operand in ModRM.rm
+    fn op_reg(self: &mut Self, w: bool, opcode: &[u8], reg: u8, rm: u8) {
+        self.rex(w, reg, rm);
+        self.bytes(opcode);
+        self.byte(0xc0 | (reg & 7) << 3 | (rm & 7));
+    }
+    
+    fn load(self: &mut Self, dst: u8, base: u8, disp: i32) {
+        self.op_mem(true, &[0x8b], dst, base, disp);
+    }
+    
+    fn store(self: &mut Self, base: u8, disp: i32, src: u8) {
+        self.op_mem(true, &[0x89], src, base, disp);
+    }
+    
+    // Zero extending loads and truncating stores of 1, 2, 4 or 8 bytes
+    fn load_sized(self: &mut Self, bytes: usize, dst: u8, base: u8, disp: i32) {
+        match bytes {
+            1 => self.op_mem(false, &[0x0f, 0xb6], dst, base, disp),
+            2 => self.op_mem(false, &[0x0f, 0xb7], dst, base, disp),
+            4 => self.op_mem(false, &[0x8b], dst, base, disp),
+            _ => self.load(dst, base, disp),
+        }
+    }
+    
+    fn store_sized(self: &mut Self, bytes: usize, base: u8, disp: i32, src: u8) {
+        match bytes {
+            1 => self.op_mem(false, &[0x88], src, base, disp),
+            2 => {
+                self.byte(0x66);
+                self.op_mem(false, &[0x89], src, base, disp);
+            }
+            4 => self.op_mem(false, &[0x89], src, base, disp),
+            _ => self.store(base, disp, src),
+        }
+    }
+    
+    fn store_imm8(self: &mut Self, base: u8, disp: i32, value: u8) {
+        self.op_mem(false, &[0xc6], 0, base, disp);
+        self.byte(value);
+    }
+    
+    fn store_imm(self: &mut Self, base: u8, disp: i32, value: u64) {
+        match i32::try_from(value) {
+            Ok(value) => {
+                self.op_mem(true, &[0xc7], 0, base, disp);
+                self.i32(value);
+            }
+            Err(_) => {
+                self.mov_imm(RAX, value);
+                self.store(base, disp, RAX);
+            }
+        }
+    }
+    
+    fn mov_imm(self: &mut Self, dst: u8, value: u64) {
+        match u32::try_from(value) {
+            Ok(value) => {
+                self.rex(false, 0, dst);
+                self.byte(0xb8 + (dst & 7));
+                self.bytes(&value.to_le_bytes());
+            }
+            Err(_) => {
+                self.rex(true, 0, dst);
+                self.byte(0xb8 + (dst & 7));
+                self.bytes(&value.to_le_bytes());
+            }
+        }
+    }
+    
+    fn lea(self: &mut Self, dst: u8, base: u8, disp: i32) {
+        self.op_mem(true, &[0x8d], dst, base, disp);
+    }
+    
+    fn add(self: &mut Self, dst: u8, src: u8) {
+        self.op_reg(true, &[0x01], src, dst);
+    }
+    
+    fn sub(self: &mut Self, dst: u8, src: u8) {
+        self.op_reg(true, &[0x29], src, dst);
+    }
+    
+    fn imul(self: &mut Self, dst: u8, src: u8) {
+        self.op_reg(true, &[0x0f, 0xaf], dst, src);
+    }
+    
+    fn cmp(self: &mut Self, a: u8, b: u8) {
+        self.op_reg(true, &[0x39], b, a);
+    }
+    
+    fn add_imm(self: &mut Self, dst: u8, value: i32) {
+        self.op_reg(true, &[0x81], 0, dst);
+        self.i32(value);
+    }
+    
+    fn sub_imm(self: &mut Self, dst: u8, value: i32) {
+        self.op_reg(true, &[0x81], 5, dst);
+        self.i32(value);
+    }
+    
+    fn cmp_imm(self: &mut Self, dst: u8, value: i32) {
+        self.op_reg(true, &[0x81], 7, dst);
+        self.i32(value);
+    }
+    
+    fn cmp_mem(self: &mut Self, reg: u8, base: u8, disp: i32) {
+        self.op_mem(true, &[0x3b], reg, base, disp);
+    }
+    
+    fn cmp_mem_imm8(self: &mut Self, base: u8, disp: i32, value: u8) {
+        self.op_mem(false, &[0x80], 7, base, disp);
+        self.byte(value);
+    }
+    
+    // dst = cc ? 1 : 0, dst has to be rax, rcx or rdx
+    fn setcc(self: &mut Self, cc: u8, dst: u8) {
+        self.bytes(&[0x0f, 0x90 + cc, 0xc0 | dst]);
+        self.op_reg(false, &[0x0f, 0xb6], dst, dst);
+    }
+    
+    fn cmove(self: &mut Self, dst: u8, src: u8) {
+        self.op_reg(false, &[0x0f, 0x44], dst, src);
+    }
+    
+    fn jcc(self: &mut Self, cc: u8, target: Target) {
+        self.bytes(&[0x0f, 0x80 + cc]);
+        self.fixups.push((self.code.len(), target));
+        self.i32(0);
+    }
+    
+    fn jmp(self: &mut Self, target: Target) {
+        self.byte(0xe9);
+        self.fixups.push((self.code.len(), target));
+        self.i32(0);
+    }
+    
+    fn push(self: &mut Self, reg: u8) {
+        self.rex(false, 0, reg);
+        self.byte(0x50 + (reg & 7));
+    }
+    
+    fn pop(self: &mut Self, reg: u8) {
+        self.rex(false, 0, reg);
+        self.byte(0x58 + (reg & 7));
+    }
+}
+
+// Where the operands of an instruction live, relative to a base register
+#[derive(Clone, Copy)]
+struct Slot {
+    base: u8,
+    disp: i32,
+}
+
+impl Slot {
+    fn reg(index: usize) -> Slot {
+        Slot { base: REGS, disp: index as i32 * WORD_SIZE }
+    }
+    
+    // n-th value from the top of the stack, 0 is the top
+    fn stack(n: usize) -> Slot {
+        Slot { base: STACK_TOP, disp: -(n as i32 + 1) * WORD_SIZE }
+    }
+    
+    // The free slot above the top of the stack
+    fn push() -> Slot {
+        Slot { base: STACK_TOP, disp: 0 }
+    }
+}
+
+// Stack indices past this are left to the interpreter to fault on
+const MAX_STACK_INDEX: u64 = 1 << 20;
+
+struct Compiler {
+    emitter: Emitter,
+    len: usize,
+}
+
+impl Compiler {
+    fn target(self: &Self, addr: usize) -> Target {
+        if addr < self.len { Target::Pc(addr) } else { Target::Exit(addr) }
+    }
+    
+    fn copy(self: &mut Self, from: Slot, to: Slot) {
+        let e = &mut self.emitter;
+        e.load(RAX, from.base, from.disp);
+        e.load(RCX, from.base, from.disp + TY);
+        e.store(to.base, to.disp, RAX);
+        e.store(to.base, to.disp + TY, RCX);
+    }
+    
+    fn store_word(self: &mut Self, to: Slot, value: TypedWord) {
+        let e = &mut self.emitter;
+        e.store_imm(to.base, to.disp, unsafe { value.word.as_u64 });
+        e.store_imm8(to.base, to.disp + TY, value.ty as u8);
+    }
+    
+    // Stores rax tagged with `ty`
+    fn store_rax(self: &mut Self, to: Slot, ty: WordType) {
+        let e = &mut self.emitter;
+        e.store(to.base, to.disp, RAX);
+        e.store_imm8(to.base, to.disp + TY, ty as u8);
+    }
+    
+    fn tsr_imm(self: &mut Self, ty: WordType) {
+        self.emitter.store_imm(CTX, CTX_TSR, ty as u64);
+    }
+    
+    // tsr = type of the value in `slot`, uses rdx
+    fn tsr_of(self: &mut Self, slot: Slot) {
+        let e = &mut self.emitter;
+        e.load_sized(1, RDX, slot.base, slot.disp + TY);
+        e.store(CTX, CTX_TSR, RDX);
+    }
+    
+    // Values the compiled code can't do arithmetic on go to the interpreter,
+    // the integer types share their bits and only floats need converting
+    fn guard_int(self: &mut Self, pc: usize, slot: Slot) {
+        self.emitter.cmp_mem_imm8(slot.base, slot.disp + TY, WordType::F64 as u8);
+        self.emitter.jcc(CC_E, Target::Exit(pc));
+    }
+    
+    // At least `n` values on the stack
+    fn guard_stack(self: &mut Self, pc: usize, n: usize) {
+        self.emitter.lea(RAX, STACK_BASE, n as i32 * WORD_SIZE);
+        self.emitter.cmp(STACK_TOP, RAX);
+        self.emitter.jcc(CC_B, Target::Exit(pc));
+    }
+    
+    // Room for `n` more values
+    fn guard_room(self: &mut Self, pc: usize, n: usize) {
+        self.emitter.lea(RAX, STACK_TOP, n as i32 * WORD_SIZE);
+        self.emitter.cmp(RAX, STACK_END);
+        self.emitter.jcc(CC_A, Target::Exit(pc));
+    }
+    
+    // Leaves the address in rax and jumps out unless `bytes` fit at it,
+    // uses rcx
+    fn guard_memory(self: &mut Self, pc: usize, addr: Slot, bytes: usize) {
+        let e = &mut self.emitter;
+        e.load(RCX, CTX, CTX_MEMORY_LEN);
+        e.sub_imm(RCX, bytes as i32);
+        e.jcc(CC_B, Target::Exit(pc));
+        e.load(RAX, addr.base, addr.disp);
+        e.cmp(RAX, RCX);
+        e.jcc(CC_A, Target::Exit(pc));
+        e.load(RCX, CTX, CTX_MEMORY);
+        e.add(RAX, RCX);
+    }
+    
+    fn pop_stack(self: &mut Self, n: usize) {
+        self.emitter.sub_imm(STACK_TOP, n as i32 * WORD_SIZE);
+    }
+    
+    fn push_stack(self: &mut Self) {
+        self.emitter.add_imm(STACK_TOP, WORD_SIZE);
+    }
+    
+    // rax = rax op rcx, None if it has to be left to the interpreter
+    fn arith(self: &mut Self, op: ArithOp) -> Option<()> {
+        match op {
+            ArithOp::Add => self.emitter.add(RAX, RCX),
+            ArithOp::Sub => self.emitter.sub(RAX, RCX),
+            ArithOp::Mul => self.emitter.imul(RAX, RCX),
+            ArithOp::Div | ArithOp::Mod => return None,
+        }
+        Some(())
+    }
+    
+    // rax = rax cmp rcx as 0 or 1, signed if `signed` is set
+    fn compare(self: &mut Self, op: CmpOp, signed: bool) {
+        let cc = match (op, signed) {
+            (CmpOp::Eq, _) => CC_E,
+            (CmpOp::Ne, _) => CC_NE,
+            (CmpOp::Lt, false) => CC_B,
+            (CmpOp::Le, false) => CC_BE,
+            (CmpOp::Gt, false) => CC_A,
+            (CmpOp::Ge, false) => CC_AE,
+            (CmpOp::Lt, true) => CC_L,
+            (CmpOp::Le, true) => CC_LE,
+            (CmpOp::Gt, true) => CC_G,
+            (CmpOp::Ge, true) => CC_GE,
+        };
+        self.emitter.cmp(RAX, RCX);
+        self.emitter.setcc(cc, RAX);
+    }
+    
+    // Like `compare` but signed only if the left operand is an i64, the way
+    // compare_tagged picks its type
+    fn compare_tagged(self: &mut Self, op: CmpOp, left: Slot) {
+        let e = &mut self.emitter;
+        e.cmp(RAX, RCX);
+        match op {
+            CmpOp::Eq | CmpOp::Ne => {
+                e.setcc(if op == CmpOp::Eq { CC_E } else { CC_NE }, RAX);
+            }
+            _ => {
+                let (unsigned, signed) = match op {
+                    CmpOp::Lt => (CC_B, CC_L),
+                    CmpOp::Le => (CC_BE, CC_LE),
+                    CmpOp::Gt => (CC_A, CC_G),
+                    _ => (CC_AE, CC_GE),
+                };
+                e.setcc(unsigned, RAX);
+                e.setcc(signed, RCX);
+                e.cmp_mem_imm8(left.base, left.disp + TY, WordType::I64 as u8);
+                e.cmove(RAX, RCX);
+            }
+        }
+    }
+    
+    fn exit(self: &mut Self, pc: usize) {
+        self.emitter.jmp(Target::Exit(pc));
+    }
+    
+    // Emits the instruction at `pc`, false if it is left to the interpreter.
+    // Everything that faults is checked before anything is changed, so the
+    // interpreter can run it again and fault the same way.
+    fn instruction(self: &mut Self, pc: usize, opcode: &Opcode) -> bool {
+        if self.try_instruction(pc, opcode).is_none() {
+            self.exit(pc);
+            return false;
+        }
+        
+        true
+    }
+    
+    fn try_instruction(self: &mut Self, pc: usize, opcode: &Opcode) -> Option<()> {
+        let regs = opcode.op_regs.len();
+        let reg = |i: usize| Slot::reg(opcode.op_regs.get(i).unwrap());
+        let arg = opcode.op_operand.map(|operand| unsafe { operand.word.as_u64 });
+        
+        // Anything the compiler can't be sure about is checked first and
+        // the instruction is handed to the interpreter as a whole
+        let pos = self.emitter.code.len();
+        let result = match (opcode.op_type, regs, arg) {
+            (OpcodeType::Mov, 2, None) => {
+                self.tsr_of(reg(1));
+                self.copy(reg(1), reg(0));
+                Some(())
+            }
+            (OpcodeType::Mov, 1, Some(_)) => {
+                let value = opcode.op_operand?;
+                self.tsr_imm(value.ty);
+                self.store_word(reg(0), value);
+                Some(())
+            }
+            (OpcodeType::Movfs, 1, Some(index)) if index < MAX_STACK_INDEX => {
+                let index = index as usize;
+                self.guard_stack(pc, index + 1);
+                self.tsr_of(Slot::stack(index));
+                self.copy(Slot::stack(index), reg(0));
+                Some(())
+            }
+            
+            (OpcodeType::Add | OpcodeType::Sub | OpcodeType::Mul, 3, _) => {
+                self.guard_int(pc, reg(1));
+                self.guard_int(pc, reg(2));
+                self.emitter.load(RAX, reg(1).base, reg(1).disp);
+                self.emitter.load(RCX, reg(2).base, reg(2).disp);
+                self.arith(untyped_arith(opcode.op_type)?)?;
+                self.tsr_of(reg(1));
+                self.emitter.store(reg(0).base, reg(0).disp, RAX);
+                self.emitter.store_sized(1, reg(0).base, reg(0).disp + TY, RDX);
+                Some(())
+            }
+            (OpcodeType::Inc | OpcodeType::Dec, 1, _) => {
+                self.guard_int(pc, reg(0));
+                self.tsr_of(reg(0));
+                self.emitter.load(RAX, reg(0).base, reg(0).disp);
+                if opcode.op_type == OpcodeType::Inc {
+                    self.emitter.add_imm(RAX, 1);
+                } else {
+                    self.emitter.sub_imm(RAX, 1);
+                }
+                self.emitter.store(reg(0).base, reg(0).disp, RAX);
+                Some(())
+            }
+            (OpcodeType::Equal | OpcodeType::Ne | OpcodeType::Lt | OpcodeType::Le | OpcodeType::Gt | OpcodeType::Ge, 3, _) => {
+                self.guard_int(pc, reg(1));
+                self.guard_int(pc, reg(2));
+                self.emitter.load(RAX, reg(1).base, reg(1).disp);
+                self.emitter.load(RCX, reg(2).base, reg(2).disp);
+                self.compare_tagged(compare_op(opcode.op_type)?, reg(1));
+                self.tsr_of(reg(1));
+                self.store_rax(reg(0), WordType::U64);
+                Some(())
+            }
+            
+            (OpcodeType::Addi | OpcodeType::Addu | OpcodeType::Subi | OpcodeType::Subu | OpcodeType::Muli | OpcodeType::Mulu, 3, _) => {
+                let (op, ty) = typed_arith(opcode.op_type)?;
+                self.emitter.load(RAX, reg(1).base, reg(1).disp);
+                self.emitter.load(RCX, reg(2).base, reg(2).disp);
+                self.arith(op)?;
+                self.tsr_imm(ty);
+                self.store_rax(reg(0), ty);
+                Some(())
+            }
+            (OpcodeType::Lti | OpcodeType::Ltu | OpcodeType::Lei | OpcodeType::Leu |
+             OpcodeType::Gti | OpcodeType::Gtu | OpcodeType::Gei | OpcodeType::Geu, 3, _) => {
+                let (op, ty) = typed_compare(opcode.op_type)?;
+                self.emitter.load(RAX, reg(1).base, reg(1).disp);
+                self.emitter.load(RCX, reg(2).base, reg(2).disp);
+                self.compare(op, ty == WordType::I64);
+                self.tsr_imm(ty);
+                self.store_rax(reg(0), WordType::U64);
+                Some(())
+            }
+            
+            (OpcodeType::Jt | OpcodeType::Jz | OpcodeType::Jnz, 1, Some(target)) => {
+                self.emitter.load(RAX, reg(0).base, reg(0).disp);
+                self.branch(opcode.op_type, target as usize);
+                Some(())
+            }
+            (OpcodeType::Jts | OpcodeType::Jzs | OpcodeType::Jnzs, _, Some(target)) => {
+                self.guard_stack(pc, 1);
+                self.emitter.load(RAX, Slot::stack(0).base, Slot::stack(0).disp);
+                self.pop_stack(1);
+                self.branch(opcode.op_type, target as usize);
+                Some(())
+            }
+            (OpcodeType::Jmp, _, Some(target)) => {
+                let target = self.target(target as usize);
+                self.emitter.jmp(target);
+                Some(())
+            }
+            (OpcodeType::Call, _, Some(target)) => {
+                let e = &mut self.emitter;
+                e.lea(RAX, CALL_TOP, FRAME_SIZE);
+                e.cmp_mem(RAX, CTX, CTX_CALL_END);
+                e.jcc(CC_A, Target::Exit(pc));
+                e.store_imm(CALL_TOP, offset_of!(Frame, call_addr) as i32, pc as u64);
+                e.store_imm(CALL_TOP, offset_of!(Frame, target_addr) as i32, target);
+                e.store_imm(CALL_TOP, offset_of!(Frame, return_addr) as i32, pc as u64 + 1);
+                e.add_imm(CALL_TOP, FRAME_SIZE);
+                let target = self.target(target as usize);
+                self.emitter.jmp(target);
+                Some(())
+            }
+            (OpcodeType::Ret, _, _) => {
+                let e = &mut self.emitter;
+                e.cmp_mem(CALL_TOP, CTX, CTX_CALL_BASE);
+                e.jcc(CC_BE, Target::Exit(pc));
+                e.sub_imm(CALL_TOP, FRAME_SIZE);
+                e.load(RAX, CALL_TOP, offset_of!(Frame, return_addr) as i32);
+                self.jump_rax();
+                Some(())
+            }
+            
+            (OpcodeType::Push, _, Some(_)) => {
+                let value = opcode.op_operand?;
+                self.guard_room(pc, 1);
+                self.store_word(Slot::push(), value);
+                self.push_stack();
+                Some(())
+            }
+            (OpcodeType::Push, 1, None) => {
+                self.guard_room(pc, 1);
+                self.copy(reg(0), Slot::push());
+                self.push_stack();
+                Some(())
+            }
+            (OpcodeType::Pop, 0, _) => {
+                self.guard_stack(pc, 1);
+                self.pop_stack(1);
+                Some(())
+            }
+            (OpcodeType::Pop, 1, _) => {
+                self.tsr_imm(WordType::U64);
+                self.store_word(reg(0), TypedWord::u64(0));
+                Some(())
+            }
+            (OpcodeType::Dupl, _, Some(index)) if index < MAX_STACK_INDEX => {
+                let index = index as usize;
+                self.guard_stack(pc, index + 1);
+                self.guard_room(pc, 1);
+                self.copy(Slot::stack(index), Slot::push());
+                self.push_stack();
+                Some(())
+            }
+            (OpcodeType::Swc, _, Some(index)) if index < MAX_STACK_INDEX => {
+                let index = index as usize;
+                self.guard_stack(pc, (index + 1).max(2));
+                let (a, b) = (Slot::stack(0), Slot::stack(index));
+                let e = &mut self.emitter;
+                for disp in [0, TY] {
+                    e.load(RAX, a.base, a.disp + disp);
+                    e.load(RCX, b.base, b.disp + disp);
+                    e.store(a.base, a.disp + disp, RCX);
+                    e.store(b.base, b.disp + disp, RAX);
+                }
+                Some(())
+            }
+            
+            (OpcodeType::Adds | OpcodeType::Subs | OpcodeType::Muls, _, _) => {
+                let (a, b) = (Slot::stack(0), Slot::stack(1));
+                self.guard_stack(pc, 2);
+                self.guard_int(pc, a);
+                self.guard_int(pc, b);
+                self.emitter.load(RAX, b.base, b.disp);
+                self.emitter.load(RCX, a.base, a.disp);
+                self.arith(untyped_arith(opcode.op_type)?)?;
+                self.tsr_of(b);
+                self.emitter.store(b.base, b.disp, RAX);
+                self.pop_stack(1);
+                Some(())
+            }
+            (OpcodeType::Equals | OpcodeType::Nes | OpcodeType::Lts | OpcodeType::Les | OpcodeType::Gts | OpcodeType::Ges, _, _) => {
+                let (a, b) = (Slot::stack(0), Slot::stack(1));
+                self.guard_stack(pc, 2);
+                self.guard_room(pc, 1);
+                self.guard_int(pc, a);
+                self.guard_int(pc, b);
+                self.emitter.load(RAX, b.base, b.disp);
+                self.emitter.load(RCX, a.base, a.disp);
+                self.compare_tagged(compare_op(opcode.op_type)?, b);
+                self.tsr_of(b);
+                self.store_rax(Slot::push(), WordType::U64);
+                self.push_stack();
+                Some(())
+            }
+            (OpcodeType::Addis | OpcodeType::Addus | OpcodeType::Subis | OpcodeType::Subus | OpcodeType::Mulis | OpcodeType::Mulus, _, _) => {
+                let (op, ty) = typed_arith(opcode.op_type)?;
+                let (a, b) = (Slot::stack(0), Slot::stack(1));
+                self.guard_stack(pc, 2);
+                self.emitter.load(RAX, b.base, b.disp);
+                self.emitter.load(RCX, a.base, a.disp);
+                self.arith(op)?;
+                self.tsr_imm(ty);
+                self.store_rax(b, ty);
+                self.pop_stack(1);
+                Some(())
+            }
+            (OpcodeType::Ltis | OpcodeType::Ltus | OpcodeType::Leis | OpcodeType::Leus |
+             OpcodeType::Gtis | OpcodeType::Gtus | OpcodeType::Geis | OpcodeType::Geus, _, _) => {
+                let (op, ty) = typed_compare(opcode.op_type)?;
+                let (a, b) = (Slot::stack(0), Slot::stack(1));
+                self.guard_stack(pc, 2);
+                self.guard_room(pc, 1);
+                self.emitter.load(RAX, b.base, b.disp);
+                self.emitter.load(RCX, a.base, a.disp);
+                self.compare(op, ty == WordType::I64);
+                self.tsr_imm(ty);
+                self.store_rax(Slot::push(), WordType::U64);
+                self.push_stack();
+                Some(())
+            }
+            
+            (OpcodeType::Read, 0 | 2, Some(size @ (8 | 16 | 32 | 64))) => {
+                let bytes = size as usize / 8;
+                let addr = if regs == 0 { Slot::stack(0) } else { reg(1) };
+                if regs == 0 {
+                    self.guard_stack(pc, 1);
+                }
+                self.guard_memory(pc, addr, bytes);
+                self.tsr_of(addr);
+                self.emitter.load_sized(bytes, RAX, RAX, 0);
+                self.store_rax(if regs == 0 { Slot::stack(0) } else { reg(0) }, WordType::U64);
+                Some(())
+            }
+            (OpcodeType::Write, 0 | 2, Some(size @ (8 | 16 | 32 | 64))) => {
+                let bytes = size as usize / 8;
+                let (addr, value) = if regs == 0 { (Slot::stack(0), Slot::stack(1)) } else { (reg(0), reg(1)) };
+                if regs == 0 {
+                    self.guard_stack(pc, 2);
+                }
+                self.guard_memory(pc, addr, bytes);
+                self.tsr_of(value);
+                self.emitter.load(RCX, value.base, value.disp);
+                self.emitter.store_sized(bytes, RAX, 0, RCX);
+                if regs == 0 {
+                    self.pop_stack(2);
+                }
+                Some(())
+            }
+            
+            // sysf, hlt, floats, division and the rest run in the interpreter
+            _ => None,
+        };
+        
+        if result.is_none() {
+            // Drop whatever was emitted before giving up
+            self.emitter.code.truncate(pos);
+            self.emitter.fixups.retain(|(at, _)| *at < pos);
+        }
+        result
+    }
+    
+    // Conditional jump on the word in rax
+    fn branch(self: &mut Self, op_type: OpcodeType, target: usize) {
+        let (value, cc) = match op_type {
+            OpcodeType::Jt | OpcodeType::Jts => (1, CC_E),
+            OpcodeType::Jz | OpcodeType::Jzs => (0, CC_E),
+            _ => (0, CC_NE),
+        };
+        let target = self.target(target);
+        self.emitter.cmp_imm(RAX, value);
+        self.emitter.jcc(cc, target);
+    }
+    
+    // Jumps to the address in rax through the table, or out of the compiled
+    // code if it's past the end
+    fn jump_rax(self: &mut Self) {
+        let e = &mut self.emitter;
+        e.cmp_imm(RAX, self.len as i32);
+        e.jcc(CC_AE, Target::DynamicExit);
+        e.load(RCX, CTX, CTX_TABLE);
+        // jmp [rcx + rax * 8]
+        e.bytes(&[0xff, 0x24, 0xc1]);
+    }
+    
+    fn prologue(self: &mut Self) {
+        let e = &mut self.emitter;
+        for reg in [RBX, RBP, R12, R13, R14, R15] {
+            e.push(reg);
+        }
+        
+        // rdi is the JitContext
+        e.op_reg(true, &[0x89], 7, CTX);
+        e.load(REGS, CTX, offset_of!(JitContext, regs) as i32);
+        e.load(STACK_BASE, CTX, offset_of!(JitContext, stack_base) as i32);
+        e.load(STACK_TOP, CTX, offset_of!(JitContext, stack_top) as i32);
+        e.load(STACK_END, CTX, offset_of!(JitContext, stack_end) as i32);
+        e.load(CALL_TOP, CTX, offset_of!(JitContext, call_top) as i32);
+        e.load(RAX, CTX, CTX_PC);
+        self.jump_rax();
+    }
+    
+    fn epilogue(self: &mut Self) {
+        let e = &mut self.emitter;
+        e.store(CTX, offset_of!(JitContext, stack_top) as i32, STACK_TOP);
+        e.store(CTX, offset_of!(JitContext, call_top) as i32, CALL_TOP);
+        for reg in [R15, R14, R13, R12, RBP, RBX] {
+            e.pop(reg);
+        }
+        e.byte(0xc3);
+    }
+}
+
+// A program compiled to native code. Every instruction gets an entry in
+// `table`, the ones the compiler can't handle just jump back to the
+// interpreter, so compiled code can be entered at any address.
+pub struct Jit {
+    code: ExecutableMemory,
+    table: Vec<usize>,
+    // Instructions that run natively
+    pub compiled: usize,
+}
+
+impl Jit {
+    pub fn is_supported() -> bool {
+        true
+    }
+    
+    pub fn compile(program: &Bytecode) -> Result<Jit, Error> {
+        let mut compiler = Compiler { emitter: Emitter::init(), len: program.len() };
+        compiler.prologue();
+        
+        let mut offsets = Vec::with_capacity(program.len());
+        let mut compiled = 0;
+        for pc in 0..program.len() {
+            offsets.push(compiler.emitter.code.len());
+            if compiler.instruction(pc, &program.get(pc).unwrap()) {
+                compiled += 1;
+            }
+        }
+        
+        // Falling off the end is the interpreter's business
+        compiler.exit(program.len());
+        
+        let mut labels: BTreeMap<Target, usize> = BTreeMap::new();
+        for (pc, offset) in offsets.iter().enumerate() {
+            labels.insert(Target::Pc(pc), *offset);
+        }
+        
+        let exits: Vec<usize> = compiler.emitter.fixups.iter()
+            .filter_map(|(_, target)| match target {
+                Target::Exit(pc) => Some(*pc),
+                _ => None,
+            })
+            .collect();
+        for pc in exits {
+            if labels.contains_key(&Target::Exit(pc)) {
+                continue;
+            }
+            
+            labels.insert(Target::Exit(pc), compiler.emitter.code.len());
+            let e = &mut compiler.emitter;
+            e.mov_imm(RAX, pc as u64);
+            e.store(CTX, CTX_PC, RAX);
+            e.jmp(Target::Epilogue);
+        }
+        
+        labels.insert(Target::DynamicExit, compiler.emitter.code.len());
+        compiler.emitter.store(CTX, CTX_PC, RAX);
+        compiler.emitter.jmp(Target::Epilogue);
+        
+        labels.insert(Target::Epilogue, compiler.emitter.code.len());
+        compiler.epilogue();
+        
+        let mut emitter = compiler.emitter;
+        for (at, target) in &emitter.fixups {
+            let rel = labels[target] as i64 - (*at as i64 + 4);
+            emitter.code[*at..*at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+        }
+        
+        let code = ExecutableMemory::init(&emitter.code)?;
+        let table = offsets.iter().map(|offset| code.addr() + offset).collect();
+        Ok(Jit { code, table, compiled })
+    }
+    
+    // Runs compiled code from the current pc until it reaches something for
+    // the interpreter. `osvm.pc` has to be inside the program.
+    pub fn run(self: &Self, osvm: &mut OSVM) {
+        osvm.stack.reserve(STACK_RESERVE);
+        osvm.call_stack.reserve(CALL_RESERVE);
+        let call_room = osvm.call_stack.capacity().min(osvm.call_stack_depth);
+        
+        unsafe {
+            let stack_base = osvm.stack.as_mut_ptr();
+            let call_base = osvm.call_stack.as_mut_ptr();
+            let mut context = JitContext {
+                regs: osvm.regs.as_mut_ptr(),
+                stack_base,
+                stack_top: stack_base.add(osvm.stack.len()),
+                stack_end: stack_base.add(osvm.stack.capacity()),
+                call_base,
+                call_top: call_base.add(osvm.call_stack.len()),
+                call_end: call_base.add(call_room),
+                memory: osvm.memory.as_mut_ptr(),
+                memory_len: osvm.memory.len(),
+                table: self.table.as_ptr(),
+                pc: osvm.pc,
+                tsr: osvm.tsr,
+            };
+            
+            let entry: extern "sysv64" fn(*mut JitContext) = std::mem::transmute(self.code.addr());
+            entry(&mut context);
+            
+            osvm.stack.set_len(context.stack_top.offset_from(stack_base) as usize);
+            osvm.call_stack.set_len(context.call_top.offset_from(call_base) as usize);
+            osvm.pc = context.pc;
+            osvm.tsr = context.tsr;
+        }
+    }
+}
+
+// A page aligned mapping that is writable while the code is copied in and
+// only executable after
+struct ExecutableMemory {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl ExecutableMemory {
+    fn init(code: &[u8]) -> Result<ExecutableMemory, Error> {
+        let len = code.len().max(1);
+        unsafe {
+            let ptr = libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0);
+            if ptr == libc::MAP_FAILED {
+                return Err(Error::with_message(ErrorKind::JitUnavailable, "could not map memory for compiled code"));
+            }
+            
+            let memory = ExecutableMemory { ptr, len };
+            std::ptr::copy_nonoverlapping(code.as_ptr(), ptr as *mut u8, code.len());
+            if libc::mprotect(ptr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err(Error::with_message(ErrorKind::JitUnavailable, "could not make compiled code executable"));
+            }
+            
+            Ok(memory)
+        }
+    }
+    
+    fn addr(self: &Self) -> usize {
+        self.ptr as usize
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(self: &mut Self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
\ No newline at end of file
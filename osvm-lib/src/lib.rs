@@ -1,10 +1,34 @@
+#![allow(clippy::needless_arbitrary_self_type, clippy::needless_return, clippy::len_zero)]
+
+pub mod assembler;
+pub mod bytecode;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod dispatch;
+pub mod disassembler;
+pub mod dump;
+pub mod gdbstub;
+pub mod jit;
 pub mod oasm;
 pub mod opcode;
+pub mod optimizer;
 pub mod osvm;
 pub mod preprocessor;
+pub mod profiler;
+pub mod program;
+pub mod recorder;
+pub mod snapshot;
+pub mod trace;
+pub mod watchpoint;
 pub mod log;
 
+#[cfg(test)]
+mod testing;
+
 pub mod utils {
+    pub mod arith;
+    pub mod buffer;
     pub mod defines;
     pub mod error;
     pub mod file;
@@ -14,6 +38,23 @@ pub mod utils {
 pub mod prelude {
     pub use crate::osvm::*;
     pub use crate::oasm::*;
+    pub use crate::assembler::*;
+    pub use crate::bytecode::*;
+    pub use crate::coverage::*;
+    pub use crate::dap::*;
+    pub use crate::disassembler::*;
+    pub use crate::dump::*;
+    pub use crate::debugger::*;
+    pub use crate::dispatch::*;
+    pub use crate::gdbstub::*;
+    pub use crate::jit::*;
+    pub use crate::profiler::*;
+    pub use crate::optimizer::*;
+    pub use crate::program::*;
+    pub use crate::recorder::*;
+    pub use crate::snapshot::*;
+    pub use crate::trace::*;
+    pub use crate::watchpoint::*;
     pub use crate::utils::file::*;
     pub use crate::log::*;
 }
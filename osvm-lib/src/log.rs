@@ -3,6 +3,8 @@ use std::io::Write;
 use colored::Colorize;
 use log::Level;
 
+use crate::utils::error::Error;
+
 pub struct Log {}
 
 impl Log {
@@ -17,11 +19,47 @@ impl Log {
                     Level::Trace => "white".to_string(),
                 };
                 let msg = format!("[{}]: {}",
-                    record.level().to_string(),
-                    record.args().to_string()).color(level_color);
+                    record.level(),
+                    record.args()).color(level_color);
                 writeln!(buf, "{}", msg)
             })
             .filter(None, log::LevelFilter::max())
-            .init();
+            .try_init()
+            .ok();
+    }
+    
+    // Renders an error rustc style, underlining the offending source if it has a span
+    pub fn diagnostic(err: &Error) -> String {
+        let mut out = format!("error[{}]", err.kind.as_string()).red().bold().to_string();
+        if let Some(message) = &err.message {
+            out += &format!(": {}", message).bold().to_string();
+        }
+        
+        if let Some(span) = &err.span {
+            let gutter = " ".repeat(span.line.to_string().len());
+            let bar = "|".blue().bold();
+            let padding: String = span.text.chars()
+                .take(span.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            
+            out += &format!("\n{}{} {}:{}:{}", gutter, "-->".blue().bold(), span.file, span.line, span.column);
+            out += &format!("\n{} {}", gutter, bar);
+            out += &format!("\n{} {} {}", span.line.to_string().blue().bold(), bar, span.text);
+            out += &format!("\n{} {} {}{}", gutter, bar, padding, "^".repeat(span.len).red().bold());
+        }
+        
+        if let Some(pc) = err.pc {
+            out += &format!("\n  {} at pc: {}", "=".blue().bold(), pc);
+            if let Some(opcode) = &err.opcode {
+                out += &format!(" ({:?} {:?})", opcode.op_type, opcode.op_regs);
+            }
+        }
+        
+        out
+    }
+    
+    pub fn report(err: &Error) {
+        eprintln!("{}\n", Log::diagnostic(err));
     }
 }
\ No newline at end of file
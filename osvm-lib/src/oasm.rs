@@ -1,4 +1,6 @@
-#[derive(Clone)]
+use crate::utils::error::Span;
+
+#[derive(Debug, Clone)]
 pub struct Label {
     pub name: String,
     pub addr: usize,
@@ -8,6 +10,9 @@ pub struct Label {
 pub struct DeferredOperand {
     pub addr: usize,
     pub label: String,
+    
+    // Where the label was used
+    pub span: Span,
 }
 
 pub struct OASM {
@@ -30,7 +35,6 @@ impl OASM {
             }
         }
         
-        eprintln!("[Error]: label: `{}` does not exist!", label_name);
         None
     }
     
@@ -41,10 +45,11 @@ impl OASM {
         });
     }
     
-    pub fn deferred_operands_push(self: &mut Self, label_name: &str, jump_addr: usize) {
+    pub fn deferred_operands_push(self: &mut Self, label_name: &str, jump_addr: usize, span: Span) {
         self.deferred_operands.push(DeferredOperand {
             addr: jump_addr,
-            label: label_name.to_string()
+            label: label_name.to_string(),
+            span,
         });
     }
 }
\ No newline at end of file
@@ -1,11 +1,17 @@
 use crate::utils::defines::*;
+use crate::utils::error::ErrorKind;
 
-#[derive(Debug, Clone, Copy)]
+use std::fmt;
+
+// The discriminants are the opcode ids stored in .vbin files,
+// so existing values must never be changed or reused.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum OpcodeType {
-    Nop,
+    Nop = 0x00,
     
     // Register opcodes
-    Mov,
+    Mov = 0x01,
     Movfs,
     
     Srg,
@@ -28,8 +34,14 @@ pub enum OpcodeType {
     
     Sysf,
     
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    
     // Stack opcodes
-    Push,
+    Push = 0x20,
     
     Dupl,
     
@@ -46,8 +58,14 @@ pub enum OpcodeType {
     
     Swc,
     
+    Nes,
+    Lts,
+    Les,
+    Gts,
+    Ges,
+    
     // Universal opcode
-    Jmp,
+    Jmp = 0x40,
     Call,
     
     Read,
@@ -67,25 +85,550 @@ pub enum OpcodeType {
     Ret,
     Hlt,
     
+    // Typed register opcodes
+    Addi = 0x60,
+    Addu,
+    Addf,
+    Subi,
+    Subu,
+    Subf,
+    Muli,
+    Mulu,
+    Mulf,
+    Divi,
+    Divu,
+    Divf,
+    Modi,
+    Modu,
+    Modf,
+    
+    // Typed stack opcodes
+    Addis = 0x70,
+    Addus,
+    Addfs,
+    Subis,
+    Subus,
+    Subfs,
+    Mulis,
+    Mulus,
+    Mulfs,
+    Divis,
+    Divus,
+    Divfs,
+    Modis,
+    Modus,
+    Modfs,
+    
+    // Typed universal opcodes
+    Negi = 0x80,
+    Negf,
+    Absi,
+    Absf,
+    
+    // Conversion opcodes
+    Itof = 0x90,
+    Ftoi,
+    Utof,
+    Ftou,
+    Sext8,
+    Sext16,
+    Sext32,
+    Zext8,
+    Zext16,
+    Zext32,
+    
+    // Typed register comparisons
+    Lti = 0xa0,
+    Ltu,
+    Ltf,
+    Lei,
+    Leu,
+    Lef,
+    Gti,
+    Gtu,
+    Gtf,
+    Gei,
+    Geu,
+    Gef,
+    
+    // Typed stack comparisons
+    Ltis = 0xb0,
+    Ltus,
+    Ltfs,
+    Leis,
+    Leus,
+    Lefs,
+    Gtis,
+    Gtus,
+    Gtfs,
+    Geis,
+    Geus,
+    Gefs,
+    
     // Deprecated
-    Phsr,
+    Phsr = 0xf0,
+}
+
+pub const OPCODE_TYPES: &[OpcodeType] = &[
+    OpcodeType::Nop,
+    
+    // Register opcodes
+    OpcodeType::Mov,
+    OpcodeType::Movfs,
+    OpcodeType::Srg,
+    OpcodeType::Clr,
+    OpcodeType::Add,
+    OpcodeType::Sub,
+    OpcodeType::Mul,
+    OpcodeType::Div,
+    OpcodeType::Dec,
+    OpcodeType::Inc,
+    OpcodeType::Equal,
+    OpcodeType::Jt,
+    OpcodeType::Jz,
+    OpcodeType::Jnz,
+    OpcodeType::Sysf,
+    OpcodeType::Ne,
+    OpcodeType::Lt,
+    OpcodeType::Le,
+    OpcodeType::Gt,
+    OpcodeType::Ge,
+    
+    // Stack opcodes
+    OpcodeType::Push,
+    OpcodeType::Dupl,
+    OpcodeType::Adds,
+    OpcodeType::Subs,
+    OpcodeType::Muls,
+    OpcodeType::Divs,
+    OpcodeType::Equals,
+    OpcodeType::Jts,
+    OpcodeType::Jzs,
+    OpcodeType::Jnzs,
+    OpcodeType::Swc,
+    OpcodeType::Nes,
+    OpcodeType::Lts,
+    OpcodeType::Les,
+    OpcodeType::Gts,
+    OpcodeType::Ges,
+    
+    // Universal opcode
+    OpcodeType::Jmp,
+    OpcodeType::Call,
+    OpcodeType::Read,
+    OpcodeType::Write,
+    OpcodeType::And,
+    OpcodeType::Or,
+    OpcodeType::Xor,
+    OpcodeType::Shr,
+    OpcodeType::Shl,
+    OpcodeType::Not,
+    OpcodeType::Pop,
+    OpcodeType::Ret,
+    OpcodeType::Hlt,
+    
+    // Typed register opcodes
+    OpcodeType::Addi,
+    OpcodeType::Addu,
+    OpcodeType::Addf,
+    OpcodeType::Subi,
+    OpcodeType::Subu,
+    OpcodeType::Subf,
+    OpcodeType::Muli,
+    OpcodeType::Mulu,
+    OpcodeType::Mulf,
+    OpcodeType::Divi,
+    OpcodeType::Divu,
+    OpcodeType::Divf,
+    OpcodeType::Modi,
+    OpcodeType::Modu,
+    OpcodeType::Modf,
+    
+    // Typed stack opcodes
+    OpcodeType::Addis,
+    OpcodeType::Addus,
+    OpcodeType::Addfs,
+    OpcodeType::Subis,
+    OpcodeType::Subus,
+    OpcodeType::Subfs,
+    OpcodeType::Mulis,
+    OpcodeType::Mulus,
+    OpcodeType::Mulfs,
+    OpcodeType::Divis,
+    OpcodeType::Divus,
+    OpcodeType::Divfs,
+    OpcodeType::Modis,
+    OpcodeType::Modus,
+    OpcodeType::Modfs,
+    
+    // Typed universal opcodes
+    OpcodeType::Negi,
+    OpcodeType::Negf,
+    OpcodeType::Absi,
+    OpcodeType::Absf,
+    
+    // Conversion opcodes
+    OpcodeType::Itof,
+    OpcodeType::Ftoi,
+    OpcodeType::Utof,
+    OpcodeType::Ftou,
+    OpcodeType::Sext8,
+    OpcodeType::Sext16,
+    OpcodeType::Sext32,
+    OpcodeType::Zext8,
+    OpcodeType::Zext16,
+    OpcodeType::Zext32,
+    
+    // Typed register comparisons
+    OpcodeType::Lti,
+    OpcodeType::Ltu,
+    OpcodeType::Ltf,
+    OpcodeType::Lei,
+    OpcodeType::Leu,
+    OpcodeType::Lef,
+    OpcodeType::Gti,
+    OpcodeType::Gtu,
+    OpcodeType::Gtf,
+    OpcodeType::Gei,
+    OpcodeType::Geu,
+    OpcodeType::Gef,
+    
+    // Typed stack comparisons
+    OpcodeType::Ltis,
+    OpcodeType::Ltus,
+    OpcodeType::Ltfs,
+    OpcodeType::Leis,
+    OpcodeType::Leus,
+    OpcodeType::Lefs,
+    OpcodeType::Gtis,
+    OpcodeType::Gtus,
+    OpcodeType::Gtfs,
+    OpcodeType::Geis,
+    OpcodeType::Geus,
+    OpcodeType::Gefs,
+    
+    // Deprecated
+    OpcodeType::Phsr,
+];
+
+// OPCODE_TYPES indexed by id, decoding runs once per executed instruction
+static OPCODE_TABLE: [Option<OpcodeType>; 256] = {
+    let mut table = [None; 256];
+    let mut i = 0;
+    while i < OPCODE_TYPES.len() {
+        table[OPCODE_TYPES[i] as usize] = Some(OPCODE_TYPES[i]);
+        i += 1;
+    }
+    table
+};
+
+impl OpcodeType {
+    pub fn from_u8(value: u8) -> Option<OpcodeType> {
+        OPCODE_TABLE[value as usize]
+    }
+    
+    // The assembler name of this opcode
+    pub fn mnemonic(self: Self) -> &'static str {
+        match self {
+            OpcodeType::Nop => NOP,
+            
+            // Register opcodes
+            OpcodeType::Mov => MOV,
+            OpcodeType::Movfs => MOV,
+            OpcodeType::Srg => SRG,
+            OpcodeType::Clr => CLR,
+            OpcodeType::Add => ADD,
+            OpcodeType::Sub => SUB,
+            OpcodeType::Mul => MUL,
+            OpcodeType::Div => DIV,
+            OpcodeType::Dec => DEC,
+            OpcodeType::Inc => INC,
+            OpcodeType::Equal => EQUAL,
+            OpcodeType::Jt => JT,
+            OpcodeType::Jz => JZ,
+            OpcodeType::Jnz => JNZ,
+            OpcodeType::Sysf => SYSF,
+            OpcodeType::Ne => NE,
+            OpcodeType::Lt => LT,
+            OpcodeType::Le => LE,
+            OpcodeType::Gt => GT,
+            OpcodeType::Ge => GE,
+            
+            // Stack opcodes
+            OpcodeType::Push => PUSH,
+            OpcodeType::Dupl => DUPL,
+            OpcodeType::Adds => ADDS,
+            OpcodeType::Subs => SUBS,
+            OpcodeType::Muls => MULS,
+            OpcodeType::Divs => DIVS,
+            OpcodeType::Equals => EQUALS,
+            OpcodeType::Jts => JTS,
+            OpcodeType::Jzs => JZS,
+            OpcodeType::Jnzs => JNZS,
+            OpcodeType::Swc => SWC,
+            OpcodeType::Nes => NES,
+            OpcodeType::Lts => LTS,
+            OpcodeType::Les => LES,
+            OpcodeType::Gts => GTS,
+            OpcodeType::Ges => GES,
+            
+            // Universal opcode
+            OpcodeType::Jmp => JMP,
+            OpcodeType::Call => CALL,
+            OpcodeType::Read => READ,
+            OpcodeType::Write => WRITE,
+            OpcodeType::And => AND,
+            OpcodeType::Or => OR,
+            OpcodeType::Xor => XOR,
+            OpcodeType::Shr => SHR,
+            OpcodeType::Shl => SHL,
+            OpcodeType::Not => NOT,
+            OpcodeType::Pop => POP,
+            OpcodeType::Ret => RET,
+            OpcodeType::Hlt => HLT,
+            
+            // Typed register opcodes
+            OpcodeType::Addi => ADDI,
+            OpcodeType::Addu => ADDU,
+            OpcodeType::Addf => ADDF,
+            OpcodeType::Subi => SUBI,
+            OpcodeType::Subu => SUBU,
+            OpcodeType::Subf => SUBF,
+            OpcodeType::Muli => MULI,
+            OpcodeType::Mulu => MULU,
+            OpcodeType::Mulf => MULF,
+            OpcodeType::Divi => DIVI,
+            OpcodeType::Divu => DIVU,
+            OpcodeType::Divf => DIVF,
+            OpcodeType::Modi => MODI,
+            OpcodeType::Modu => MODU,
+            OpcodeType::Modf => MODF,
+            
+            // Typed stack opcodes
+            OpcodeType::Addis => ADDIS,
+            OpcodeType::Addus => ADDUS,
+            OpcodeType::Addfs => ADDFS,
+            OpcodeType::Subis => SUBIS,
+            OpcodeType::Subus => SUBUS,
+            OpcodeType::Subfs => SUBFS,
+            OpcodeType::Mulis => MULIS,
+            OpcodeType::Mulus => MULUS,
+            OpcodeType::Mulfs => MULFS,
+            OpcodeType::Divis => DIVIS,
+            OpcodeType::Divus => DIVUS,
+            OpcodeType::Divfs => DIVFS,
+            OpcodeType::Modis => MODIS,
+            OpcodeType::Modus => MODUS,
+            OpcodeType::Modfs => MODFS,
+            
+            // Typed universal opcodes
+            OpcodeType::Negi => NEGI,
+            OpcodeType::Negf => NEGF,
+            OpcodeType::Absi => ABSI,
+            OpcodeType::Absf => ABSF,
+            
+            // Conversion opcodes
+            OpcodeType::Itof => ITOF,
+            OpcodeType::Ftoi => FTOI,
+            OpcodeType::Utof => UTOF,
+            OpcodeType::Ftou => FTOU,
+            OpcodeType::Sext8 => SEXT8,
+            OpcodeType::Sext16 => SEXT16,
+            OpcodeType::Sext32 => SEXT32,
+            OpcodeType::Zext8 => ZEXT8,
+            OpcodeType::Zext16 => ZEXT16,
+            OpcodeType::Zext32 => ZEXT32,
+            
+            // Typed register comparisons
+            OpcodeType::Lti => LTI,
+            OpcodeType::Ltu => LTU,
+            OpcodeType::Ltf => LTF,
+            OpcodeType::Lei => LEI,
+            OpcodeType::Leu => LEU,
+            OpcodeType::Lef => LEF,
+            OpcodeType::Gti => GTI,
+            OpcodeType::Gtu => GTU,
+            OpcodeType::Gtf => GTF,
+            OpcodeType::Gei => GEI,
+            OpcodeType::Geu => GEU,
+            OpcodeType::Gef => GEF,
+            
+            // Typed stack comparisons
+            OpcodeType::Ltis => LTIS,
+            OpcodeType::Ltus => LTUS,
+            OpcodeType::Ltfs => LTFS,
+            OpcodeType::Leis => LEIS,
+            OpcodeType::Leus => LEUS,
+            OpcodeType::Lefs => LEFS,
+            OpcodeType::Gtis => GTIS,
+            OpcodeType::Gtus => GTUS,
+            OpcodeType::Gtfs => GTFS,
+            OpcodeType::Geis => GEIS,
+            OpcodeType::Geus => GEUS,
+            OpcodeType::Gefs => GEFS,
+            
+            // Deprecated
+            OpcodeType::Phsr => PHSR,
+        }
+    }
+}
+
+// Register operands as indices into REGISTERS, resolved when the program is
+// assembled or loaded so executing an opcode never looks at a name
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OpRegs {
+    regs: [u8; VBIN_MAX_REGS],
+    len: u8,
+}
+
+impl OpRegs {
+    pub fn init() -> OpRegs {
+        OpRegs {
+            regs: [0; VBIN_MAX_REGS],
+            len: 0,
+        }
+    }
+    
+    pub fn push(self: &mut Self, index: usize) -> Result<(), ErrorKind> {
+        if index >= REGISTERS.len() {
+            return Err(ErrorKind::InvalidRegister);
+        }
+        
+        if self.len as usize >= VBIN_MAX_REGS {
+            return Err(ErrorKind::RegisterOverflow);
+        }
+        
+        self.regs[self.len as usize] = index as u8;
+        self.len += 1;
+        Ok(())
+    }
+    
+    // Indices are trusted, Bytecode checks them when a program is loaded
+    pub(crate) fn from_raw(regs: [u8; VBIN_MAX_REGS], len: usize) -> OpRegs {
+        OpRegs { regs, len: len as u8 }
+    }
+    
+    pub fn len(self: &Self) -> usize {
+        self.len as usize
+    }
+    
+    pub fn is_empty(self: &Self) -> bool {
+        self.len == 0
+    }
+    
+    pub fn get(self: &Self, index: usize) -> Option<usize> {
+        if index < self.len as usize {
+            Some(self.regs[index] as usize)
+        } else {
+            None
+        }
+    }
+    
+    pub fn iter(self: &Self) -> impl Iterator<Item = usize> + '_ {
+        self.regs[..self.len as usize].iter().map(|reg| *reg as usize)
+    }
+    
+    pub fn names(self: &Self) -> Vec<&'static str> {
+        self.iter().map(|reg| REGISTERS[reg]).collect()
+    }
+}
+
+impl fmt::Debug for OpRegs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
 }
 
 #[repr(C)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct Opcode {
     pub op_type: OpcodeType,
-    pub op_operand: Option<Word>,
+    pub op_operand: Option<TypedWord>,
     
-    pub op_regs: Vec<String>,
+    pub op_regs: OpRegs,
 }
 
 impl Opcode {
     pub fn init() -> Opcode {
         Opcode {
             op_type: OpcodeType::Nop,
-            op_operand: Some(Word { as_u64: 0 }),
-            op_regs: Vec::new(),
+            op_operand: Some(TypedWord::u64(0)),
+            op_regs: OpRegs::init(),
+        }
+    }
+    
+    pub fn operand(self: &Self) -> Result<TypedWord, ErrorKind> {
+        self.op_operand.ok_or(ErrorKind::InvalidOperand)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    
+    fn regs(indices: &[usize]) -> OpRegs {
+        let mut regs = OpRegs::init();
+        for index in indices {
+            regs.push(*index).unwrap();
+        }
+        regs
+    }
+    
+    #[test]
+    fn op_regs_hold_up_to_three_indices() {
+        let mut regs = regs(&[0, 16, 7]);
+        assert_eq!(regs.len(), 3);
+        assert_eq!(regs.iter().collect::<Vec<_>>(), [0, 16, 7]);
+        assert_eq!((regs.get(1), regs.get(3)), (Some(16), None));
+        assert_eq!(regs.names(), ["r0", "r16", "r7"]);
+        assert_eq!(format!("{:?}", regs), "[\"r0\", \"r16\", \"r7\"]");
+        
+        assert_eq!(regs.push(1), Err(ErrorKind::RegisterOverflow));
+        assert_eq!(OpRegs::init().push(REGISTERS.len()), Err(ErrorKind::InvalidRegister));
+        assert!(OpRegs::init().is_empty());
+    }
+    
+    #[test]
+    fn raw_regs_match_pushed_regs() {
+        let mut a = regs(&[3]);
+        let b = OpRegs::from_raw([3, 0, 0], 1);
+        assert_eq!(a, b);
+        
+        a.push(0).unwrap();
+        assert_ne!(a, b);
+    }
+    
+    #[test]
+    fn the_assembler_resolves_register_names() {
+        let program = assemble("\n_start:\n    add r16, r8, r0\n    hlt\n");
+        assert_eq!(program.code[0].op_regs, regs(&[16, 8, 0]));
+        
+        let errors = try_assemble("\n_start:\n    mov r17, #1\n    hlt\n").unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::InvalidRegister);
+    }
+    
+    #[test]
+    fn every_register_is_a_slot() {
+        let mut source = String::from("\n_start:\n");
+        for (i, name) in REGISTERS.iter().enumerate() {
+            source.push_str(&format!("    mov {}, #{}\n", name, i * 10));
+        }
+        source.push_str("    hlt\n");
+        
+        let (mut osvm, _, result) = run_source(&source);
+        result.unwrap();
+        for (i, name) in REGISTERS.iter().enumerate() {
+            assert_eq!(osvm.registers()[i], TypedWord::u64(i as u64 * 10));
+            assert_eq!(osvm.register_by_name(name).copied(), Some(TypedWord::u64(i as u64 * 10)));
         }
+        assert!(osvm.register_by_name("r17").is_none());
+        assert!(osvm.register_by_index(17).is_none());
+        
+        let mut opcode = Opcode::init();
+        opcode.op_regs = regs(&[5]);
+        assert_eq!(osvm.read_register(&opcode, 0), Ok(TypedWord::u64(50)));
+        assert_eq!(osvm.read_register(&opcode, 1), Err(ErrorKind::InvalidRegister));
+        assert_eq!(osvm.assign_register(&opcode, 1, TypedWord::u64(0)), Err(ErrorKind::InvalidRegister));
     }
 }
\ No newline at end of file
@@ -0,0 +1,484 @@
+use crate::opcode::*;
+use crate::program::Program;
+use crate::utils::{arith::*, defines::*};
+
+use log::*;
+
+// Peephole optimizer that runs between the Assembler and OSVM. The rewrites
+// keep what a program computes (output, registers, stack and memory) but not
+// the tsr or the number of instructions it takes to get there.
+pub struct Optimizer {
+    // Rewrites applied by the last `optimize`
+    pub rewrites: usize,
+}
+
+impl Optimizer {
+    pub fn init() -> Optimizer {
+        Optimizer {
+            rewrites: 0,
+        }
+    }
+    
+    // Runs every pass until none of them finds anything left to rewrite,
+    // jump targets, symbols, the entry and line info are moved along
+    pub fn optimize(self: &mut Self, original: &Program) -> Program {
+        let mut program = original.clone();
+        self.rewrites = 0;
+        loop {
+            let rewrites = self.pass(&mut program);
+            if rewrites == 0 {
+                break;
+            }
+            
+            self.rewrites += rewrites;
+        }
+        
+        info!("[Optimized] => {} rewrites, {} -> {} instructions", self.rewrites, original.code.len(), program.code.len());
+        program
+    }
+    
+    fn pass(self: &Self, program: &mut Program) -> usize {
+        let targets = self.targets(program);
+        let len = program.code.len();
+        let mut keep = vec![true; len];
+        let mut rewrites = 0;
+        
+        // Jumps to a `jmp` go straight to where it leads
+        for i in 0..len {
+            if let Some(target) = self.branch_target(&program.code[i]) {
+                let threaded = self.thread(&program.code, target);
+                if threaded != target {
+                    program.code[i].op_operand = Some(TypedWord::u64(threaded as u64));
+                    rewrites += 1;
+                }
+            }
+        }
+        
+        // Patterns never span a jump target, something may enter halfway
+        let enters = |i: usize| targets[i];
+        let code = &mut program.code;
+        let mut i = 0;
+        while i < len {
+            let opcode = code[i];
+            
+            // mov rX, rX
+            if opcode.op_type == OpcodeType::Mov && opcode.op_operand.is_none() && opcode.op_regs.len() == 2 && opcode.op_regs.get(0) == opcode.op_regs.get(1) {
+                keep[i] = false;
+                rewrites += 1;
+                i += 1;
+                continue;
+            }
+            
+            // push X; pop
+            if i + 1 < len && !enters(i + 1) && self.is_push(&opcode) && code[i + 1].op_type == OpcodeType::Pop && code[i + 1].op_regs.is_empty() {
+                keep[i] = false;
+                keep[i + 1] = false;
+                rewrites += 1;
+                i += 2;
+                continue;
+            }
+            
+            // push #a; push #b; adds -> push #(a + b), a fault is left for run time
+            if i + 2 < len && !enters(i + 1) && !enters(i + 2) {
+                if let Some(value) = self.fold(&opcode, &code[i + 1], &code[i + 2]) {
+                    code[i].op_operand = Some(value);
+                    keep[i + 1] = false;
+                    keep[i + 2] = false;
+                    rewrites += 1;
+                    i += 3;
+                    continue;
+                }
+            }
+            
+            // Nothing falls through an unconditional jmp, hlt or ret
+            if self.ends_block(&opcode) {
+                let mut j = i + 1;
+                while j < len && !enters(j) {
+                    keep[j] = false;
+                    j += 1;
+                }
+                
+                if j > i + 1 {
+                    rewrites += 1;
+                }
+                i = j;
+                continue;
+            }
+            
+            i += 1;
+        }
+        
+        if keep.contains(&false) {
+            self.compact(program, &keep);
+        }
+        
+        rewrites
+    }
+    
+    // Addresses something can get to other than by falling through: the
+    // entry, every symbol and every jump or call target
+    fn targets(self: &Self, program: &Program) -> Vec<bool> {
+        let mut targets = vec![false; program.code.len() + 1];
+        let addrs = program.symbols.iter()
+            .map(|label| label.addr)
+            .chain(std::iter::once(program.entry))
+            .chain(program.code.iter().filter_map(|opcode| self.jump_target(opcode)));
+        
+        for addr in addrs {
+            if let Some(target) = targets.get_mut(addr) {
+                *target = true;
+            }
+        }
+        
+        targets
+    }
+    
+    fn jump_target(self: &Self, opcode: &Opcode) -> Option<usize> {
+        match opcode.op_type {
+            OpcodeType::Call => opcode.op_operand.map(|operand| unsafe { operand.word.as_usize }),
+            _ => self.branch_target(opcode),
+        }
+    }
+    
+    // Calls are left alone, their target is what names the frame
+    fn branch_target(self: &Self, opcode: &Opcode) -> Option<usize> {
+        match opcode.op_type {
+            OpcodeType::Jt | OpcodeType::Jz | OpcodeType::Jnz |
+            OpcodeType::Jts | OpcodeType::Jzs | OpcodeType::Jnzs |
+            OpcodeType::Jmp => {
+                opcode.op_operand.map(|operand| unsafe { operand.word.as_usize })
+            }
+            
+            _ => None,
+        }
+    }
+    
+    // Follows a chain of `jmp`s, a cycle stays where it is
+    fn thread(self: &Self, code: &[Opcode], target: usize) -> usize {
+        let mut addr = target;
+        for _ in 0..code.len() {
+            match code.get(addr) {
+                Some(opcode) if opcode.op_type == OpcodeType::Jmp => match self.branch_target(opcode) {
+                    Some(next) if next != addr => addr = next,
+                    _ => return target,
+                },
+                _ => return addr,
+            }
+        }
+        
+        target
+    }
+    
+    fn is_push(self: &Self, opcode: &Opcode) -> bool {
+        opcode.op_type == OpcodeType::Push && (opcode.op_operand.is_some() || opcode.op_regs.len() == 1)
+    }
+    
+    fn ends_block(self: &Self, opcode: &Opcode) -> bool {
+        match opcode.op_type {
+            OpcodeType::Jmp => opcode.op_operand.is_some(),
+            OpcodeType::Hlt | OpcodeType::Ret => true,
+            
+            _ => false,
+        }
+    }
+    
+    // The value of pushing two literals and combining them with stack arithmetic
+    fn fold(self: &Self, first: &Opcode, second: &Opcode, op: &Opcode) -> Option<TypedWord> {
+        if first.op_type != OpcodeType::Push || second.op_type != OpcodeType::Push {
+            return None;
+        }
+        
+        let (b, a) = (first.op_operand?, second.op_operand?);
+        match op.op_type {
+            OpcodeType::Adds | OpcodeType::Subs | OpcodeType::Muls | OpcodeType::Divs => {
+                arith_tagged(untyped_arith(op.op_type)?, b, a).ok()
+            }
+            
+            OpcodeType::Addis | OpcodeType::Addus | OpcodeType::Addfs |
+            OpcodeType::Subis | OpcodeType::Subus | OpcodeType::Subfs |
+            OpcodeType::Mulis | OpcodeType::Mulus | OpcodeType::Mulfs |
+            OpcodeType::Divis | OpcodeType::Divus | OpcodeType::Divfs |
+            OpcodeType::Modis | OpcodeType::Modus | OpcodeType::Modfs => {
+                let (arith_op, ty) = typed_arith(op.op_type)?;
+                let word = arith(arith_op, ty, b.word, a.word).ok()?;
+                Some(TypedWord { word, ty })
+            }
+            
+            _ => None,
+        }
+    }
+    
+    // Drops the removed instructions. Anything that pointed at one now points
+    // at the next instruction that is kept.
+    fn compact(self: &Self, program: &mut Program, keep: &[bool]) {
+        let len = program.code.len();
+        let mut map = Vec::with_capacity(len + 1);
+        let mut kept = 0;
+        for keep in keep {
+            map.push(kept);
+            if *keep {
+                kept += 1;
+            }
+        }
+        map.push(kept);
+        
+        let moved = |addr: usize| match map.get(addr) {
+            Some(addr) => *addr,
+            None => addr - (len - kept),
+        };
+        
+        let mut code = Vec::with_capacity(kept);
+        for (i, opcode) in program.code.iter().enumerate() {
+            if !keep[i] {
+                continue;
+            }
+            
+            let mut opcode = *opcode;
+            if let Some(target) = self.jump_target(&opcode) {
+                opcode.op_operand = Some(TypedWord::u64(moved(target) as u64));
+            }
+            code.push(opcode);
+        }
+        program.code = code;
+        
+        for label in &mut program.symbols {
+            label.addr = moved(label.addr);
+        }
+        program.entry = moved(program.entry);
+        
+        if program.lines.len() == len {
+            let lines = program.lines.iter().enumerate().filter(|(i, _)| keep[*i]).map(|(_, info)| *info);
+            program.lines = lines.collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    
+    fn optimize(source: &str) -> (Program, Program, usize) {
+        let original = assemble(source);
+        let mut optimizer = Optimizer::init();
+        let optimized = optimizer.optimize(&original);
+        (original, optimized, optimizer.rewrites)
+    }
+    
+    fn operand(opcode: &Opcode) -> usize {
+        unsafe { opcode.op_operand.unwrap().word.as_usize }
+    }
+    
+    fn types(program: &Program) -> Vec<OpcodeType> {
+        program.code.iter().map(|opcode| opcode.op_type).collect()
+    }
+    
+    // The rewrites keep everything a program computes apart from the tsr
+    fn assert_same_run(original: &Program, optimized: &Program) {
+        let (before, before_output, before_result) = run(original);
+        let (after, after_output, after_result) = run(optimized);
+        assert!(before_result.is_ok() && after_result.is_ok());
+        assert_eq!(after.registers(), before.registers());
+        assert_eq!(after.stack, before.stack);
+        assert!(after.memory == before.memory);
+        assert_eq!(after_output, before_output);
+    }
+    
+    #[test]
+    fn threads_jumps_to_jumps() {
+        let (original, optimized, rewrites) = optimize("
+_start:
+    mov r0, #1
+    jnz a, r0
+    hlt
+a:
+    jmp b
+b:
+    jmp c
+c:
+    mov r1, #2
+    hlt
+");
+        
+        // Every label is a symbol, so the jmps stay and only their targets move
+        assert_eq!(rewrites, 2);
+        assert_eq!(optimized.code.len(), original.code.len());
+        assert_eq!(operand(&optimized.code[1]), 5);
+        assert_eq!(operand(&optimized.code[3]), 5);
+        assert_eq!(operand(&optimized.code[4]), 5);
+        assert_eq!(optimized.symbol("c"), Some(5));
+        assert_same_run(&original, &optimized);
+    }
+    
+    #[test]
+    fn jump_cycles_are_left_alone() {
+        let (original, optimized, rewrites) = optimize("
+_start:
+    jmp a
+a:
+    jmp b
+b:
+    jmp a
+");
+        
+        assert_eq!(rewrites, 0);
+        let targets: Vec<usize> = optimized.code.iter().map(operand).collect();
+        assert_eq!(targets, original.code.iter().map(operand).collect::<Vec<_>>());
+    }
+    
+    #[test]
+    fn removes_moves_to_the_same_register() {
+        let (original, optimized, rewrites) = optimize("
+helper:
+    mov r2, r2
+    ret
+_start:
+    mov r0, #3
+loop:
+    mov r0, r0
+    call helper
+    dec r0
+    jnz loop, r0
+    jmp end
+end:
+    hlt
+");
+        
+        assert_eq!(rewrites, 2);
+        assert_eq!(types(&optimized), [OpcodeType::Ret, OpcodeType::Mov, OpcodeType::Call, OpcodeType::Dec, OpcodeType::Jnz, OpcodeType::Jmp, OpcodeType::Hlt]);
+        
+        // A label on a removed instruction moves to the next one kept
+        assert_eq!(optimized.symbol("helper"), Some(0));
+        assert_eq!(optimized.symbol("_start"), Some(1));
+        assert_eq!(optimized.symbol("loop"), Some(2));
+        assert_eq!(optimized.symbol("end"), Some(6));
+        assert_eq!(optimized.entry, 1);
+        assert_eq!(operand(&optimized.code[2]), 0);
+        assert_eq!(operand(&optimized.code[4]), 2);
+        assert_eq!(operand(&optimized.code[5]), 6);
+        assert_same_run(&original, &optimized);
+    }
+    
+    #[test]
+    fn removes_a_push_that_is_popped() {
+        let (original, optimized, rewrites) = optimize("
+_start:
+    push #1
+    push r0
+    pop
+    mov r1, #2
+    jmp end
+end:
+    hlt
+");
+        
+        assert_eq!(rewrites, 1);
+        assert_eq!(types(&optimized), [OpcodeType::Push, OpcodeType::Mov, OpcodeType::Jmp, OpcodeType::Hlt]);
+        assert_eq!(operand(&optimized.code[2]), 3);
+        assert_eq!(optimized.symbol("end"), Some(3));
+        assert_same_run(&original, &optimized);
+        
+        // A label on the pop means something may reach it without the push
+        let (original, optimized, rewrites) = optimize("
+_start:
+    push #1
+    push #2
+back:
+    pop
+    hlt
+");
+        
+        assert_eq!(rewrites, 0);
+        assert_eq!(types(&optimized), types(&original));
+    }
+    
+    #[test]
+    fn folds_constant_stack_arithmetic() {
+        let (original, optimized, rewrites) = optimize("
+_start:
+    push #2
+    push #3
+    adds
+    push #10
+    push #4
+    subis
+    push #1.5
+    push #2.0
+    mulfs
+    jmp end
+end:
+    hlt
+");
+        
+        assert_eq!(rewrites, 3);
+        assert_eq!(types(&optimized), [OpcodeType::Push, OpcodeType::Push, OpcodeType::Push, OpcodeType::Jmp, OpcodeType::Hlt]);
+        unsafe {
+            assert_eq!(optimized.code[0].op_operand.unwrap().word.as_u64, 5);
+            assert_eq!(optimized.code[1].op_operand.unwrap().word.as_i64, 6);
+            assert_eq!(optimized.code[2].op_operand.unwrap().word.as_f64, 3.0);
+        }
+        assert_eq!(operand(&optimized.code[3]), 4);
+        assert_eq!(optimized.symbol("end"), Some(4));
+        assert_same_run(&original, &optimized);
+    }
+    
+    #[test]
+    fn leaves_faulting_arithmetic_for_run_time() {
+        let (original, optimized, rewrites) = optimize("
+_start:
+    push #1
+    push #0
+    divs
+    hlt
+");
+        
+        assert_eq!(rewrites, 0);
+        assert_eq!(types(&optimized), types(&original));
+        assert!(run(&optimized).2.is_err());
+    }
+    
+    #[test]
+    fn removes_code_nothing_reaches() {
+        let (original, optimized, rewrites) = optimize("
+_start:
+    mov r0, #1
+    call f
+    hlt
+    mov r0, #2
+    inc r0
+f:
+    inc r1
+    ret
+    dec r1
+    jmp f
+after:
+    hlt
+");
+        
+        assert_eq!(rewrites, 2);
+        assert_eq!(types(&optimized), [OpcodeType::Mov, OpcodeType::Call, OpcodeType::Hlt, OpcodeType::Inc, OpcodeType::Ret, OpcodeType::Hlt]);
+        assert_eq!(operand(&optimized.code[1]), 3);
+        assert_eq!(optimized.symbol("f"), Some(3));
+        assert_eq!(optimized.symbol("after"), Some(5));
+        assert_eq!(optimized.entry, 0);
+        
+        // Line info is kept for the instructions that are
+        assert_eq!(optimized.lines.len(), optimized.code.len());
+        assert_eq!(optimized.lines[3], original.lines[5]);
+        assert_eq!(optimized.lines[5], original.lines[9]);
+        assert_same_run(&original, &optimized);
+    }
+    
+    #[test]
+    fn examples_compute_the_same() {
+        for (name, program) in examples() {
+            let optimized = Optimizer::init().optimize(&program);
+            let (before, before_output, before_result) = run(&program);
+            let (after, after_output, after_result) = run(&optimized);
+            assert_eq!(after_result.is_ok(), before_result.is_ok(), "{}", name);
+            assert_eq!(after.registers(), before.registers(), "{}", name);
+            assert_eq!(after.stack, before.stack, "{}", name);
+            assert_eq!(after_output, before_output, "{}", name);
+        }
+    }
+}
\ No newline at end of file
@@ -1,1823 +1,1705 @@
-#![allow(unused, dead_code)]
-
-use crate::log::Log;
-use crate::preprocessor;
-
-use crate::utils::defines;
-use crate::utils::error;
-use crate::utils::file;
-
-use crate::oasm;
-use crate::opcode;
-use crate::utils::sys_functions::SysFunction;
-use crate::utils::sys_functions::SystemFunctions;
-
-use log::*;
-
-use std::{
-    alloc::alloc,
-    env,
-    ffi::{c_void, CString},
-    fs::File,
-    io::{Read, Write},
-    ops::{Add, Deref, Index},
-    process::exit
-};
-
-use libc::{free, malloc};
-
-use std::io::stdin;
-
-use preprocessor::*;
-
-use defines::*;
-use oasm::*;
-use opcode::*;
-use error::*;
-use file::*;
-
-pub struct OSVM {
-    // Registers
-    r0: Word,
-    r1: Word,
-    r2: Word,
-    r3: Word,
-    r4: Word,
-    r5: Word,
-    r6: Word,
-    r7: Word,
-    r8: Word,
-    r9: Word,
-    r10: Word,
-    r11: Word,
-    r12: Word,
-    r13: Word,
-    r14: Word,
-    r15: Word,
-    r16: Word,
-    
-    pub tsr: usize,
-    rspc: usize,
-    pc: usize,
-
-    // Stack
-    pub stack: Vec<Word>,
-    pub memory: Vec<u8>,
-    
-    // Other
-    pub program: Vec<Opcode>,
-    pub sys_functions: Vec<SysFunction>,
-    
-    halt: bool,
-}
-
-impl OSVM {
-    pub fn init_log(self: &Self) {
-        Log::init();
-        info!("Initialized Log!");
-    }
-    
-    pub fn init() -> OSVM {
-        OSVM {
-            // Registers
-            r0: Word { as_u64: 0 },
-            r1: Word { as_u64: 0 },
-            r2: Word { as_u64: 0 },
-            r3: Word { as_u64: 0 },
-            r4: Word { as_u64: 0 },
-            r5: Word { as_u64: 0 },
-            r6: Word { as_u64: 0 },
-            r7: Word { as_u64: 0 },
-            r8: Word { as_u64: 0 },
-            r9: Word { as_u64: 0 },
-            r10: Word { as_u64: 0 },
-            r11: Word { as_u64: 0 },
-            r12: Word { as_u64: 0 },
-            r13: Word { as_u64: 0 },
-            r14: Word { as_u64: 0 },
-            r15: Word { as_u64: 0 },
-            r16: Word { as_u64: 0 },
-            
-            tsr: 0,
-            rspc: 0,
-            pc: 0,
-            
-            // Stack
-            stack: Vec::new(),
-            memory: vec![0 ; MEMORY_CAPACITY],
-            
-            // Other
-            program: Vec::new(),
-            
-            sys_functions: Vec::new(),
-            
-            halt: false,
-        }
-    }
-    
-    pub fn init_default_sysf(self: &mut Self) {
-        self.sys_functions = vec![
-            SystemFunctions::print_mem,
-            SystemFunctions::print_ptr,
-            SystemFunctions::print_f64,
-            SystemFunctions::print_i64,
-            SystemFunctions::print_u64,
-            SystemFunctions::free,
-            SystemFunctions::alloc,
-        ]
-    }
-    
-    pub fn assign_register(self: &mut Self, opcode: &Opcode, index: usize, new_value: Word) {
-        let reg: String = opcode.op_regs[index].clone();
-        match reg.as_str() {
-            R0 => {
-                self.r0 = new_value;
-            }
-            R1 => {
-                self.r1 = new_value;
-            }
-            R2 => {
-                self.r2 = new_value;
-            }
-            R3 => {
-                self.r3 = new_value;
-            }
-            R4 => {
-                self.r4 = new_value;
-            }
-            R5 => {
-                self.r5 = new_value;
-            }
-            R6 => {
-                self.r6 = new_value;
-            }
-            R7 => {
-                self.r7 = new_value;
-            }
-            R8 => {
-                self.r8 = new_value;
-            }
-            R9 => {
-                self.r9 = new_value;
-            }
-            R10 => {
-                self.r10 = new_value;
-            }
-            R11 => {
-                self.r11 = new_value;
-            }
-            R12 => {
-                self.r12 = new_value;
-            }
-            R13 => {
-                self.r13 = new_value;
-            }
-            R14 => {
-                self.r14 = new_value;
-            }
-            R15 => {
-                self.r15 = new_value;
-            }
-            R16 => {
-                self.r16 = new_value;
-            }
-            
-            _ => {
-                
-            }
-        }
-    }
-    
-    pub fn find_register(self: &mut Self, opcode: &Opcode, index: usize) -> Option<&mut Word> {
-        let reg: String = opcode.op_regs[index].clone();
-        match reg.as_str() {
-            R0 => {
-                return Some(&mut self.r0);
-            }
-            R1 => {
-                return Some(&mut self.r1);
-            }
-            R2 => {
-                return Some(&mut self.r2);
-            }
-            R3 => {
-                return Some(&mut self.r3);
-            }
-            R4 => {
-                return Some(&mut self.r4);
-            }
-            R5 => {
-                return Some(&mut self.r5);
-            }
-            R6 => {
-                return Some(&mut self.r6);
-            }
-            R7 => {
-                return Some(&mut self.r7);
-            }
-            R8 => {
-                return Some(&mut self.r8);
-            }
-            R9 => {
-                return Some(&mut self.r9);
-            }
-            R10 => {
-                return Some(&mut self.r10);
-            }
-            R11 => {
-                return Some(&mut self.r11);
-            }
-            R12 => {
-                return Some(&mut self.r12);
-            }
-            R13 => {
-                return Some(&mut self.r13);
-            }
-            R14 => {
-                return Some(&mut self.r14);
-            }
-            R15 => {
-                return Some(&mut self.r15);
-            }
-            R16 => {
-                return Some(&mut self.r16);
-            }
-            
-            _  => {
-                None
-            }
-        }
-    }
-    
-    pub fn set_tsr(self: &mut Self, value: Word) {
-        match value {
-            Word { as_u64: _ } => self.tsr = 0,
-            Word { as_i64: _ } => self.tsr = 1,
-            Word { as_f64: _ } => self.tsr = 2,
-            
-            Word { as_ptr: _ } => self.tsr = 3,
-        }
-    }
-    
-    pub fn execute_opcode(self: &mut Self) -> Error {
-        if self.pc >= self.program.len() {
-            return Error::InvalidOpcodeAccess;
-        }
-        
-        let opcode = self.program[self.pc].clone();
-        
-        match opcode.op_type {
-            OpcodeType::Mov => {
-                match opcode.op_operand {
-                    None => {
-                        if opcode.op_regs.len() < 1 {
-                            return Error::RegisterOverflow;
-                        } else if opcode.op_regs.len() > 2 {
-                            return Error::RegisterUnderflow;
-                        }
-                        
-                        let reg = *self.find_register(&opcode, 1).unwrap();
-                        self.set_tsr(reg);
-                        self.assign_register(&opcode, 0, reg);
-                    }
-                    _ => {
-                        if opcode.op_regs.len() < 1 {
-                            return Error::RegisterOverflow;
-                        } else if opcode.op_regs.len() > 1 {
-                            return Error::RegisterUnderflow;
-                        }
-                    
-                        self.set_tsr(opcode.op_operand.unwrap());
-                        self.assign_register(&opcode, 0, opcode.op_operand.unwrap());
-                    }
-                }
-                self.pc += 1
-            }
-            OpcodeType::Movfs => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterOverflow;
-                } else if opcode.op_regs.len() > 1 {
-                    return Error::RegisterUnderflow;
-                }
-                
-                unsafe {
-                    self.set_tsr(self.stack[self.stack.len() - 1 - opcode.op_operand.unwrap().as_usize]);
-                    self.assign_register(&opcode, 0, self.stack[self.stack.len() - 1 - opcode.op_operand.unwrap().as_usize]);
-                }
-                
-                self.pc += 1
-            }
-            
-            OpcodeType::Srg => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterOverflow;
-                } else if opcode.op_regs.len() > 2 {
-                    return Error::RegisterUnderflow;
-                }
-                
-                let reg1 = *self.find_register(&opcode, 0).unwrap();
-                let reg2 = *self.find_register(&opcode, 1).unwrap();
-                self.set_tsr(reg1);
-                self.assign_register(&opcode, 0, reg2);
-                self.assign_register(&opcode, 1, reg1);
-                self.pc += 1
-            }
-
-            OpcodeType::Add => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterUnderflow;
-                } else if opcode.op_regs.len() > 3 {
-                    return Error::RegisterOverflow;
-                }
-                
-                let reg1 = *self.find_register(&opcode, 1).unwrap();
-                let reg2 = *self.find_register(&opcode, 2).unwrap();
-                self.set_tsr(reg1);
-                unsafe {
-                    match self.tsr {
-                        0 => {
-                            let sum = reg1.as_u64 + reg2.as_u64;
-                    
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_u64: sum };
-                        }
-                        1 => {
-                            let sum = reg1.as_i64 + reg2.as_i64;
-                            
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_i64: sum };
-                        }
-                        2 => {
-                            let sum = reg1.as_f64 + reg2.as_f64;
-                            
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_f64: sum };
-                        }
-    
-                        _ => {}
-                    }
-                }
-                self.pc += 1
-            }
-            OpcodeType::Sub => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterUnderflow;
-                } else if opcode.op_regs.len() > 3 {
-                    return Error::RegisterOverflow;
-                }
-                
-                let reg1 = *self.find_register(&opcode, 1).unwrap();
-                let reg2 = *self.find_register(&opcode, 2).unwrap();
-                self.set_tsr(reg1);
-                unsafe {
-                    match self.tsr {
-                        0 => {
-                            let sum = reg1.as_u64 - reg2.as_u64;
-                    
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_u64: sum };
-                        }
-                        1 => {
-                            let sum = reg1.as_i64 - reg2.as_i64;
-                            
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_i64: sum };
-                        }
-                        2 => {
-                            let sum = reg1.as_f64 - reg2.as_f64;
-                            
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_f64: sum };
-                        }
-    
-                        _ => {}
-                    }
-                }
-                self.pc += 1
-            }
-            OpcodeType::Mul => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterUnderflow;
-                } else if opcode.op_regs.len() > 3 {
-                    return Error::RegisterOverflow;
-                }
-                
-                let reg1 = *self.find_register(&opcode, 1).unwrap();
-                let reg2 = *self.find_register(&opcode, 2).unwrap();
-                self.set_tsr(reg1);
-                unsafe {
-                    match self.tsr {
-                        0 => {
-                            let sum = reg1.as_u64 * reg2.as_u64;
-                    
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_u64: sum };
-                        }
-                        1 => {
-                            let sum = reg1.as_i64 * reg2.as_i64;
-                            
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_i64: sum };
-                        }
-                        2 => {
-                            let sum = reg1.as_f64 * reg2.as_f64;
-                            
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_f64: sum };
-                        }
-        
-                        _ => {}
-                    }
-                }
-                self.pc += 1
-            }
-            OpcodeType::Div => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterUnderflow;
-                } else if opcode.op_regs.len() > 3 {
-                    return Error::RegisterOverflow;
-                }
-                
-                let reg1 = *self.find_register(&opcode, 1).unwrap();
-                let reg2 = *self.find_register(&opcode, 2).unwrap();
-                self.set_tsr(reg1);
-                unsafe {
-                    match self.tsr {
-                        0 => {
-                            if reg1.as_u64 == 0 || reg2.as_u64 == 0 {
-                                return Error::DivByZero;
-                            }
-    
-                            let sum = reg1.as_u64 + reg2.as_u64;
-                    
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_u64: sum };
-                        }
-                        1 => {
-                            if reg1.as_i64 == 0 || reg2.as_i64 == 0 {
-                                return Error::DivByZero;
-                            }
-    
-                            let sum = reg1.as_i64 + reg2.as_i64;
-                            
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_i64: sum };
-                        }
-                        2 => {
-                            if reg1.as_f64 == 0.0 || reg2.as_f64 == 0.0 {
-                                return Error::DivByZero;
-                            }
-    
-                            let sum = reg1.as_f64 + reg2.as_f64;
-                            
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = Word { as_f64: sum };
-                        }
-    
-                        _ => {}
-                    }
-                }
-                self.pc += 1
-            }
-            
-            OpcodeType::Dec => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterUnderflow;
-                } else if opcode.op_regs.len() > 1 {
-                    return Error::RegisterOverflow;
-                }
-                
-                let reg1 = *self.find_register(&opcode, 0).unwrap();
-                self.set_tsr(reg1);
-                unsafe {
-                    match self.tsr {
-                        0 => {
-                            let reg1 = self.find_register(&opcode, 0).unwrap();
-                            *reg1 = Word { as_u64: reg1.as_u64 - 1 };
-                        }
-                        1 => {
-                            let reg1 = self.find_register(&opcode, 0).unwrap();
-                            *reg1 = Word { as_i64: reg1.as_i64 - 1 };
-                        }
-                        2 => {
-                            let reg1 = self.find_register(&opcode, 0).unwrap();
-                            *reg1 = Word { as_f64: reg1.as_f64 - 1.0 };
-                        }
-    
-                        _ => {}
-                    }
-                }
-                self.pc += 1
-            }
-            OpcodeType::Inc => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterUnderflow;
-                } else if opcode.op_regs.len() > 1 {
-                    return Error::RegisterOverflow;
-                }
-                
-                let reg1 = *self.find_register(&opcode, 0).unwrap();
-                self.set_tsr(reg1);
-                unsafe {
-                    match self.tsr {
-                        0 => {
-                            let reg1 = self.find_register(&opcode, 0).unwrap();
-                            *reg1 = Word { as_u64: reg1.as_u64 + 1 };
-                        }
-                        1 => {
-                            let reg1 = self.find_register(&opcode, 0).unwrap();
-                            *reg1 = Word { as_i64: reg1.as_i64 + 1 };
-                        }
-                        2 => {
-                            let reg1 = self.find_register(&opcode, 0).unwrap();
-                            *reg1 = Word { as_f64: reg1.as_f64 + 1.0 };
-                        }
-    
-                        _ => {}
-                    }
-                }
-                self.pc += 1
-            }
-            
-            OpcodeType::Equal => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterUnderflow;
-                } else if opcode.op_regs.len() > 3 {
-                    return Error::RegisterOverflow;
-                }
-                
-                let reg1 = *self.find_register(&opcode, 1).unwrap();
-                let reg2 = *self.find_register(&opcode, 2).unwrap();
-                self.set_tsr(reg1);
-                unsafe {
-                    match self.tsr {
-                        0 => {
-                            let sum = Word { as_u64: (reg1.as_u64 == reg2.as_u64) as u64 };
-                            
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = sum;
-                        }
-                        1 => {
-                            let sum = Word { as_u64: (reg1.as_i64 == reg2.as_i64) as u64 };
-                            
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = sum;
-                        }
-                        2 => {
-                            let sum = Word { as_u64: (reg1.as_f64 == reg2.as_f64) as u64 };
-                            
-                            let dest = self.find_register(&opcode, 0).unwrap();
-                            *dest = sum;
-                        }
-    
-                        _ => {}
-                    }
-                }
-                self.pc += 1
-            }
-            
-            OpcodeType::Jt => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterUnderflow;
-                } else if opcode.op_regs.len() > 1 {
-                    return Error::RegisterOverflow;
-                }
-                
-                let reg1 = *self.find_register(&opcode, 0).unwrap();
-                unsafe {
-                    if reg1.as_u64 == 1 {
-                        self.pc = opcode.op_operand.unwrap().as_usize;
-                    } else {
-                        self.pc += 1
-                    }
-                }
-            }
-            OpcodeType::Jz => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterUnderflow;
-                } else if opcode.op_regs.len() > 1 {
-                    return Error::RegisterOverflow;
-                }
-                
-                let reg1 = *self.find_register(&opcode, 0).unwrap();
-                unsafe {
-                    if reg1.as_u64 == 0 {
-                        self.pc = opcode.op_operand.unwrap().as_usize;
-                    } else {
-                        self.pc += 1
-                    }
-                }
-            }
-            OpcodeType::Jnz => {
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterUnderflow;
-                } else if opcode.op_regs.len() > 1 {
-                    return Error::RegisterOverflow;
-                }
-                
-                let reg1 = *self.find_register(&opcode, 0).unwrap();
-                unsafe {
-                    if reg1.as_u64 != 0 {
-                        self.pc = opcode.op_operand.unwrap().as_usize;
-                    } else {
-                        self.pc += 1
-                    }
-                }
-            }
-            
-            OpcodeType::Sysf => {
-                unsafe {
-                    if self.r7.as_u64 == 0 {
-                        return Error::InvalidSysFunction;
-                    }
-                    
-                    if opcode.op_regs.is_empty() {
-                        self.sys_functions[self.sys_functions.len() - self.r7.as_usize](self, &opcode.clone(), Vec::new());
-                    } else {
-                        self.sys_functions[self.sys_functions.len() - self.r7.as_usize](self, &opcode.clone(), opcode.op_regs);
-                    }
-                }
-                self.pc += 1;
-            }
-            
-            // Stack opcodes
-            OpcodeType::Push => {
-                match opcode.op_operand {
-                    None => {
-                        if opcode.op_regs.len() < 1 {
-                            return Error::RegisterUnderflow;
-                        } else if opcode.op_regs.len() > 1 {
-                            return Error::RegisterOverflow;
-                        }
-                        
-                        let reg = *self.find_register(&opcode, 0).unwrap();
-                        self.stack.push(reg);
-                    }
-                    
-                    _ => {
-                        self.stack.push(opcode.op_operand.unwrap());
-                    }
-                }
-                self.pc += 1
-            }
-            
-            OpcodeType::Adds => {
-                if self.stack.len() < 2 {
-                    return Error::StackUnderflow;
-                }
-                
-                let a = self.stack.pop().unwrap();
-                let b = self.stack.pop().unwrap();
-                self.set_tsr(b);
-                unsafe {
-                    match self.tsr {
-                        0 => self.stack.push(Word { as_u64: b.as_u64 + a.as_u64 }),
-                        1 => self.stack.push(Word { as_i64: b.as_i64 + a.as_i64 }),
-                        2 => self.stack.push(Word { as_f64: b.as_f64 + a.as_f64 }),
-    
-                        _ => {}
-                    }
-                }
-                self.pc += 1
-            }
-            OpcodeType::Subs => {
-                if self.stack.len() < 2 {
-                    return Error::StackUnderflow;
-                }
-                
-                let a = self.stack.pop().unwrap();
-                let b = self.stack.pop().unwrap();
-                self.set_tsr(b);
-                unsafe {
-                    match self.tsr {
-                        0 => self.stack.push(Word { as_u64: b.as_u64 - a.as_u64 }),
-                        1 => self.stack.push(Word { as_i64: b.as_i64 - a.as_i64 }),
-                        2 => self.stack.push(Word { as_f64: b.as_f64 - a.as_f64 }),
-    
-                        _ => {}
-                    }
-                }
-                self.pc += 1;
-            }
-            
-            OpcodeType::Muls => {
-                if self.stack.len() < 2 {
-                    return Error::StackUnderflow;
-                }
-                
-                let a = self.stack.pop().unwrap();
-                let b = self.stack.pop().unwrap();
-                self.set_tsr(b);
-                unsafe {
-                    match self.tsr {
-                        0 => self.stack.push(Word { as_u64: b.as_u64 * a.as_u64 }),
-                        1 => self.stack.push(Word { as_i64: b.as_i64 * a.as_i64 }),
-                        2 => self.stack.push(Word { as_f64: b.as_f64 * a.as_f64 }),
-    
-                        _ => {}
-                    }
-                }
-                self.pc += 1
-            }
-            OpcodeType::Divs => {
-                if self.stack.len() < 2 {
-                    return Error::StackUnderflow;
-                }
-                
-                let a = self.stack.pop().unwrap();
-                let b = self.stack.pop().unwrap();
-                self.set_tsr(b);
-                unsafe {
-                    match self.tsr {
-                        0 => self.stack.push(Word { as_u64: b.as_u64 / a.as_u64 }),
-                        1 => self.stack.push(Word { as_i64: b.as_i64 / a.as_i64 }),
-                        2 => self.stack.push(Word { as_f64: b.as_f64 / a.as_f64 }),
-    
-                        _ => {}
-                    }
-                }
-                self.pc += 1
-            }
-            
-            OpcodeType::Equals => {
-                if self.stack.len() < 2 {
-                    return Error::StackUnderflow;
-                }
-                
-                let a = self.stack[self.stack.len() - 1];
-                let b = self.stack[self.stack.len() - 2];
-                self.set_tsr(b);
-                unsafe {
-                    match self.tsr {
-                        0 => self.stack.push(Word { as_u64: (b.as_u64 == a.as_u64) as u64 }),
-                        1 => self.stack.push(Word { as_u64: (b.as_i64 == a.as_i64) as u64 }),
-                        2 => self.stack.push(Word { as_u64: (b.as_f64 == a.as_f64) as u64 }),
-    
-                        _ => {}
-                    }
-                }
-                self.pc += 1
-            }
-            
-            OpcodeType::Dupl => {
-                unsafe {
-                    if self.stack.len() - opcode.op_operand.unwrap().as_usize <= 0 {
-                        return Error::StackUnderflow;
-                    }
-                    
-                    self.stack.push(self.stack[self.stack.len() - 1 - opcode.op_operand.unwrap().as_usize]);
-                    self.pc += 1
-                }
-            }
-            
-            OpcodeType::Jts => {
-                if self.stack.len() < 1 {
-                    return Error::StackUnderflow;
-                }
-                
-                let a = self.stack.pop().unwrap();
-                unsafe {
-                    if a.as_u64 == 1 {
-                        self.pc = opcode.op_operand.unwrap().as_usize;
-                    } else {
-                        self.pc += 1
-                    }
-                }
-            }
-            OpcodeType::Jzs => {
-                if self.stack.len() < 1 {
-                    return Error::StackUnderflow;
-                }
-                
-                let a = self.stack.pop().unwrap();
-                unsafe {
-                    if a.as_u64 == 0 {
-                        self.pc = opcode.op_operand.unwrap().as_usize;
-                    } else {
-                        self.pc += 1;
-                    }
-                }
-            }
-            OpcodeType::Jnzs => {
-                if self.stack.len() < 1 {
-                    return Error::StackUnderflow;
-                }
-                
-                let a = self.stack.pop().unwrap();
-                unsafe {
-                    if a.as_u64 != 0 {
-                        self.pc = opcode.op_operand.unwrap().as_usize;
-                    } else {
-                        self.pc += 1
-                    }
-                }
-            }
-            
-            OpcodeType::Swc => {
-                if self.stack.len() < 2 {
-                    return Error::StackUnderflow;
-                }
-                
-                let a = self.stack.len() - 1;
-                unsafe {
-                    let b = self.stack.len() - 1 - opcode.op_operand.unwrap().as_usize;
-                    let old = self.stack[a];
-                    self.stack[a] = self.stack[b];
-                    self.stack[b] = old;
-                }
-                self.pc += 1
-            }
-            
-            // Universal opcodes
-            OpcodeType::Jmp => {
-                unsafe {
-                    self.pc = opcode.op_operand.unwrap().as_usize;
-                }
-            }
-            OpcodeType::Call => {
-                self.rspc = self.pc + 1;
-                unsafe {
-                    self.pc = opcode.op_operand.unwrap().as_usize;
-                }
-            }
-            
-            OpcodeType::Read => {
-                unsafe {
-                    match opcode.op_operand.unwrap().as_u64 {
-                        8 => {}
-                        16 => {}
-                        32 => {}
-                        64 => {}
-                        
-                        _ => {
-                            error!("invalid read size: `{}`", opcode.op_operand.unwrap().as_u64);
-                            exit(1);
-                        }
-                    }
-                }
-                
-                if opcode.op_regs.is_empty() {
-                    if self.stack.len() < 1 {
-                        return Error::StackUnderflow;
-                    }
-                    
-                    unsafe {
-                        let addr = self.stack.pop().unwrap();
-                        self.set_tsr(addr);
-                        match opcode.op_operand.unwrap().as_u64 {
-                            8 => {
-                                if addr.as_usize >= self.memory.capacity() {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                self.stack.push(Word { as_u64: self.memory[addr.as_usize] as u64 });
-                            }
-                            16 => {
-                                if addr.as_usize >= self.memory.capacity() - 1 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                let value = u16::from_ne_bytes(self.memory[addr.as_usize..addr.as_usize + 2].try_into().unwrap());
-                                self.stack.push(Word { as_u64: value as u64 });
-                            }
-                            32 => {
-                                if addr.as_usize >= self.memory.capacity() - 3 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                let value = u32::from_ne_bytes(self.memory[addr.as_usize..addr.as_usize + 4].try_into().unwrap());
-                                self.stack.push(Word { as_u64: value as u64 });
-                            }
-                            64 => {
-                                if addr.as_usize >= self.memory.capacity() - 7 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                let value = u64::from_ne_bytes(self.memory[addr.as_usize..addr.as_usize + 8].try_into().unwrap());
-                                self.stack.push(Word { as_u64: value });
-                            }
-                            
-                            _ => {}
-                        }
-                    }
-                } else {
-                    if opcode.op_regs.len() < 1 {
-                        return Error::RegisterUnderflow;
-                    } else if opcode.op_regs.len() > 2 {
-                        return Error::RegisterOverflow;
-                    }
-                    
-                    let reg1 = *self.find_register(&opcode, 2).unwrap();
-                    unsafe {
-                        self.set_tsr(reg1);
-                        match opcode.op_operand.unwrap().as_u64 {
-                            8 => {
-                                if reg1.as_usize >= self.memory.capacity() {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                self.assign_register(&opcode, 1, Word { as_u64: self.memory[reg1.as_usize] as u64 });
-                            }
-                            16 => {
-                                if reg1.as_usize >= self.memory.capacity() - 1 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                let value = u16::from_ne_bytes(self.memory[reg1.as_usize..reg1.as_usize + 2].try_into().unwrap());
-                                self.assign_register(&opcode, 1, Word { as_u64: value as u64 });
-                            }
-                            32 => {
-                                if reg1.as_usize >= self.memory.capacity() - 3 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                let value = u32::from_ne_bytes(self.memory[reg1.as_usize..reg1.as_usize + 4].try_into().unwrap());
-                                self.assign_register(&opcode, 1, Word { as_u64: value as u64 });
-                            }
-                            64 => {
-                                if reg1.as_usize >= self.memory.capacity() - 7 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                let value = u64::from_ne_bytes(self.memory[reg1.as_usize..reg1.as_usize + 8].try_into().unwrap());
-                                self.assign_register(&opcode, 1, Word { as_u64: value });
-                            }
-                            
-                            _ => {}
-                        }
-                    }
-                }
-                self.pc += 1
-            }
-            OpcodeType::Write => {
-                unsafe {
-                    match opcode.op_operand.unwrap().as_u64 {
-                        8 => {}
-                        16 => {}
-                        32 => {}
-                        64 => {}
-                        
-                        _ => {
-                            error!("invalid read size: `{}`", opcode.op_operand.unwrap().as_u64);
-                            exit(1);
-                        }
-                    }
-                }
-                
-                if opcode.op_regs.is_empty() {
-                    if self.stack.len() < 1 {
-                        return Error::StackUnderflow;
-                    }
-                    
-                    unsafe {
-                        let addr = self.stack.pop().unwrap();
-                        let value = self.stack.pop().unwrap();
-                        self.set_tsr(value);
-                        match opcode.op_operand.unwrap().as_u64 {
-                            8 => {
-                                if addr.as_usize >= self.memory.capacity() {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                self.memory[addr.as_usize] = value.as_u64 as u8;
-                            }
-                            16 => {
-                                if addr.as_usize >= self.memory.capacity() - 1 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                self.memory[addr.as_usize..addr.as_usize + 2].copy_from_slice(&(value.as_u64 as u16).to_ne_bytes());
-                            }
-                            32 => {
-                                if addr.as_usize >= self.memory.capacity() - 3 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                self.memory[addr.as_usize..addr.as_usize + 4].copy_from_slice(&(value.as_u64 as u32).to_ne_bytes());
-                            }
-                            64 => {
-                                if addr.as_usize >= self.memory.capacity() - 7 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                self.memory[addr.as_usize..addr.as_usize + 8].copy_from_slice(&value.as_u64.to_ne_bytes());
-                            }
-                            
-                            _ => {}
-                        }
-                    }
-                } else {
-                    if opcode.op_regs.len() < 1 {
-                        return Error::RegisterUnderflow;
-                    } else if opcode.op_regs.len() > 2 {
-                        return Error::RegisterOverflow;
-                    }
-                    
-                    let addr = *self.find_register(&opcode, 0).unwrap();
-                    let reg1 = *self.find_register(&opcode, 1).unwrap();
-                    unsafe {
-                        self.set_tsr(reg1);
-                        match opcode.op_operand.unwrap().as_u64 {
-                            8 => {
-                                if reg1.as_usize >= self.memory.capacity() {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                self.memory[addr.as_usize] = reg1.as_u64 as u8;
-                            }
-                            16 => {
-                                if reg1.as_usize >= self.memory.capacity() - 1 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                self.memory[addr.as_usize..addr.as_usize + 2].copy_from_slice(&(reg1.as_u64 as u16).to_ne_bytes());
-                            }
-                            32 => {
-                                if reg1.as_usize >= self.memory.capacity() - 3 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                self.memory[addr.as_usize..addr.as_usize + 4].copy_from_slice(&(reg1.as_u64 as u32).to_ne_bytes());
-                            }
-                            64 => {
-                                if reg1.as_usize >= self.memory.capacity() - 7 {
-                                    return Error::ErrIllegalMemoryAccess;
-                                }
-                                
-                                self.memory[addr.as_usize..addr.as_usize + 8].copy_from_slice(&(reg1.as_u64 as u64).to_ne_bytes());
-                            }
-                            
-                            _ => {}
-                        }
-                    }
-                }
-                self.pc += 1
-            }
-            
-            OpcodeType::And => {
-                if opcode.op_regs.is_empty() {
-                    if self.stack.len() < 2 {
-                        return Error::StackUnderflow;
-                    }
-                    
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-                    self.set_tsr(b);
-                    unsafe {
-                        match self.tsr {
-                            0 => self.stack.push(Word { as_u64: b.as_u64 & a.as_u64 }),
-                            1 => self.stack.push(Word { as_i64: b.as_i64 & a.as_i64 }),
-                            
-                            _ => {}
-                        }
-                    }
-                } else {
-                    if opcode.op_regs.len() < 1 {
-                        return Error::RegisterUnderflow;
-                    } else if opcode.op_regs.len() > 3 {
-                        return Error::RegisterOverflow;
-                    }
-                    
-                    let reg1 = *self.find_register(&opcode, 1).unwrap();
-                    let reg2 = *self.find_register(&opcode, 2).unwrap();
-                    self.set_tsr(reg1);
-                    unsafe {
-                        match self.tsr {
-                            0 => {
-                                let sum = reg1.as_u64 & reg2.as_u64;
-                        
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_u64: sum };
-                            }
-                            1 => {
-                                let sum = reg1.as_i64 ^ reg2.as_i64;
-                                
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_i64: sum };
-                            }
-        
-                            _ => {}
-                        }
-                    }
-                }
-                self.pc += 1
-            }
-            OpcodeType::Or => {
-                if opcode.op_regs.is_empty() {
-                    if self.stack.len() < 2 {
-                        return Error::StackUnderflow;
-                    }
-                    
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-                    self.set_tsr(b);
-                    unsafe {
-                        match self.tsr {
-                            0 => self.stack.push(Word { as_u64: b.as_u64 | a.as_u64 }),
-                            1 => self.stack.push(Word { as_i64: b.as_i64 | a.as_i64 }),
-                            
-                            _ => {}
-                        }
-                    }
-                } else {
-                    if opcode.op_regs.len() < 1 {
-                        return Error::RegisterUnderflow;
-                    } else if opcode.op_regs.len() > 3 {
-                        return Error::RegisterOverflow;
-                    }
-                    
-                    let reg1 = *self.find_register(&opcode, 1).unwrap();
-                    let reg2 = *self.find_register(&opcode, 2).unwrap();
-                    self.set_tsr(reg1);
-                    unsafe {
-                        match self.tsr {
-                            0 => {
-                                let sum = reg1.as_u64 | reg2.as_u64;
-                        
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_u64: sum };
-                            }
-                            1 => {
-                                let sum = reg1.as_i64 | reg2.as_i64;
-                                
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_i64: sum };
-                            }
-        
-                            _ => {}
-                        }
-                    }
-                }
-                self.pc += 1
-            }
-            OpcodeType::Xor => {
-                if opcode.op_regs.is_empty() {
-                    if self.stack.len() < 2 {
-                        return Error::StackUnderflow;
-                    }
-                    
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-                    self.set_tsr(b);
-                    unsafe {
-                        match self.tsr {
-                            0 => self.stack.push(Word { as_u64: b.as_u64 ^ a.as_u64 }),
-                            1 => self.stack.push(Word { as_i64: b.as_i64 ^ a.as_i64 }),
-                            
-                            _ => {}
-                        }
-                    }
-                } else {
-                    if opcode.op_regs.len() < 1 {
-                        return Error::RegisterUnderflow;
-                    } else if opcode.op_regs.len() > 3 {
-                        return Error::RegisterOverflow;
-                    }
-                    
-                    let reg1 = *self.find_register(&opcode, 1).unwrap();
-                    let reg2 = *self.find_register(&opcode, 2).unwrap();
-                    self.set_tsr(reg1);
-                    unsafe {
-                        match self.tsr {
-                            0 => {
-                                let sum = reg1.as_u64 ^ reg2.as_u64;
-                        
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_u64: sum };
-                            }
-                            1 => {
-                                let sum = reg1.as_i64 ^ reg2.as_i64;
-                                
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_i64: sum };
-                            }
-        
-                            _ => {}
-                        }
-                    }
-                }
-                self.pc += 1
-            }
-            
-            OpcodeType::Shr => {
-                if opcode.op_regs.is_empty() {
-                    if self.stack.len() < 2 {
-                        return Error::StackUnderflow;
-                    }
-                    
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-                    self.set_tsr(b);
-                    unsafe {
-                        match self.tsr {
-                            0 => self.stack.push(Word { as_u64: b.as_u64 >> a.as_u64 }),
-                            1 => self.stack.push(Word { as_i64: b.as_i64 >> a.as_i64 }),
-                            
-                            _ => {}
-                        }
-                    }
-                } else {
-                    if opcode.op_regs.len() < 1 {
-                        return Error::RegisterUnderflow;
-                    } else if opcode.op_regs.len() > 3 {
-                        return Error::RegisterOverflow;
-                    }
-                    
-                    let reg1 = *self.find_register(&opcode, 1).unwrap();
-                    let reg2 = *self.find_register(&opcode, 2).unwrap();
-                    self.set_tsr(reg1);
-                    unsafe {
-                        match self.tsr {
-                            0 => {
-                                let sum = reg1.as_u64 >> reg2.as_u64;
-                        
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_u64: sum };
-                            }
-                            1 => {
-                                let sum = reg1.as_i64 >> reg2.as_i64;
-                                
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_i64: sum };
-                            }
-        
-                            _ => {}
-                        }
-                    }
-                }
-                self.pc += 1
-            }
-            OpcodeType::Shl => {
-                if opcode.op_regs.is_empty() {
-                    if self.stack.len() < 2 {
-                        return Error::StackUnderflow;
-                    }
-                    
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-                    self.set_tsr(b);
-                    unsafe {
-                        match self.tsr {
-                            0 => self.stack.push(Word { as_u64: b.as_u64 << a.as_u64 }),
-                            1 => self.stack.push(Word { as_i64: b.as_i64 << a.as_i64 }),
-                            
-                            _ => {}
-                        }
-                    }
-                } else {
-                    if opcode.op_regs.len() < 1 {
-                        return Error::RegisterUnderflow;
-                    } else if opcode.op_regs.len() > 3 {
-                        return Error::RegisterOverflow;
-                    }
-                    
-                    let reg1 = *self.find_register(&opcode, 1).unwrap();
-                    let reg2 = *self.find_register(&opcode, 2).unwrap();
-                    self.set_tsr(reg1);
-                    unsafe {
-                        match self.tsr {
-                            0 => {
-                                let sum = reg1.as_u64 << reg2.as_u64;
-                        
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_u64: sum };
-                            }
-                            1 => {
-                                let sum = reg1.as_i64 << reg2.as_i64;
-                                
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_i64: sum };
-                            }
-        
-                            _ => {}
-                        }
-                    }
-                }
-                self.pc += 1
-            }
-            
-            OpcodeType::Not => {
-                if opcode.op_regs.is_empty() {
-                    if self.stack.len() < 1 {
-                        return Error::StackUnderflow;
-                    }
-                    
-                    let a = self.stack.pop().unwrap();
-                    self.set_tsr(a);
-                    unsafe {
-                        match self.tsr {
-                            0 => self.stack.push(Word { as_u64: !a.as_u64 }),
-                            1 => self.stack.push(Word { as_i64: !a.as_i64 }),
-                            
-                            _ => {}
-                        }
-                    }
-                } else {
-                    if opcode.op_regs.len() < 1 {
-                        return Error::RegisterUnderflow;
-                    } else if opcode.op_regs.len() > 3 {
-                        return Error::RegisterOverflow;
-                    }
-                    
-                    let reg1 = *self.find_register(&opcode, 1).unwrap();
-                    self.set_tsr(reg1);
-                    unsafe {
-                        match self.tsr {
-                            0 => {
-                                let sum = !reg1.as_u64;
-                        
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_u64: sum };
-                            }
-                            1 => {
-                                let sum = !reg1.as_i64;
-                                
-                                let dest = self.find_register(&opcode, 0).unwrap();
-                                *dest = Word { as_i64: sum };
-                            }
-        
-                            _ => {}
-                        }
-                    }
-                }
-                self.pc += 1
-            }
-            
-            OpcodeType::Pop => {
-                if opcode.op_regs.is_empty() {
-                    if self.stack.len() < 1 {
-                        return Error::StackUnderflow;
-                    }
-                    
-                    self.stack.pop();
-                } else {
-                    if opcode.op_regs.len() < 1 {
-                        return Error::RegisterOverflow;
-                    } else if opcode.op_regs.len() > 1 {
-                        return Error::RegisterUnderflow;
-                    }
-                    
-                    self.set_tsr(Word { as_u64: 0 });
-                    self.assign_register(&opcode, 0, Word { as_u64: 0 });
-                }
-                self.pc += 1
-            }
-            
-            OpcodeType::Ret => {
-                self.pc = self.rspc;
-                self.rspc = 0;
-            }
-            OpcodeType::Hlt => {
-                self.halt = true;
-            }
-            
-            // Deprecated
-            OpcodeType::Phsr => {
-                if self.stack.len() < 1 {
-                    return Error::StackUnderflow;
-                }
-                
-                if opcode.op_regs.len() < 1 {
-                    return Error::RegisterOverflow;
-                } else if opcode.op_regs.len() > 1 {
-                    return Error::RegisterUnderflow;
-                }
-                
-                self.set_tsr(self.stack[self.stack.len() - 1]);
-                self.assign_register(&opcode, 0, self.stack[self.stack.len() - 1]);
-                self.pc += 1
-            }
-            
-            _ => {
-                return Error::InvalidOperand;
-            }
-        }
-        
-        Error::None
-    }
-    
-    fn get_operands<'a>(self: &Self, tokens: Vec<&'a str>, len1: usize, len2: usize, line_num: &usize) -> Vec<&'a str> {
-        let mut operands: Vec<&str> = tokens[0].trim().split(", ").collect();
-        if operands.len() < len1 || operands.len() > len2 {
-            error!("Invalid number of opcode arguments at line: {}", line_num);
-            exit(1);
-        }
-
-        operands
-    }
-    
-    pub fn translate_source(self: &mut Self, mut oasm: OASM, input_path: String, source: String) {
-        let preprocessor = Preprocessor {};
-        let mut source = preprocessor.process_includes(input_path.clone(), source);
-        source = preprocessor.process_source(input_path.clone(), source);
-        
-        let lines: Vec<&str> = source.lines().collect();
-        let mut line_num = 0;
-        for line in lines {
-            let ic = 0;
-            line_num += 1;
-            
-            let mut tokens: Vec<&str> = line.trim().splitn(2, char::is_whitespace).collect();
-            if !tokens.is_empty() && !tokens[0].is_empty() {
-                let mut inst_name = tokens[0];
-                tokens.remove(0);
-                
-                if inst_name.ends_with(':') {
-                    let label = inst_name.replace(":", "");
-                    oasm.labels_push(&label, self.program.len());
-                    
-                    if tokens.len() > 0 {
-                        tokens = tokens[0].trim().splitn(2, char::is_whitespace).collect();
-                        inst_name = tokens[0];
-                        tokens.remove(0);
-                    } else {
-                        continue;
-                    }
-                }
-                
-                match inst_name {
-                    // Register opcodes
-                    MOV => {
-                        let mut operands: Vec<&str> = self.get_operands(tokens.clone(), 2, 2, &line_num);
-                        
-                        if operands[1].starts_with("r") {
-                            self.program.push(Opcode { op_type: OpcodeType::Mov, op_operand: None, op_regs: vec![operands[0].to_string(), operands[1].to_string()] });
-                        } else if operands[1].starts_with(CONST) {
-                            if operands[1].replace(CONST, "").parse::<u64>().is_ok() {
-                                self.program.push(Opcode { op_type: OpcodeType::Mov, op_operand: Some(Word { as_u64: (operands[1].replace(CONST, "").parse().unwrap()) }), op_regs: vec![operands[0].to_string()] });
-                            } else if operands[1].replace(CONST, "").parse::<i64>().is_ok() {
-                                self.program.push(Opcode { op_type: OpcodeType::Mov, op_operand: Some(Word { as_i64: (operands[1].replace(CONST, "").parse().unwrap()) }), op_regs: vec![operands[0].to_string()] });
-                            } else if operands[1].replace(CONST, "").parse::<f64>().is_ok() {
-                                self.program.push(Opcode { op_type: OpcodeType::Mov, op_operand: Some(Word { as_f64: (operands[1].replace(CONST, "").parse().unwrap()) }), op_regs: vec![operands[0].to_string()] });
-                            }
-                        } else if operands[1].starts_with(GSI) {
-                            self.program.push(Opcode { op_type: OpcodeType::Movfs, op_operand: Some(Word { as_u64: (operands[1].replace(GSI, "").parse().unwrap()) }), op_regs: vec![operands[0].to_string()] });
-                        } else {
-                            error!("Invalid operand `{}` at line: {}", operands[1], line_num);
-                        }
-                    }
-                    PHSR => {
-                        error!("[Warning]: `phsr` is deprecated use `mov [reg], $[index]` instead.");
-                        self.program.push(Opcode { op_type: OpcodeType::Phsr, op_operand: None, op_regs: vec![tokens[0].to_string()] });
-                    }
-                    
-                    SRG => {
-                        let mut operands: Vec<&str> = self.get_operands(tokens.clone(), 2, 2, &line_num);
-                        
-                        self.program.push(Opcode { op_type: OpcodeType::Srg, op_operand: None, op_regs: vec![operands[0].to_string(), operands[1].to_string()] });
-                    }
-                    
-                    CLR => {
-                        let mut operands: Vec<&str> = self.get_operands(tokens.clone(), 1, 1, &line_num);
-                        
-                        self.program.push(Opcode { op_type: OpcodeType::Clr, op_operand: None, op_regs: vec![tokens[0].to_string()] });
-                    }
-                    
-                    ADD | SUB | MUL | DIV => {
-                        let mut operands: Vec<&str> = self.get_operands(tokens.clone(), 3, 3, &line_num);
-                        
-                        match inst_name {
-                            ADD => {
-                                self.program.push(Opcode { op_type: OpcodeType::Add, op_operand: None, op_regs: vec![operands[0].to_string(), operands[1].to_string(), operands[2].to_string()] });
-                            }
-                            SUB => {
-                                self.program.push(Opcode { op_type: OpcodeType::Sub, op_operand: None, op_regs: vec![operands[0].to_string(), operands[1].to_string(), operands[2].to_string()] });
-                            }
-                            MUL => {
-                                self.program.push(Opcode { op_type: OpcodeType::Mul, op_operand: None, op_regs: vec![operands[0].to_string(), operands[1].to_string(), operands[2].to_string()] });
-                            }
-                            DIV => {
-                                self.program.push(Opcode { op_type: OpcodeType::Div, op_operand: None, op_regs: vec![operands[0].to_string(), operands[1].to_string(), operands[2].to_string()] });
-                            }
-                    
-                            _ => {}
-                        }
-                    }
-                    
-                    DEC => {
-                        self.program.push(Opcode { op_type: OpcodeType::Dec, op_operand: None, op_regs: vec![tokens[0].to_string()] });
-                    }
-                    INC => {
-                        self.program.push(Opcode { op_type: OpcodeType::Inc, op_operand: None, op_regs: vec![tokens[0].to_string()] });
-                    }
-                    
-                    EQUAL => {
-                        let mut operands: Vec<&str> = self.get_operands(tokens.clone(), 3, 3, &line_num);
-                        
-                        self.program.push(Opcode { op_type: OpcodeType::Equal, op_operand: None, op_regs: vec![operands[0].to_string(), operands[1].to_string(), operands[2].to_string()] });
-                    }
-                    
-                    JT | JZ | JNZ => {
-                        let mut operands: Vec<&str> = self.get_operands(tokens.clone(), 2, 2, &line_num);
-                        
-                        if operands[0].starts_with(CONST) {
-                            match inst_name {
-                                JT => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jt, op_operand: Some(Word { as_u64: (operands[1].replace(CONST, "").parse().unwrap()) }), op_regs: vec![operands[1].to_string()] });
-                                }
-                                JZ => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jz, op_operand: Some(Word { as_u64: (operands[1].replace(CONST, "").parse().unwrap()) }), op_regs: vec![operands[1].to_string()] });
-                                }
-                                JNZ => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jnz, op_operand: Some(Word { as_u64: (operands[1].replace(CONST, "").parse().unwrap()) }), op_regs: vec![operands[1].to_string()] });
-                                }
-                                
-                                _ => {}
-                            }
-                        } else {
-                            oasm.deferred_operands_push(operands[0], self.program.len());
-                            match inst_name {
-                                JT => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jt, op_operand: None, op_regs: vec![operands[1].to_string()] });
-                                }
-                                JZ => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jz, op_operand: None, op_regs: vec![operands[1].to_string()] });
-                                }
-                                JNZ => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jnz, op_operand: None, op_regs: vec![operands[1].to_string()] });
-                                }
-                                
-                                _ => {}
-                            }
-                        }
-                    }
-                    
-                    SYSF => {
-                        if tokens.is_empty() {
-                            self.program.push(Opcode { op_type: OpcodeType::Sysf, op_operand: None, op_regs: Vec::new() });
-                        } else {
-                            let operands = self.get_operands(tokens, 1, 1, &line_num);
-                            self.program.push(Opcode { op_type: OpcodeType::Sysf, op_operand: None, op_regs: vec![operands[0].to_string()] });
-                        }
-                    }
-                    
-                    // Stack opcodes
-                    PUSH => {
-                        if tokens[0].starts_with('r') {
-                            self.program.push(Opcode { op_type: OpcodeType::Push, op_operand: None, op_regs: vec![tokens[0].to_string()] });
-                        } else if tokens[0].starts_with(CONST) {
-                            if tokens[0].replace(CONST, "").parse::<u64>().is_ok() {
-                                self.program.push(Opcode { op_type: OpcodeType::Push, op_operand: Some(Word { as_u64: (tokens[0].replace(CONST, "").parse().unwrap()) }), op_regs: Vec::new() });
-                            } else if tokens[0].replace(CONST, "").parse::<i64>().is_ok() {
-                                self.program.push(Opcode { op_type: OpcodeType::Push, op_operand: Some(Word { as_i64: (tokens[0].replace(CONST, "").parse().unwrap()) }), op_regs: Vec::new() });
-                            } else if tokens[0].replace(CONST, "").parse::<f64>().is_ok() {
-                                self.program.push(Opcode { op_type: OpcodeType::Push, op_operand: Some(Word { as_f64: (tokens[0].replace(CONST, "").parse().unwrap()) }), op_regs: Vec::new() });
-                            }
-                        } else {
-                            error!("Invalid operand `{}` at line: {}", tokens[0], line_num);
-                        }
-                    }
-                    
-                    ADDS | SUBS | MULS | DIVS => {
-                        match inst_name {
-                            ADDS => {
-                                self.program.push(Opcode { op_type: OpcodeType::Adds, op_operand: None, op_regs: Vec::new() });
-                            }
-                            SUBS => {
-                                self.program.push(Opcode { op_type: OpcodeType::Subs, op_operand: None, op_regs: Vec::new() });
-                            }
-                            MULS => {
-                                self.program.push(Opcode { op_type: OpcodeType::Muls, op_operand: None, op_regs: Vec::new() });
-                            }
-                            DIVS => {
-                                self.program.push(Opcode { op_type: OpcodeType::Divs, op_operand: None, op_regs: Vec::new() });
-                            }
-                            
-                            _ => {}
-                        }
-                    }
-                    
-                    DUPL => {
-                        let op: i64 = tokens[0].parse().unwrap();
-                        self.program.push(Opcode { op_type: OpcodeType::Dupl, op_operand: Some(Word { as_u64: op as u64 }), op_regs: Vec::new() });
-                    }
-                    
-                    EQUALS => {
-                        self.program.push(Opcode { op_type: OpcodeType::Equals, op_operand: None, op_regs: Vec::new() });
-                    }
-                    
-                    JTS | JZS | JNZS => {
-                        if tokens[0].starts_with(CONST) {
-                            match inst_name {
-                                JTS => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jts, op_operand: Some(Word { as_u64: (tokens[0].replace(CONST, "").parse().unwrap()) }), op_regs: Vec::new() });
-                                }
-                                JZS => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jzs, op_operand: Some(Word { as_u64: (tokens[0].replace(CONST, "").parse().unwrap()) }), op_regs: Vec::new() });
-                                }
-                                JNZS => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jnzs, op_operand: Some(Word { as_u64: (tokens[0].replace(CONST, "").parse().unwrap()) }), op_regs: Vec::new() });
-                                }
-                                
-                                _ => {}
-                            }
-                        } else {
-                            oasm.deferred_operands_push(tokens[0], self.program.len());
-                            match inst_name {
-                                JTS => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jts, op_operand: None, op_regs: Vec::new() });
-                                }
-                                JZS => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jzs, op_operand: None, op_regs: Vec::new() });
-                                }
-                                JNZS => {
-                                    self.program.push(Opcode { op_type: OpcodeType::Jnzs, op_operand: None, op_regs: Vec::new() });
-                                }
-                                
-                                _ => {}
-                            }
-                        }
-                    }
-                    
-                    SWC => {
-                        let op: i64 = tokens[0].parse().unwrap();
-                        self.program.push(Opcode { op_type: OpcodeType::Swc, op_operand: Some(Word { as_u64: op as u64 }), op_regs: Vec::new() });
-                    }
-                    
-                    // Universal opcodes
-                    JMP => {
-                        if tokens[0].starts_with(CONST) {
-                            self.program.push(Opcode { op_type: OpcodeType::Jmp, op_operand: Some(Word { as_u64: (tokens[0].replace(CONST, "").parse().unwrap()) }), op_regs: Vec::new() });
-                        } else {
-                            oasm.deferred_operands_push(tokens[0], self.program.len());
-                            self.program.push(Opcode { op_type: OpcodeType::Jmp, op_operand: None, op_regs: Vec::new() });
-                        }
-                    }
-                    
-                    CALL => {
-                        oasm.deferred_operands_push(tokens[0], self.program.len());
-                        self.program.push(Opcode { op_type: OpcodeType::Call, op_operand: None, op_regs: Vec::new() });
-                    }
-                    
-                    READ => {
-                        if tokens.len() > 1 && tokens[1].starts_with('r') && !tokens.is_empty() {
-                            let operand = self.get_operands(tokens.clone(), 3, 3, &line_num);
-                            self.program.push(Opcode { op_type: OpcodeType::Read, op_operand: Some(Word { as_u64: (operand[0].replace(CONST, "").parse().unwrap()) }), op_regs: vec![operand[1].to_string(), operand[2].to_string()] });
-                        } else {
-                            self.program.push(Opcode { op_type: OpcodeType::Read, op_operand: Some(Word { as_u64: (tokens[0].replace(CONST, "").parse().unwrap()) }), op_regs: Vec::new() });
-                        }
-                    }
-                    WRITE => {
-                        if tokens.len() > 0 && tokens[0].contains('r') && !tokens.is_empty() {
-                            let operand = self.get_operands(tokens.clone(), 3, 3, &line_num);
-                            self.program.push(Opcode { op_type: OpcodeType::Write, op_operand: Some(Word { as_u64: (operand[0].replace(CONST, "").parse().unwrap()) }), op_regs: vec![operand[1].to_string(), operand[2].to_string()] });
-                        } else {
-                            self.program.push(Opcode { op_type: OpcodeType::Write, op_operand: Some(Word { as_u64: (tokens[0].replace(CONST, "").parse().unwrap()) }), op_regs: Vec::new() });
-                        }
-                    }
-                    
-                    AND => {
-                        if tokens.len() > 0 && tokens[0].starts_with('r') && !tokens.is_empty() {
-                            let operand = self.get_operands(tokens.clone(), 3, 3, &line_num);
-                            self.program.push(Opcode { op_type: OpcodeType::And, op_operand: None, op_regs: vec![operand[0].to_string(), operand[1].to_string(), operand[2].to_string()] });
-                        } else {
-                            self.program.push(Opcode { op_type: OpcodeType::And, op_operand: None, op_regs: Vec::new() });
-                        }
-                    }
-                    
-                    OR => {
-                        if tokens.len() > 0 && tokens[0].starts_with('r') && !tokens.is_empty() {
-                            let operand = self.get_operands(tokens.clone(), 3, 3, &line_num);
-                            self.program.push(Opcode { op_type: OpcodeType::Or, op_operand: None, op_regs: vec![operand[0].to_string(), operand[1].to_string(), operand[2].to_string()] });
-                        } else {
-                            self.program.push(Opcode { op_type: OpcodeType::Or, op_operand: None, op_regs: Vec::new() });
-                        }
-                    }
-                    XOR => {
-                        if tokens.len() > 0 && tokens[0].starts_with('r') && !tokens.is_empty() {
-                            let operand = self.get_operands(tokens.clone(), 3, 3, &line_num);
-                            self.program.push(Opcode { op_type: OpcodeType::Xor, op_operand: None, op_regs: vec![operand[0].to_string(), operand[1].to_string(), operand[2].to_string()] });
-                        } else {
-                            self.program.push(Opcode { op_type: OpcodeType::Xor, op_operand: None, op_regs: Vec::new() });
-                        }
-                    }
-                    
-                    SHL => {
-                        if tokens.len() > 0 && tokens[0].starts_with('r') && !tokens.is_empty() {
-                            let operand = self.get_operands(tokens.clone(), 3, 3, &line_num);
-                            self.program.push(Opcode { op_type: OpcodeType::Shl, op_operand: None, op_regs: vec![operand[0].to_string(), operand[1].to_string(), operand[2].to_string()] });
-                        } else {
-                            self.program.push(Opcode { op_type: OpcodeType::Shl, op_operand: None, op_regs: Vec::new() });
-                        }
-                    }
-                    SHR => {
-                        if tokens.len() > 0 && tokens[0].starts_with('r') && !tokens.is_empty() {
-                            let operand = self.get_operands(tokens.clone(), 3, 3, &line_num);
-                            self.program.push(Opcode { op_type: OpcodeType::Shr, op_operand: None, op_regs: vec![operand[0].to_string(), operand[1].to_string(), operand[2].to_string()] });
-                        } else {
-                            self.program.push(Opcode { op_type: OpcodeType::Shr, op_operand: None, op_regs: Vec::new() });
-                        }
-                    }
-                    
-                    NOT => {
-                        if tokens.len() > 0 && tokens[0].starts_with('r') && !tokens.is_empty() {
-                            let operand = self.get_operands(tokens.clone(), 2, 2, &line_num);
-                            self.program.push(Opcode { op_type: OpcodeType::Not, op_operand: None, op_regs: vec![operand[0].to_string(), operand[1].to_string()] });
-                        } else {
-                            self.program.push(Opcode { op_type: OpcodeType::Not, op_operand: None, op_regs: Vec::new() });
-                        }
-                    }
-                    
-                    POP => {
-                        if tokens.len() > 0 && tokens[0].starts_with('r') && !tokens.is_empty() {
-                            self.program.push(Opcode { op_type: OpcodeType::Pop, op_operand: None, op_regs: vec![tokens[0].to_string()] });
-                        } else {
-                            self.program.push(Opcode { op_type: OpcodeType::Pop, op_operand: None, op_regs: Vec::new() });
-                        }
-                    }
-                    
-                    RET => {
-                        self.program.push(Opcode { op_type: OpcodeType::Ret, op_operand: None, op_regs: Vec::new() });
-                    }
-                    
-                    HLT => {
-                        self.program.push(Opcode { op_type: OpcodeType::Hlt, op_operand: None, op_regs: Vec::new() });
-                    }
-                    
-                    _ => {
-                        error!("Invalid instruction `{}` at line: {}", inst_name, line_num);
-                    }
-                }
-            }
-            
-        }
-        
-        for label in oasm.labels.clone() {
-            if label.name == "_start" {
-                self.pc = label.addr;
-            }
-        }
-        
-        for i in 0..oasm.deferred_operands.len() {
-            let label_addr = oasm.labels_contains(oasm.deferred_operands[i].label.as_str());
-            self.program[oasm.deferred_operands[i].addr].op_operand = Some(Word { as_u64: label_addr.unwrap() as u64 });
-        }
-        
-    }
-    
-    pub fn load_program_from_memory(self: &mut Self, program: Vec<Opcode>) {
-        self.program.extend_from_slice(&program);
-    }
-    
-    pub fn dump(self: &Self) {
-        println!("\n[Registers]:");
-        println!("    r0:  {:?}", self.r0);
-        println!("    r1:  {:?}", self.r1);
-        println!("    r2:  {:?}", self.r2);
-        println!("    r3:  {:?}", self.r3);
-        println!("    r4:  {:?}", self.r4);
-        println!("    r5:  {:?}", self.r5);
-        println!("    r6:  {:?}", self.r6);
-        println!("    r7:  {:?}", self.r7);
-        println!("    r8:  {:?}", self.r8);
-        println!("    r9:  {:?}", self.r9);
-        println!("    r10: {:?}", self.r10);
-        println!("    r11: {:?}", self.r11);
-        println!("    r12: {:?}", self.r12);
-        println!("    r13: {:?}", self.r13);
-        println!("    r14: {:?}", self.r14);
-        println!("    r15: {:?}", self.r15);
-        println!("    r16: {:?}", self.r16);
-        println!("    tsr: {}", self.tsr);
-        println!("    rspc: {}", self.rspc);
-        println!("    pc:  {}", self.pc);
-        
-        print!("[Memory]: ");
-        for i in 0..20 {
-            print!("{:02x} ", self.memory[i]);
-        }
-        println!("");
-        
-        if self.stack.len() > 0 {
-            println!("[Stack]:");
-            for i in self.stack.clone() {
-                println!("    {:?}", i);
-            }
-        }
-    }
-    
-    pub fn execute_program(self: &mut Self) {
-        while !self.halt {
-            let err: Error = self.execute_opcode();
-            if err != Error::None {
-                error!("{}", err.as_string());
-                exit(1);
-            }
-        }
-    }
-    
-    pub fn execute_program_debug(self: &mut Self) {
-        while !self.halt {
-            let err: Error = self.execute_opcode();
-            let mut buffer = String::new();
-            
-            let _ = stdin().read_line(&mut buffer);
-            self.dump();
-            println!("[Instruction] => {:?}", self.program[self.pc]);
-            
-            if err != Error::None {
-                error!("{}", err.as_string());
-                exit(1);
-            }
-        }
-    }
+#![allow(unused, dead_code)]
+
+use crate::log::Log;
+
+use crate::utils::arith::*;
+use crate::utils::defines;
+use crate::utils::error;
+use crate::utils::file;
+
+use crate::oasm;
+use crate::opcode;
+use crate::program::Program;
+use crate::debugger::Debugger;
+use crate::watchpoint::*;
+use crate::recorder::*;
+use crate::trace::*;
+use crate::profiler::*;
+use crate::coverage::*;
+use crate::snapshot::*;
+use crate::dump::*;
+use crate::bytecode::*;
+use crate::dispatch::*;
+use crate::jit::*;
+use crate::utils::sys_functions::SysFunction;
+use crate::utils::sys_functions::SystemFunctions;
+
+use log::*;
+
+use std::{
+    alloc::alloc,
+    env,
+    ffi::{c_void, CString},
+    fs::File,
+    io::{self, Read, Write},
+    ops::{Add, Deref, Index},
+};
+
+use libc::{free, malloc};
+
+use std::io::{stdin, stdout};
+
+use defines::*;
+use oasm::*;
+use opcode::*;
+use error::*;
+use file::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub struct Frame {
+    pub call_addr: usize,
+    pub target_addr: usize,
+    pub return_addr: usize,
+}
+
+pub struct OSVM {
+    // Registers, indexed like REGISTERS
+    pub(crate) regs: [TypedWord; 17],
+    
+    pub tsr: usize,
+    pub(crate) pc: usize,
+    entry: usize,
+
+    // Stack
+    pub stack: Vec<TypedWord>,
+    pub memory: Vec<u8>,
+    
+    // Call stack
+    pub call_stack: Vec<Frame>,
+    pub(crate) call_stack_depth: usize,
+    
+    // Other
+    pub program: Bytecode,
+    pub symbols: Vec<Label>,
+    pub sys_functions: Vec<SysFunction>,
+    
+    // Where the print sysfs write to, stdout unless the host captures it
+    pub output: Box<dyn Write>,
+    
+    // Watchpoints
+    pub watchpoints: Vec<Watchpoint>,
+    pub watch_hits: Vec<WatchHit>,
+    watch_callback: Option<WatchCallback>,
+    
+    // Reverse execution and instrumentation
+    pub recorder: Option<Recorder>,
+    pub tracer: Option<Tracer>,
+    pub profiler: Option<Profiler>,
+    pub coverage: Option<Coverage>,
+    
+    pub(crate) halt: bool,
+    
+    // Full error of the last fault in the decoded loop, see dispatch.rs
+    pub(crate) fault: Option<Error>,
+}
+
+impl OSVM {
+    pub fn init_log(self: &Self) {
+        Log::init();
+        info!("Initialized Log!");
+    }
+    
+    pub fn init() -> OSVM {
+        OSVM {
+            // Registers
+            regs: [TypedWord::u64(0); 17],
+            
+            tsr: 0,
+            pc: 0,
+            entry: 0,
+            
+            // Stack
+            stack: Vec::new(),
+            memory: vec![0 ; MEMORY_CAPACITY],
+            
+            // Call stack
+            call_stack: Vec::new(),
+            call_stack_depth: CALL_STACK_CAPACITY,
+            
+            // Other
+            program: Bytecode::init(),
+            symbols: Vec::new(),
+            
+            sys_functions: Vec::new(),
+            output: Box::new(stdout()),
+            
+            // Watchpoints
+            watchpoints: Vec::new(),
+            watch_hits: Vec::new(),
+            watch_callback: None,
+            
+            // Reverse execution and instrumentation
+            recorder: None,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            
+            halt: false,
+            fault: None,
+        }
+    }
+    
+    pub fn init_default_sysf(self: &mut Self) {
+        self.sys_functions = vec![
+            SystemFunctions::print,
+            SystemFunctions::print_mem,
+            SystemFunctions::print_ptr,
+            SystemFunctions::print_f64,
+            SystemFunctions::print_i64,
+            SystemFunctions::print_u64,
+            SystemFunctions::free,
+            SystemFunctions::alloc,
+        ]
+    }
+    
+    pub fn set_call_stack_depth(self: &mut Self, depth: usize) {
+        self.call_stack_depth = depth;
+    }
+    
+    pub fn assign_register(self: &mut Self, opcode: &Opcode, index: usize, new_value: TypedWord) -> Result<(), ErrorKind> {
+        let reg = opcode.op_regs.get(index).ok_or(ErrorKind::InvalidRegister)?;
+        let slot = &mut self.regs[reg];
+        let old = *slot;
+        *slot = new_value;
+        
+        if self.is_observed() {
+            self.register_assigned(reg, old, new_value);
+        }
+        
+        Ok(())
+    }
+    
+    pub fn find_register(self: &mut Self, opcode: &Opcode, index: usize) -> Option<&mut TypedWord> {
+        let reg = opcode.op_regs.get(index)?;
+        Some(&mut self.regs[reg])
+    }
+    
+    pub fn register_by_name(self: &mut Self, name: &str) -> Option<&mut TypedWord> {
+        self.register_by_index(register_index(name)?)
+    }
+    
+    // Index into REGISTERS
+    pub fn register_by_index(self: &mut Self, index: usize) -> Option<&mut TypedWord> {
+        self.regs.get_mut(index)
+    }
+    
+    pub fn registers(self: &Self) -> [TypedWord; 17] {
+        self.regs
+    }
+    
+    pub fn read_register(self: &mut Self, opcode: &Opcode, index: usize) -> Result<TypedWord, ErrorKind> {
+        let value = match self.find_register(opcode, index) {
+            Some(reg) => *reg,
+            None => return Err(ErrorKind::InvalidRegister),
+        };
+        
+        if let Some(tracer) = &mut self.tracer {
+            if let Some(reg) = opcode.op_regs.get(index) {
+                tracer.register_read(reg, value);
+            }
+        }
+        
+        Ok(value)
+    }
+    
+    pub fn add_watchpoint(self: &mut Self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(watchpoint);
+        self.watchpoints.len() - 1
+    }
+    
+    pub fn set_watch_callback(self: &mut Self, callback: WatchCallback) {
+        self.watch_callback = Some(callback);
+    }
+    
+    fn watch_hit(self: &mut Self, watchpoint: usize, event: WatchEvent) {
+        let hit = WatchHit { pc: self.pc, watchpoint, event };
+        if let Some(callback) = &mut self.watch_callback {
+            callback(&hit);
+        }
+        
+        self.watch_hits.push(hit);
+    }
+    
+    // Watchpoints and the tracer need to see every register and memory access
+    fn is_observed(self: &Self) -> bool {
+        !self.watchpoints.is_empty() || self.tracer.is_some()
+    }
+    
+    fn register_assigned(self: &mut Self, reg: usize, old: TypedWord, new: TypedWord) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.writes.push((reg, old, new));
+        }
+        
+        for i in 0..self.watchpoints.len() {
+            if self.watchpoints[i] == Watchpoint::Register(reg) {
+                self.watch_hit(i, WatchEvent::Register { reg, old, new });
+            }
+        }
+    }
+    
+    fn memory_accessed(self: &mut Self, addr: usize, len: usize, access: WatchAccess, old: u64, new: u64) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.memory.push(MemoryEffect { access, addr, len, old, new });
+        }
+        
+        for i in 0..self.watchpoints.len() {
+            if let Watchpoint::Memory { start, len: watch_len, access: watch_access } = self.watchpoints[i] {
+                let overlaps = addr < start.saturating_add(watch_len) && start < addr + len;
+                if overlaps && watch_access.matches(access) {
+                    self.watch_hit(i, WatchEvent::Memory { addr, len, access, old, new });
+                }
+            }
+        }
+    }
+    
+    pub(crate) fn load_memory(self: &Self, addr: usize, bytes: usize) -> u64 {
+        match bytes {
+            1 => self.memory[addr] as u64,
+            2 => u16::from_ne_bytes(self.memory[addr..addr + 2].try_into().unwrap()) as u64,
+            4 => u32::from_ne_bytes(self.memory[addr..addr + 4].try_into().unwrap()) as u64,
+            _ => u64::from_ne_bytes(self.memory[addr..addr + 8].try_into().unwrap()),
+        }
+    }
+    
+    pub fn set_tsr(self: &mut Self, value: TypedWord) {
+        self.tsr = value.ty as usize;
+    }
+    
+    // Executes the opcode at pc, errors carry the pc and the faulting opcode
+    pub fn execute_opcode(self: &mut Self) -> Result<(), Error> {
+        let opcode = match self.program.get(self.pc) {
+            Some(opcode) => opcode,
+            None => return Err(Error::new(ErrorKind::InvalidOpcodeAccess).at_pc(self.pc, None)),
+        };
+        
+        self.watch_hits.clear();
+        let pc = self.pc;
+        if !self.is_instrumented() {
+            return self.step(&opcode).map_err(|err| err.at_pc(pc, Some(opcode)));
+        }
+        
+        let registers = self.registers();
+        let stack_depth = self.stack.len();
+        let call_depth = self.call_stack.len();
+        if self.recorder.is_some() {
+            self.begin_delta(&opcode);
+        }
+        
+        let result = self.step(&opcode);
+        if self.recorder.is_some() {
+            self.finish_delta(registers);
+        }
+        
+        let result = result.map_err(|err| err.at_pc(pc, Some(opcode)));
+        if let Some(tracer) = &mut self.tracer {
+            let error = result.as_ref().err().map(|err| err.to_string());
+            tracer.record(pc, &opcode, (stack_depth, self.stack.len()), error);
+        }
+        
+        if result.is_ok() {
+            if let Some(profiler) = &mut self.profiler {
+                profiler.record(pc, &opcode, (call_depth, self.call_stack.len()), self.pc);
+            }
+            
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record(pc, opcode.op_type, self.pc);
+            }
+        }
+        
+        result
+    }
+    
+    // Traces every instruction executed from now on to `out` as JSON lines
+    pub fn start_tracing(self: &mut Self, out: Box<dyn Write>) {
+        self.tracer = Some(Tracer::init(out, &self.program.opcodes(), &self.symbols));
+    }
+    
+    // Anything that needs to see each instruction, without it execute_opcode
+    // skips straight to step
+    fn is_instrumented(self: &Self) -> bool {
+        self.recorder.is_some() || self.tracer.is_some() || self.profiler.is_some() || self.coverage.is_some()
+    }
+    
+    pub fn start_coverage(self: &mut Self) {
+        self.coverage = Some(Coverage::init(self.program.len()));
+    }
+    
+    pub fn stop_coverage(self: &mut Self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+    
+    pub fn start_profiling(self: &mut Self) {
+        self.profiler = Some(Profiler::init(self.program.len(), self.pc));
+    }
+    
+    // Closes the active calls and hands the profile over
+    pub fn stop_profiling(self: &mut Self) -> Option<Profiler> {
+        let mut profiler = self.profiler.take()?;
+        profiler.finish();
+        Some(profiler)
+    }
+    
+    pub fn stop_tracing(self: &mut Self) -> io::Result<()> {
+        match self.tracer.take() {
+            Some(mut tracer) => tracer.finish(),
+            None => Ok(()),
+        }
+    }
+    
+    pub fn start_recording(self: &mut Self, capacity: usize) {
+        self.recorder = Some(Recorder::init(capacity));
+    }
+    
+    pub fn stop_recording(self: &mut Self) {
+        self.recorder = None;
+    }
+    
+    pub fn is_recording(self: &Self) -> bool {
+        self.recorder.is_some()
+    }
+    
+    // Saves the part of the state the opcode can touch before it runs
+    fn begin_delta(self: &mut Self, opcode: &Opcode) {
+        // Nothing pops more than two values, swc reaches further down
+        let window = match opcode.op_type {
+            OpcodeType::Swc => opcode.operand().map(|index| unsafe { index.word.as_usize } + 1).unwrap_or(2),
+            _ => 2,
+        };
+        
+        let stack_len = self.stack.len().saturating_sub(window);
+        let delta = Delta {
+            pc: self.pc,
+            tsr: self.tsr,
+            halt: self.halt,
+            registers: Vec::new(),
+            stack_len,
+            stack: self.stack[stack_len..].to_vec(),
+            memory: Vec::new(),
+            call_depth: self.call_stack.len(),
+            frame: self.call_stack.last().copied(),
+        };
+        
+        if let Some(recorder) = &mut self.recorder {
+            recorder.pending = Some(delta);
+        }
+    }
+    
+    // Trims the saved state down to what actually changed and records it.
+    // A faulting instruction is recorded too: it leaves the same partial
+    // changes as it does without the recorder, and stepping back undoes them
+    fn finish_delta(self: &mut Self, registers: [TypedWord; 17]) {
+        let mut delta = match self.recorder.as_mut().and_then(|recorder| recorder.pending.take()) {
+            Some(delta) => delta,
+            None => return,
+        };
+        
+        let current = self.registers();
+        for (index, old) in registers.iter().enumerate() {
+            if current[index] != *old {
+                delta.registers.push((index, *old));
+            }
+        }
+        
+        let unchanged = delta.stack.iter()
+            .zip(self.stack[delta.stack_len.min(self.stack.len())..].iter())
+            .take_while(|(old, new)| old == new)
+            .count();
+        delta.stack.drain(..unchanged);
+        delta.stack_len += unchanged;
+        
+        // Only a `ret` needs its frame back
+        if self.call_stack.len() >= delta.call_depth {
+            delta.frame = None;
+        }
+        
+        if let Some(recorder) = &mut self.recorder {
+            recorder.push(delta);
+        }
+    }
+    
+    fn undo_delta(self: &mut Self, delta: Delta) {
+        for (index, old) in &delta.registers {
+            if let Some(reg) = self.register_by_index(*index) {
+                *reg = *old;
+            }
+        }
+        
+        self.stack.truncate(delta.stack_len);
+        self.stack.extend_from_slice(&delta.stack);
+        
+        for (addr, old) in delta.memory.iter().rev() {
+            self.memory[*addr..*addr + old.len()].copy_from_slice(old);
+        }
+        
+        self.call_stack.truncate(delta.call_depth);
+        if let Some(frame) = delta.frame {
+            if self.call_stack.len() < delta.call_depth {
+                self.call_stack.push(frame);
+            }
+        }
+        
+        self.pc = delta.pc;
+        self.tsr = delta.tsr;
+        self.halt = delta.halt;
+    }
+    
+    // Undoes the last recorded instruction, returns the delta that was undone
+    pub fn step_back(self: &mut Self) -> Option<Delta> {
+        let delta = self.recorder.as_mut()?.pop()?;
+        self.undo_delta(delta.clone());
+        Some(delta)
+    }
+    
+    pub(crate) fn step(self: &mut Self, opcode: &Opcode) -> Result<(), Error> {
+        match opcode.op_type {
+            OpcodeType::Mov => {
+                match opcode.op_operand {
+                    None => {
+                        if opcode.op_regs.len() < 1 {
+                            return Err(ErrorKind::RegisterOverflow.into());
+                        } else if opcode.op_regs.len() > 2 {
+                            return Err(ErrorKind::RegisterUnderflow.into());
+                        }
+                        
+                        let reg = self.read_register(opcode, 1)?;
+                        self.set_tsr(reg);
+                        self.assign_register(opcode, 0, reg)?;
+                    }
+                    Some(operand) => {
+                        if opcode.op_regs.len() < 1 {
+                            return Err(ErrorKind::RegisterOverflow.into());
+                        } else if opcode.op_regs.len() > 1 {
+                            return Err(ErrorKind::RegisterUnderflow.into());
+                        }
+                    
+                        self.set_tsr(operand);
+                        self.assign_register(opcode, 0, operand)?;
+                    }
+                }
+                self.pc += 1
+            }
+            OpcodeType::Movfs => {
+                if opcode.op_regs.len() < 1 {
+                    return Err(ErrorKind::RegisterOverflow.into());
+                } else if opcode.op_regs.len() > 1 {
+                    return Err(ErrorKind::RegisterUnderflow.into());
+                }
+                
+                let index = unsafe { opcode.operand()?.word.as_usize };
+                if self.stack.len() <= index {
+                    return Err(ErrorKind::StackUnderflow.into());
+                }
+                
+                let value = self.stack[self.stack.len() - 1 - index];
+                self.set_tsr(value);
+                self.assign_register(opcode, 0, value)?;
+                
+                self.pc += 1
+            }
+            
+            OpcodeType::Srg => {
+                if opcode.op_regs.len() < 1 {
+                    return Err(ErrorKind::RegisterOverflow.into());
+                } else if opcode.op_regs.len() > 2 {
+                    return Err(ErrorKind::RegisterUnderflow.into());
+                }
+                
+                let reg1 = self.read_register(opcode, 0)?;
+                let reg2 = self.read_register(opcode, 1)?;
+                self.set_tsr(reg1);
+                self.assign_register(opcode, 0, reg2)?;
+                self.assign_register(opcode, 1, reg1)?;
+                self.pc += 1
+            }
+            
+            OpcodeType::Add | OpcodeType::Sub | OpcodeType::Mul | OpcodeType::Div => {
+                if opcode.op_regs.len() < 1 {
+                    return Err(ErrorKind::RegisterUnderflow.into());
+                } else if opcode.op_regs.len() > 3 {
+                    return Err(ErrorKind::RegisterOverflow.into());
+                }
+                
+                let op = match opcode.op_type {
+                    OpcodeType::Add => ArithOp::Add,
+                    OpcodeType::Sub => ArithOp::Sub,
+                    OpcodeType::Mul => ArithOp::Mul,
+                    _ => ArithOp::Div,
+                };
+                
+                let reg1 = self.read_register(opcode, 1)?;
+                let reg2 = self.read_register(opcode, 2)?;
+                self.set_tsr(reg1);
+                let value = arith_tagged(op, reg1, reg2)?;
+                self.assign_register(opcode, 0, value)?;
+                self.pc += 1
+            }
+            
+            OpcodeType::Dec | OpcodeType::Inc => {
+                if opcode.op_regs.len() < 1 {
+                    return Err(ErrorKind::RegisterUnderflow.into());
+                } else if opcode.op_regs.len() > 1 {
+                    return Err(ErrorKind::RegisterOverflow.into());
+                }
+                
+                let op = match opcode.op_type {
+                    OpcodeType::Dec => ArithOp::Sub,
+                    _ => ArithOp::Add,
+                };
+                
+                let reg1 = self.read_register(opcode, 0)?;
+                self.set_tsr(reg1);
+                let word = arith(op, reg1.ty, reg1.word, one(reg1.ty))?;
+                self.assign_register(opcode, 0, TypedWord { word, ty: reg1.ty })?;
+                self.pc += 1
+            }
+            
+            OpcodeType::Equal | OpcodeType::Ne | OpcodeType::Lt | OpcodeType::Le | OpcodeType::Gt | OpcodeType::Ge => {
+                if opcode.op_regs.len() < 1 {
+                    return Err(ErrorKind::RegisterUnderflow.into());
+                } else if opcode.op_regs.len() > 3 {
+                    return Err(ErrorKind::RegisterOverflow.into());
+                }
+                
+                let op = compare_op(opcode.op_type).unwrap();
+                let reg1 = self.read_register(opcode, 1)?;
+                let reg2 = self.read_register(opcode, 2)?;
+                self.set_tsr(reg1);
+                self.assign_register(opcode, 0, compare_tagged(op, reg1, reg2))?;
+                self.pc += 1
+            }
+            
+            OpcodeType::Jt => {
+                if opcode.op_regs.len() < 1 {
+                    return Err(ErrorKind::RegisterUnderflow.into());
+                } else if opcode.op_regs.len() > 1 {
+                    return Err(ErrorKind::RegisterOverflow.into());
+                }
+                
+                let reg1 = self.read_register(opcode, 0)?;
+                unsafe {
+                    if reg1.word.as_u64 == 1 {
+                        self.pc = opcode.operand()?.word.as_usize;
+                    } else {
+                        self.pc += 1
+                    }
+                }
+            }
+            OpcodeType::Jz => {
+                if opcode.op_regs.len() < 1 {
+                    return Err(ErrorKind::RegisterUnderflow.into());
+                } else if opcode.op_regs.len() > 1 {
+                    return Err(ErrorKind::RegisterOverflow.into());
+                }
+                
+                let reg1 = self.read_register(opcode, 0)?;
+                unsafe {
+                    if reg1.word.as_u64 == 0 {
+                        self.pc = opcode.operand()?.word.as_usize;
+                    } else {
+                        self.pc += 1
+                    }
+                }
+            }
+            OpcodeType::Jnz => {
+                if opcode.op_regs.len() < 1 {
+                    return Err(ErrorKind::RegisterUnderflow.into());
+                } else if opcode.op_regs.len() > 1 {
+                    return Err(ErrorKind::RegisterOverflow.into());
+                }
+                
+                let reg1 = self.read_register(opcode, 0)?;
+                unsafe {
+                    if reg1.word.as_u64 != 0 {
+                        self.pc = opcode.operand()?.word.as_usize;
+                    } else {
+                        self.pc += 1
+                    }
+                }
+            }
+            
+            OpcodeType::Sysf => {
+                let r7 = self.regs[7];
+                let index = unsafe { r7.word.as_usize };
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.register_read(7, r7);
+                    tracer.sysf = Some(index);
+                }
+                
+                if index == 0 || index > self.sys_functions.len() {
+                    return Err(ErrorKind::InvalidSysFunction.into());
+                }
+                
+                let sys_function = self.sys_functions[self.sys_functions.len() - index];
+                sys_function(self, opcode, opcode.op_regs)?;
+                self.pc += 1;
+            }
+            
+            // Stack opcodes
+            OpcodeType::Push => {
+                match opcode.op_operand {
+                    None => {
+                        if opcode.op_regs.len() < 1 {
+                            return Err(ErrorKind::RegisterUnderflow.into());
+                        } else if opcode.op_regs.len() > 1 {
+                            return Err(ErrorKind::RegisterOverflow.into());
+                        }
+                        
+                        let reg = self.read_register(opcode, 0)?;
+                        self.stack.push(reg);
+                    }
+                    
+                    Some(operand) => {
+                        self.stack.push(operand);
+                    }
+                }
+                self.pc += 1
+            }
+            
+            OpcodeType::Adds | OpcodeType::Subs | OpcodeType::Muls | OpcodeType::Divs => {
+                if self.stack.len() < 2 {
+                    return Err(ErrorKind::StackUnderflow.into());
+                }
+                
+                let op = match opcode.op_type {
+                    OpcodeType::Adds => ArithOp::Add,
+                    OpcodeType::Subs => ArithOp::Sub,
+                    OpcodeType::Muls => ArithOp::Mul,
+                    _ => ArithOp::Div,
+                };
+                
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                self.set_tsr(b);
+                let value = arith_tagged(op, b, a)?;
+                self.stack.push(value);
+                self.pc += 1
+            }
+            
+            // Unlike most stack opcodes the comparisons leave their operands on the stack
+            OpcodeType::Equals | OpcodeType::Nes | OpcodeType::Lts | OpcodeType::Les | OpcodeType::Gts | OpcodeType::Ges => {
+                if self.stack.len() < 2 {
+                    return Err(ErrorKind::StackUnderflow.into());
+                }
+                
+                let op = compare_op(opcode.op_type).unwrap();
+                let a = self.stack[self.stack.len() - 1];
+                let b = self.stack[self.stack.len() - 2];
+                self.set_tsr(b);
+                self.stack.push(compare_tagged(op, b, a));
+                self.pc += 1
+            }
+            
+            OpcodeType::Dupl => {
+                unsafe {
+                    if self.stack.len() <= opcode.operand()?.word.as_usize {
+                        return Err(ErrorKind::StackUnderflow.into());
+                    }
+                    
+                    self.stack.push(self.stack[self.stack.len() - 1 - opcode.operand()?.word.as_usize]);
+                    self.pc += 1
+                }
+            }
+            
+            OpcodeType::Jts => {
+                if self.stack.len() < 1 {
+                    return Err(ErrorKind::StackUnderflow.into());
+                }
+                
+                let a = self.stack.pop().unwrap();
+                unsafe {
+                    if a.word.as_u64 == 1 {
+                        self.pc = opcode.operand()?.word.as_usize;
+                    } else {
+                        self.pc += 1
+                    }
+                }
+            }
+            OpcodeType::Jzs => {
+                if self.stack.len() < 1 {
+                    return Err(ErrorKind::StackUnderflow.into());
+                }
+                
+                let a = self.stack.pop().unwrap();
+                unsafe {
+                    if a.word.as_u64 == 0 {
+                        self.pc = opcode.operand()?.word.as_usize;
+                    } else {
+                        self.pc += 1;
+                    }
+                }
+            }
+            OpcodeType::Jnzs => {
+                if self.stack.len() < 1 {
+                    return Err(ErrorKind::StackUnderflow.into());
+                }
+                
+                let a = self.stack.pop().unwrap();
+                unsafe {
+                    if a.word.as_u64 != 0 {
+                        self.pc = opcode.operand()?.word.as_usize;
+                    } else {
+                        self.pc += 1
+                    }
+                }
+            }
+            
+            OpcodeType::Swc => {
+                if self.stack.len() < 2 {
+                    return Err(ErrorKind::StackUnderflow.into());
+                }
+                
+                let index = unsafe { opcode.operand()?.word.as_usize };
+                if self.stack.len() <= index {
+                    return Err(ErrorKind::StackUnderflow.into());
+                }
+                
+                let a = self.stack.len() - 1;
+                let b = self.stack.len() - 1 - index;
+                self.stack.swap(a, b);
+                self.pc += 1
+            }
+            
+            // Universal opcodes
+            OpcodeType::Jmp => {
+                unsafe {
+                    self.pc = opcode.operand()?.word.as_usize;
+                }
+            }
+            OpcodeType::Call => {
+                if self.call_stack.len() >= self.call_stack_depth {
+                    return Err(ErrorKind::CallStackOverflow.into());
+                }
+                
+                unsafe {
+                    let target = opcode.operand()?.word.as_usize;
+                    self.call_stack.push(Frame {
+                        call_addr: self.pc,
+                        target_addr: target,
+                        return_addr: self.pc + 1,
+                    });
+                    self.pc = target;
+                }
+            }
+            
+            OpcodeType::Read => {
+                let size = unsafe { opcode.operand()?.word.as_u64 };
+                match size {
+                    8 => {}
+                    16 => {}
+                    32 => {}
+                    64 => {}
+                    
+                    _ => {
+                        return Err(Error::with_message(ErrorKind::InvalidOperand, format!("invalid read size: `{}`", size)));
+                    }
+                }
+                
+                let addr = if opcode.op_regs.is_empty() {
+                    if self.stack.len() < 1 {
+                        return Err(ErrorKind::StackUnderflow.into());
+                    }
+                    
+                    self.stack.pop().unwrap()
+                } else {
+                    if opcode.op_regs.len() < 2 {
+                        return Err(ErrorKind::RegisterUnderflow.into());
+                    } else if opcode.op_regs.len() > 2 {
+                        return Err(ErrorKind::RegisterOverflow.into());
+                    }
+                    
+                    self.read_register(opcode, 1)?
+                };
+                
+                self.set_tsr(addr);
+                let addr = unsafe { addr.word.as_usize };
+                let bytes = (size / 8) as usize;
+                if addr >= self.memory.len() || self.memory.len() - addr < bytes {
+                    return Err(ErrorKind::ErrIllegalMemoryAccess.into());
+                }
+                
+                let value = self.load_memory(addr, bytes);
+                if self.is_observed() {
+                    self.memory_accessed(addr, bytes, WatchAccess::Read, value, value);
+                }
+                
+                if opcode.op_regs.is_empty() {
+                    self.stack.push(TypedWord::u64(value));
+                } else {
+                    self.assign_register(opcode, 0, TypedWord::u64(value))?;
+                }
+                self.pc += 1
+            }
+            OpcodeType::Write => {
+                let size = unsafe { opcode.operand()?.word.as_u64 };
+                match size {
+                    8 => {}
+                    16 => {}
+                    32 => {}
+                    64 => {}
+                    
+                    _ => {
+                        return Err(Error::with_message(ErrorKind::InvalidOperand, format!("invalid write size: `{}`", size)));
+                    }
+                }
+                
+                let (addr, value) = if opcode.op_regs.is_empty() {
+                    if self.stack.len() < 2 {
+                        return Err(ErrorKind::StackUnderflow.into());
+                    }
+                    
+                    let addr = self.stack.pop().unwrap();
+                    let value = self.stack.pop().unwrap();
+                    (addr, value)
+                } else {
+                    if opcode.op_regs.len() < 2 {
+                        return Err(ErrorKind::RegisterUnderflow.into());
+                    } else if opcode.op_regs.len() > 2 {
+                        return Err(ErrorKind::RegisterOverflow.into());
+                    }
+                    
+                    (self.read_register(opcode, 0)?, self.read_register(opcode, 1)?)
+                };
+                
+                self.set_tsr(value);
+                let addr = unsafe { addr.word.as_usize };
+                let bytes = (size / 8) as usize;
+                if addr >= self.memory.len() || self.memory.len() - addr < bytes {
+                    return Err(ErrorKind::ErrIllegalMemoryAccess.into());
+                }
+                
+                let value = unsafe { value.word.as_u64 };
+                let old = if self.is_observed() { self.load_memory(addr, bytes) } else { 0 };
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.record_memory(addr, &self.memory[addr..addr + bytes]);
+                }
+                
+                match size {
+                    8 => self.memory[addr] = value as u8,
+                    16 => self.memory[addr..addr + 2].copy_from_slice(&(value as u16).to_ne_bytes()),
+                    32 => self.memory[addr..addr + 4].copy_from_slice(&(value as u32).to_ne_bytes()),
+                    _ => self.memory[addr..addr + 8].copy_from_slice(&value.to_ne_bytes()),
+                }
+                
+                if self.is_observed() {
+                    let new = self.load_memory(addr, bytes);
+                    self.memory_accessed(addr, bytes, WatchAccess::Write, old, new);
+                }
+                self.pc += 1
+            }
+            
+            OpcodeType::And | OpcodeType::Or | OpcodeType::Xor | OpcodeType::Shr | OpcodeType::Shl => {
+                let op = match opcode.op_type {
+                    OpcodeType::And => BitOp::And,
+                    OpcodeType::Or => BitOp::Or,
+                    OpcodeType::Xor => BitOp::Xor,
+                    OpcodeType::Shr => BitOp::Shr,
+                    _ => BitOp::Shl,
+                };
+                
+                if opcode.op_regs.is_empty() {
+                    if self.stack.len() < 2 {
+                        return Err(ErrorKind::StackUnderflow.into());
+                    }
+                    
+                    let a = self.stack.pop().unwrap();
+                    let b = self.stack.pop().unwrap();
+                    self.set_tsr(b);
+                    let value = bitwise_tagged(op, b, a)?;
+                    self.stack.push(value);
+                } else {
+                    if opcode.op_regs.len() < 1 {
+                        return Err(ErrorKind::RegisterUnderflow.into());
+                    } else if opcode.op_regs.len() > 3 {
+                        return Err(ErrorKind::RegisterOverflow.into());
+                    }
+                    
+                    let reg1 = self.read_register(opcode, 1)?;
+                    let reg2 = self.read_register(opcode, 2)?;
+                    self.set_tsr(reg1);
+                    let value = bitwise_tagged(op, reg1, reg2)?;
+                    self.assign_register(opcode, 0, value)?;
+                }
+                self.pc += 1
+            }
+            
+            OpcodeType::Not => {
+                if opcode.op_regs.is_empty() {
+                    if self.stack.len() < 1 {
+                        return Err(ErrorKind::StackUnderflow.into());
+                    }
+                    
+                    let a = self.stack.pop().unwrap();
+                    self.set_tsr(a);
+                    let value = not(a)?;
+                    self.stack.push(value);
+                } else {
+                    if opcode.op_regs.len() < 1 {
+                        return Err(ErrorKind::RegisterUnderflow.into());
+                    } else if opcode.op_regs.len() > 3 {
+                        return Err(ErrorKind::RegisterOverflow.into());
+                    }
+                    
+                    let reg1 = self.read_register(opcode, 1)?;
+                    self.set_tsr(reg1);
+                    let value = not(reg1)?;
+                    self.assign_register(opcode, 0, value)?;
+                }
+                self.pc += 1
+            }
+            
+            OpcodeType::Pop => {
+                if opcode.op_regs.is_empty() {
+                    if self.stack.len() < 1 {
+                        return Err(ErrorKind::StackUnderflow.into());
+                    }
+                    
+                    self.stack.pop();
+                } else {
+                    if opcode.op_regs.len() < 1 {
+                        return Err(ErrorKind::RegisterOverflow.into());
+                    } else if opcode.op_regs.len() > 1 {
+                        return Err(ErrorKind::RegisterUnderflow.into());
+                    }
+                    
+                    self.set_tsr(TypedWord::u64(0));
+                    self.assign_register(opcode, 0, TypedWord::u64(0))?;
+                }
+                self.pc += 1
+            }
+            
+            OpcodeType::Ret => {
+                match self.call_stack.pop() {
+                    Some(frame) => self.pc = frame.return_addr,
+                    None => return Err(ErrorKind::CallStackUnderflow.into()),
+                }
+            }
+            OpcodeType::Hlt => {
+                self.halt = true;
+            }
+            
+            // Typed opcodes
+            OpcodeType::Addi | OpcodeType::Addu | OpcodeType::Addf |
+            OpcodeType::Subi | OpcodeType::Subu | OpcodeType::Subf |
+            OpcodeType::Muli | OpcodeType::Mulu | OpcodeType::Mulf |
+            OpcodeType::Divi | OpcodeType::Divu | OpcodeType::Divf |
+            OpcodeType::Modi | OpcodeType::Modu | OpcodeType::Modf => {
+                if opcode.op_regs.len() < 3 {
+                    return Err(ErrorKind::RegisterUnderflow.into());
+                } else if opcode.op_regs.len() > 3 {
+                    return Err(ErrorKind::RegisterOverflow.into());
+                }
+                
+                let (op, ty) = typed_arith(opcode.op_type).unwrap();
+                let reg1 = self.read_register(opcode, 1)?;
+                let reg2 = self.read_register(opcode, 2)?;
+                self.tsr = ty as usize;
+                let word = arith(op, ty, reg1.word, reg2.word)?;
+                self.assign_register(opcode, 0, TypedWord { word, ty })?;
+                self.pc += 1
+            }
+            
+            OpcodeType::Addis | OpcodeType::Addus | OpcodeType::Addfs |
+            OpcodeType::Subis | OpcodeType::Subus | OpcodeType::Subfs |
+            OpcodeType::Mulis | OpcodeType::Mulus | OpcodeType::Mulfs |
+            OpcodeType::Divis | OpcodeType::Divus | OpcodeType::Divfs |
+            OpcodeType::Modis | OpcodeType::Modus | OpcodeType::Modfs => {
+                if self.stack.len() < 2 {
+                    return Err(ErrorKind::StackUnderflow.into());
+                }
+                
+                let (op, ty) = typed_arith(opcode.op_type).unwrap();
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                self.tsr = ty as usize;
+                let word = arith(op, ty, b.word, a.word)?;
+                self.stack.push(TypedWord { word, ty });
+                self.pc += 1
+            }
+            
+            OpcodeType::Negi | OpcodeType::Negf | OpcodeType::Absi | OpcodeType::Absf => {
+                let (op, ty) = typed_unary(opcode.op_type).unwrap();
+                self.tsr = ty as usize;
+                if opcode.op_regs.is_empty() {
+                    if self.stack.len() < 1 {
+                        return Err(ErrorKind::StackUnderflow.into());
+                    }
+                    
+                    let a = self.stack.pop().unwrap();
+                    let word = unary(op, ty, a.word)?;
+                    self.stack.push(TypedWord { word, ty });
+                } else {
+                    if opcode.op_regs.len() < 2 {
+                        return Err(ErrorKind::RegisterUnderflow.into());
+                    } else if opcode.op_regs.len() > 2 {
+                        return Err(ErrorKind::RegisterOverflow.into());
+                    }
+                    
+                    let reg1 = self.read_register(opcode, 1)?;
+                    let word = unary(op, ty, reg1.word)?;
+                    self.assign_register(opcode, 0, TypedWord { word, ty })?;
+                }
+                self.pc += 1
+            }
+            
+            OpcodeType::Lti | OpcodeType::Ltu | OpcodeType::Ltf |
+            OpcodeType::Lei | OpcodeType::Leu | OpcodeType::Lef |
+            OpcodeType::Gti | OpcodeType::Gtu | OpcodeType::Gtf |
+            OpcodeType::Gei | OpcodeType::Geu | OpcodeType::Gef => {
+                if opcode.op_regs.len() < 3 {
+                    return Err(ErrorKind::RegisterUnderflow.into());
+                } else if opcode.op_regs.len() > 3 {
+                    return Err(ErrorKind::RegisterOverflow.into());
+                }
+                
+                let (op, ty) = typed_compare(opcode.op_type).unwrap();
+                let reg1 = self.read_register(opcode, 1)?;
+                let reg2 = self.read_register(opcode, 2)?;
+                self.tsr = ty as usize;
+                self.assign_register(opcode, 0, compare(op, ty, reg1.word, reg2.word))?;
+                self.pc += 1
+            }
+            
+            OpcodeType::Ltis | OpcodeType::Ltus | OpcodeType::Ltfs |
+            OpcodeType::Leis | OpcodeType::Leus | OpcodeType::Lefs |
+            OpcodeType::Gtis | OpcodeType::Gtus | OpcodeType::Gtfs |
+            OpcodeType::Geis | OpcodeType::Geus | OpcodeType::Gefs => {
+                if self.stack.len() < 2 {
+                    return Err(ErrorKind::StackUnderflow.into());
+                }
+                
+                let (op, ty) = typed_compare(opcode.op_type).unwrap();
+                let a = self.stack[self.stack.len() - 1];
+                let b = self.stack[self.stack.len() - 2];
+                self.tsr = ty as usize;
+                self.stack.push(compare(op, ty, b.word, a.word));
+                self.pc += 1
+            }
+            
+            OpcodeType::Itof | OpcodeType::Ftoi | OpcodeType::Utof | OpcodeType::Ftou |
+            OpcodeType::Sext8 | OpcodeType::Sext16 | OpcodeType::Sext32 |
+            OpcodeType::Zext8 | OpcodeType::Zext16 | OpcodeType::Zext32 => {
+                if opcode.op_regs.is_empty() {
+                    if self.stack.len() < 1 {
+                        return Err(ErrorKind::StackUnderflow.into());
+                    }
+                    
+                    let a = self.stack.pop().unwrap();
+                    let value = conversion(opcode.op_type, a.word).unwrap();
+                    self.set_tsr(value);
+                    self.stack.push(value);
+                } else {
+                    if opcode.op_regs.len() < 2 {
+                        return Err(ErrorKind::RegisterUnderflow.into());
+                    } else if opcode.op_regs.len() > 2 {
+                        return Err(ErrorKind::RegisterOverflow.into());
+                    }
+                    
+                    let reg1 = self.read_register(opcode, 1)?;
+                    let value = conversion(opcode.op_type, reg1.word).unwrap();
+                    self.set_tsr(value);
+                    self.assign_register(opcode, 0, value)?;
+                }
+                self.pc += 1
+            }
+            
+            // Deprecated
+            OpcodeType::Phsr => {
+                if self.stack.len() < 1 {
+                    return Err(ErrorKind::StackUnderflow.into());
+                }
+                
+                if opcode.op_regs.len() < 1 {
+                    return Err(ErrorKind::RegisterOverflow.into());
+                } else if opcode.op_regs.len() > 1 {
+                    return Err(ErrorKind::RegisterUnderflow.into());
+                }
+                
+                self.set_tsr(self.stack[self.stack.len() - 1]);
+                self.assign_register(opcode, 0, self.stack[self.stack.len() - 1])?;
+                self.pc += 1
+            }
+            
+            _ => {
+                return Err(ErrorKind::InvalidOperand.into());
+            }
+        }
+        
+        Ok(())
+    }
+    
+    pub fn load_program_from_memory(self: &mut Self, program: Vec<Opcode>) -> Result<(), Error> {
+        let mut code = self.program.opcodes();
+        code.extend_from_slice(&program);
+        self.program = Bytecode::encode(&code)?;
+        Ok(())
+    }
+    
+    // Fails only if the program's literals overflow the constant pool
+    pub fn load_program(self: &mut Self, program: &Program) -> Result<(), Error> {
+        self.program = Bytecode::encode(&program.code)?;
+        self.symbols = program.symbols.clone();
+        self.entry = program.entry;
+        self.pc = program.entry;
+        self.call_stack.clear();
+        self.halt = false;
+        Ok(())
+    }
+    
+    // Captures the machine state, `program_path` is where `resume` finds
+    // the program again
+    pub fn snapshot(self: &Self, program_path: &str) -> Snapshot {
+        Snapshot {
+            program_path: program_path.to_string(),
+            fingerprint: program_fingerprint(&self.program.opcodes(), self.entry),
+            registers: self.registers(),
+            pc: self.pc,
+            tsr: self.tsr,
+            halt: self.halt,
+            call_stack: self.call_stack.clone(),
+            stack: self.stack.clone(),
+            memory: self.memory.clone(),
+        }
+    }
+    
+    // Puts the machine back into the snapshot's state, the snapshot has to
+    // come from the program that is loaded
+    pub fn restore(self: &mut Self, snapshot: &Snapshot) -> Result<(), Error> {
+        if snapshot.fingerprint != program_fingerprint(&self.program.opcodes(), self.entry) {
+            return Err(Error::with_message(ErrorKind::SnapshotMismatch, format!("snapshot was taken from a different build of `{}`", snapshot.program_path)));
+        }
+        
+        if snapshot.memory.len() != self.memory.len() {
+            return Err(Error::with_message(ErrorKind::SnapshotMismatch, "snapshot does not fit this machine"));
+        }
+        
+        if snapshot.call_stack.len() > self.call_stack_depth {
+            return Err(Error::with_message(ErrorKind::SnapshotMismatch, format!("snapshot call stack is deeper than {} frames", self.call_stack_depth)));
+        }
+        
+        for (index, value) in snapshot.registers.iter().enumerate() {
+            if let Some(reg) = self.register_by_index(index) {
+                *reg = *value;
+            }
+        }
+        
+        self.pc = snapshot.pc;
+        self.tsr = snapshot.tsr;
+        self.halt = snapshot.halt;
+        self.call_stack = snapshot.call_stack.clone();
+        self.stack = snapshot.stack.clone();
+        self.memory.copy_from_slice(&snapshot.memory);
+        
+        // Recorded history leads up to the old state, not this one
+        if let Some(recorder) = &self.recorder {
+            self.recorder = Some(Recorder::init(recorder.capacity()));
+        }
+        
+        Ok(())
+    }
+    
+    pub fn get_entry(self: &Self) -> usize {
+        self.entry
+    }
+    
+    pub fn get_pc(self: &Self) -> usize {
+        self.pc
+    }
+    
+    pub fn set_pc(self: &mut Self, pc: usize) {
+        self.pc = pc;
+    }
+    
+    pub fn is_halted(self: &Self) -> bool {
+        self.halt
+    }
+    
+    // Registers, stack, call stack and the non-zero parts of memory
+    pub fn dump(self: &Self) {
+        println!("\n{}", StateDump::capture(self, &DumpOptions::init(), None).to_text());
+    }
+    
+    // Runs until `hlt`. Without instrumentation or watchpoints the program is
+    // decoded once up front and run in the dispatch loop, otherwise every
+    // instruction goes through execute_opcode.
+    pub fn execute_program(self: &mut Self) -> Result<(), Error> {
+        if self.is_instrumented() || self.is_observed() {
+            while !self.halt {
+                self.execute_opcode()?;
+            }
+            
+            return Ok(());
+        }
+        
+        let code = decode_program(&self.program);
+        self.watch_hits.clear();
+        while !self.halt {
+            self.dispatch(&code)?;
+        }
+        
+        Ok(())
+    }
+    
+    // Runs the decoded instruction at pc
+    #[inline(always)]
+    fn dispatch(self: &mut Self, code: &[Decoded]) -> Result<(), Error> {
+        let pc = self.pc;
+        let decoded = match code.get(pc) {
+            Some(decoded) => decoded,
+            None => return Err(Error::new(ErrorKind::InvalidOpcodeAccess).at_pc(pc, None)),
+        };
+        
+        if let Err(kind) = (decoded.handler)(self, decoded) {
+            let err = self.fault.take().unwrap_or_else(|| Error::new(kind));
+            return Err(err.at_pc(pc, self.program.get(pc)));
+        }
+        
+        Ok(())
+    }
+    
+    // Like execute_program, but once `threshold` instructions have run the
+    // program is compiled to native code, see jit.rs. Whatever the compiled
+    // code can't run is handed back to the dispatch loop one instruction at
+    // a time.
+    pub fn execute_program_jit(self: &mut Self, threshold: u64) -> Result<(), Error> {
+        if self.is_instrumented() || self.is_observed() || !Jit::is_supported() {
+            return self.execute_program();
+        }
+        
+        let code = decode_program(&self.program);
+        self.watch_hits.clear();
+        for _ in 0..threshold {
+            if self.halt {
+                return Ok(());
+            }
+            
+            self.dispatch(&code)?;
+        }
+        
+        let jit = Jit::compile(&self.program)?;
+        while !self.halt {
+            if self.pc < code.len() {
+                jit.run(self);
+            }
+            
+            self.dispatch(&code)?;
+        }
+        
+        Ok(())
+    }
+    
+    pub fn execute_program_debug(self: &mut Self) -> Result<(), Error> {
+        let mut debugger = Debugger::init();
+        debugger.run(self, &mut stdin().lock(), &mut stdout());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    
+    fn fault(source: &str) -> Error {
+        match run_source(source).2 {
+            Ok(()) => panic!("program ran without a fault"),
+            Err(err) => err,
+        }
+    }
+    
+    #[test]
+    fn nested_calls_return_to_their_callers() {
+        let (osvm, _, result) = run_source("
+_start:
+    call outer
+    mov r2, #3
+    hlt
+outer:
+    call inner
+    mov r1, #2
+    ret
+inner:
+    mov r0, #1
+    ret
+");
+        result.unwrap();
+        assert_eq!(osvm.registers()[..3], [TypedWord::u64(1), TypedWord::u64(2), TypedWord::u64(3)]);
+        assert!(osvm.call_stack.is_empty());
+    }
+    
+    #[test]
+    fn recursive_calls_unwind() {
+        let (osvm, _, result) = run_source("
+_start:
+    mov r0, #50
+    call down
+    hlt
+down:
+    jz done, r0
+    dec r0
+    inc r1
+    call down
+done:
+    ret
+");
+        result.unwrap();
+        assert_eq!(osvm.registers()[1], TypedWord::u64(50));
+        assert!(osvm.call_stack.is_empty());
+    }
+    
+    #[test]
+    fn call_stack_depth_is_enforced() {
+        let (mut osvm, _) = machine(&assemble("
+_start:
+    call again
+again:
+    call again
+"));
+        osvm.set_call_stack_depth(10);
+        let err = osvm.execute_program().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::CallStackOverflow);
+        assert_eq!(err.pc, Some(1));
+        assert_eq!(osvm.call_stack.len(), 10);
+        
+        let frame = osvm.call_stack[1];
+        assert_eq!((frame.call_addr, frame.target_addr, frame.return_addr), (1, 1, 2));
+    }
+    
+    #[test]
+    fn ret_without_a_call_underflows() {
+        let err = fault("
+_start:
+    ret
+");
+        assert_eq!(err.kind, ErrorKind::CallStackUnderflow);
+        assert_eq!(err.pc, Some(0));
+    }
+    
+    #[test]
+    fn tsr_follows_the_operand_type() {
+        for (literal, ty) in [("#5", WordType::U64), ("#-5", WordType::I64), ("#2.3", WordType::F64)] {
+            let (osvm, _, result) = run_source(&format!("_start:\n    mov r0, {}\n    hlt", literal));
+            result.unwrap();
+            assert_eq!(osvm.tsr, ty as usize);
+            assert_eq!(osvm.registers()[0].ty, ty);
+        }
+    }
+    
+    #[test]
+    fn register_arithmetic_follows_the_operand_type() {
+        let (osvm, _, result) = run_source("
+_start:
+    mov r0, #-5
+    mov r1, #3
+    add r2, r0, r1
+    mov r3, #2.5
+    mov r4, #2.0
+    mul r5, r3, r4
+    hlt
+");
+        result.unwrap();
+        assert_eq!(osvm.registers()[2], TypedWord::i64(-2));
+        assert_eq!(osvm.registers()[5], TypedWord::f64(5.0));
+    }
+    
+    #[test]
+    fn print_follows_the_value_type() {
+        let (_, output, result) = run_source("
+%include \"sys_libs.osv\"
+_start:
+    push #-5
+    call sprint
+    push #2.5
+    call sprint
+    push #7
+    call sprint
+    push #7i
+    call sprint
+    hlt
+");
+        result.unwrap();
+        assert_eq!(output, "-5\n2.5\n7\n7\n");
+    }
+    
+    #[test]
+    fn typed_register_arithmetic_ignores_the_tags() {
+        let (osvm, _, result) = run_source("
+_start:
+    mov r0, #-7
+    mov r1, #2
+    divi r2, r0, r1
+    divu r3, r0, r1
+    modi r4, r0, r1
+    addu r5, r0, r1
+    mov r6, #1.5
+    mov r7, #4.0
+    mulf r8, r6, r7
+    subf r9, r6, r7
+    hlt
+");
+        result.unwrap();
+        assert_eq!(osvm.registers()[2], TypedWord::i64(-3));
+        assert_eq!(osvm.registers()[3], TypedWord::u64((-7i64 as u64) / 2));
+        assert_eq!(osvm.registers()[4], TypedWord::i64(-1));
+        assert_eq!(osvm.registers()[5], TypedWord::u64(-5i64 as u64));
+        assert_eq!(osvm.registers()[8], TypedWord::f64(6.0));
+        assert_eq!(osvm.registers()[9], TypedWord::f64(-2.5));
+        assert_eq!(osvm.tsr, WordType::F64 as usize);
+    }
+    
+    #[test]
+    fn typed_stack_arithmetic_takes_the_left_operand_first() {
+        let (osvm, _, result) = run_source("
+_start:
+    push #-7
+    push #2
+    subis
+    push #10
+    push #4
+    modus
+    push #1.0
+    push #4.0
+    divfs
+    hlt
+");
+        result.unwrap();
+        assert_eq!(osvm.stack, [TypedWord::i64(-9), TypedWord::u64(2), TypedWord::f64(0.25)]);
+    }
+    
+    #[test]
+    fn neg_and_abs_work_on_registers_and_the_stack() {
+        let (osvm, _, result) = run_source("
+_start:
+    mov r0, #5
+    negi r1, r0
+    absi r2, r1
+    mov r3, #-2.5
+    absf r4, r3
+    push #2.5
+    negf
+    push #-3
+    absi
+    hlt
+");
+        result.unwrap();
+        assert_eq!(osvm.registers()[1], TypedWord::i64(-5));
+        assert_eq!(osvm.registers()[2], TypedWord::i64(5));
+        assert_eq!(osvm.registers()[4], TypedWord::f64(2.5));
+        assert_eq!(osvm.stack, [TypedWord::f64(-2.5), TypedWord::i64(3)]);
+    }
+    
+    #[test]
+    fn typed_division_by_zero_faults() {
+        for op in ["divi", "divu", "divf", "modi", "modu", "modf"] {
+            let err = fault(&format!("_start:\n    mov r0, #1\n    {} r1, r0, r2\n    hlt", op));
+            assert_eq!(err.kind, ErrorKind::DivByZero, "{}", op);
+            assert_eq!(err.pc, Some(1));
+        }
+    }
+    
+    #[test]
+    fn conversions_work_on_registers_and_the_stack() {
+        let (osvm, _, result) = run_source("
+_start:
+    mov r0, #-3
+    itof r1, r0
+    mov r2, #2.75
+    ftoi r3, r2
+    ftou r4, r2
+    push #7
+    utof
+    push #-1.5
+    ftoi
+    hlt
+");
+        result.unwrap();
+        assert_eq!(osvm.registers()[1], TypedWord::f64(-3.0));
+        assert_eq!(osvm.registers()[3], TypedWord::i64(2));
+        assert_eq!(osvm.registers()[4], TypedWord::u64(2));
+        assert_eq!(osvm.stack, [TypedWord::f64(7.0), TypedWord::i64(-1)]);
+        assert_eq!(osvm.tsr, WordType::I64 as usize);
+    }
+    
+    #[test]
+    fn byte_loads_can_be_sign_extended() {
+        let (osvm, _, result) = run_source("
+_start:
+    mov r0, #100
+    mov r1, #200
+    wrt #8, r0, r1
+    rd #8, r2, r0
+    sext8 r3, r2
+    zext8 r4, r3
+    mov r5, #-3
+    addi r6, r3, r5
+    hlt
+");
+        result.unwrap();
+        assert_eq!(osvm.registers()[3], TypedWord::i64(-56));
+        assert_eq!(osvm.registers()[4], TypedWord::u64(200));
+        assert_eq!(osvm.registers()[6], TypedWord::i64(-59));
+    }
+    
+    #[test]
+    fn comparisons_write_zero_or_one() {
+        let (osvm, _, result) = run_source("
+_start:
+    mov r0, #-1
+    mov r1, #1
+    lt r2, r0, r1
+    ge r3, r0, r1
+    ne r4, r0, r1
+    ltu r5, r0, r1
+    gti r6, r1, r0
+    mov r7, #0.5
+    mov r8, #0.25
+    lef r9, r7, r8
+    hlt
+");
+        result.unwrap();
+        let flags = [osvm.registers()[2], osvm.registers()[3], osvm.registers()[4], osvm.registers()[5], osvm.registers()[6], osvm.registers()[9]];
+        assert_eq!(flags, [1, 0, 1, 0, 1, 0].map(TypedWord::u64));
+    }
+    
+    #[test]
+    fn stack_comparisons_keep_their_operands() {
+        let (osvm, _, result) = run_source("
+_start:
+    push #1
+    push #2
+    lts
+    push #-2
+    push #-1
+    gtis
+    push #3
+    push #3
+    les
+    push #1.5
+    push #2.5
+    nes
+    hlt
+");
+        result.unwrap();
+        
+        // Like `eqs` the result is pushed on top of both operands
+        let results: Vec<TypedWord> = osvm.stack.iter().skip(2).step_by(3).copied().collect();
+        assert_eq!(osvm.stack.len(), 12);
+        assert_eq!(results, [1, 0, 1, 1].map(TypedWord::u64));
+    }
+    
+    #[test]
+    fn loops_can_count_to_an_inexact_bound() {
+        let (osvm, _, result) = run_source("
+_start:
+    mov r0, #0
+    mov r1, #10
+loop:
+    mov r2, #3
+    add r0, r0, r2
+    lt r3, r0, r1
+    jnz loop, r3
+    hlt
+");
+        result.unwrap();
+        assert_eq!(osvm.registers()[0], TypedWord::u64(12));
+    }
+    
+    #[test]
+    fn faults_are_returned_with_the_pc_and_opcode() {
+        let err = fault("
+_start:
+    push #1
+    push #0
+    divs
+    hlt
+");
+        assert_eq!(err.kind, ErrorKind::DivByZero);
+        assert_eq!(err.pc, Some(2));
+        assert_eq!(err.opcode.as_ref().map(|opcode| opcode.op_type), Some(OpcodeType::Divs));
+        assert_eq!(err.to_string(), "DivByZero at pc: 2 (Divs [])");
+    }
+    
+    #[test]
+    fn dupl_past_the_bottom_of_the_stack_underflows() {
+        let err = fault("
+_start:
+    push #1
+    dupl #1
+    hlt
+");
+        assert_eq!(err.kind, ErrorKind::StackUnderflow);
+        assert_eq!(err.pc, Some(1));
+        
+        let err = fault("
+_start:
+    push #1
+    dupl #5
+    hlt
+");
+        assert_eq!(err.kind, ErrorKind::StackUnderflow);
+    }
+    
+    #[test]
+    fn memory_accesses_are_checked_against_the_memory_size() {
+        let cases = [
+            ("rd #64, r1, r0", MEMORY_CAPACITY - 4, Some(ErrorKind::ErrIllegalMemoryAccess)),
+            ("rd #8, r1, r0", MEMORY_CAPACITY - 1, None),
+            ("rd #8, r1, r0", MEMORY_CAPACITY, Some(ErrorKind::ErrIllegalMemoryAccess)),
+            ("wrt #32, r0, r1", MEMORY_CAPACITY - 4, None),
+            ("wrt #32, r0, r1", MEMORY_CAPACITY - 3, Some(ErrorKind::ErrIllegalMemoryAccess)),
+        ];
+        
+        for (access, addr, expected) in cases {
+            let (mut osvm, _) = machine(&assemble(&format!("_start:\n    mov r0, #{}\n    {}\n    hlt", addr, access)));
+            
+            // Spare capacity past the end of memory is still out of bounds
+            osvm.memory.reserve(1024);
+            assert!(osvm.memory.capacity() > osvm.memory.len());
+            
+            let result = osvm.execute_program();
+            assert_eq!(result.as_ref().err().map(|err| err.kind), expected, "{} at {}", access, addr);
+            if expected.is_some() {
+                assert_eq!(result.unwrap_err().pc, Some(1));
+            }
+        }
+    }
+    
+    #[test]
+    fn sysf_faults_are_returned() {
+        let err = fault(&format!("
+_start:
+    push #0
+    push #{}
+    mov r7, #7
+    sysf
+    hlt
+", MEMORY_CAPACITY + 1));
+        assert_eq!(err.kind, ErrorKind::ErrIllegalMemoryAccess);
+        assert_eq!(err.pc, Some(3));
+        
+        let err = fault("
+_start:
+    mov r7, #1
+    sysf
+    hlt
+");
+        assert_eq!(err.kind, ErrorKind::StackUnderflow);
+    }
+    
+    #[test]
+    fn any_number_of_machines_can_load_a_program() {
+        let program = assemble("
+_start:
+    call work
+    hlt
+work:
+    mov r0, #2
+    ret
+");
+        for _ in 0..2 {
+            let (osvm, _, result) = run(&program);
+            result.unwrap();
+            assert_eq!(osvm.registers()[0], TypedWord::u64(2));
+        }
+    }
 }
\ No newline at end of file
@@ -1,22 +1,88 @@
-use std::{env, fs::File, io::Read, path::PathBuf, process::exit};
+use std::{env, fs, path::PathBuf};
 use log::*;
 
-pub struct Preprocessor {}
+use crate::utils::error::{Error, ErrorKind, Span};
 
-impl Preprocessor {
-    fn remove_line_by_sstr(self: &Self, starts_with: &str,  source: String) -> String {
-        let mut new_source = String::new();
-        for line in source.lines() {
-            let line = line.trim();
-            if line.starts_with(starts_with) {
-                continue;
-            }
+// A preprocessed line and where it came from, so errors
+// can point into the file the user actually wrote
+#[derive(Debug, Clone)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: usize,
+    
+    // The line as written and the line after macro expansion
+    pub text: String,
+    pub code: String,
+    
+    // The range of `text` every byte of `code` came from, bytes a macro
+    // expanded to come from the whole macro call
+    origin: Vec<(usize, usize)>,
+}
+
+impl SourceLine {
+    pub fn init(file: &str, line: usize, text: &str) -> SourceLine {
+        let start = text.len() - text.trim_start().len();
+        let code = text.trim();
+        SourceLine {
+            file: file.to_string(),
+            line,
+            text: text.to_string(),
+            code: code.to_string(),
+            origin: (start..start + code.len()).map(|index| (index, index + 1)).collect(),
+        }
+    }
+    
+    // The span of `token` in the original line. Tokens are slices of `code`,
+    // so where one starts is known without searching for it; anything else
+    // spans the whole line.
+    pub fn span(self: &Self, token: &str) -> Span {
+        let start = (token.as_ptr() as usize).wrapping_sub(self.code.as_ptr() as usize);
+        let (start, end) = if !token.is_empty() && start < self.code.len() && token.len() <= self.code.len() - start {
+            (self.origin[start].0, self.origin[start + token.len() - 1].1)
+        } else {
+            let start = self.text.len() - self.text.trim_start().len();
+            (start, start + self.text.trim().len())
+        };
+        
+        Span {
+            file: self.file.clone(),
+            line: self.line,
+            column: self.text[..start].chars().count() + 1,
+            len: self.text[start..end].chars().count().max(1),
+            text: self.text.clone(),
+        }
+    }
+    
+    // Replaces every `call` in `code` with `value`, like str::replace
+    fn expand(self: &mut Self, call: &str, value: &str) {
+        if !self.code.contains(call) {
+            return;
+        }
+        
+        let mut code = String::with_capacity(self.code.len());
+        let mut origin = Vec::with_capacity(self.origin.len());
+        let mut end = 0;
+        for (start, _) in self.code.match_indices(call) {
+            code.push_str(&self.code[end..start]);
+            origin.extend_from_slice(&self.origin[end..start]);
             
-            new_source.push_str(line);
-            new_source.push('\n');
+            end = start + call.len();
+            code.push_str(value);
+            origin.resize(code.len(), (self.origin[start].0, self.origin[end - 1].1));
         }
         
-        new_source
+        code.push_str(&self.code[end..]);
+        origin.extend_from_slice(&self.origin[end..]);
+        self.code = code;
+        self.origin = origin;
+    }
+}
+
+pub struct Preprocessor {}
+
+impl Preprocessor {
+    fn is_directive(self: &Self, line: &str, directive: &str) -> bool {
+        line.starts_with("%") && line.replace("%", "").starts_with(directive)
     }
     
     fn get_string(self: &Self, line: &str) -> String {
@@ -24,15 +90,11 @@ impl Preprocessor {
         let mut found = false;
         for c in line.chars() {
             if c == '"' {
-                if found != true {
-                    found = true;
-                } else {
-                    found = false;
-                }
+                found = !found;
                 continue;
             }
             
-            if found == true {
+            if found {
                 string.push(c);
             }
         }
@@ -40,86 +102,136 @@ impl Preprocessor {
         string
     }
     
-    fn get_cindex(self: &Self, index: usize, source: &str) -> usize {
-        let mut lindex = 0;
-        let mut cindex = 0;
-        for c in source.chars() {
-            if lindex == index {
-                break;
-            }
-            
-            if c != '\n' {
-                cindex += 1;
-            } else {
-                cindex += 1;
-                lindex += 1;
-            }
+    // Includes are looked up in OSVM_LIBS_DIR first, then next to the including file
+    fn find_include(self: &Self, file_path: &str, path: &str) -> Option<(PathBuf, String)> {
+        let mut search_paths = Vec::new();
+        if let Ok(libs_dir) = env::var("OSVM_LIBS_DIR") {
+            search_paths.push(PathBuf::from(libs_dir).join(path));
         }
         
-        cindex
+        if let Some(parent) = PathBuf::from(file_path).parent() {
+            search_paths.push(parent.join(path));
+        }
+        
+        search_paths.into_iter().find_map(|path| {
+            let source = fs::read_to_string(&path).ok()?;
+            Some((path, source))
+        })
     }
     
-    pub fn process_includes(self: &Self, file_path: String, mut source: String) -> String {
+    // Splices includes in place, each file is only included once
+    fn process_includes(self: &Self, file_path: &str, source: &str, included: &mut Vec<PathBuf>, lines: &mut Vec<SourceLine>, errors: &mut Vec<Error>) {
         info!("[Preprocessor] => includes => {}", file_path);
-        let mut index = 0;
-        for line in source.clone().lines() {
-            let line = line.trim();
-            if line.starts_with("%") {
-                if line.replace("%", "").starts_with("include") {
-                    let path = self.get_string(line);
-                    let mut file;
-                    match env::var("OSVM_LIBS_DIR") {
-                        Ok(_) => {}
-                        Err(_) => {
-                            eprintln!("[Error]: OSVM_LIBS_DIR NOT SET!");
-                            exit(1);
-                        }
-                    }
-                    
-                    match File::open(PathBuf::from(env::var("OSVM_LIBS_DIR").unwrap()).join(path.clone())) {
-                        Ok(_) => file = File::open(PathBuf::from(env::var("OSVM_LIBS_DIR").unwrap()).join(path)).unwrap(),
-                        Err(_) => file = File::open(PathBuf::from(file_path.clone()).parent().unwrap().join(path)).unwrap(),
+        for (index, text) in source.lines().enumerate() {
+            let line = SourceLine::init(file_path, index + 1, text);
+            
+            if !self.is_directive(&line.code, "include") {
+                lines.push(line);
+                continue;
+            }
+            
+            let path = self.get_string(&line.code);
+            match self.find_include(file_path, &path) {
+                Some((include_path, include_source)) => {
+                    let canonical = fs::canonicalize(&include_path).unwrap_or(include_path.clone());
+                    if included.contains(&canonical) {
+                        continue;
                     }
                     
-                    let mut include_source = String::new();
-                    let _ = file.read_to_string(&mut include_source);
-                    
-                    let isource = include_source + "\n";
-                    source.insert_str(self.get_cindex(index + 1, source.as_str()), isource.as_str());
+                    included.push(canonical);
+                    self.process_includes(&include_path.to_string_lossy(), &include_source, included, lines, errors);
+                }
+                None => {
+                    errors.push(Error::with_message(
+                        ErrorKind::IncludeNotFound,
+                        format!("could not find `{}`", path)
+                    ).at_span(line.span(line.code.split('"').nth(1).unwrap_or(""))));
                 }
             }
-            
-            index += 1;
         }
-        
-        source = self.remove_line_by_sstr("%include", source);
-        source
     }
     
-    pub fn process_source(self: &Self, file_path: String, source: String) -> String {
-        info!("[Preprocessor] => all => {}", file_path);
-        let mut _index = 0;
+    // Resolves includes, strips directives and comments and expands macros
+    pub fn process(self: &Self, file_path: &str, source: &str) -> Result<Vec<SourceLine>, Vec<Error>> {
+        let mut lines = Vec::new();
+        let mut errors = Vec::new();
+        let mut included = vec![fs::canonicalize(file_path).unwrap_or(PathBuf::from(file_path))];
+        self.process_includes(file_path, source, &mut included, &mut lines, &mut errors);
         
-        let mut macros = Vec::<(&str, &str)>::new();
-        for line in &mut source.lines() {
-            let line = line.trim();
-            if line.starts_with("%") {
-                if line.replace("%", "").starts_with("define") {
-                    let splitted: Vec<&str> = line.trim().splitn(3, |c: char| c.is_whitespace()).collect();
-                    macros.push((splitted[1], splitted[2]));
+        info!("[Preprocessor] => all => {}", file_path);
+        let mut macros = Vec::<(String, String)>::new();
+        for line in &lines {
+            if self.is_directive(&line.code, "define") {
+                let splitted: Vec<&str> = line.code.splitn(3, |c: char| c.is_whitespace()).collect();
+                if splitted.len() < 3 {
+                    errors.push(Error::with_message(
+                        ErrorKind::InvalidDirective,
+                        "expected `%define <name> <value>`"
+                    ).at_span(line.span(&line.code)));
+                    continue;
                 }
+                
+                macros.push((splitted[1].to_string() + "!", splitted[2].trim().to_string()));
             }
-            
-            _index += 1;
         }
         
-        let mut source = self.remove_line_by_sstr("%", source.clone());
-        source = self.remove_line_by_sstr(";", source);
-        for _macro in macros.clone() {
-            let macro_call: String = _macro.0.to_string() + "!";
-            source = source.replace(macro_call.as_str(), _macro.1);
+        lines.retain(|line| !line.code.is_empty() && !line.code.starts_with("%") && !line.code.starts_with(";"));
+        for line in &mut lines {
+            for (macro_call, value) in &macros {
+                line.expand(macro_call, value);
+            }
         }
         
-        source
+        if errors.is_empty() {
+            Ok(lines)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn columns(line: &SourceLine, tokens: &[&str]) -> Vec<(usize, usize)> {
+        tokens.iter().map(|token| {
+            let span = line.span(token);
+            (span.column, span.len)
+        }).collect()
+    }
+    
+    #[test]
+    fn repeated_tokens_get_their_own_span() {
+        let line = SourceLine::init("test.osv", 1, "    add r1, r2, r1");
+        let operands: Vec<&str> = line.code[4..].split(", ").collect();
+        assert_eq!(columns(&line, &operands), [(9, 2), (13, 2), (17, 2)]);
+    }
+    
+    #[test]
+    fn tokens_after_a_macro_point_into_the_original_line() {
+        let mut line = SourceLine::init("test.osv", 3, "\twrt size!, r0, r0");
+        line.expand("size!", "#64");
+        assert_eq!(line.code, "wrt #64, r0, r0");
+        
+        let operands: Vec<&str> = line.code[4..].split(", ").collect();
+        assert_eq!(columns(&line, &operands), [(6, 5), (13, 2), (17, 2)]);
+    }
+    
+    #[test]
+    fn macros_expand_like_replace() {
+        let mut line = SourceLine::init("test.osv", 1, "push a! a!a!");
+        line.expand("a!", "#1");
+        assert_eq!(line.code, "push #1 #1#1");
+        
+        let tokens = [&line.code[5..7], &line.code[8..10], &line.code[10..12], &line.code[8..12]];
+        assert_eq!(columns(&line, &tokens), [(6, 2), (9, 2), (11, 2), (9, 4)]);
+    }
+    
+    #[test]
+    fn other_tokens_span_the_whole_line() {
+        let line = SourceLine::init("test.osv", 1, "  mov r0, r1  ");
+        assert_eq!(columns(&line, &["r1", ""]), [(3, 10), (3, 10)]);
+        assert_eq!(columns(&line, &[&line.code]), [(3, 10)]);
     }
 }
\ No newline at end of file
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::disassembler::Disassembler;
+use crate::oasm::Label;
+use crate::opcode::{Opcode, OpcodeType};
+
+// How many rows the report shows per table
+const REPORT_ROWS: usize = 20;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FunctionStats {
+    pub calls: u64,
+    
+    // Instructions executed in the function and its callees, and in the function alone
+    pub inclusive: u64,
+    pub exclusive: u64,
+}
+
+// A node of the call tree, the path from the root is a folded stack
+struct CallNode {
+    function: usize,
+    parent: usize,
+    children: HashMap<usize, usize>,
+    cycles: u64,
+}
+
+// An active call, `start` is the cycle count when it was entered
+struct Activation {
+    function: usize,
+    node: usize,
+    start: u64,
+}
+
+// Counts executions per instruction, opcode, label and function. Every
+// instruction is one cycle, functions are call targets plus the entry.
+pub struct Profiler {
+    pub cycles: u64,
+    pub pc_counts: Vec<u64>,
+    pub opcode_counts: HashMap<OpcodeType, u64>,
+    pub functions: HashMap<usize, FunctionStats>,
+    
+    nodes: Vec<CallNode>,
+    stack: Vec<Activation>,
+}
+
+impl Profiler {
+    pub fn init(program_len: usize, entry: usize) -> Profiler {
+        let root = CallNode { function: entry, parent: 0, children: HashMap::new(), cycles: 0 };
+        let mut functions = HashMap::new();
+        functions.insert(entry, FunctionStats { calls: 1, ..FunctionStats::default() });
+        
+        Profiler {
+            cycles: 0,
+            pc_counts: vec![0; program_len],
+            opcode_counts: HashMap::new(),
+            functions,
+            nodes: vec![root],
+            stack: vec![Activation { function: entry, node: 0, start: 0 }],
+        }
+    }
+    
+    // `depth` is the call stack depth before the instruction ran and `next_pc` the pc after it
+    pub(crate) fn record(self: &mut Self, pc: usize, opcode: &Opcode, depth: (usize, usize), next_pc: usize) {
+        self.cycles += 1;
+        if let Some(count) = self.pc_counts.get_mut(pc) {
+            *count += 1;
+        }
+        *self.opcode_counts.entry(opcode.op_type).or_insert(0) += 1;
+        
+        let current = self.stack.last().unwrap();
+        self.nodes[current.node].cycles += 1;
+        self.functions.entry(current.function).or_default().exclusive += 1;
+        
+        if depth.1 > depth.0 {
+            self.enter(next_pc);
+        } else if depth.1 < depth.0 && self.stack.len() > 1 {
+            self.leave();
+        }
+    }
+    
+    fn enter(self: &mut Self, function: usize) {
+        let parent = self.stack.last().unwrap().node;
+        let node = match self.nodes[parent].children.get(&function) {
+            Some(node) => *node,
+            None => {
+                self.nodes.push(CallNode { function, parent, children: HashMap::new(), cycles: 0 });
+                let node = self.nodes.len() - 1;
+                self.nodes[parent].children.insert(function, node);
+                node
+            }
+        };
+        
+        self.functions.entry(function).or_default().calls += 1;
+        self.stack.push(Activation { function, node, start: self.cycles });
+    }
+    
+    fn leave(self: &mut Self) {
+        let activation = self.stack.pop().unwrap();
+        
+        // Recursive calls are already counted by the outermost activation
+        if !self.stack.iter().any(|outer| outer.function == activation.function) {
+            self.functions.entry(activation.function).or_default().inclusive += self.cycles - activation.start;
+        }
+    }
+    
+    // Closes the calls that are still active, e.g. when the program halted inside one
+    pub fn finish(self: &mut Self) {
+        while self.stack.len() > 1 {
+            self.leave();
+        }
+        
+        if let Some(entry) = self.stack.last() {
+            self.functions.entry(entry.function).or_default().inclusive = self.cycles - entry.start;
+        }
+    }
+    
+    // The label each address falls under, the closest one at or before it
+    fn enclosing_labels(self: &Self, symbols: &[Label]) -> Vec<Option<usize>> {
+        let mut labels: Vec<(usize, usize)> = symbols.iter().enumerate().map(|(i, label)| (label.addr, i)).collect();
+        labels.sort();
+        
+        let mut enclosing = vec![None; self.pc_counts.len()];
+        let mut next = 0;
+        let mut current = None;
+        for (addr, label) in enclosing.iter_mut().enumerate() {
+            while next < labels.len() && labels[next].0 <= addr {
+                current = Some(labels[next].1);
+                next += 1;
+            }
+            *label = current;
+        }
+        enclosing
+    }
+    
+    fn function_name(self: &Self, addr: usize, symbols: &[Label]) -> String {
+        match symbols.iter().find(|label| label.addr == addr) {
+            Some(label) => label.name.clone(),
+            None => format!("{:04}", addr),
+        }
+    }
+    
+    pub fn report(self: &Self, code: &[Opcode], symbols: &[Label]) -> String {
+        let disassembler = Disassembler {};
+        let names = disassembler.target_names(code, symbols);
+        let total = self.cycles.max(1) as f64;
+        let percent = |count: u64| count as f64 * 100.0 / total;
+        let mut report = format!("[Profile]: {} instructions executed\n", self.cycles);
+        
+        report.push_str("\n[Hot Instructions]:\n       count       %  addr  instruction\n");
+        let mut hot: Vec<(usize, u64)> = self.pc_counts.iter().copied().enumerate().filter(|(_, count)| *count > 0).collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (addr, count) in hot.iter().take(REPORT_ROWS) {
+            let text = code.get(*addr).map(|opcode| disassembler.format_opcode(opcode, &names)).unwrap_or_default();
+            report.push_str(&format!("    {:>8} {:>6.2}%  {:04}  {}\n", count, percent(*count), addr, text));
+        }
+        
+        report.push_str("\n[Opcodes]:\n       count       %  opcode\n");
+        let mut opcodes: Vec<(OpcodeType, u64)> = self.opcode_counts.iter().map(|(op, count)| (*op, *count)).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then((a.0 as u8).cmp(&(b.0 as u8))));
+        for (op_type, count) in opcodes.iter().take(REPORT_ROWS) {
+            report.push_str(&format!("    {:>8} {:>6.2}%  {:?}\n", count, percent(*count), op_type));
+        }
+        
+        report.push_str("\n[Labels]:\n       count       %  label\n");
+        let mut labels: HashMap<Option<usize>, u64> = HashMap::new();
+        for (addr, label) in self.enclosing_labels(symbols).into_iter().enumerate() {
+            if self.pc_counts[addr] > 0 {
+                *labels.entry(label).or_insert(0) += self.pc_counts[addr];
+            }
+        }
+        let mut labels: Vec<(String, u64)> = labels.into_iter()
+            .map(|(label, count)| (label.map(|i| symbols[i].name.clone()).unwrap_or_else(|| "<none>".to_string()), count))
+            .collect();
+        labels.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (name, count) in labels.iter().take(REPORT_ROWS) {
+            report.push_str(&format!("    {:>8} {:>6.2}%  {}\n", count, percent(*count), name));
+        }
+        
+        report.push_str("\n[Functions]:\n       calls   inclusive       %   exclusive       %  function\n");
+        let mut functions: Vec<(String, FunctionStats)> = self.functions.iter()
+            .map(|(addr, stats)| (self.function_name(*addr, symbols), *stats))
+            .collect();
+        functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(&b.0)));
+        for (name, stats) in functions.iter().take(REPORT_ROWS) {
+            report.push_str(&format!(
+                "    {:>8} {:>11} {:>6.2}% {:>11} {:>6.2}%  {}\n",
+                stats.calls, stats.inclusive, percent(stats.inclusive), stats.exclusive, percent(stats.exclusive), name
+            ));
+        }
+        
+        report
+    }
+    
+    // One `root;caller;callee cycles` line per call path, the input format of flamegraph.pl
+    pub fn write_folded(self: &Self, out: &mut dyn Write, symbols: &[Label]) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+            
+            let mut path = vec![self.function_name(node.function, symbols)];
+            let mut parent = index;
+            while parent != 0 {
+                parent = self.nodes[parent].parent;
+                path.push(self.function_name(self.nodes[parent].function, symbols));
+            }
+            
+            path.reverse();
+            lines.push(format!("{} {}", path.join(";"), node.cycles));
+        }
+        
+        lines.sort();
+        for line in lines {
+            writeln!(out, "{}", line)?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osvm::OSVM;
+    use crate::testing::*;
+    
+    fn profile(source: &str) -> (OSVM, Profiler) {
+        let (mut osvm, _) = machine(&assemble(source));
+        osvm.start_profiling();
+        osvm.execute_program().unwrap();
+        let profiler = osvm.stop_profiling().unwrap();
+        (osvm, profiler)
+    }
+    
+    fn stats(osvm: &OSVM, profiler: &Profiler, name: &str) -> (u64, u64, u64) {
+        let addr = osvm.symbols.iter().find(|label| label.name == name).unwrap().addr;
+        let stats = profiler.functions[&addr];
+        (stats.calls, stats.inclusive, stats.exclusive)
+    }
+    
+    fn folded(osvm: &OSVM, profiler: &Profiler) -> String {
+        let mut out = Vec::new();
+        profiler.write_folded(&mut out, &osvm.symbols).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+    
+    const CALLS: &str = "
+_start:
+    call a
+    call b
+    hlt
+a:
+    call b
+    ret
+b:
+    mov r0, #1
+    ret
+";
+    
+    #[test]
+    fn counts_instructions_and_opcodes() {
+        let (_, profiler) = profile(CALLS);
+        assert_eq!(profiler.cycles, 9);
+        assert_eq!(profiler.pc_counts, [1, 1, 1, 1, 1, 2, 2]);
+        assert_eq!(profiler.opcode_counts[&OpcodeType::Call], 3);
+        assert_eq!(profiler.opcode_counts[&OpcodeType::Ret], 3);
+        assert_eq!(profiler.opcode_counts[&OpcodeType::Mov], 2);
+    }
+    
+    #[test]
+    fn attributes_cycles_to_functions() {
+        let (osvm, profiler) = profile(CALLS);
+        assert_eq!(stats(&osvm, &profiler, "_start"), (1, 9, 3));
+        assert_eq!(stats(&osvm, &profiler, "a"), (1, 4, 2));
+        assert_eq!(stats(&osvm, &profiler, "b"), (2, 4, 4));
+        assert_eq!(folded(&osvm, &profiler), "_start 3\n_start;a 2\n_start;a;b 2\n_start;b 2\n");
+    }
+    
+    #[test]
+    fn recursion_is_counted_once() {
+        let (osvm, profiler) = profile("
+_start:
+    mov r0, #3
+    call f
+    hlt
+f:
+    dec r0
+    jnz more, r0
+    ret
+more:
+    call f
+    ret
+");
+        assert_eq!(profiler.cycles, 14);
+        assert_eq!(stats(&osvm, &profiler, "f"), (3, 11, 11));
+        assert_eq!(folded(&osvm, &profiler), "_start 3\n_start;f 4\n_start;f;f 4\n_start;f;f;f 3\n");
+    }
+    
+    #[test]
+    fn halting_inside_a_call_closes_it() {
+        let (osvm, profiler) = profile("
+_start:
+    call f
+f:
+    hlt
+");
+        assert_eq!(stats(&osvm, &profiler, "_start"), (1, 2, 1));
+        assert_eq!(stats(&osvm, &profiler, "f"), (1, 1, 1));
+    }
+    
+    #[test]
+    fn reports_every_table() {
+        let (osvm, profiler) = profile(CALLS);
+        let report = profiler.report(&osvm.program.opcodes(), &osvm.symbols);
+        assert!(report.starts_with("[Profile]: 9 instructions executed\n"));
+        assert!(report.contains("           2  22.22%  0005  mov r0, #1\n"));
+        assert!(report.contains("           3  33.33%  Call\n"));
+        assert!(report.contains("           4  44.44%  b\n"));
+        assert!(report.contains("           2           4  44.44%           4  44.44%  b\n"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,49 @@
+use crate::oasm::Label;
+use crate::opcode::Opcode;
+
+// Where an instruction came from, `file` indexes Program::files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineInfo {
+    pub file: usize,
+    pub line: usize,
+}
+
+// An assembled program, any number of OSVMs can load the same one
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub code: Vec<Opcode>,
+    pub entry: usize,
+    
+    pub symbols: Vec<Label>,
+    
+    // One entry per instruction, empty when there is no source (e.g. a .vbin)
+    pub files: Vec<String>,
+    pub lines: Vec<LineInfo>,
+}
+
+impl Program {
+    pub fn init() -> Program {
+        Program {
+            code: Vec::new(),
+            entry: 0,
+            symbols: Vec::new(),
+            files: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+    
+    pub fn symbol(self: &Self, name: &str) -> Option<usize> {
+        self.symbols.iter().find(|label| label.name == name).map(|label| label.addr)
+    }
+    
+    // The source file and line of the instruction at `addr`
+    pub fn source_line(self: &Self, addr: usize) -> Option<(&str, usize)> {
+        let info = self.lines.get(addr)?;
+        Some((self.files[info.file].as_str(), info.line))
+    }
+    
+    // The first instruction generated by `line` of `file`
+    pub fn line_address(self: &Self, file: usize, line: usize) -> Option<usize> {
+        self.lines.iter().position(|info| info.file == file && info.line == line)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,201 @@
+use std::collections::VecDeque;
+
+use crate::osvm::Frame;
+use crate::utils::defines::*;
+
+pub const DEFAULT_RECORD_CAPACITY: usize = 100_000;
+
+// Everything needed to undo one executed instruction
+#[derive(Debug, Clone)]
+pub struct Delta {
+    pub pc: usize,
+    pub tsr: usize,
+    pub halt: bool,
+    
+    // Registers that changed, with their old values
+    pub registers: Vec<(usize, TypedWord)>,
+    
+    // The stack is truncated to stack_len and the old values are pushed back
+    pub stack_len: usize,
+    pub stack: Vec<TypedWord>,
+    
+    // Old bytes of every `wrt`, in the order they were written
+    pub memory: Vec<(usize, Vec<u8>)>,
+    
+    pub call_depth: usize,
+    pub frame: Option<Frame>,
+}
+
+// Bounded ring buffer of deltas, the oldest instruction is forgotten
+// once it is full so memory use only depends on the capacity
+pub struct Recorder {
+    deltas: VecDeque<Delta>,
+    capacity: usize,
+    
+    // Delta of the instruction currently executing
+    pub(crate) pending: Option<Delta>,
+}
+
+impl Recorder {
+    pub fn init(capacity: usize) -> Recorder {
+        Recorder {
+            deltas: VecDeque::with_capacity(capacity.min(DEFAULT_RECORD_CAPACITY)),
+            capacity: capacity.max(1),
+            pending: None,
+        }
+    }
+    
+    pub fn push(self: &mut Self, delta: Delta) {
+        if self.deltas.len() >= self.capacity {
+            self.deltas.pop_front();
+        }
+        
+        self.deltas.push_back(delta);
+    }
+    
+    pub fn pop(self: &mut Self) -> Option<Delta> {
+        self.deltas.pop_back()
+    }
+    
+    pub fn last(self: &Self) -> Option<&Delta> {
+        self.deltas.back()
+    }
+    
+    pub fn record_memory(self: &mut Self, addr: usize, old: &[u8]) {
+        if let Some(pending) = &mut self.pending {
+            pending.memory.push((addr, old.to_vec()));
+        }
+    }
+    
+    pub fn len(self: &Self) -> usize {
+        self.deltas.len()
+    }
+    
+    pub fn is_empty(self: &Self) -> bool {
+        self.deltas.is_empty()
+    }
+    
+    pub fn capacity(self: &Self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osvm::OSVM;
+    use crate::testing::*;
+    use crate::utils::error::ErrorKind;
+    
+    type State = (usize, usize, bool, [TypedWord; 17], Vec<TypedWord>, Vec<Frame>);
+    
+    fn state(osvm: &OSVM) -> State {
+        (osvm.get_pc(), osvm.tsr, osvm.is_halted(), osvm.registers(), osvm.stack.clone(), osvm.call_stack.clone())
+    }
+    
+    fn delta(pc: usize) -> Delta {
+        Delta {
+            pc,
+            tsr: 0,
+            halt: false,
+            registers: Vec::new(),
+            stack_len: 0,
+            stack: Vec::new(),
+            memory: Vec::new(),
+            call_depth: 0,
+            frame: None,
+        }
+    }
+    
+    #[test]
+    fn forgets_the_oldest_delta_when_full() {
+        let mut recorder = Recorder::init(3);
+        for pc in 0..5 {
+            recorder.push(delta(pc));
+        }
+        
+        assert_eq!(recorder.len(), 3);
+        assert_eq!(recorder.last().map(|delta| delta.pc), Some(4));
+        let pcs: Vec<usize> = std::iter::from_fn(|| recorder.pop()).map(|delta| delta.pc).collect();
+        assert_eq!(pcs, [4, 3, 2]);
+        assert!(recorder.is_empty());
+        assert_eq!(Recorder::init(0).capacity(), 1);
+    }
+    
+    #[test]
+    fn stepping_back_restores_every_state() {
+        for (name, program) in examples() {
+            let (mut osvm, _) = machine(&program);
+            let memory = osvm.memory.clone();
+            osvm.start_recording(usize::MAX);
+            
+            let mut states = vec![state(&osvm)];
+            while !osvm.is_halted() {
+                osvm.execute_opcode().unwrap();
+                states.push(state(&osvm));
+            }
+            
+            states.pop();
+            while let Some(expected) = states.pop() {
+                assert!(osvm.step_back().is_some(), "{} ran out of deltas", name);
+                assert_eq!(state(&osvm), expected, "{}", name);
+            }
+            
+            assert!(osvm.step_back().is_none());
+            assert!(osvm.memory == memory, "{} memory differs after stepping back", name);
+        }
+    }
+    
+    #[test]
+    fn only_the_last_instructions_are_kept() {
+        let (mut osvm, _) = machine(&assemble("
+_start:
+    mov r0, #10
+loop:
+    dec r0
+    jnz loop, r0
+    hlt
+"));
+        osvm.start_recording(4);
+        osvm.execute_program().unwrap();
+        assert_eq!(osvm.recorder.as_ref().map(|recorder| recorder.len()), Some(4));
+        
+        while osvm.step_back().is_some() {}
+        assert_eq!(osvm.get_pc(), 2);
+        assert_eq!(osvm.registers()[0], TypedWord::u64(1));
+    }
+    
+    #[test]
+    fn faults_leave_the_same_state_with_or_without_the_recorder() {
+        let program = assemble("
+_start:
+    push #1
+    push #2
+    mov r0, #9
+    push #0
+    divs
+    hlt
+");
+        let (plain, _, result) = run(&program);
+        assert_eq!(result.unwrap_err().kind, ErrorKind::DivByZero);
+        
+        let (mut osvm, _) = machine(&program);
+        osvm.start_recording(16);
+        for _ in 0..4 {
+            osvm.execute_opcode().unwrap();
+        }
+        
+        let before = state(&osvm);
+        assert!(osvm.execute_opcode().is_err());
+        assert_eq!(state(&osvm), state(&plain));
+        assert_eq!(osvm.stack, [TypedWord::u64(1)]);
+        assert_eq!(osvm.recorder.as_ref().map(|recorder| recorder.len()), Some(5));
+        
+        // Stepping back over the fault undoes its partial changes
+        osvm.step_back();
+        assert_eq!(state(&osvm), before);
+        osvm.step_back();
+        assert_eq!(osvm.stack, [TypedWord::u64(1), TypedWord::u64(2)]);
+        assert_eq!(osvm.registers()[0], TypedWord::u64(9));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,200 @@
+use crate::oasm::Label;
+use crate::opcode::Opcode;
+use crate::osvm::Frame;
+use crate::utils::{defines::*, error::{Error, ErrorKind}};
+
+// The whole machine state, enough to carry on running `program_path`
+// from exactly where the snapshot was taken
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub program_path: String,
+    pub fingerprint: u64,
+    
+    pub registers: [TypedWord; 17],
+    pub pc: usize,
+    pub tsr: usize,
+    pub halt: bool,
+    
+    pub call_stack: Vec<Frame>,
+    pub stack: Vec<TypedWord>,
+    pub memory: Vec<u8>,
+}
+
+// When `run --snapshot-at` saves the state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnapshotPoint {
+    // The first time the pc reaches the address
+    Pc(usize),
+    // After this many instructions
+    Count(u64),
+}
+
+impl SnapshotPoint {
+    // `pc:<addr|label>` or an instruction count
+    pub fn parse(text: &str, symbols: &[Label]) -> Result<SnapshotPoint, Error> {
+        let invalid = || Error::with_message(ErrorKind::InvalidOperand, format!("invalid snapshot point `{}`, expected pc:<addr|label> or an instruction count", text));
+        
+        match text.strip_prefix("pc:") {
+            Some(target) => {
+                if let Some(label) = symbols.iter().find(|label| label.name == target) {
+                    return Ok(SnapshotPoint::Pc(label.addr));
+                }
+                
+                target.parse().map(SnapshotPoint::Pc).map_err(|_| invalid())
+            }
+            None => text.parse().map(SnapshotPoint::Count).map_err(|_| invalid()),
+        }
+    }
+    
+    pub fn reached(self: &Self, pc: usize, executed: u64) -> bool {
+        match self {
+            SnapshotPoint::Pc(addr) => pc == *addr,
+            SnapshotPoint::Count(count) => executed == *count,
+        }
+    }
+}
+
+// FNV-1a over the code and entry point, a snapshot only restores onto the
+// program it was taken from
+pub fn program_fingerprint(code: &[Opcode], entry: usize) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    
+    feed(&(entry as u64).to_le_bytes());
+    for opcode in code {
+        feed(&[opcode.op_type as u8, opcode.op_regs.len() as u8]);
+        for reg in opcode.op_regs.iter() {
+            feed(&[reg as u8]);
+        }
+        
+        match opcode.op_operand {
+            Some(value) => {
+                feed(&[1, value.ty as u8]);
+                feed(&unsafe { value.word.as_u64 }.to_le_bytes());
+            }
+            None => feed(&[0]),
+        }
+    }
+    
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osvm::OSVM;
+    use crate::testing::*;
+    use crate::utils::buffer::SharedBuffer;
+    use crate::utils::file::OSVMFile;
+    
+    const RECURSION: &str = "
+_start:
+    mov r0, #1500
+    call f
+    hlt
+f:
+    dec r0
+    jnz more, r0
+    ret
+more:
+    call f
+    ret
+";
+    
+    // Takes a snapshot and reads it back the way `resume` does
+    fn reload(osvm: &OSVM) -> Result<Snapshot, Error> {
+        let file = OSVMFile {};
+        let bytes = file.encode_snapshot(&osvm.snapshot("test.vbin"))?;
+        file.decode_snapshot(&bytes)
+    }
+    
+    fn text(output: &SharedBuffer) -> String {
+        String::from_utf8_lossy(&output.0.borrow()).into_owned()
+    }
+    
+    #[test]
+    fn resumed_runs_finish_like_uninterrupted_ones() {
+        for (name, program) in examples() {
+            let (full, full_output, result) = run(&program);
+            result.unwrap();
+            
+            let (mut counter, _) = machine(&program);
+            let mut steps = 0;
+            while !counter.is_halted() {
+                counter.execute_opcode().unwrap();
+                steps += 1;
+            }
+            
+            let (mut before, before_output) = machine(&program);
+            for _ in 0..steps / 2 {
+                before.execute_opcode().unwrap();
+            }
+            
+            let (mut after, after_output) = machine(&program);
+            after.restore(&reload(&before).unwrap()).unwrap();
+            assert_eq!(after.get_pc(), before.get_pc(), "{}", name);
+            after.execute_program().unwrap();
+            
+            assert_eq!(after.registers(), full.registers(), "{}", name);
+            assert!(after.memory == full.memory, "{} memory differs", name);
+            assert_eq!(text(&before_output) + &text(&after_output), full_output, "{}", name);
+        }
+    }
+    
+    // Deeper than CALL_STACK_CAPACITY, the machine's own limit decides
+    #[test]
+    fn call_stack_limit_is_the_machines() {
+        let program = assemble(RECURSION);
+        let (mut deep, _) = machine(&program);
+        deep.set_call_stack_depth(2000);
+        while deep.call_stack.len() < 1500 {
+            deep.execute_opcode().unwrap();
+        }
+        
+        let snapshot = reload(&deep).unwrap();
+        assert_eq!(snapshot.call_stack.len(), 1500);
+        
+        let (mut shallow, _) = machine(&program);
+        let err = shallow.restore(&snapshot).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::SnapshotMismatch);
+        assert_eq!(err.to_string(), format!("SnapshotMismatch: snapshot call stack is deeper than {} frames", CALL_STACK_CAPACITY));
+        
+        let (mut resumed, _) = machine(&program);
+        resumed.set_call_stack_depth(2000);
+        resumed.restore(&snapshot).unwrap();
+        resumed.execute_program().unwrap();
+        assert!(resumed.call_stack.is_empty());
+    }
+    
+    #[test]
+    fn snapshots_only_restore_onto_their_program() {
+        let (mut osvm, _) = machine(&assemble(RECURSION));
+        osvm.execute_opcode().unwrap();
+        let snapshot = reload(&osvm).unwrap();
+        
+        let (mut other, _) = machine(&assemble("\n_start:\n    hlt\n"));
+        assert_eq!(other.restore(&snapshot).unwrap_err().kind, ErrorKind::SnapshotMismatch);
+        
+        let mut small = snapshot.clone();
+        small.memory.truncate(16);
+        assert_eq!(osvm.restore(&small).unwrap_err().kind, ErrorKind::SnapshotMismatch);
+    }
+    
+    #[test]
+    fn parses_snapshot_points() {
+        let symbols = assemble(RECURSION).symbols;
+        assert_eq!(SnapshotPoint::parse("pc:more", &symbols).unwrap(), SnapshotPoint::Pc(6));
+        assert_eq!(SnapshotPoint::parse("pc:3", &symbols).unwrap(), SnapshotPoint::Pc(3));
+        assert_eq!(SnapshotPoint::parse("250", &symbols).unwrap(), SnapshotPoint::Count(250));
+        assert!(SnapshotPoint::parse("pc:nowhere", &symbols).is_err());
+        assert!(SnapshotPoint::parse("soon", &symbols).is_err());
+        
+        assert!(SnapshotPoint::Pc(7).reached(7, 0));
+        assert!(!SnapshotPoint::Count(5).reached(7, 4));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,72 @@
+// Helpers shared by the unit tests
+use std::{env, fs, path::PathBuf, sync::Once};
+
+use crate::assembler::Assembler;
+use crate::osvm::OSVM;
+use crate::program::Program;
+use crate::utils::buffer::SharedBuffer;
+use crate::utils::error::Error;
+
+static LIBS_DIR: Once = Once::new();
+
+pub(crate) fn crate_path(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+// Everything assembles through here so OSVM_LIBS_DIR is set before the
+// preprocessor reads it
+pub(crate) fn assemble_file(path: &str, source: &str) -> Result<Program, Vec<Error>> {
+    LIBS_DIR.call_once(|| env::set_var("OSVM_LIBS_DIR", crate_path("libs")));
+    Assembler::init().assemble(path, source)
+}
+
+pub(crate) fn try_assemble(source: &str) -> Result<Program, Vec<Error>> {
+    assemble_file("test.osv", source)
+}
+
+pub(crate) fn assemble(source: &str) -> Program {
+    match try_assemble(source) {
+        Ok(program) => program,
+        Err(errors) => panic!("assembly failed: {:?}", errors.iter().map(|err| err.to_string()).collect::<Vec<_>>()),
+    }
+}
+
+// Every program in examples/, by file name
+pub(crate) fn examples() -> Vec<(String, Program)> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(crate_path("../examples")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "osv"))
+        .collect();
+    paths.sort();
+    
+    paths.into_iter().map(|path| {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let source = fs::read_to_string(&path).unwrap();
+        match assemble_file(&path.to_string_lossy(), &source) {
+            Ok(program) => (name, program),
+            Err(errors) => panic!("{} failed to assemble: {:?}", name, errors.iter().map(|err| err.to_string()).collect::<Vec<_>>()),
+        }
+    }).collect()
+}
+
+// An OSVM with the default sysfs that prints into a buffer
+pub(crate) fn machine(program: &Program) -> (OSVM, SharedBuffer) {
+    let output = SharedBuffer::default();
+    let mut osvm = OSVM::init();
+    osvm.init_default_sysf();
+    osvm.output = Box::new(output.clone());
+    osvm.load_program(program).unwrap();
+    (osvm, output)
+}
+
+// Runs `program` to the end, returns the machine, what it printed and how it ended
+pub(crate) fn run(program: &Program) -> (OSVM, String, Result<(), Error>) {
+    let (mut osvm, output) = machine(program);
+    let result = osvm.execute_program();
+    let output = String::from_utf8_lossy(&output.0.borrow()).into_owned();
+    (osvm, output, result)
+}
+
+pub(crate) fn run_source(source: &str) -> (OSVM, String, Result<(), Error>) {
+    run(&assemble(source))
+}
\ No newline at end of file
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::disassembler::Disassembler;
+use crate::oasm::Label;
+use crate::opcode::Opcode;
+use crate::utils::defines::*;
+use crate::watchpoint::WatchAccess;
+
+// A `rd` or `wrt` of the traced instruction
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryEffect {
+    pub access: WatchAccess,
+    pub addr: usize,
+    pub len: usize,
+    pub old: u64,
+    pub new: u64,
+}
+
+// Writes one JSON object per executed instruction, e.g.
+// {"step":3,"pc":47,"op":"mov r0, r14","reads":[["r14","u64: 0"]],"writes":[["r0","u64: 0","u64: 0"]],"stack":[0,0]}
+pub struct Tracer {
+    out: Box<dyn Write>,
+    step: u64,
+    
+    names: HashMap<usize, String>,
+    disassembler: Disassembler,
+    
+    // Effects of the instruction currently executing
+    reads: Vec<(usize, TypedWord)>,
+    pub(crate) writes: Vec<(usize, TypedWord, TypedWord)>,
+    pub(crate) memory: Vec<MemoryEffect>,
+    pub(crate) sysf: Option<usize>,
+    
+    // The first write error, tracing stops quietly after it
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn init(out: Box<dyn Write>, code: &[Opcode], symbols: &[Label]) -> Tracer {
+        let disassembler = Disassembler {};
+        Tracer {
+            out,
+            step: 0,
+            names: disassembler.target_names(code, symbols),
+            disassembler,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            memory: Vec::new(),
+            sysf: None,
+            error: None,
+        }
+    }
+    
+    pub(crate) fn register_read(self: &mut Self, reg: usize, value: TypedWord) {
+        if !self.reads.iter().any(|(read, _)| *read == reg) {
+            self.reads.push((reg, value));
+        }
+    }
+    
+    // `stack` is the depth before and after the instruction ran
+    pub(crate) fn record(self: &mut Self, pc: usize, opcode: &Opcode, stack: (usize, usize), error: Option<String>) {
+        let mut line = format!("{{\"step\":{},\"pc\":{},\"op\":{}", self.step, pc, quote(&self.disassembler.format_opcode(opcode, &self.names)));
+        self.step += 1;
+        
+        line.push_str(",\"reads\":[");
+        for (i, (reg, value)) in self.reads.drain(..).enumerate() {
+            line.push_str(&format!("{}[\"{}\",{}]", if i == 0 { "" } else { "," }, REGISTERS[reg], quote(&format!("{:?}", value))));
+        }
+        
+        line.push_str("],\"writes\":[");
+        for (i, (reg, old, new)) in self.writes.drain(..).enumerate() {
+            line.push_str(&format!("{}[\"{}\",{},{}]", if i == 0 { "" } else { "," }, REGISTERS[reg], quote(&format!("{:?}", old)), quote(&format!("{:?}", new))));
+        }
+        
+        line.push_str(&format!("],\"stack\":[{},{}]", stack.0, stack.1));
+        if !self.memory.is_empty() {
+            line.push_str(",\"memory\":[");
+            for (i, effect) in self.memory.drain(..).enumerate() {
+                let access = if effect.access == WatchAccess::Read { "rd" } else { "wrt" };
+                line.push_str(&format!(
+                    "{}{{\"access\":\"{}\",\"addr\":{},\"len\":{},\"old\":{},\"new\":{}}}",
+                    if i == 0 { "" } else { "," }, access, effect.addr, effect.len, effect.old, effect.new
+                ));
+            }
+            line.push(']');
+        }
+        
+        if let Some(index) = self.sysf.take() {
+            line.push_str(&format!(",\"sysf\":{}", index));
+        }
+        
+        if let Some(error) = error {
+            line.push_str(&format!(",\"error\":{}", quote(&error)));
+        }
+        
+        line.push_str("}\n");
+        if self.error.is_none() {
+            if let Err(err) = self.out.write_all(line.as_bytes()) {
+                self.error = Some(err);
+            }
+        }
+    }
+    
+    // Flushes the trace, reporting the first error hit while writing it
+    pub fn finish(self: &mut Self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        
+        self.out.flush()
+    }
+}
+
+fn quote(text: &str) -> String {
+    serde_json::Value::from(text).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    
+    use super::*;
+    use crate::utils::buffer::SharedBuffer;
+    use crate::testing::*;
+    use crate::utils::error::Error;
+    
+    const PROGRAM: &str = "
+_start:
+    mov r1, #16
+    mov r2, #258
+    wrt #16, r1, r2
+    rd #8, r3, r1
+    push r3
+    mov r7, #3
+    sysf r3
+    hlt
+";
+    
+    fn trace(source: &str) -> (Vec<Value>, Result<(), Error>) {
+        let (mut osvm, _) = machine(&assemble(source));
+        let trace = SharedBuffer::default();
+        osvm.start_tracing(Box::new(trace.clone()));
+        let result = osvm.execute_program();
+        osvm.stop_tracing().unwrap();
+        
+        let text = String::from_utf8(trace.0.borrow().clone()).unwrap();
+        (text.lines().map(|line| serde_json::from_str(line).unwrap()).collect(), result)
+    }
+    
+    #[test]
+    fn records_every_instruction() {
+        let (records, result) = trace(PROGRAM);
+        result.unwrap();
+        assert_eq!(records.len(), 8);
+        for (step, record) in records.iter().enumerate() {
+            assert_eq!(record["step"], step);
+            assert_eq!(record["pc"], step);
+        }
+        
+        assert_eq!(records[0], json!({ "step": 0, "pc": 0, "op": "mov r1, #16", "reads": [], "writes": [["r1", "u64: 0", "u64: 16"]], "stack": [0, 0] }));
+        assert_eq!(records[2]["reads"], json!([["r1", "u64: 16"], ["r2", "u64: 258"]]));
+        assert_eq!(records[2]["memory"], json!([{ "access": "wrt", "addr": 16, "len": 2, "old": 0, "new": 258 }]));
+        assert_eq!(records[3]["memory"], json!([{ "access": "rd", "addr": 16, "len": 1, "old": 2, "new": 2 }]));
+        assert_eq!(records[3]["writes"], json!([["r3", "u64: 0", "u64: 2"]]));
+        assert_eq!(records[4]["stack"], json!([0, 1]));
+        assert_eq!(records[6]["sysf"], 3);
+        assert_eq!(records[6]["reads"][0], json!(["r7", "u64: 3"]));
+        assert!(records[5].get("sysf").is_none());
+    }
+    
+    #[test]
+    fn records_the_fault() {
+        let (records, result) = trace("
+_start:
+    push #1
+    push #0
+    divs
+    hlt
+");
+        assert!(result.is_err());
+        let last = records.last().unwrap();
+        assert_eq!(last["op"], "divs");
+        assert_eq!(last["error"], result.unwrap_err().to_string());
+    }
+    
+    #[test]
+    fn tracing_does_not_change_the_run() {
+        for (name, program) in examples() {
+            let (plain, plain_output, result) = run(&program);
+            result.unwrap();
+            
+            let (mut traced, output) = machine(&program);
+            traced.start_tracing(Box::new(io::sink()));
+            traced.execute_program().unwrap();
+            traced.stop_tracing().unwrap();
+            
+            assert_eq!(traced.registers(), plain.registers(), "{}", name);
+            assert_eq!(String::from_utf8_lossy(&output.0.borrow()), plain_output, "{}", name);
+        }
+    }
+    
+    struct Broken;
+    
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+        
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    
+    #[test]
+    fn reports_write_errors_when_stopped() {
+        let (mut osvm, _) = machine(&assemble(PROGRAM));
+        osvm.start_tracing(Box::new(Broken));
+        osvm.execute_program().unwrap();
+        assert_eq!(osvm.stop_tracing().unwrap_err().to_string(), "disk full");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,404 @@
+use crate::opcode::OpcodeType;
+use crate::utils::{defines::*, error::ErrorKind};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Abs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Shr,
+    Shl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// `arith`, `bitwise` and `compare` work on raw words of a given type,
+// the `*_tagged` versions take the type from the left operand and
+// convert the right operand to it first.
+
+pub fn arith(op: ArithOp, ty: WordType, a: Word, b: Word) -> Result<Word, ErrorKind> {
+    unsafe {
+        match ty {
+            WordType::U64 | WordType::Ptr => {
+                let (a, b) = (a.as_u64, b.as_u64);
+                let value = match op {
+                    ArithOp::Add => a.wrapping_add(b),
+                    ArithOp::Sub => a.wrapping_sub(b),
+                    ArithOp::Mul => a.wrapping_mul(b),
+                    ArithOp::Div => {
+                        if b == 0 {
+                            return Err(ErrorKind::DivByZero);
+                        }
+                        a / b
+                    }
+                    ArithOp::Mod => {
+                        if b == 0 {
+                            return Err(ErrorKind::DivByZero);
+                        }
+                        a % b
+                    }
+                };
+                Ok(Word { as_u64: value })
+            }
+            WordType::I64 => {
+                let (a, b) = (a.as_i64, b.as_i64);
+                let value = match op {
+                    ArithOp::Add => a.wrapping_add(b),
+                    ArithOp::Sub => a.wrapping_sub(b),
+                    ArithOp::Mul => a.wrapping_mul(b),
+                    ArithOp::Div => {
+                        if b == 0 {
+                            return Err(ErrorKind::DivByZero);
+                        }
+                        a.wrapping_div(b)
+                    }
+                    ArithOp::Mod => {
+                        if b == 0 {
+                            return Err(ErrorKind::DivByZero);
+                        }
+                        a.wrapping_rem(b)
+                    }
+                };
+                Ok(Word { as_i64: value })
+            }
+            WordType::F64 => {
+                let (a, b) = (a.as_f64, b.as_f64);
+                let value = match op {
+                    ArithOp::Add => a + b,
+                    ArithOp::Sub => a - b,
+                    ArithOp::Mul => a * b,
+                    ArithOp::Div => {
+                        if b == 0.0 {
+                            return Err(ErrorKind::DivByZero);
+                        }
+                        a / b
+                    }
+                    ArithOp::Mod => {
+                        if b == 0.0 {
+                            return Err(ErrorKind::DivByZero);
+                        }
+                        a % b
+                    }
+                };
+                Ok(Word { as_f64: value })
+            }
+        }
+    }
+}
+
+pub fn arith_tagged(op: ArithOp, a: TypedWord, b: TypedWord) -> Result<TypedWord, ErrorKind> {
+    let word = arith(op, a.ty, a.word, b.convert(a.ty).word)?;
+    Ok(TypedWord { word, ty: a.ty })
+}
+
+pub fn unary(op: UnaryOp, ty: WordType, a: Word) -> Result<Word, ErrorKind> {
+    unsafe {
+        match ty {
+            WordType::I64 => match op {
+                UnaryOp::Neg => Ok(Word { as_i64: a.as_i64.wrapping_neg() }),
+                UnaryOp::Abs => Ok(Word { as_i64: a.as_i64.wrapping_abs() }),
+            },
+            WordType::F64 => match op {
+                UnaryOp::Neg => Ok(Word { as_f64: -a.as_f64 }),
+                UnaryOp::Abs => Ok(Word { as_f64: a.as_f64.abs() }),
+            },
+            
+            _ => Err(ErrorKind::InvalidOperand),
+        }
+    }
+}
+
+// The value `inc`/`dec` step by
+pub fn one(ty: WordType) -> Word {
+    match ty {
+        WordType::F64 => Word { as_f64: 1.0 },
+        WordType::I64 => Word { as_i64: 1 },
+        _ => Word { as_u64: 1 },
+    }
+}
+
+pub fn bitwise(op: BitOp, ty: WordType, a: Word, b: Word) -> Result<Word, ErrorKind> {
+    unsafe {
+        match ty {
+            WordType::U64 | WordType::Ptr => {
+                let (a, b) = (a.as_u64, b.as_u64);
+                let value = match op {
+                    BitOp::And => a & b,
+                    BitOp::Or => a | b,
+                    BitOp::Xor => a ^ b,
+                    BitOp::Shr => a.wrapping_shr(b as u32),
+                    BitOp::Shl => a.wrapping_shl(b as u32),
+                };
+                Ok(Word { as_u64: value })
+            }
+            WordType::I64 => {
+                let (a, b) = (a.as_i64, b.as_i64);
+                let value = match op {
+                    BitOp::And => a & b,
+                    BitOp::Or => a | b,
+                    BitOp::Xor => a ^ b,
+                    BitOp::Shr => a.wrapping_shr(b as u32),
+                    BitOp::Shl => a.wrapping_shl(b as u32),
+                };
+                Ok(Word { as_i64: value })
+            }
+            WordType::F64 => Err(ErrorKind::InvalidOperand),
+        }
+    }
+}
+
+pub fn bitwise_tagged(op: BitOp, a: TypedWord, b: TypedWord) -> Result<TypedWord, ErrorKind> {
+    let word = bitwise(op, a.ty, a.word, b.convert(a.ty).word)?;
+    Ok(TypedWord { word, ty: a.ty })
+}
+
+pub fn not(value: TypedWord) -> Result<TypedWord, ErrorKind> {
+    unsafe {
+        match value.ty {
+            WordType::F64 => Err(ErrorKind::InvalidOperand),
+            _ => Ok(TypedWord { word: Word { as_u64: !value.word.as_u64 }, ty: value.ty }),
+        }
+    }
+}
+
+pub fn compare(op: CmpOp, ty: WordType, a: Word, b: Word) -> TypedWord {
+    unsafe {
+        let result = match ty {
+            WordType::U64 | WordType::Ptr => ordered(op, a.as_u64, b.as_u64),
+            WordType::I64 => ordered(op, a.as_i64, b.as_i64),
+            WordType::F64 => ordered(op, a.as_f64, b.as_f64),
+        };
+        
+        TypedWord::u64(result as u64)
+    }
+}
+
+fn ordered<T: PartialOrd>(op: CmpOp, a: T, b: T) -> bool {
+    match op {
+        CmpOp::Eq => a == b,
+        CmpOp::Ne => a != b,
+        CmpOp::Lt => a < b,
+        CmpOp::Le => a <= b,
+        CmpOp::Gt => a > b,
+        CmpOp::Ge => a >= b,
+    }
+}
+
+pub fn compare_tagged(op: CmpOp, a: TypedWord, b: TypedWord) -> TypedWord {
+    compare(op, a.ty, a.word, b.convert(a.ty).word)
+}
+
+// Operation and operand type of the typed (register or stack) arithmetic opcodes
+pub fn typed_arith(op_type: OpcodeType) -> Option<(ArithOp, WordType)> {
+    let typed = match op_type {
+        OpcodeType::Addi | OpcodeType::Addis => (ArithOp::Add, WordType::I64),
+        OpcodeType::Addu | OpcodeType::Addus => (ArithOp::Add, WordType::U64),
+        OpcodeType::Addf | OpcodeType::Addfs => (ArithOp::Add, WordType::F64),
+        OpcodeType::Subi | OpcodeType::Subis => (ArithOp::Sub, WordType::I64),
+        OpcodeType::Subu | OpcodeType::Subus => (ArithOp::Sub, WordType::U64),
+        OpcodeType::Subf | OpcodeType::Subfs => (ArithOp::Sub, WordType::F64),
+        OpcodeType::Muli | OpcodeType::Mulis => (ArithOp::Mul, WordType::I64),
+        OpcodeType::Mulu | OpcodeType::Mulus => (ArithOp::Mul, WordType::U64),
+        OpcodeType::Mulf | OpcodeType::Mulfs => (ArithOp::Mul, WordType::F64),
+        OpcodeType::Divi | OpcodeType::Divis => (ArithOp::Div, WordType::I64),
+        OpcodeType::Divu | OpcodeType::Divus => (ArithOp::Div, WordType::U64),
+        OpcodeType::Divf | OpcodeType::Divfs => (ArithOp::Div, WordType::F64),
+        OpcodeType::Modi | OpcodeType::Modis => (ArithOp::Mod, WordType::I64),
+        OpcodeType::Modu | OpcodeType::Modus => (ArithOp::Mod, WordType::U64),
+        OpcodeType::Modf | OpcodeType::Modfs => (ArithOp::Mod, WordType::F64),
+        
+        _ => return None,
+    };
+    
+    Some(typed)
+}
+
+pub fn typed_unary(op_type: OpcodeType) -> Option<(UnaryOp, WordType)> {
+    match op_type {
+        OpcodeType::Negi => Some((UnaryOp::Neg, WordType::I64)),
+        OpcodeType::Negf => Some((UnaryOp::Neg, WordType::F64)),
+        OpcodeType::Absi => Some((UnaryOp::Abs, WordType::I64)),
+        OpcodeType::Absf => Some((UnaryOp::Abs, WordType::F64)),
+        
+        _ => None,
+    }
+}
+
+// Conversion opcodes read the raw bits as their source type no matter
+// how the value is tagged, float to int conversions saturate.
+pub fn conversion(op_type: OpcodeType, a: Word) -> Option<TypedWord> {
+    unsafe {
+        let value = match op_type {
+            OpcodeType::Itof => TypedWord::f64(a.as_i64 as f64),
+            OpcodeType::Ftoi => TypedWord::i64(a.as_f64 as i64),
+            OpcodeType::Utof => TypedWord::f64(a.as_u64 as f64),
+            OpcodeType::Ftou => TypedWord::u64(a.as_f64 as u64),
+            OpcodeType::Sext8 => TypedWord::i64(a.as_u64 as i8 as i64),
+            OpcodeType::Sext16 => TypedWord::i64(a.as_u64 as i16 as i64),
+            OpcodeType::Sext32 => TypedWord::i64(a.as_u64 as i32 as i64),
+            OpcodeType::Zext8 => TypedWord::u64(a.as_u64 as u8 as u64),
+            OpcodeType::Zext16 => TypedWord::u64(a.as_u64 as u16 as u64),
+            OpcodeType::Zext32 => TypedWord::u64(a.as_u64 as u32 as u64),
+            
+            _ => return None,
+        };
+        
+        Some(value)
+    }
+}
+
+// Operation of the untyped (register or stack) arithmetic opcodes
+pub fn untyped_arith(op_type: OpcodeType) -> Option<ArithOp> {
+    match op_type {
+        OpcodeType::Add | OpcodeType::Adds => Some(ArithOp::Add),
+        OpcodeType::Sub | OpcodeType::Subs => Some(ArithOp::Sub),
+        OpcodeType::Mul | OpcodeType::Muls => Some(ArithOp::Mul),
+        OpcodeType::Div | OpcodeType::Divs => Some(ArithOp::Div),
+        
+        _ => None,
+    }
+}
+
+// Comparison of the untyped (register or stack) comparison opcodes
+pub fn compare_op(op_type: OpcodeType) -> Option<CmpOp> {
+    match op_type {
+        OpcodeType::Equal | OpcodeType::Equals => Some(CmpOp::Eq),
+        OpcodeType::Ne | OpcodeType::Nes => Some(CmpOp::Ne),
+        OpcodeType::Lt | OpcodeType::Lts => Some(CmpOp::Lt),
+        OpcodeType::Le | OpcodeType::Les => Some(CmpOp::Le),
+        OpcodeType::Gt | OpcodeType::Gts => Some(CmpOp::Gt),
+        OpcodeType::Ge | OpcodeType::Ges => Some(CmpOp::Ge),
+        
+        _ => None,
+    }
+}
+
+pub fn typed_compare(op_type: OpcodeType) -> Option<(CmpOp, WordType)> {
+    let typed = match op_type {
+        OpcodeType::Lti | OpcodeType::Ltis => (CmpOp::Lt, WordType::I64),
+        OpcodeType::Ltu | OpcodeType::Ltus => (CmpOp::Lt, WordType::U64),
+        OpcodeType::Ltf | OpcodeType::Ltfs => (CmpOp::Lt, WordType::F64),
+        OpcodeType::Lei | OpcodeType::Leis => (CmpOp::Le, WordType::I64),
+        OpcodeType::Leu | OpcodeType::Leus => (CmpOp::Le, WordType::U64),
+        OpcodeType::Lef | OpcodeType::Lefs => (CmpOp::Le, WordType::F64),
+        OpcodeType::Gti | OpcodeType::Gtis => (CmpOp::Gt, WordType::I64),
+        OpcodeType::Gtu | OpcodeType::Gtus => (CmpOp::Gt, WordType::U64),
+        OpcodeType::Gtf | OpcodeType::Gtfs => (CmpOp::Gt, WordType::F64),
+        OpcodeType::Gei | OpcodeType::Geis => (CmpOp::Ge, WordType::I64),
+        OpcodeType::Geu | OpcodeType::Geus => (CmpOp::Ge, WordType::U64),
+        OpcodeType::Gef | OpcodeType::Gefs => (CmpOp::Ge, WordType::F64),
+        
+        _ => return None,
+    };
+    
+    Some(typed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn arithmetic_follows_the_left_operand_type() {
+        assert_eq!(arith_tagged(ArithOp::Add, TypedWord::i64(-5), TypedWord::i64(3)), Ok(TypedWord::i64(-2)));
+        assert_eq!(arith_tagged(ArithOp::Div, TypedWord::i64(-7), TypedWord::i64(2)), Ok(TypedWord::i64(-3)));
+        assert_eq!(arith_tagged(ArithOp::Sub, TypedWord::u64(1), TypedWord::u64(2)), Ok(TypedWord::u64(u64::MAX)));
+        assert_eq!(arith_tagged(ArithOp::Mul, TypedWord::f64(2.5), TypedWord::u64(2)), Ok(TypedWord::f64(5.0)));
+        assert_eq!(arith_tagged(ArithOp::Add, TypedWord::u64(2), TypedWord::f64(2.5)), Ok(TypedWord::u64(4)));
+    }
+    
+    #[test]
+    fn division_by_zero_faults_for_every_type() {
+        let operands = [
+            (TypedWord::u64(1), TypedWord::u64(0)),
+            (TypedWord::i64(1), TypedWord::i64(0)),
+            (TypedWord::f64(1.0), TypedWord::f64(0.0)),
+        ];
+        
+        for (a, b) in operands {
+            assert_eq!(arith_tagged(ArithOp::Div, a, b), Err(ErrorKind::DivByZero));
+            assert_eq!(arith_tagged(ArithOp::Mod, a, b), Err(ErrorKind::DivByZero));
+        }
+    }
+    
+    #[test]
+    fn equality_follows_the_left_operand_type() {
+        assert_eq!(compare_tagged(CmpOp::Eq, TypedWord::f64(2.0), TypedWord::u64(2)), TypedWord::u64(1));
+        assert_eq!(compare_tagged(CmpOp::Eq, TypedWord::u64(2), TypedWord::f64(2.5)), TypedWord::u64(1));
+        assert_eq!(compare_tagged(CmpOp::Eq, TypedWord::i64(-1), TypedWord::i64(1)), TypedWord::u64(0));
+    }
+    
+    #[test]
+    fn conversions_read_the_bits_as_their_source_type() {
+        let convert = |op_type, word: TypedWord| conversion(op_type, word.word).unwrap();
+        assert_eq!(convert(OpcodeType::Itof, TypedWord::i64(-3)), TypedWord::f64(-3.0));
+        assert_eq!(convert(OpcodeType::Utof, TypedWord::u64(7)), TypedWord::f64(7.0));
+        assert_eq!(convert(OpcodeType::Ftoi, TypedWord::f64(-2.75)), TypedWord::i64(-2));
+        assert_eq!(convert(OpcodeType::Ftou, TypedWord::f64(2.75)), TypedWord::u64(2));
+        
+        // The tag doesn't matter, the opcode says how to read the bits
+        assert_eq!(convert(OpcodeType::Itof, TypedWord::u64(-3i64 as u64)), TypedWord::f64(-3.0));
+        assert_eq!(conversion(OpcodeType::Add, Word { as_u64: 0 }), None);
+    }
+    
+    #[test]
+    fn float_to_int_conversions_saturate() {
+        let convert = |op_type, value: f64| conversion(op_type, Word { as_f64: value }).unwrap();
+        assert_eq!(convert(OpcodeType::Ftoi, 1e30), TypedWord::i64(i64::MAX));
+        assert_eq!(convert(OpcodeType::Ftoi, -1e30), TypedWord::i64(i64::MIN));
+        assert_eq!(convert(OpcodeType::Ftou, -1.0), TypedWord::u64(0));
+        assert_eq!(convert(OpcodeType::Ftou, f64::NAN), TypedWord::u64(0));
+    }
+    
+    #[test]
+    fn extensions_use_the_low_bits() {
+        let extend = |op_type, value: u64| conversion(op_type, Word { as_u64: value }).unwrap();
+        assert_eq!(extend(OpcodeType::Sext8, 0x1ff), TypedWord::i64(-1));
+        assert_eq!(extend(OpcodeType::Sext8, 0x7f), TypedWord::i64(127));
+        assert_eq!(extend(OpcodeType::Sext16, 0x8000), TypedWord::i64(-32768));
+        assert_eq!(extend(OpcodeType::Sext32, 0xffff_fffe), TypedWord::i64(-2));
+        assert_eq!(extend(OpcodeType::Zext8, 0x1ff), TypedWord::u64(0xff));
+        assert_eq!(extend(OpcodeType::Zext16, u64::MAX), TypedWord::u64(0xffff));
+        assert_eq!(extend(OpcodeType::Zext32, u64::MAX), TypedWord::u64(0xffff_ffff));
+    }
+    
+    #[test]
+    fn ordered_comparisons_follow_the_left_operand_type() {
+        let cases = [
+            (CmpOp::Lt, TypedWord::i64(-1), TypedWord::i64(1), 1),
+            (CmpOp::Lt, TypedWord::u64(-1i64 as u64), TypedWord::u64(1), 0),
+            (CmpOp::Le, TypedWord::f64(1.5), TypedWord::f64(1.5), 1),
+            (CmpOp::Gt, TypedWord::f64(-0.5), TypedWord::i64(-1), 1),
+            (CmpOp::Ge, TypedWord::u64(2), TypedWord::u64(3), 0),
+            (CmpOp::Ne, TypedWord::i64(4), TypedWord::u64(4), 0),
+            (CmpOp::Ne, TypedWord::f64(f64::NAN), TypedWord::f64(f64::NAN), 1),
+        ];
+        
+        for (op, a, b, expected) in cases {
+            assert_eq!(compare_tagged(op, a, b), TypedWord::u64(expected), "{:?} {:?} {:?}", op, a, b);
+        }
+    }
+}
\ No newline at end of file
@@ -18,7 +18,125 @@ impl fmt::Debug for Word {
     }
 }
 
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WordType {
+    U64 = 0,
+    I64 = 1,
+    F64 = 2,
+    Ptr = 3,
+}
+
+impl WordType {
+    pub fn from_u8(value: u8) -> Option<WordType> {
+        match value {
+            0 => Some(WordType::U64),
+            1 => Some(WordType::I64),
+            2 => Some(WordType::F64),
+            3 => Some(WordType::Ptr),
+            
+            _ => None,
+        }
+    }
+}
+
+// A Word together with the type it was created as, the type is
+// what arithmetic, comparisons and the print sysfs dispatch on.
+// Compiled code relies on the C layout.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct TypedWord {
+    pub word: Word,
+    pub ty: WordType,
+}
+
+impl TypedWord {
+    pub fn u64(value: u64) -> TypedWord {
+        TypedWord { word: Word { as_u64: value }, ty: WordType::U64 }
+    }
+    
+    pub fn i64(value: i64) -> TypedWord {
+        TypedWord { word: Word { as_i64: value }, ty: WordType::I64 }
+    }
+    
+    pub fn f64(value: f64) -> TypedWord {
+        TypedWord { word: Word { as_f64: value }, ty: WordType::F64 }
+    }
+    
+    pub fn ptr(value: *const c_void) -> TypedWord {
+        TypedWord { word: Word { as_ptr: value }, ty: WordType::Ptr }
+    }
+    
+    // Literals are tagged with the first type they parse as: u64, i64 then f64.
+    // An `i` suffix makes any integer an i64, e.g. `#6i`
+    pub fn parse_literal(token: &str) -> Option<TypedWord> {
+        let literal = token.replace(CONST, "");
+        if let Some(value) = literal.strip_suffix('i') {
+            value.parse::<i64>().ok().map(TypedWord::i64)
+        } else if let Ok(value) = literal.parse::<u64>() {
+            Some(TypedWord::u64(value))
+        } else if let Ok(value) = literal.parse::<i64>() {
+            Some(TypedWord::i64(value))
+        } else if let Ok(value) = literal.parse::<f64>() {
+            Some(TypedWord::f64(value))
+        } else {
+            None
+        }
+    }
+    
+    // Numeric conversion to another type (not a bit reinterpretation)
+    pub fn convert(self: &Self, ty: WordType) -> TypedWord {
+        unsafe {
+            let word = match (self.ty, ty) {
+                (WordType::F64, WordType::F64) => self.word,
+                (WordType::F64, WordType::I64) => Word { as_i64: self.word.as_f64 as i64 },
+                (WordType::F64, _) => Word { as_u64: self.word.as_f64 as u64 },
+                (WordType::I64, WordType::F64) => Word { as_f64: self.word.as_i64 as f64 },
+                (_, WordType::F64) => Word { as_f64: self.word.as_u64 as f64 },
+                
+                // u64, i64 and ptr share the same two's complement bits
+                _ => self.word,
+            };
+            
+            TypedWord { word, ty }
+        }
+    }
+}
+
+impl fmt::Debug for TypedWord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        unsafe {
+            match self.ty {
+                WordType::U64 => write!(f, "u64: {}", self.word.as_u64),
+                WordType::I64 => write!(f, "i64: {}", self.word.as_i64),
+                WordType::F64 => write!(f, "f64: {:.10}", self.word.as_f64),
+                WordType::Ptr => write!(f, "ptr: {:p}", self.word.as_ptr),
+            }
+        }
+    }
+}
+
+// Same type and the same bits
+impl PartialEq for TypedWord {
+    fn eq(&self, other: &Self) -> bool {
+        self.ty == other.ty && unsafe { self.word.as_u64 == other.word.as_u64 }
+    }
+}
+
 pub const MEMORY_CAPACITY: usize = 640 * 1000;
+pub const CALL_STACK_CAPACITY: usize = 1024;
+
+// Bytecode file (.vbin)
+pub const VBIN_MAGIC: [u8; 4] = *b"OSVM";
+pub const VBIN_VERSION: u16 = 1;
+pub const VBIN_HEADER_SIZE: usize = 24;
+pub const VBIN_INSTRUCTION_SIZE: usize = 8;
+pub const VBIN_CONSTANT_SIZE: usize = 9;
+pub const VBIN_MAX_REGS: usize = 3;
+
+// Snapshot file
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"OSNP";
+pub const SNAPSHOT_VERSION: u16 = 1;
 
 // Register Names
 pub const R0: &str = "r0";
@@ -39,11 +157,21 @@ pub const R14: &str = "r14";
 pub const R15: &str = "r15";
 pub const R16: &str = "r16";
 
+pub const REGISTERS: [&str; 17] = [
+    R0, R1, R2, R3, R4, R5, R6, R7, R8,
+    R9, R10, R11, R12, R13, R14, R15, R16,
+];
+
+pub fn register_index(name: &str) -> Option<usize> {
+    REGISTERS.iter().position(|reg| *reg == name)
+}
+
 // Special Characters
 pub const CONST: &str = "#";
 pub const GSI: &str = "$";
 
 // Opcode Names
+pub const NOP: &str = "nop";
 
 // Register opcodes
 pub const MOV: &str = "mov";
@@ -56,6 +184,11 @@ pub const DIV: &str = "div";
 pub const DEC: &str = "dec";
 pub const INC: &str = "inc";
 pub const EQUAL: &str = "eq";
+pub const NE: &str = "ne";
+pub const LT: &str = "lt";
+pub const LE: &str = "le";
+pub const GT: &str = "gt";
+pub const GE: &str = "ge";
 pub const JT: &str = "jt";
 pub const JZ: &str = "jz";
 pub const JNZ: &str = "jnz";
@@ -68,6 +201,11 @@ pub const SUBS: &str = "subs";
 pub const MULS: &str = "muls";
 pub const DIVS: &str = "divs";
 pub const EQUALS: &str = "eqs";
+pub const NES: &str = "nes";
+pub const LTS: &str = "lts";
+pub const LES: &str = "les";
+pub const GTS: &str = "gts";
+pub const GES: &str = "ges";
 pub const DUPL: &str = "dupl";
 pub const JTS: &str = "jts";
 pub const JZS: &str = "jzs";
@@ -75,7 +213,6 @@ pub const JNZS: &str = "jnzs";
 
 pub const SWC: &str = "swc";
 
-
 // Universal opcode
 pub const JMP: &str = "jmp";
 pub const CALL: &str = "call";
@@ -91,5 +228,120 @@ pub const POP: &str = "pop";
 pub const RET: &str = "ret";
 pub const HLT: &str = "hlt";
 
+// Typed register opcodes
+pub const ADDI: &str = "addi";
+pub const ADDU: &str = "addu";
+pub const ADDF: &str = "addf";
+pub const SUBI: &str = "subi";
+pub const SUBU: &str = "subu";
+pub const SUBF: &str = "subf";
+pub const MULI: &str = "muli";
+pub const MULU: &str = "mulu";
+pub const MULF: &str = "mulf";
+pub const DIVI: &str = "divi";
+pub const DIVU: &str = "divu";
+pub const DIVF: &str = "divf";
+pub const MODI: &str = "modi";
+pub const MODU: &str = "modu";
+pub const MODF: &str = "modf";
+
+// Typed stack opcodes
+pub const ADDIS: &str = "addis";
+pub const ADDUS: &str = "addus";
+pub const ADDFS: &str = "addfs";
+pub const SUBIS: &str = "subis";
+pub const SUBUS: &str = "subus";
+pub const SUBFS: &str = "subfs";
+pub const MULIS: &str = "mulis";
+pub const MULUS: &str = "mulus";
+pub const MULFS: &str = "mulfs";
+pub const DIVIS: &str = "divis";
+pub const DIVUS: &str = "divus";
+pub const DIVFS: &str = "divfs";
+pub const MODIS: &str = "modis";
+pub const MODUS: &str = "modus";
+pub const MODFS: &str = "modfs";
+
+// Typed universal opcodes
+pub const NEGI: &str = "negi";
+pub const NEGF: &str = "negf";
+pub const ABSI: &str = "absi";
+pub const ABSF: &str = "absf";
+
+// Conversion opcodes
+pub const ITOF: &str = "itof";
+pub const FTOI: &str = "ftoi";
+pub const UTOF: &str = "utof";
+pub const FTOU: &str = "ftou";
+pub const SEXT8: &str = "sext8";
+pub const SEXT16: &str = "sext16";
+pub const SEXT32: &str = "sext32";
+pub const ZEXT8: &str = "zext8";
+pub const ZEXT16: &str = "zext16";
+pub const ZEXT32: &str = "zext32";
+
+// Typed register comparisons
+pub const LTI: &str = "lti";
+pub const LTU: &str = "ltu";
+pub const LTF: &str = "ltf";
+pub const LEI: &str = "lei";
+pub const LEU: &str = "leu";
+pub const LEF: &str = "lef";
+pub const GTI: &str = "gti";
+pub const GTU: &str = "gtu";
+pub const GTF: &str = "gtf";
+pub const GEI: &str = "gei";
+pub const GEU: &str = "geu";
+pub const GEF: &str = "gef";
+
+// Typed stack comparisons
+pub const LTIS: &str = "ltis";
+pub const LTUS: &str = "ltus";
+pub const LTFS: &str = "ltfs";
+pub const LEIS: &str = "leis";
+pub const LEUS: &str = "leus";
+pub const LEFS: &str = "lefs";
+pub const GTIS: &str = "gtis";
+pub const GTUS: &str = "gtus";
+pub const GTFS: &str = "gtfs";
+pub const GEIS: &str = "geis";
+pub const GEUS: &str = "geus";
+pub const GEFS: &str = "gefs";
+
+
 // Deprecated
-pub const PHSR: &str = "phsr";
\ No newline at end of file
+pub const PHSR: &str = "phsr";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn convert_keeps_the_value() {
+        assert_eq!(TypedWord::i64(-3).convert(WordType::F64), TypedWord::f64(-3.0));
+        assert_eq!(TypedWord::u64(7).convert(WordType::F64), TypedWord::f64(7.0));
+        assert_eq!(TypedWord::f64(2.9).convert(WordType::U64), TypedWord::u64(2));
+        assert_eq!(TypedWord::f64(-2.9).convert(WordType::I64), TypedWord::i64(-2));
+        assert_eq!(TypedWord::i64(-1).convert(WordType::U64), TypedWord::u64(u64::MAX));
+    }
+    
+    #[test]
+    fn literals_are_tagged_with_the_first_type_they_parse_as() {
+        assert_eq!(TypedWord::parse_literal("#5"), Some(TypedWord::u64(5)));
+        assert_eq!(TypedWord::parse_literal("#18446744073709551615"), Some(TypedWord::u64(u64::MAX)));
+        assert_eq!(TypedWord::parse_literal("#-5"), Some(TypedWord::i64(-5)));
+        assert_eq!(TypedWord::parse_literal("#2.5"), Some(TypedWord::f64(2.5)));
+        assert_eq!(TypedWord::parse_literal("#five"), None);
+    }
+    
+    #[test]
+    fn an_i_suffix_makes_an_i64() {
+        assert_eq!(TypedWord::parse_literal("#6i"), Some(TypedWord::i64(6)));
+        assert_eq!(TypedWord::parse_literal("#-6i"), Some(TypedWord::i64(-6)));
+        assert_eq!(TypedWord::parse_literal("#0i"), Some(TypedWord::i64(0)));
+        assert_eq!(TypedWord::parse_literal("#18446744073709551615i"), None);
+        assert_eq!(TypedWord::parse_literal("#2.5i"), None);
+        assert_eq!(TypedWord::parse_literal("#i"), None);
+        assert_eq!(TypedWord::parse_literal("#inf"), Some(TypedWord::f64(f64::INFINITY)));
+    }
+}
\ No newline at end of file
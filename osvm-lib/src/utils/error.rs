@@ -1,11 +1,15 @@
-#[derive(PartialEq)]
-pub enum Error {
-    None = 0,
-    
+use std::fmt;
+
+use crate::opcode::Opcode;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
     RegisterOverflow,
     RegisterUnderflow,
     StackOverflow,
     StackUnderflow,
+    CallStackOverflow,
+    CallStackUnderflow,
     
     InvalidOpcodeAccess,
     InvalidOperand,
@@ -16,27 +20,152 @@ pub enum Error {
     ErrIllegalMemoryAccess,
     
     DivByZero,
+    
+    FileIo,
+    InvalidFileMagic,
+    UnsupportedFileVersion,
+    CorruptedFile,
+    ConnectionIo,
+    SnapshotMismatch,
+    JitUnavailable,
+    JitMismatch,
+    
+    InvalidInstruction,
+    InvalidOperandCount,
+    UndefinedLabel,
+    DuplicateLabel,
+    MissingEntryPoint,
+    IncludeNotFound,
+    InvalidDirective,
 }
 
-impl Error {
+impl ErrorKind {
     pub fn as_string(self: &Self) -> String {
         match self {
-            Error::None => return "None".to_string(),
+            ErrorKind::RegisterOverflow => return "RegisterOverflow".to_string(),
+            ErrorKind::RegisterUnderflow => return "RegisterUnderflow".to_string(),
+            ErrorKind::StackOverflow => return "StackOverflow".to_string(),
+            ErrorKind::StackUnderflow => return "StackUnderflow".to_string(),
+            ErrorKind::CallStackOverflow => return "CallStackOverflow".to_string(),
+            ErrorKind::CallStackUnderflow => return "CallStackUnderflow".to_string(),
             
-            Error::RegisterOverflow => return "RegisterOverflow".to_string(),
-            Error::RegisterUnderflow => return "RegisterUnderflow".to_string(),
-            Error::StackOverflow => return "StackOverflow".to_string(),
-            Error::StackUnderflow => return "StackUnderflow".to_string(),
+            ErrorKind::InvalidOpcodeAccess => return "InvalidOpcodeAccess".to_string(),
+            ErrorKind::InvalidOperand => return "InvalidOperand".to_string(),
+            ErrorKind::InvalidRegister => return "InvalidRegister".to_string(),
+            ErrorKind::InvalidSection => return "InvalidSection".to_string(),
+            ErrorKind::InvalidSysFunction => return "InvalidSysFunction".to_string(),
             
-            Error::InvalidOpcodeAccess => return "InvalidOpcodeAccess".to_string(),
-            Error::InvalidOperand => return "InvalidOperand".to_string(),
-            Error::InvalidRegister => return "InvalidRegister".to_string(),
-            Error::InvalidSection => return "InvalidSection".to_string(),
-            Error::InvalidSysFunction => return "InvalidSysFunction".to_string(),
+            ErrorKind::ErrIllegalMemoryAccess => return "ErrIllegalMemoryAccess".to_string(),
             
-            Error::ErrIllegalMemoryAccess => return "ErrIllegalMemoryAccess".to_string(),
+            ErrorKind::DivByZero => return "DivByZero".to_string(),
             
-            Error::DivByZero => return "DivByZero".to_string(),
+            ErrorKind::FileIo => return "FileIo".to_string(),
+            ErrorKind::InvalidFileMagic => return "InvalidFileMagic".to_string(),
+            ErrorKind::UnsupportedFileVersion => return "UnsupportedFileVersion".to_string(),
+            ErrorKind::CorruptedFile => return "CorruptedFile".to_string(),
+            ErrorKind::ConnectionIo => return "ConnectionIo".to_string(),
+            ErrorKind::SnapshotMismatch => return "SnapshotMismatch".to_string(),
+            ErrorKind::JitUnavailable => return "JitUnavailable".to_string(),
+            ErrorKind::JitMismatch => return "JitMismatch".to_string(),
+            
+            ErrorKind::InvalidInstruction => return "InvalidInstruction".to_string(),
+            ErrorKind::InvalidOperandCount => return "InvalidOperandCount".to_string(),
+            ErrorKind::UndefinedLabel => return "UndefinedLabel".to_string(),
+            ErrorKind::DuplicateLabel => return "DuplicateLabel".to_string(),
+            ErrorKind::MissingEntryPoint => return "MissingEntryPoint".to_string(),
+            ErrorKind::IncludeNotFound => return "IncludeNotFound".to_string(),
+            ErrorKind::InvalidDirective => return "InvalidDirective".to_string(),
+        }
+    }
+}
+
+// A location in the user's source, lines and columns start at 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+    
+    // The full source line, used to render the snippet
+    pub text: String,
+}
+
+// The error type of every fallible osvm-lib API. Runtime errors carry the
+// pc and faulting opcode, assembler errors carry the source span.
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: Option<String>,
+    
+    pub span: Option<Box<Span>>,
+    pub pc: Option<usize>,
+    pub opcode: Option<Opcode>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Error {
+        Error {
+            kind,
+            message: None,
+            span: None,
+            pc: None,
+            opcode: None,
+        }
+    }
+    
+    pub fn with_message(kind: ErrorKind, message: impl Into<String>) -> Error {
+        Error {
+            message: Some(message.into()),
+            ..Error::new(kind)
         }
     }
-}
\ No newline at end of file
+    
+    pub fn at_span(mut self: Self, span: Span) -> Error {
+        self.span.get_or_insert(Box::new(span));
+        self
+    }
+    
+    pub fn at_pc(mut self: Self, pc: usize, opcode: Option<Opcode>) -> Error {
+        if self.pc.is_none() {
+            self.pc = Some(pc);
+            self.opcode = opcode;
+        }
+        self
+    }
+    
+    pub fn as_string(self: &Self) -> String {
+        self.to_string()
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error::new(kind)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind.as_string())?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        
+        if let Some(span) = &self.span {
+            write!(f, " at {}:{}:{}", span.file, span.line, span.column)?;
+        }
+        
+        if let Some(pc) = self.pc {
+            write!(f, " at pc: {}", pc)?;
+        }
+        
+        if let Some(opcode) = &self.opcode {
+            write!(f, " ({:?} {:?})", opcode.op_type, opcode.op_regs)?;
+        }
+        
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
\ No newline at end of file
@@ -1,80 +1,651 @@
-use crate::{osvm::OSVM, opcode::Opcode};
+use crate::{bytecode::{Bytecode, Instruction}, oasm::Label, opcode::Opcode, osvm::Frame, program::{LineInfo, Program}, snapshot::Snapshot};
+use crate::utils::{defines::*, error::{Error, ErrorKind}};
 use log::*;
 
-use std::{ffi::{c_void, CString}, process::exit};
-use libc::{
-    fclose, ferror, fopen, fread,
-    fseek, ftell, fwrite,
-    SEEK_END, SEEK_SET
-};
+use std::fs;
 
-use std::mem;
+// .vbin layout (all integers little endian):
+//
+// header:
+//     magic     [u8; 4]   "OSVM"
+//     version   u16
+//     reserved  u16
+//     entry     u64       address of `_start`
+//     count     u64       number of instructions
+//
+// code, see bytecode.rs:
+//     instruction u64     VBIN_INSTRUCTION_SIZE bytes each
+//     count     u64       number of constants
+//     constant  type u8 (WordType), bits u64
+//
+// symbols (directly after the code):
+//     count     u64       number of symbols
+//     symbol    addr u64, name_len u16, name [u8; name_len] (utf-8)
+//
+// lines (directly after the symbols):
+//     count     u64       number of source files
+//     file      name_len u16, name [u8; name_len] (utf-8)
+//     count     u64       number of line entries, 0 or one per opcode
+//     line      file u32, line u32
+
+// Snapshot layout (all integers little endian):
+//
+// header:
+//     magic       [u8; 4]   "OSNP"
+//     version     u16
+//     reserved    u16
+//     fingerprint u64       program_fingerprint of the program
+//     path        len u16, path [u8; len] (utf-8) of the program's .vbin
+//
+// state:
+//     pc u64, tsr u64, halt u8
+//     registers   17 values
+//     call stack  count u64, frame call_addr u64, target_addr u64, return_addr u64
+//     stack       count u64, values
+//     memory      len u64, [u8; len], trailing zero bytes are left out
+//
+// value: type u8 (WordType), bits u64. Pointers from `alloc` are saved as is
+// and only stay valid within the process that took the snapshot
 
 pub struct OSVMFile {}
 
 impl OSVMFile {
-    pub fn load_program_from_file(self: &mut Self, osvm: &mut OSVM, file_path: &str) {
-        unsafe {
-            let file_name = CString::new(file_path).unwrap();
-            let file_mode = CString::new("rb").unwrap();
-            let file = fopen(file_name.as_ptr(), file_mode.as_ptr());
-            if file.is_null() {
-                error!("[Error]: Could not open file `{}`", file_path);
-                exit(1);
-            }
-            
-            if fseek(file, 0, SEEK_END) < 0 {
-                error!("[Error]: Could not read file `{}`", file_path);
-                exit(1);
+    pub fn encode_program(self: &Self, program: &Program) -> Result<Vec<u8>, Error> {
+        let bytecode = Bytecode::encode(&program.code)?;
+        let mut bytes = Vec::with_capacity(VBIN_HEADER_SIZE + bytecode.len() * VBIN_INSTRUCTION_SIZE + 8 + bytecode.constants.len() * VBIN_CONSTANT_SIZE);
+        bytes.extend_from_slice(&VBIN_MAGIC);
+        bytes.extend_from_slice(&VBIN_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(&(program.entry as u64).to_le_bytes());
+        bytes.extend_from_slice(&(bytecode.len() as u64).to_le_bytes());
+        
+        for instruction in &bytecode.code {
+            bytes.extend_from_slice(&instruction.0.to_le_bytes());
+        }
+        
+        bytes.extend_from_slice(&(bytecode.constants.len() as u64).to_le_bytes());
+        for value in &bytecode.constants {
+            bytes.push(value.ty as u8);
+            bytes.extend_from_slice(&unsafe { value.word.as_u64 }.to_le_bytes());
+        }
+        
+        bytes.extend_from_slice(&(program.symbols.len() as u64).to_le_bytes());
+        for symbol in &program.symbols {
+            if symbol.name.len() > u16::MAX as usize {
+                return Err(Error::with_message(ErrorKind::InvalidOperand, format!("symbol name `{}` is too long", symbol.name)));
             }
             
-            let m = ftell(file);
-            if m < 0 {
-                error!("[Error]: Could not read file `{}`", file_path);
-                exit(1);
+            bytes.extend_from_slice(&(symbol.addr as u64).to_le_bytes());
+            bytes.extend_from_slice(&(symbol.name.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(symbol.name.as_bytes());
+        }
+        
+        bytes.extend_from_slice(&(program.files.len() as u64).to_le_bytes());
+        for file in &program.files {
+            if file.len() > u16::MAX as usize {
+                return Err(Error::with_message(ErrorKind::InvalidOperand, format!("file name `{}` is too long", file)));
             }
             
-            assert!(m as usize % mem::size_of::<Opcode>() == 0);
+            bytes.extend_from_slice(&(file.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(file.as_bytes());
+        }
+        
+        bytes.extend_from_slice(&(program.lines.len() as u64).to_le_bytes());
+        for info in &program.lines {
+            bytes.extend_from_slice(&(info.file as u32).to_le_bytes());
+            bytes.extend_from_slice(&(info.line as u32).to_le_bytes());
+        }
+        
+        Ok(bytes)
+    }
+    
+    pub fn decode_program(self: &Self, bytes: &[u8]) -> Result<Program, Error> {
+        if bytes.len() < VBIN_HEADER_SIZE {
+            return Err(Error::with_message(ErrorKind::CorruptedFile, "file is too small to be a .vbin file"));
+        }
+        
+        if bytes[0..4] != VBIN_MAGIC {
+            return Err(Error::with_message(ErrorKind::InvalidFileMagic, "not a .vbin file (bad magic)"));
+        }
+        
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VBIN_VERSION {
+            return Err(Error::with_message(ErrorKind::UnsupportedFileVersion, format!("unsupported .vbin version {} (expected {})", version, VBIN_VERSION)));
+        }
+        
+        let entry = read_u64(bytes, 8) as usize;
+        let count = read_u64(bytes, 16) as usize;
+        if (count > 0 && entry >= count) || (count == 0 && entry != 0) {
+            return Err(Error::with_message(ErrorKind::CorruptedFile, format!("entry point {} is outside of the program", entry)));
+        }
+        
+        let (program, code_end) = self.decode_code(bytes, count)?;
+        let (symbols, symbols_len) = self.decode_symbols(&bytes[code_end..])?;
+        let (files, lines) = self.decode_lines(&bytes[code_end + symbols_len..], count)?;
+        
+        Ok(Program { code: program, entry, symbols, files, lines })
+    }
+    
+    // The instruction words and constant pool, returns where they end
+    fn decode_code(self: &Self, bytes: &[u8], count: usize) -> Result<(Vec<Opcode>, usize), Error> {
+        let size_error = || Error::with_message(ErrorKind::CorruptedFile, format!(".vbin size does not match its instruction count ({})", count));
+        
+        // The constant and symbol counts have to fit after the code
+        let constants_start = count.checked_mul(VBIN_INSTRUCTION_SIZE)
+            .and_then(|len| len.checked_add(VBIN_HEADER_SIZE))
+            .filter(|end| bytes.len() >= 16 && *end <= bytes.len() - 16)
+            .ok_or_else(size_error)?;
+        
+        let code: Vec<Instruction> = bytes[VBIN_HEADER_SIZE..constants_start]
+            .chunks_exact(VBIN_INSTRUCTION_SIZE)
+            .map(|word| Instruction(read_u64(word, 0)))
+            .collect();
+        
+        let constant_count = read_u64(bytes, constants_start) as usize;
+        let code_end = constant_count.checked_mul(VBIN_CONSTANT_SIZE)
+            .and_then(|len| len.checked_add(constants_start + 8))
+            .filter(|end| *end <= bytes.len() - 8)
+            .ok_or_else(size_error)?;
+        
+        let mut constants = Vec::with_capacity(constant_count);
+        for constant in bytes[constants_start + 8..code_end].chunks_exact(VBIN_CONSTANT_SIZE) {
+            let ty = match WordType::from_u8(constant[0]) {
+                Some(ty) => ty,
+                None => return Err(Error::with_message(ErrorKind::CorruptedFile, format!("constant {} has an unknown type", constants.len()))),
+            };
             
-            if fseek(file, 0, SEEK_SET) < 0 {
-                error!("[Error]: Could not read file `{}`", file_path);
-                exit(1);
+            constants.push(TypedWord { word: Word { as_u64: read_u64(constant, 1) }, ty });
+        }
+        
+        let bytecode = Bytecode::from_parts(code, constants)?;
+        Ok((bytecode.opcodes(), code_end))
+    }
+    
+    // Returns the symbols and how many bytes they took up
+    fn decode_symbols(self: &Self, bytes: &[u8]) -> Result<(Vec<Label>, usize), Error> {
+        let corrupted = || Error::with_message(ErrorKind::CorruptedFile, "malformed symbol table");
+        
+        let count = read_u64(bytes, 0) as usize;
+        let mut offset = 8;
+        let mut symbols = Vec::new();
+        for _ in 0..count {
+            if bytes.len() - offset < 10 {
+                return Err(corrupted());
             }
             
-            osvm.program.set_len(fread(osvm.program.as_ptr() as *mut c_void, mem::size_of::<Opcode>(),
-                m as usize / mem::size_of::<Opcode>(), file));
-            
-            if ferror(file) != 0 {
-                error!("[Error]: Could not read file `{}`", file_path);
-                exit(1);
+            let addr = read_u64(bytes, offset) as usize;
+            let len = u16::from_le_bytes([bytes[offset + 8], bytes[offset + 9]]) as usize;
+            offset += 10;
+            if bytes.len() - offset < len {
+                return Err(corrupted());
             }
             
-            info!("[Loading File] => {} => OSVM", file_path);
+            let name = match std::str::from_utf8(&bytes[offset..offset + len]) {
+                Ok(name) => name.to_string(),
+                Err(_) => return Err(corrupted()),
+            };
             
-            fclose(file);
+            offset += len;
+            symbols.push(Label { name, addr });
         }
+        
+        Ok((symbols, offset))
     }
-
-    pub fn save_program_to_file(self: &Self, osvm: &mut OSVM, file_path: &str) {
-        unsafe {
-            let file_name = CString::new(file_path).unwrap();
-            let file_mode = CString::new("wb").unwrap();
-            let file = fopen(file_name.as_ptr(), file_mode.as_ptr());
-            if file.is_null() {
-                error!("[Error]: Could not open file `{}`", file_path);
-                exit(1);
+    
+    fn decode_lines(self: &Self, bytes: &[u8], opcode_count: usize) -> Result<(Vec<String>, Vec<LineInfo>), Error> {
+        let corrupted = || Error::with_message(ErrorKind::CorruptedFile, "malformed line table");
+        if bytes.len() < 8 {
+            return Err(corrupted());
+        }
+        
+        let count = read_u64(bytes, 0) as usize;
+        let mut offset = 8;
+        let mut files = Vec::new();
+        for _ in 0..count {
+            if bytes.len() - offset < 2 {
+                return Err(corrupted());
             }
             
-            fwrite(osvm.program.as_ptr() as *const c_void, mem::size_of::<Opcode>(), osvm.program.len(), file);
-        
-            if ferror(file) != 0 {
-                error!("[Error]: Could not write to file `{}`", file_path);
-                exit(1);
+            let len = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as usize;
+            offset += 2;
+            if bytes.len() - offset < len {
+                return Err(corrupted());
             }
             
-            info!("[Created File] => {}", file_path);
+            match std::str::from_utf8(&bytes[offset..offset + len]) {
+                Ok(file) => files.push(file.to_string()),
+                Err(_) => return Err(corrupted()),
+            }
+            offset += len;
+        }
+        
+        if bytes.len() - offset < 8 {
+            return Err(corrupted());
+        }
+        
+        let count = read_u64(bytes, offset) as usize;
+        offset += 8;
+        if (count != 0 && count != opcode_count) || count.checked_mul(8) != Some(bytes.len() - offset) {
+            return Err(corrupted());
+        }
+        
+        let mut lines = Vec::with_capacity(count);
+        for record in bytes[offset..].chunks(8) {
+            let file = u32::from_le_bytes(record[0..4].try_into().unwrap()) as usize;
+            let line = u32::from_le_bytes(record[4..8].try_into().unwrap()) as usize;
+            if file >= files.len() {
+                return Err(corrupted());
+            }
             
-            fclose(file);
+            lines.push(LineInfo { file, line });
+        }
+        
+        Ok((files, lines))
+    }
+    
+    pub fn load_program_from_file(self: &Self, file_path: &str) -> Result<Program, Error> {
+        let bytes = match fs::read(file_path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                return Err(Error::with_message(ErrorKind::FileIo, format!("could not read file `{}`: {}", file_path, err)));
+            }
+        };
+        
+        let program = self.decode_program(&bytes)?;
+        
+        info!("[Loading File] => {}", file_path);
+        Ok(program)
+    }
+    
+    pub fn save_program_to_file(self: &Self, program: &Program, file_path: &str) -> Result<(), Error> {
+        let bytes = self.encode_program(program)?;
+        if let Err(err) = fs::write(file_path, bytes) {
+            return Err(Error::with_message(ErrorKind::FileIo, format!("could not write to file `{}`: {}", file_path, err)));
+        }
+        
+        info!("[Created File] => {}", file_path);
+        Ok(())
+    }
+}
+
+// Reads a snapshot front to back, every read fails cleanly past the end
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn take(self: &mut Self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() - self.offset < len {
+            return Err(Error::with_message(ErrorKind::CorruptedFile, "snapshot is truncated"));
+        }
+        
+        let bytes = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+    
+    fn u8(self: &mut Self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+    
+    fn u64(self: &mut Self) -> Result<u64, Error> {
+        Ok(read_u64(self.take(8)?, 0))
+    }
+    
+    fn value(self: &mut Self) -> Result<TypedWord, Error> {
+        let ty = match WordType::from_u8(self.u8()?) {
+            Some(ty) => ty,
+            None => return Err(Error::with_message(ErrorKind::CorruptedFile, "snapshot has a value of unknown type")),
+        };
+        
+        Ok(TypedWord { word: Word { as_u64: self.u64()? }, ty })
+    }
+    
+    // Counts are checked against what is left so a bad one can't allocate
+    fn count(self: &mut Self, item_size: usize) -> Result<usize, Error> {
+        let count = self.u64()? as usize;
+        if count > (self.bytes.len() - self.offset) / item_size {
+            return Err(Error::with_message(ErrorKind::CorruptedFile, "snapshot is truncated"));
+        }
+        
+        Ok(count)
+    }
+}
+
+fn push_value(bytes: &mut Vec<u8>, value: &TypedWord) {
+    bytes.push(value.ty as u8);
+    bytes.extend_from_slice(&unsafe { value.word.as_u64 }.to_le_bytes());
+}
+
+impl OSVMFile {
+    pub fn encode_snapshot(self: &Self, snapshot: &Snapshot) -> Result<Vec<u8>, Error> {
+        if snapshot.program_path.len() > u16::MAX as usize {
+            return Err(Error::with_message(ErrorKind::InvalidOperand, format!("program path `{}` is too long", snapshot.program_path)));
+        }
+        
+        let memory_len = snapshot.memory.iter().rposition(|byte| *byte != 0).map(|last| last + 1).unwrap_or(0);
+        let mut bytes = Vec::with_capacity(256 + snapshot.stack.len() * 9 + memory_len);
+        bytes.extend_from_slice(&SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(&snapshot.fingerprint.to_le_bytes());
+        bytes.extend_from_slice(&(snapshot.program_path.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(snapshot.program_path.as_bytes());
+        
+        bytes.extend_from_slice(&(snapshot.pc as u64).to_le_bytes());
+        bytes.extend_from_slice(&(snapshot.tsr as u64).to_le_bytes());
+        bytes.push(snapshot.halt as u8);
+        for value in &snapshot.registers {
+            push_value(&mut bytes, value);
+        }
+        
+        bytes.extend_from_slice(&(snapshot.call_stack.len() as u64).to_le_bytes());
+        for frame in &snapshot.call_stack {
+            bytes.extend_from_slice(&(frame.call_addr as u64).to_le_bytes());
+            bytes.extend_from_slice(&(frame.target_addr as u64).to_le_bytes());
+            bytes.extend_from_slice(&(frame.return_addr as u64).to_le_bytes());
+        }
+        
+        bytes.extend_from_slice(&(snapshot.stack.len() as u64).to_le_bytes());
+        for value in &snapshot.stack {
+            push_value(&mut bytes, value);
+        }
+        
+        bytes.extend_from_slice(&(memory_len as u64).to_le_bytes());
+        bytes.extend_from_slice(&snapshot.memory[..memory_len]);
+        
+        Ok(bytes)
+    }
+    
+    pub fn decode_snapshot(self: &Self, bytes: &[u8]) -> Result<Snapshot, Error> {
+        let mut reader = SnapshotReader { bytes, offset: 0 };
+        if reader.take(4).ok() != Some(&SNAPSHOT_MAGIC[..]) {
+            return Err(Error::with_message(ErrorKind::InvalidFileMagic, "not a snapshot file (bad magic)"));
+        }
+        
+        let header = reader.take(4)?;
+        let version = u16::from_le_bytes([header[0], header[1]]);
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::with_message(ErrorKind::UnsupportedFileVersion, format!("unsupported snapshot version {} (expected {})", version, SNAPSHOT_VERSION)));
+        }
+        
+        let fingerprint = reader.u64()?;
+        let path_len = reader.take(2)?;
+        let path_len = u16::from_le_bytes([path_len[0], path_len[1]]) as usize;
+        let program_path = match std::str::from_utf8(reader.take(path_len)?) {
+            Ok(path) => path.to_string(),
+            Err(_) => return Err(Error::with_message(ErrorKind::CorruptedFile, "snapshot program path is not valid utf-8")),
+        };
+        
+        let pc = reader.u64()? as usize;
+        let tsr = reader.u64()? as usize;
+        let halt = match reader.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(Error::with_message(ErrorKind::CorruptedFile, "snapshot has an invalid halt flag")),
+        };
+        
+        let mut registers = [TypedWord::u64(0); 17];
+        for register in registers.iter_mut() {
+            *register = reader.value()?;
         }
+        
+        // The depth limit belongs to the machine, OSVM::restore checks it
+        let call_depth = reader.count(24)?;
+        let mut call_stack = Vec::with_capacity(call_depth);
+        for _ in 0..call_depth {
+            call_stack.push(Frame {
+                call_addr: reader.u64()? as usize,
+                target_addr: reader.u64()? as usize,
+                return_addr: reader.u64()? as usize,
+            });
+        }
+        
+        let stack_len = reader.count(9)?;
+        let mut stack = Vec::with_capacity(stack_len);
+        for _ in 0..stack_len {
+            stack.push(reader.value()?);
+        }
+        
+        let memory_len = reader.count(1)?;
+        if memory_len > MEMORY_CAPACITY {
+            return Err(Error::with_message(ErrorKind::CorruptedFile, format!("snapshot memory is larger than {} bytes", MEMORY_CAPACITY)));
+        }
+        
+        let mut memory = reader.take(memory_len)?.to_vec();
+        memory.resize(MEMORY_CAPACITY, 0);
+        
+        if reader.offset != bytes.len() {
+            return Err(Error::with_message(ErrorKind::CorruptedFile, "snapshot has trailing bytes"));
+        }
+        
+        Ok(Snapshot { program_path, fingerprint, registers, pc, tsr, halt, call_stack, stack, memory })
+    }
+    
+    pub fn load_snapshot_from_file(self: &Self, file_path: &str) -> Result<Snapshot, Error> {
+        let bytes = match fs::read(file_path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                return Err(Error::with_message(ErrorKind::FileIo, format!("could not read file `{}`: {}", file_path, err)));
+            }
+        };
+        
+        let snapshot = self.decode_snapshot(&bytes)?;
+        
+        info!("[Loading Snapshot] => {}", file_path);
+        Ok(snapshot)
+    }
+    
+    pub fn save_snapshot_to_file(self: &Self, snapshot: &Snapshot, file_path: &str) -> Result<(), Error> {
+        let bytes = self.encode_snapshot(snapshot)?;
+        if let Err(err) = fs::write(file_path, bytes) {
+            return Err(Error::with_message(ErrorKind::FileIo, format!("could not write to file `{}`: {}", file_path, err)));
+        }
+        
+        info!("[Created Snapshot] => {}", file_path);
+        Ok(())
+    }
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    
+    const SOURCE: &str = "
+_start:
+    mov r0, #5
+loop:
+    dec r0
+    jnz loop, r0
+    push #1.5
+    hlt
+";
+    
+    fn encoded() -> Vec<u8> {
+        OSVMFile {}.encode_program(&assemble(SOURCE)).unwrap()
+    }
+    
+    fn decode_error(bytes: &[u8]) -> ErrorKind {
+        let file = OSVMFile {};
+        match file.decode_program(bytes) {
+            Ok(_) => panic!("malformed .vbin was accepted"),
+            Err(err) => err.kind,
+        }
+    }
+    
+    #[test]
+    fn program_round_trips() {
+        let program = assemble(SOURCE);
+        let decoded = OSVMFile {}.decode_program(&encoded()).unwrap();
+        assert_eq!(format!("{:?}", decoded.code), format!("{:?}", program.code));
+        assert_eq!(format!("{:?}", decoded.symbols), format!("{:?}", program.symbols));
+        assert_eq!(decoded.entry, program.entry);
+        assert_eq!(decoded.files, program.files);
+        assert_eq!(decoded.lines, program.lines);
+    }
+    
+    #[test]
+    fn layout_is_fixed() {
+        let bytes = encoded();
+        let code = 5 * VBIN_INSTRUCTION_SIZE + 8 + VBIN_CONSTANT_SIZE;
+        let symbols = 8 + (10 + "_start".len()) + (10 + "loop".len());
+        let lines = 8 + (2 + "test.osv".len()) + 8 + 5 * 8;
+        assert_eq!(bytes.len(), VBIN_HEADER_SIZE + code + symbols + lines);
+        assert_eq!(bytes[0..4], VBIN_MAGIC);
+        assert_eq!(bytes[4..6], VBIN_VERSION.to_le_bytes());
+        assert_eq!(read_u64(&bytes, 16), 5);
+        
+        let bytecode = Bytecode::encode(&assemble(SOURCE).code).unwrap();
+        for (i, instruction) in bytecode.code.iter().enumerate() {
+            assert_eq!(read_u64(&bytes, VBIN_HEADER_SIZE + i * VBIN_INSTRUCTION_SIZE), instruction.0);
+        }
+        
+        // push #1.5 keeps its type in the constant pool
+        let constants = VBIN_HEADER_SIZE + 5 * VBIN_INSTRUCTION_SIZE;
+        assert_eq!(read_u64(&bytes, constants), 1);
+        assert_eq!(bytes[constants + 8], WordType::F64 as u8);
+        assert_eq!(read_u64(&bytes, constants + 9), 1.5f64.to_bits());
+    }
+    
+    #[test]
+    fn truncated_header_is_rejected() {
+        let bytes = encoded();
+        for len in 0..VBIN_HEADER_SIZE {
+            assert_eq!(decode_error(&bytes[..len]), ErrorKind::CorruptedFile);
+        }
+    }
+    
+    #[test]
+    fn truncated_file_is_rejected() {
+        let bytes = encoded();
+        for len in VBIN_HEADER_SIZE..bytes.len() {
+            assert_eq!(decode_error(&bytes[..len]), ErrorKind::CorruptedFile, "accepted {} of {} bytes", len, bytes.len());
+        }
+    }
+    
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut bytes = encoded();
+        bytes[0..4].copy_from_slice(b"OSNP");
+        assert_eq!(decode_error(&bytes), ErrorKind::InvalidFileMagic);
+    }
+    
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut bytes = encoded();
+        for version in [0, VBIN_VERSION + 1, u16::MAX] {
+            bytes[4..6].copy_from_slice(&version.to_le_bytes());
+            assert_eq!(decode_error(&bytes), ErrorKind::UnsupportedFileVersion);
+        }
+    }
+    
+    #[test]
+    fn malformed_code_is_rejected() {
+        // An unknown opcode id, a constant count past the end and a
+        // constant of an unknown type
+        let mut bytes = encoded();
+        bytes[VBIN_HEADER_SIZE] = 0xee;
+        assert_eq!(decode_error(&bytes), ErrorKind::CorruptedFile);
+        
+        let constants = VBIN_HEADER_SIZE + 5 * VBIN_INSTRUCTION_SIZE;
+        let mut bytes = encoded();
+        bytes[constants] = 200;
+        assert_eq!(decode_error(&bytes), ErrorKind::CorruptedFile);
+        
+        let mut bytes = encoded();
+        bytes[constants + 8] = 9;
+        assert_eq!(decode_error(&bytes), ErrorKind::CorruptedFile);
+    }
+    
+    #[test]
+    fn malformed_symbols_are_rejected() {
+        let symbols = VBIN_HEADER_SIZE + 5 * VBIN_INSTRUCTION_SIZE + 8 + VBIN_CONSTANT_SIZE;
+        
+        // A symbol count past the end, a name that is not utf-8, trailing bytes
+        let mut bytes = encoded();
+        bytes[symbols] = 3;
+        assert_eq!(decode_error(&bytes), ErrorKind::CorruptedFile);
+        
+        let mut bytes = encoded();
+        bytes[symbols + 8 + 10] = 0xff;
+        assert_eq!(decode_error(&bytes), ErrorKind::CorruptedFile);
+        
+        let mut bytes = encoded();
+        bytes.push(0);
+        assert_eq!(decode_error(&bytes), ErrorKind::CorruptedFile);
+    }
+    
+    #[test]
+    fn entry_outside_of_the_program_is_rejected() {
+        let mut bytes = encoded();
+        bytes[8..16].copy_from_slice(&100u64.to_le_bytes());
+        assert_eq!(decode_error(&bytes), ErrorKind::CorruptedFile);
+    }
+    
+    #[test]
+    fn missing_file_is_an_error() {
+        let err = OSVMFile {}.load_program_from_file("does/not/exist.vbin").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::FileIo);
+    }
+    
+    fn snapshot_bytes() -> Vec<u8> {
+        let (mut osvm, _) = machine(&assemble(SOURCE));
+        osvm.execute_program().unwrap();
+        OSVMFile {}.encode_snapshot(&osvm.snapshot("test.vbin")).unwrap()
+    }
+    
+    fn snapshot_error(bytes: &[u8]) -> ErrorKind {
+        let file = OSVMFile {};
+        match file.decode_snapshot(bytes) {
+            Ok(_) => panic!("malformed snapshot was accepted"),
+            Err(err) => err.kind,
+        }
+    }
+    
+    #[test]
+    fn snapshot_round_trips() {
+        let (mut osvm, _) = machine(&assemble(SOURCE));
+        osvm.execute_program().unwrap();
+        let snapshot = osvm.snapshot("test.vbin");
+        let decoded = OSVMFile {}.decode_snapshot(&snapshot_bytes()).unwrap();
+        assert_eq!(decoded.program_path, "test.vbin");
+        assert_eq!(decoded.fingerprint, snapshot.fingerprint);
+        assert_eq!(decoded.registers, snapshot.registers);
+        assert_eq!((decoded.pc, decoded.tsr, decoded.halt), (snapshot.pc, snapshot.tsr, true));
+        assert_eq!(decoded.stack, snapshot.stack);
+        assert!(decoded.memory == snapshot.memory);
+    }
+    
+    #[test]
+    fn malformed_snapshots_are_rejected() {
+        let bytes = snapshot_bytes();
+        for len in 0..bytes.len() {
+            assert!(OSVMFile {}.decode_snapshot(&bytes[..len]).is_err(), "accepted {} of {} bytes", len, bytes.len());
+        }
+        
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(snapshot_error(&trailing), ErrorKind::CorruptedFile);
+        
+        let mut magic = bytes.clone();
+        magic[0..4].copy_from_slice(&VBIN_MAGIC);
+        assert_eq!(snapshot_error(&magic), ErrorKind::InvalidFileMagic);
+        
+        let mut version = bytes.clone();
+        version[4..6].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert_eq!(snapshot_error(&version), ErrorKind::UnsupportedFileVersion);
+        
+        // Magic, version, flags, fingerprint and the path come before the halt flag
+        let mut halt = bytes.clone();
+        halt[8 + 8 + 2 + "test.vbin".len() + 16] = 2;
+        assert_eq!(snapshot_error(&halt), ErrorKind::CorruptedFile);
     }
 }
\ No newline at end of file
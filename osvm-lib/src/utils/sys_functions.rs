@@ -1,101 +1,131 @@
 #![allow(unused)]
 
 use std::ffi::c_void;
+use std::io::Write;
 
-use libc::{exit, free, malloc};
-use log::error;
+use libc::{free, malloc};
 
-use crate::{opcode::Opcode, osvm::OSVM};
+use crate::{opcode::{Opcode, OpRegs}, osvm::OSVM, utils::{defines::*, error::{Error, ErrorKind}}};
 
 pub struct SystemFunctions {}
 
-pub type SysFunction = fn(osvm: &mut OSVM, opcode: &Opcode, reg: Vec<String>);
+pub type SysFunction = fn(osvm: &mut OSVM, opcode: &Opcode, reg: OpRegs) -> Result<(), Error>;
 
 impl SystemFunctions {
-    pub fn alloc(osvm: &mut OSVM, opcode: &Opcode, reg: Vec<String>) {
+    // The value a sysf works on, either the register it was
+    // given or the top of the stack
+    fn argument(osvm: &mut OSVM, opcode: &Opcode, reg: &OpRegs) -> Result<TypedWord, Error> {
+        if !reg.is_empty() {
+            Ok(osvm.read_register(opcode, 0)?)
+        } else {
+            match osvm.stack.last() {
+                Some(value) => Ok(*value),
+                None => Err(ErrorKind::StackUnderflow.into()),
+            }
+        }
+    }
+    
+    pub fn alloc(osvm: &mut OSVM, opcode: &Opcode, reg: OpRegs) -> Result<(), Error> {
         unsafe {
             if reg.is_empty() {
                 if osvm.stack.len() < 1 {
-                    eprintln!("[Error]: Stack Underflow");
-                    exit(1);
+                    return Err(ErrorKind::StackUnderflow.into());
                 }
                 
                 let a = osvm.stack.len() - 1;
-                osvm.stack[a].as_ptr = malloc(osvm.stack[a].as_usize);
+                osvm.stack[a] = TypedWord::ptr(malloc(osvm.stack[a].word.as_usize));
             } else {
-                let reg1 = &osvm.find_register(opcode, 0).unwrap();
-                osvm.find_register(opcode, 0).unwrap().as_ptr = malloc(reg1.as_usize);
+                let reg1 = osvm.read_register(opcode, 0)?;
+                osvm.assign_register(opcode, 0, TypedWord::ptr(malloc(reg1.word.as_usize)))?;
             }
         }
+        
+        Ok(())
     }
     
-    pub fn free(osvm: &mut OSVM, opcode: &Opcode, reg: Vec<String>) {
+    pub fn free(osvm: &mut OSVM, opcode: &Opcode, reg: OpRegs) -> Result<(), Error> {
         unsafe {
             if reg.is_empty() {
-                if osvm.stack.len() < 1 {
-                    eprintln!("[Error]: Stack Underflow");
-                    exit(1);
+                match osvm.stack.pop() {
+                    Some(value) => free(value.word.as_ptr as *mut c_void),
+                    None => return Err(ErrorKind::StackUnderflow.into()),
                 }
-                
-                free(osvm.stack.pop().unwrap().as_ptr as *mut c_void);
             } else {
-                free(osvm.find_register(opcode, 0).unwrap().as_ptr as *mut c_void);
-                osvm.find_register(opcode, 0).unwrap().as_usize = 0;
+                free(osvm.read_register(opcode, 0)?.word.as_ptr as *mut c_void);
+                osvm.assign_register(opcode, 0, TypedWord::u64(0))?;
             }
         }
+        
+        Ok(())
     }
     
-    pub fn print_u64(osvm: &mut OSVM, opcode: &Opcode, reg: Vec<String>) {
-        unsafe {
-            if !reg.is_empty() {
-                println!("{}", osvm.find_register(opcode, 0).unwrap().as_u64);
-            } else {
-                println!("{}", osvm.stack[osvm.stack.len() - 1].as_u64);
+    pub fn print(osvm: &mut OSVM, opcode: &Opcode, reg: OpRegs) -> Result<(), Error> {
+        let value = SystemFunctions::argument(osvm, opcode, &reg)?;
+        let _ = unsafe {
+            match value.ty {
+                WordType::U64 => writeln!(osvm.output, "{}", value.word.as_u64),
+                WordType::I64 => writeln!(osvm.output, "{}", value.word.as_i64),
+                WordType::F64 => writeln!(osvm.output, "{}", value.word.as_f64),
+                WordType::Ptr => writeln!(osvm.output, "{:?}", value.word.as_ptr),
             }
+        };
+        
+        Ok(())
+    }
+    
+    pub fn print_u64(osvm: &mut OSVM, opcode: &Opcode, reg: OpRegs) -> Result<(), Error> {
+        let value = SystemFunctions::argument(osvm, opcode, &reg)?;
+        unsafe {
+            let _ = writeln!(osvm.output, "{}", value.convert(WordType::U64).word.as_u64);
         }
+        
+        Ok(())
     }
     
-    pub fn print_i64(osvm: &mut OSVM, opcode: &Opcode, reg: Vec<String>) {
+    pub fn print_i64(osvm: &mut OSVM, opcode: &Opcode, reg: OpRegs) -> Result<(), Error> {
+        let value = SystemFunctions::argument(osvm, opcode, &reg)?;
         unsafe {
-            if !reg.is_empty() {
-                println!("{}", osvm.find_register(opcode, 0).unwrap().as_i64);
-            } else {
-                println!("{}", osvm.stack[osvm.stack.len() - 1].as_i64);
-            }
+            let _ = writeln!(osvm.output, "{}", value.convert(WordType::I64).word.as_i64);
         }
+        
+        Ok(())
     }
     
-    pub fn print_f64(osvm: &mut OSVM, opcode: &Opcode, reg: Vec<String>) {
+    pub fn print_f64(osvm: &mut OSVM, opcode: &Opcode, reg: OpRegs) -> Result<(), Error> {
+        let value = SystemFunctions::argument(osvm, opcode, &reg)?;
         unsafe {
-            if !reg.is_empty() {
-                println!("{}", osvm.find_register(opcode, 0).unwrap().as_f64);
-            } else {
-                println!("{}", osvm.stack[osvm.stack.len() - 1].as_f64);
-            }
+            let _ = writeln!(osvm.output, "{}", value.convert(WordType::F64).word.as_f64);
         }
+        
+        Ok(())
     }
     
-    pub fn print_ptr(osvm: &mut OSVM, opcode: &Opcode, reg: Vec<String>) {
+    pub fn print_ptr(osvm: &mut OSVM, opcode: &Opcode, reg: OpRegs) -> Result<(), Error> {
+        let value = SystemFunctions::argument(osvm, opcode, &reg)?;
         unsafe {
-            if !reg.is_empty() {
-                    println!("{:?}", osvm.find_register(opcode, 0).unwrap().as_ptr);
-            } else {
-                println!("{:?}", osvm.stack[osvm.stack.len() - 1].as_ptr);
-            }
+            let _ = writeln!(osvm.output, "{:?}", value.word.as_ptr);
         }
+        
+        Ok(())
     }
     
-    pub fn print_mem(osvm: &mut OSVM, opcode: &Opcode, reg: Vec<String>) {
+    pub fn print_mem(osvm: &mut OSVM, opcode: &Opcode, reg: OpRegs) -> Result<(), Error> {
+        if osvm.stack.len() < 2 {
+            return Err(ErrorKind::StackUnderflow.into());
+        }
+        
         unsafe {
-            let a = osvm.stack.pop().unwrap().as_usize;
-            let b = osvm.stack.pop().unwrap().as_usize;
-            if a > osvm.memory.capacity() {
-                error!("Index is larger than the mems capacity");
-            } else {
-                for i in b..a {
-                    print!("{:02x} ", osvm.memory[i]);
-                }
+            let a = osvm.stack.pop().unwrap().word.as_usize;
+            let b = osvm.stack.pop().unwrap().word.as_usize;
+            if a > osvm.memory.len() || b > a {
+                return Err(Error::with_message(ErrorKind::ErrIllegalMemoryAccess, "index is larger than the mems capacity"));
+            }
+            
+            for i in b..a {
+                let _ = write!(osvm.output, "{:02x} ", osvm.memory[i]);
             }
         }
+        
+        Ok(())
     }
 }
\ No newline at end of file
@@ -0,0 +1,147 @@
+use crate::utils::defines::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchAccess {
+    Read,
+    Write,
+    
+    // Either a read or a write
+    Access,
+}
+
+impl WatchAccess {
+    pub fn matches(self: Self, access: WatchAccess) -> bool {
+        self == WatchAccess::Access || self == access
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Watchpoint {
+    // Index into REGISTERS, fires whenever the register is assigned
+    Register(usize),
+    
+    // Fires when a `rd`/`wrt` touches any byte of start..start + len
+    Memory { start: usize, len: usize, access: WatchAccess },
+}
+
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+    Register { reg: usize, old: TypedWord, new: TypedWord },
+    Memory { addr: usize, len: usize, access: WatchAccess, old: u64, new: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchHit {
+    // The instruction that triggered the watchpoint
+    pub pc: usize,
+    pub watchpoint: usize,
+    pub event: WatchEvent,
+}
+
+pub type WatchCallback = Box<dyn FnMut(&WatchHit)>;
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+    
+    use super::*;
+    use crate::osvm::OSVM;
+    use crate::testing::*;
+    
+    const PROGRAM: &str = "
+_start:
+    mov r1, #16
+    mov r2, #258
+    wrt #16, r1, r2
+    mov r7, #3
+    rd #8, r3, r1
+    push #7
+    push #20
+    wrt #64
+    mov r7, #4
+    hlt
+";
+    
+    // Runs PROGRAM with `watchpoints` and returns every hit in order
+    fn hits(watchpoints: &[Watchpoint]) -> (OSVM, Vec<WatchHit>) {
+        let (mut osvm, _) = machine(&assemble(PROGRAM));
+        for watchpoint in watchpoints {
+            osvm.add_watchpoint(watchpoint.clone());
+        }
+        
+        let hits = Rc::new(RefCell::new(Vec::new()));
+        let sink = hits.clone();
+        osvm.set_watch_callback(Box::new(move |hit| sink.borrow_mut().push(hit.clone())));
+        osvm.execute_program().unwrap();
+        
+        let hits = hits.borrow().clone();
+        (osvm, hits)
+    }
+    
+    #[test]
+    fn register_writes_fire() {
+        let (_, hits) = hits(&[Watchpoint::Register(7)]);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].pc, 3);
+        assert_eq!(hits[0].watchpoint, 0);
+        match hits[1].event {
+            WatchEvent::Register { reg, old, new } => {
+                assert_eq!(reg, 7);
+                assert_eq!(old, TypedWord::u64(3));
+                assert_eq!(new, TypedWord::u64(4));
+            }
+            _ => panic!("expected a register event"),
+        }
+    }
+    
+    #[test]
+    fn memory_writes_and_reads_fire_on_overlap() {
+        let (_, hits) = hits(&[
+            Watchpoint::Memory { start: 17, len: 1, access: WatchAccess::Write },
+            Watchpoint::Memory { start: 16, len: 1, access: WatchAccess::Read },
+            Watchpoint::Memory { start: 20, len: 4, access: WatchAccess::Access },
+            Watchpoint::Memory { start: 18, len: 2, access: WatchAccess::Access },
+        ]);
+        
+        let summary: Vec<(usize, usize)> = hits.iter().map(|hit| (hit.pc, hit.watchpoint)).collect();
+        assert_eq!(summary, [(2, 0), (4, 1), (7, 2)]);
+        match hits[0].event {
+            WatchEvent::Memory { addr, len, access, old, new } => {
+                assert_eq!((addr, len, access), (16, 2, WatchAccess::Write));
+                assert_eq!((old, new), (0, 258));
+            }
+            _ => panic!("expected a memory event"),
+        }
+        
+        match hits[2].event {
+            WatchEvent::Memory { addr, len, new, .. } => assert_eq!((addr, len, new), (20, 8, 7)),
+            _ => panic!("expected a memory event"),
+        }
+    }
+    
+    #[test]
+    fn hits_are_kept_for_the_last_instruction() {
+        let (mut osvm, _) = machine(&assemble(PROGRAM));
+        osvm.add_watchpoint(Watchpoint::Register(2));
+        osvm.execute_opcode().unwrap();
+        assert!(osvm.watch_hits.is_empty());
+        
+        osvm.execute_opcode().unwrap();
+        assert_eq!(osvm.watch_hits.len(), 1);
+        assert_eq!(osvm.watch_hits[0].pc, 1);
+        
+        osvm.execute_opcode().unwrap();
+        assert!(osvm.watch_hits.is_empty());
+    }
+    
+    #[test]
+    fn watched_runs_match_unwatched_runs() {
+        let (unwatched, _, result) = run(&assemble(PROGRAM));
+        result.unwrap();
+        
+        let (watched, _) = hits(&[Watchpoint::Register(7), Watchpoint::Memory { start: 0, len: 64, access: WatchAccess::Access }]);
+        assert_eq!(watched.stack, unwatched.stack);
+        assert_eq!(watched.tsr, unwatched.tsr);
+        assert_eq!(watched.memory[..64], unwatched.memory[..64]);
+    }
+}
\ No newline at end of file
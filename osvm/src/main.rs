@@ -1,8 +1,16 @@
-use std::{env, fs::File, io::Read, process::exit};
-use osvm_lib::prelude::*;
+#![allow(clippy::needless_return)]
+
+use std::{env, fs::{self, File}, io::{stdin, stdout, BufReader, BufWriter, Read}, process::exit};
+use osvm_lib::{prelude::*, utils::error::Error};
 
 pub fn get_file_contents(file_path: &str) -> String {
-    let mut file: File = File::open(file_path).unwrap();
+    let mut file: File = match File::open(file_path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("[Error]: could not open `{}`: {}", file_path, err);
+            exit(1);
+        }
+    };
     let mut contents = String::new();
     let _ = file.read_to_string(&mut contents);
     
@@ -12,12 +20,26 @@ pub fn get_file_contents(file_path: &str) -> String {
 fn usage(program_file: &String) {
     println!("[Usage]: {program_file} <SUBCOMMAND> <ARGS>");
     println!("[Subcommands]:");
-    println!("  -   build <INPUT.OSV> <OUTPUT.VBIN>  ->  Compiles the program");
-    println!("  -   run   <INPUT.OSV> <OUTPUT.VBIN>  ->  Runs the program");
-    println!("  -   debug <INPUT.OSV> <OUTPUT.VBIN>  ->  Compiles the program");
+    println!("  -   build  <INPUT.OSV> <OUTPUT.VBIN>  ->  Compiles the program");
+    println!("  -   run    <INPUT.VBIN> [OPTIONS]     ->  Runs the program");
+    println!("  -   resume <INPUT.SNAP> [OPTIONS]     ->  Runs the program on from a snapshot");
+    println!("  -   debug  <INPUT.VBIN>               ->  Runs the program in the debugger");
+    println!("  -   disasm <INPUT.VBIN> [OUTPUT.OSV]  ->  Disassembles the program");
+    println!("  -   gdb    <INPUT.VBIN> [ADDRESS]     ->  Serves the program to gdb on host:port or unix:<path>");
+    println!("  -   dap                               ->  Speaks the Debug Adapter Protocol over stdio");
+    println!("[Build Options]:");
+    println!("  -   --optimize                        ->  Runs the peephole optimizer over the assembled program");
+    println!("[Run Options]:");
+    println!("  -   --trace <OUT.JSONL>               ->  Writes one JSON record per executed instruction");
+    println!("  -   --profile                         ->  Prints hot instructions, opcodes, labels and functions");
+    println!("  -   --folded <OUT.FOLDED>             ->  Writes the profile as folded stacks for flamegraphs");
+    println!("  -   --coverage <OUT.INFO>             ->  Writes lcov line and branch coverage and prints a summary");
+    println!("  -   --snapshot-at <WHEN> <OUT.SNAP>   ->  Saves the machine state at pc:<ADDR|LABEL> or after <COUNT> instructions");
+    println!("  -   --jit                             ->  Compiles long running programs to native code (x86-64 Linux)");
+    println!("  -   --jit-check                       ->  Runs interpreted and compiled and fails if the results differ");
 }
 
-fn shift(index: &mut usize, args: &Vec<String>) -> String {
+fn shift(index: &mut usize, args: &[String]) -> String {
     let last_index = *index;
     if last_index >= args.len() {
         usage(&args[0]);
@@ -28,37 +50,268 @@ fn shift(index: &mut usize, args: &Vec<String>) -> String {
     return args[last_index].clone();
 }
 
+// Runs like execute_program and saves a snapshot once `point` is reached
+fn execute_with_snapshot(osvm: &mut OSVM, osvm_file: &OSVMFile, point: SnapshotPoint, snapshot_path: &str, program_path: &str) -> Result<(), Error> {
+    let mut executed: u64 = 0;
+    let mut saved = false;
+    loop {
+        if !saved && point.reached(osvm.get_pc(), executed) {
+            osvm_file.save_snapshot_to_file(&osvm.snapshot(program_path), snapshot_path)?;
+            saved = true;
+        }
+        
+        if osvm.is_halted() {
+            break;
+        }
+        
+        osvm.execute_opcode()?;
+        executed += 1;
+    }
+    
+    if !saved {
+        eprintln!("[Warning]: the program halted before the snapshot point, `{}` was not written", snapshot_path);
+    }
+    
+    Ok(())
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let mut index = 0;
     let program_file = shift(&mut index, &args);
     
-    let mut osvm: OSVM = OSVM::init();
-    osvm.init_log();
-    osvm.init_default_sysf();
-    
-    let mut osvm_file: OSVMFile = OSVMFile {}; 
-    let oasm: OASM = OASM::init();
+    Log::init();
+    let osvm_file: OSVMFile = OSVMFile {};
     
     let subcommand = shift(&mut index, &args);
     
     match subcommand.as_str() {
-        "build" | "run" | "debug" => {
+        "build" => {
             println!("----------- Compiling -----------");
             let input_path = shift(&mut index, &args);
             let output_path = shift(&mut index, &args);
             let source = get_file_contents(input_path.clone().as_str());
-            osvm.translate_source(oasm, input_path.clone(), source.clone());
-            osvm_file.save_program_to_file(&mut osvm, &output_path);
+            let mut assembler: Assembler = Assembler::init();
+            let mut program = match assembler.assemble(&input_path, &source) {
+                Ok(program) => program,
+                Err(errors) => {
+                    for err in &errors {
+                        Log::report(err);
+                    }
+                    
+                    eprintln!("[Error]: could not compile `{}` due to {} previous error(s)", input_path, errors.len());
+                    exit(1);
+                }
+            };
             
-            if subcommand == "run" {
-                osvm_file.load_program_from_file(&mut osvm, &output_path);
+            while index < args.len() {
+                let option = shift(&mut index, &args);
+                match option.as_str() {
+                    "--optimize" => {
+                        program = Optimizer::init().optimize(&program);
+                    }
+                    
+                    _ => {
+                        usage(&program_file);
+                        eprintln!("[Error]: unknown option `{}`", option);
+                        exit(1);
+                    }
+                }
+            }
+            
+            if let Err(err) = osvm_file.save_program_to_file(&program, &output_path) {
+                Log::report(&err);
+                exit(1);
+            }
+        }
+        
+        "run" | "resume" | "debug" | "gdb" => {
+            let input_path = shift(&mut index, &args);
+            let snapshot = if subcommand == "resume" {
+                match osvm_file.load_snapshot_from_file(&input_path) {
+                    Ok(snapshot) => Some(snapshot),
+                    Err(err) => {
+                        Log::report(&err);
+                        exit(1);
+                    }
+                }
+            } else {
+                None
+            };
+            
+            // A snapshot refers to its program by path
+            let program_path = snapshot.as_ref().map(|snapshot| snapshot.program_path.clone()).unwrap_or(input_path);
+            let program = match osvm_file.load_program_from_file(&program_path) {
+                Ok(program) => program,
+                Err(err) => {
+                    Log::report(&err);
+                    exit(1);
+                }
+            };
+            
+            let mut osvm: OSVM = OSVM::init();
+            osvm.init_default_sysf();
+            if let Err(err) = osvm.load_program(&program) {
+                Log::report(&err);
+                exit(1);
+            }
+            
+            if let Some(snapshot) = &snapshot {
+                if let Err(err) = osvm.restore(snapshot) {
+                    Log::report(&err);
+                    exit(1);
+                }
+            }
+            
+            let result = if subcommand == "run" || subcommand == "resume" {
+                let mut profile = false;
+                let mut folded_path = None;
+                let mut coverage_path = None;
+                let mut snapshot_at = None;
+                let mut jit = false;
+                let mut jit_check = false;
+                while index < args.len() {
+                    let option = shift(&mut index, &args);
+                    match option.as_str() {
+                        "--trace" => {
+                            let trace_path = shift(&mut index, &args);
+                            match File::create(&trace_path) {
+                                Ok(file) => osvm.start_tracing(Box::new(BufWriter::new(file))),
+                                Err(err) => {
+                                    eprintln!("[Error]: could not create `{}`: {}", trace_path, err);
+                                    exit(1);
+                                }
+                            }
+                        }
+                        
+                        "--profile" => profile = true,
+                        "--folded" => folded_path = Some(shift(&mut index, &args)),
+                        "--coverage" => coverage_path = Some(shift(&mut index, &args)),
+                        "--jit" => jit = true,
+                        "--jit-check" => jit_check = true,
+                        "--snapshot-at" => {
+                            let point = shift(&mut index, &args);
+                            let snapshot_path = shift(&mut index, &args);
+                            match SnapshotPoint::parse(&point, &program.symbols) {
+                                Ok(point) => snapshot_at = Some((point, snapshot_path)),
+                                Err(err) => {
+                                    Log::report(&err);
+                                    exit(1);
+                                }
+                            }
+                        }
+                        
+                        _ => {
+                            usage(&program_file);
+                            eprintln!("[Error]: unknown option `{}`", option);
+                            exit(1);
+                        }
+                    }
+                }
+                
+                // A snapshot is taken by the interpreter stepping one instruction at a time
+                if snapshot_at.is_some() && (jit || jit_check) {
+                    eprintln!("[Error]: `--snapshot-at` can't be combined with `--jit` or `--jit-check`");
+                    exit(1);
+                }
+                
+                if profile || folded_path.is_some() {
+                    osvm.start_profiling();
+                }
+                
+                if coverage_path.is_some() {
+                    osvm.start_coverage();
+                }
+                
                 println!("------------ Running ------------");
-                osvm.execute_program();
-            } else if subcommand == "debug" {
-                osvm_file.load_program_from_file(&mut osvm, &output_path);
+                let result = match &snapshot_at {
+                    Some((point, snapshot_path)) => {
+                        // Stored absolute so `resume` works from any directory
+                        let program_path = fs::canonicalize(&program_path).map(|path| path.to_string_lossy().into_owned()).unwrap_or(program_path.clone());
+                        execute_with_snapshot(&mut osvm, &osvm_file, *point, snapshot_path, &program_path)
+                    }
+                    None if jit_check => match check_jit(&mut osvm, &program) {
+                        Ok((output, result)) => {
+                            print!("{}", String::from_utf8_lossy(&output));
+                            println!("[JIT]: interpreted and compiled runs match");
+                            result
+                        }
+                        Err(err) => Err(err),
+                    },
+                    None if jit => osvm.execute_program_jit(JIT_THRESHOLD),
+                    None => osvm.execute_program(),
+                };
+                if let Err(err) = osvm.stop_tracing() {
+                    eprintln!("[Error]: could not write the trace: {}", err);
+                }
+                
+                if let Some(profiler) = osvm.stop_profiling() {
+                    if profile {
+                        println!();
+                        print!("{}", profiler.report(&program.code, &program.symbols));
+                    }
+                    
+                    if let Some(folded_path) = folded_path {
+                        let written = File::create(&folded_path).and_then(|file| profiler.write_folded(&mut BufWriter::new(file), &program.symbols));
+                        if let Err(err) = written {
+                            eprintln!("[Error]: could not write to file `{}`: {}", folded_path, err);
+                        }
+                    }
+                }
+                
+                if let (Some(coverage), Some(coverage_path)) = (osvm.stop_coverage(), coverage_path) {
+                    println!();
+                    print!("{}", coverage.summary(&program));
+                    
+                    let written = File::create(&coverage_path).and_then(|file| coverage.write_lcov(&mut BufWriter::new(file), &program));
+                    if let Err(err) = written {
+                        eprintln!("[Error]: could not write to file `{}`: {}", coverage_path, err);
+                    }
+                }
+                result
+            } else if subcommand == "gdb" {
+                let address = if index < args.len() { shift(&mut index, &args) } else { "127.0.0.1:1234".to_string() };
+                println!("--------- Running (GDB) ---------");
+                GdbStub::init().listen(&mut osvm, &address)
+            } else {
                 println!("------ Running (Debugging) ------");
-                osvm.execute_program_debug();
+                osvm.execute_program_debug()
+            };
+            
+            if let Err(err) = result {
+                Log::report(&err);
+                exit(1);
+            }
+        }
+        
+        "dap" => {
+            let mut osvm: OSVM = OSVM::init();
+            osvm.init_default_sysf();
+            
+            let mut dap: DapServer = DapServer::init();
+            dap.run(&mut osvm, Box::new(BufReader::new(stdin())), &mut stdout());
+        }
+        
+        "disasm" => {
+            let input_path = shift(&mut index, &args);
+            let program = match osvm_file.load_program_from_file(&input_path) {
+                Ok(program) => program,
+                Err(err) => {
+                    Log::report(&err);
+                    exit(1);
+                }
+            };
+            
+            let disassembler: Disassembler = Disassembler {};
+            let source = disassembler.disassemble(&program);
+            if index < args.len() {
+                let output_path = shift(&mut index, &args);
+                if let Err(err) = fs::write(&output_path, source) {
+                    eprintln!("[Error]: could not write to file `{}`: {}", output_path, err);
+                    exit(1);
+                }
+            } else {
+                print!("{}", source);
             }
         }
         